@@ -72,7 +72,7 @@ use namada::ledger::queries::{
 };
 use namada::state::StorageRead;
 use namada::tx::data::pos::Bond;
-use namada::tx::data::{Fee, TxResult, VpsResult};
+use namada::tx::data::{Fee, TxResult};
 use namada::tx::{Authorization, Code, Data, Section, Tx};
 use namada::vm::wasm::run;
 use namada::{proof_of_stake, tendermint};
@@ -884,13 +884,8 @@ impl Client for BenchShell {
                     .enumerate()
                     .map(|(idx, (_tx, changed_keys))| {
                         let tx_result = TxResult {
-                            gas_used: 0.into(),
-                            wrapper_changed_keys: Default::default(),
                             changed_keys: changed_keys.to_owned(),
-                            vps_result: VpsResult::default(),
-                            initialized_accounts: vec![],
-                            ibc_events: BTreeSet::default(),
-                            eth_bridge_events: BTreeSet::default(),
+                            ..Default::default()
                         };
                         namada::tendermint::abci::Event {
                             kind: "applied".to_string(),
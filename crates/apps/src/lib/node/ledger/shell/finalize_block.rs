@@ -12,7 +12,7 @@ use namada::ledger::events::EmitEvents;
 use namada::ledger::gas::GasMetering;
 use namada::ledger::ibc;
 use namada::ledger::pos::namada_proof_of_stake;
-use namada::ledger::protocol::WrapperArgs;
+use namada::ledger::protocol::{BlockAccumulators, DispatchArgs, WrapperArgs};
 use namada::proof_of_stake;
 use namada::proof_of_stake::storage::{
     find_validator_by_raw_hash, write_last_block_proposer_address,
@@ -125,6 +125,14 @@ where
         }
 
         let mut stats = InternalStats::default();
+        let block_accumulators = RefCell::new(BlockAccumulators::default());
+        // Look up the transfer code of the fee unshieldings once per block, a
+        // missing code is reported by the unshieldings themselves
+        let transfer_hash =
+            protocol::get_transfer_hash_from_storage(&self.state).ok();
+        // Same for the gas cost per byte of the wrappers
+        let wrapper_gas_per_byte =
+            protocol::get_wrapper_gas_per_byte(&self.state).ok();
 
         let native_block_proposer_address = {
             let tm_raw_hash_string =
@@ -224,10 +232,9 @@ where
                         (
                             tx_event,
                             gas_meter,
-                            Some(WrapperArgs {
-                                block_proposer: &native_block_proposer_address,
-                                is_committed_fee_unshield: false,
-                            }),
+                            Some(WrapperArgs::new(
+                                &native_block_proposer_address,
+                            )),
                         )
                     }
                     TxType::Raw => {
@@ -241,7 +248,8 @@ where
                         ProtocolTxType::BridgePoolVext
                         | ProtocolTxType::BridgePool
                         | ProtocolTxType::ValSetUpdateVext
-                        | ProtocolTxType::ValidatorSetUpdate => (
+                        | ProtocolTxType::ValidatorSetUpdate
+                        | ProtocolTxType::EthHotKeyRotation => (
                             new_tx_event(&tx, height.0),
                             TxGasMeter::new_from_sub_limit(0.into()),
                             None,
@@ -308,6 +316,7 @@ where
                     None
                 };
             let tx_gas_meter = RefCell::new(tx_gas_meter);
+            let tx_events = RefCell::new(vec![]);
             let tx_result = protocol::dispatch_tx(
                 tx.clone(),
                 processed_tx.tx.as_ref(),
@@ -320,9 +329,17 @@ where
                 &mut self.state,
                 &mut self.vp_wasm_cache,
                 &mut self.tx_wasm_cache,
+                Some(&block_accumulators),
+                &DispatchArgs {
+                    event_sink: Some(&tx_events),
+                    transfer_hash,
+                    wrapper_gas_per_byte,
+                    ..Default::default()
+                },
                 wrapper_args.as_mut(),
             )
             .map_err(Error::TxApply);
+            response.events.emit_many(tx_events.into_inner());
             let tx_gas_meter = tx_gas_meter.into_inner();
 
             // save the gas cost
@@ -332,6 +349,14 @@ where
                 tx_gas_meter.get_tx_consumed_gas().into(),
             );
 
+            // The token and the amount of the fees charged, if any
+            let fee_payment =
+                wrapper_args.as_ref().and_then(WrapperArgs::charged_fee);
+            // The token and the amount credited to the block proposer, if any
+            let proposer_fee = wrapper_args
+                .as_ref()
+                .and_then(|args| args.proposer_fee.clone());
+
             match tx_result {
                 Ok(result) => {
                     if result.is_accepted() {
@@ -448,16 +473,19 @@ where
                         if !matches!(
                             msg,
                             Error::TxApply(protocol::Error::GasError(_))
+                                | Error::TxApply(
+                                    protocol::Error::VpsGasError { .. }
+                                )
                                 | Error::TxApply(
                                     protocol::Error::MissingSection(_)
                                 )
                                 | Error::TxApply(
-                                    protocol::Error::ReplayAttempt(_)
+                                    protocol::Error::ReplayAttempt(..)
                                 )
                         ) {
                             self.commit_inner_tx_hash(replay_protection_hashes);
                         } else if let Error::TxApply(
-                            protocol::Error::ReplayAttempt(_),
+                            protocol::Error::ReplayAttempt(..),
                         ) = msg
                         {
                             // Mark the wrapper hash as redundant but keep the
@@ -494,6 +522,41 @@ where
                     tx_event.extend(Code(ResultCode::WasmRuntimeError));
                 }
             }
+
+            if let (
+                Some((fee_token, fee_amount)),
+                Some((proposer_token, proposer_fees)),
+                TxType::Wrapper(wrapper),
+            ) = (fee_payment, proposer_fee, &tx_header.tx_type)
+            {
+                match protocol::refund_unused_gas(
+                    &mut self.state,
+                    &native_block_proposer_address,
+                    &protocol::fee_delegated_wrapper(&tx, wrapper),
+                    &fee_token,
+                    fee_amount,
+                    (&proposer_token, proposer_fees),
+                    tx_gas_meter.get_tx_consumed_gas(),
+                ) {
+                    Ok(refund) => {
+                        protocol::audit_fee_refund(
+                            &mut self.state,
+                            &tx,
+                            refund,
+                        );
+                        self.state.commit_tx();
+                    }
+                    Err(msg) => {
+                        tracing::error!(
+                            "Failed to refund the unused gas of wrapper {}: \
+                             {}",
+                            tx_event["hash"],
+                            msg
+                        );
+                        self.state.drop_tx();
+                    }
+                }
+            }
             response.events.emit(tx_event);
         }
 
@@ -3297,6 +3360,111 @@ mod test_finalize_block {
         )
     }
 
+    // Test that the fees of the unused gas are refunded to the fee payer
+    // delegated by the wrapper signer
+    #[test]
+    fn test_refund_unused_gas_to_fee_delegate() {
+        let (mut shell, _, _, _) = setup();
+        shell
+            .state
+            .write(
+                &namada::parameters::storage::get_refund_unused_gas_key(),
+                true,
+            )
+            .unwrap();
+
+        let validator = shell.mode.get_validator_address().unwrap().to_owned();
+        let pos_params =
+            namada_proof_of_stake::storage::read_pos_params(&shell.state)
+                .unwrap();
+        let consensus_key =
+            namada_proof_of_stake::storage::validator_consensus_key_handle(
+                &validator,
+            )
+            .get(&shell.state, Epoch::default(), &pos_params)
+            .unwrap()
+            .unwrap();
+        let proposer_address = HEXUPPER
+            .decode(consensus_key.tm_raw_hash().as_bytes())
+            .unwrap();
+
+        let signer_keypair = crate::wallet::defaults::albert_keypair();
+        let delegate_keypair = crate::wallet::defaults::bertha_keypair();
+        let mut wasm_path = top_level_directory();
+        wasm_path.push("wasm_for_tests/tx_no_op.wasm");
+        let tx_code = std::fs::read(wasm_path)
+            .expect("Expected a file at given code path");
+        let mut wrapper =
+            Tx::from_type(TxType::Wrapper(Box::new(WrapperTx::new(
+                Fee {
+                    amount_per_gas_unit: DenominatedAmount::native(1.into()),
+                    token: shell.state.in_mem().native_token.clone(),
+                },
+                signer_keypair.ref_to(),
+                Epoch(0),
+                5_000_000.into(),
+                None,
+            ))));
+        wrapper.header.chain_id = shell.chain_id.clone();
+        wrapper.set_code(Code::new(tx_code, None));
+        wrapper.set_data(Data::new("Transaction data".as_bytes().to_owned()));
+        wrapper.add_section(Section::Authorization(Authorization::new(
+            vec![wrapper.header_hash()],
+            [(0, delegate_keypair.clone())].into_iter().collect(),
+            None,
+        )));
+        wrapper.add_section(Section::Authorization(Authorization::new(
+            wrapper.sechashes(),
+            [(0, signer_keypair.clone())].into_iter().collect(),
+            None,
+        )));
+        let fee_amount =
+            wrapper.header().wrapper().unwrap().get_tx_fee().unwrap();
+        let fee_amount = namada::token::denom_to_amount(
+            fee_amount,
+            &wrapper.header().wrapper().unwrap().fee.token,
+            &shell.state,
+        )
+        .unwrap();
+
+        let read_balance = |shell: &TestShell, keypair: &common::SecretKey| {
+            namada::token::read_balance(
+                &shell.state,
+                &shell.state.in_mem().native_token,
+                &Address::from(&keypair.ref_to()),
+            )
+            .unwrap()
+        };
+        let signer_balance = read_balance(&shell, &signer_keypair);
+        let delegate_balance = read_balance(&shell, &delegate_keypair);
+
+        let processed_tx = ProcessedTx {
+            tx: wrapper.to_bytes().into(),
+            result: TxResult {
+                code: ResultCode::Ok.into(),
+                info: "".into(),
+            },
+        };
+        let event = &shell
+            .finalize_block(FinalizeBlock {
+                txs: vec![processed_tx],
+                proposer_address,
+                ..Default::default()
+            })
+            .expect("Test failed")[0];
+        let code = event.attributes.get("code").expect("Test failed").as_str();
+        assert_eq!(code, String::from(ResultCode::Ok).as_str());
+
+        // the delegate paid the fees of the gas used only
+        assert_eq!(read_balance(&shell, &signer_keypair), signer_balance);
+        let new_delegate_balance = read_balance(&shell, &delegate_keypair);
+        assert!(new_delegate_balance < delegate_balance);
+        assert!(
+            new_delegate_balance
+                > delegate_balance.checked_sub(fee_amount).unwrap()
+        );
+    }
+
     #[test]
     fn test_ledger_slashing() -> namada::state::StorageResult<()> {
         let num_validators = 7_u64;
@@ -4986,4 +5154,31 @@ mod test_finalize_block {
             control_receiver.recv().await.expect("Test failed");
         assert_eq!(u64::from(cmd.min_confirmations), 42);
     }
+
+    /// Test that the fee unshielding outcomes are only memoised within the
+    /// execution of a single block
+    #[test]
+    fn test_fee_unshielding_outcomes_scoped_to_block() {
+        let (mut shell, _, _, _) = setup();
+        let hash = Hash::sha256(b"wrapper");
+
+        shell
+            .state
+            .write_log_mut()
+            .write_fee_unshielding_outcome(hash, false);
+        shell
+            .finalize_block(FinalizeBlock::default())
+            .expect("Test failed");
+        assert_eq!(
+            shell.state.write_log().fee_unshielding_outcome(&hash),
+            Some(false)
+        );
+
+        // The outcomes are discarded once the block is committed
+        shell.commit();
+        assert_eq!(
+            shell.state.write_log().fee_unshielding_outcome(&hash),
+            None
+        );
+    }
 }
@@ -344,6 +344,8 @@ where
         &mut shell.vp_wasm_cache,
         &mut shell.tx_wasm_cache,
         None,
+        &Default::default(),
+        None,
     );
     shell
         .state
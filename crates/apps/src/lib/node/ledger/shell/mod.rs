@@ -59,7 +59,7 @@ use namada::state::{
 };
 use namada::token;
 pub use namada::tx::data::ResultCode;
-use namada::tx::data::{TxType, WrapperTx, WrapperTxErr};
+use namada::tx::data::{Fee, TxType, WrapperTx, WrapperTxErr};
 use namada::tx::{Section, Tx};
 use namada::vm::wasm::{TxCache, VpCache};
 use namada::vm::{WasmCacheAccess, WasmCacheRwAccess};
@@ -1036,6 +1036,29 @@ where
                         response.priority = i64::MAX;
                     }
                 }
+                ProtocolTxType::EthHotKeyRotation => {
+                    if let Err(err) =
+                        ethereum_tx_data_variants::EthHotKeyRotation::try_from(
+                            &tx,
+                        )
+                        .map_err(|err| err.to_string())
+                        .and_then(|rotation| {
+                            protocol::validate_eth_hot_key_rotation(
+                                &self.state,
+                                &rotation,
+                            )
+                            .map_err(|err| err.to_string())
+                        })
+                    {
+                        response.code = ResultCode::InvalidTx.into();
+                        response.log = format!(
+                            "{INVALID_MSG}: Invalid Ethereum hot key \
+                             rotation: {err}",
+                        );
+                    } else {
+                        response.log = String::from(VALID_MSG);
+                    }
+                }
                 _ => {
                     response.code = ResultCode::InvalidTx.into();
                     response.log = format!(
@@ -1058,7 +1081,16 @@ where
 
                 // Tx gas limit
                 let mut gas_meter = TxGasMeter::new(wrapper.gas_limit);
-                if gas_meter.add_wrapper_gas(tx_bytes).is_err() {
+                let gas_per_byte =
+                    match protocol::get_wrapper_gas_per_byte(&self.state) {
+                        Ok(gas_per_byte) => gas_per_byte,
+                        Err(err) => {
+                            response.code = ResultCode::InvalidTx.into();
+                            response.log = format!("{INVALID_MSG}: {err}");
+                            return response;
+                        }
+                    };
+                if gas_meter.add_wrapper_gas(tx_bytes, gas_per_byte).is_err() {
                     response.code = ResultCode::TxGasLimit.into();
                     response.log = "{INVALID_MSG}: Wrapper transaction \
                                     exceeds its gas limit"
@@ -1078,6 +1110,18 @@ where
                     return response;
                 }
 
+                // Wrapper expiry height, the tx can't be included before the
+                // next block
+                let next_height =
+                    self.state.in_mem().get_last_block_height().next_height();
+                if let Err(err) =
+                    protocol::check_wrapper_expiry(&tx, next_height)
+                {
+                    response.code = ResultCode::ExpiredTx.into();
+                    response.log = format!("{INVALID_MSG}: {err}");
+                    return response;
+                }
+
                 // Replay protection check
                 let inner_tx_hash = tx.raw_header_hash();
                 if self
@@ -1111,7 +1155,8 @@ where
 
                 // Validate wrapper fees
                 if let Err(e) = mempool_fee_check(
-                    &wrapper,
+                    &protocol::fee_delegated_wrapper(&tx, &wrapper),
+                    &tx.wrapper_fee_token_fallbacks(),
                     get_fee_unshielding_transaction(&tx, &wrapper),
                     &mut ShellParams::new(
                         &RefCell::new(gas_meter),
@@ -1244,6 +1289,7 @@ where
 // Perform the fee check in mempool
 fn mempool_fee_check<D, H, CA>(
     wrapper: &WrapperTx,
+    fee_token_fallbacks: &[Fee],
     masp_transaction: Option<Transaction>,
     shell_params: &mut ShellParams<'_, TempWlState<D, H>, D, H, CA>,
 ) -> Result<()>
@@ -1257,10 +1303,12 @@ where
         &wrapper.fee.token,
     )
     .expect("Must be able to read gas cost parameter")
-    .ok_or(Error::TxApply(protocol::Error::FeeError(format!(
-        "The provided {} token is not allowed for fee payment",
-        wrapper.fee.token
-    ))))?;
+    .ok_or(Error::TxApply(protocol::Error::FeeError(
+        protocol::FeeValidationError::Other(format!(
+            "The provided {} token is not allowed for fee payment",
+            wrapper.fee.token
+        )),
+    )))?;
 
     wrapper_fee_check(
         wrapper,
@@ -1268,7 +1316,9 @@ where
         minimum_gas_price,
         shell_params,
     )?;
-    protocol::check_fees(shell_params.state, wrapper).map_err(Error::TxApply)
+    protocol::check_fees(shell_params.state, wrapper, fee_token_fallbacks)
+        .map(|_fee_token| ())
+        .map_err(Error::TxApply)
 }
 
 /// Check the validity of the fee payment, including the minimum amounts
@@ -1291,21 +1341,25 @@ where
     ) {
         Ok(amount_per_gas_unit) if amount_per_gas_unit < minimum_gas_price => {
             // The fees do not match the minimum required
-            return Err(Error::TxApply(protocol::Error::FeeError(format!(
-                "Fee amount {:?} do not match the minimum required amount \
-                 {:?} for token {}",
-                wrapper.fee.amount_per_gas_unit,
-                minimum_gas_price,
-                wrapper.fee.token
-            ))));
+            return Err(Error::TxApply(protocol::Error::FeeError(
+                protocol::FeeValidationError::Other(format!(
+                    "Fee amount {:?} do not match the minimum required amount \
+                     {:?} for token {}",
+                    wrapper.fee.amount_per_gas_unit,
+                    minimum_gas_price,
+                    wrapper.fee.token
+                )),
+            )));
         }
         Ok(_) => {}
         Err(err) => {
-            return Err(Error::TxApply(protocol::Error::FeeError(format!(
-                "The precision of the fee amount {:?} is higher than the \
-                 denomination for token {}: {}",
-                wrapper.fee.amount_per_gas_unit, wrapper.fee.token, err,
-            ))));
+            return Err(Error::TxApply(protocol::Error::FeeError(
+                protocol::FeeValidationError::ConversionFailed(format!(
+                    "The precision of the fee amount {:?} is higher than the \
+                     denomination for token {}: {}",
+                    wrapper.fee.amount_per_gas_unit, wrapper.fee.token, err,
+                )),
+            )));
         }
     }
 
@@ -1316,6 +1370,31 @@ where
     Ok(())
 }
 
+/// Reject a wrapper introducing a new fee token beyond the per block limit,
+/// its fee transfer is dropped along with the rest of its changes
+fn record_proposal_fee_token<D, H, CA>(
+    token: &Address,
+    shell_params: &ShellParams<'_, TempWlState<D, H>, D, H, CA>,
+) -> Result<()>
+where
+    D: DB + for<'iter> DBIter<'iter> + Sync + 'static,
+    H: StorageHasher + Sync + 'static,
+    CA: 'static + WasmCacheAccess + Sync,
+{
+    let Some(accumulators) = shell_params.block_accumulators else {
+        return Ok(());
+    };
+    let max_fee_tokens =
+        parameters::storage::get_max_fee_tokens_per_block(&*shell_params.state)
+            .expect(
+                "Must be able to read the max fee tokens per block parameter",
+            );
+    accumulators
+        .borrow_mut()
+        .record_fee_token(token, max_fee_tokens)
+        .map_err(Error::TxApply)
+}
+
 // Verifies the correctness of the masp transaction for fee payment
 fn fee_unshielding_validation<D, H, CA>(
     wrapper: &WrapperTx,
@@ -1331,6 +1410,19 @@ where
     // checking the aggregated signature of the wrapper, no need for
     // further validation
 
+    // A proposal can't include more fee unshieldings than allowed per block
+    if let Some(accumulators) = shell_params.block_accumulators {
+        let max_fee_unshields =
+            parameters::storage::get_max_fee_unshields_per_block(
+                shell_params.state,
+            )
+            .expect("Must be able to read the max fee unshields parameter");
+        accumulators
+            .borrow()
+            .check_fee_unshields(max_fee_unshields)
+            .map_err(Error::TxApply)?;
+    }
+
     // Validate data and generate unshielding tx
     check_fee_unshielding(shell_params.state, &masp_transaction)?;
 
@@ -1339,14 +1431,20 @@ where
         shell_params,
         masp_transaction,
     )
-    .map_err(|e| {
-        Error::TxApply(protocol::Error::FeeUnshieldingError(
+    .map_err(|e| match e {
+        protocol::Error::MissingTransferHash
+        | protocol::Error::StorageError(_) => Error::TxApply(e),
+        _ => Error::TxApply(protocol::Error::FeeUnshieldingError(
             WrapperTxErr::InvalidUnshield(format!(
                 "Fee unshielding went out of gas: {}",
                 e
             )),
-        ))
+        )),
     })? {
+        // Only a successful unshielding consumes the budget of the block
+        if let Some(accumulators) = shell_params.block_accumulators {
+            accumulators.borrow_mut().fee_unshields += 1;
+        }
         Ok(())
     } else {
         Err(Error::TxApply(protocol::Error::FeeUnshieldingError(
@@ -1961,7 +2059,7 @@ mod shell_tests {
     use namada::replay_protection;
     use namada::token::read_denom;
     use namada::tx::data::protocol::{ProtocolTx, ProtocolTxType};
-    use namada::tx::data::Fee;
+    use namada::tx::data::{Fee, WrapperExtension};
     use namada::tx::{Authorization, Code, Data, Signed};
     use namada::vote_ext::{
         bridge_pool_roots, ethereum_events, ethereum_tx_data_variants,
@@ -2437,7 +2535,7 @@ mod shell_tests {
         let wrapper_hash_key = replay_protection::current_key(&wrapper_hash);
         shell
             .state
-            .write_replay_protection_entry(&mut batch, &wrapper_hash_key)
+            .write_replay_protection_entry(&mut batch, &wrapper_hash_key, None)
             .expect("Test failed");
 
         // Try wrapper tx replay attack
@@ -2474,7 +2572,7 @@ mod shell_tests {
         let inner_hash_key = replay_protection::current_key(&inner_tx_hash);
         shell
             .state
-            .write_replay_protection_entry(&mut batch, &inner_hash_key)
+            .write_replay_protection_entry(&mut batch, &inner_hash_key, None)
             .expect("Test failed");
 
         // Try inner tx replay attack
@@ -2555,6 +2653,43 @@ mod shell_tests {
         assert_eq!(result.code, ResultCode::ExpiredTx.into());
     }
 
+    /// Check that a wrapper past its expiry height gets rejected
+    #[test]
+    fn test_expired_wrapper_height() {
+        let (shell, _recv, _, _) = test_utils::setup();
+        let keypair = super::test_utils::gen_keypair();
+
+        let mut wrapper =
+            Tx::from_type(TxType::Wrapper(Box::new(WrapperTx::new(
+                Fee {
+                    amount_per_gas_unit: DenominatedAmount::native(100.into()),
+                    token: shell.state.in_mem().native_token.clone(),
+                },
+                keypair.ref_to(),
+                Epoch(0),
+                GAS_LIMIT_MULTIPLIER.into(),
+                None,
+            ))));
+        wrapper.header.chain_id = shell.chain_id.clone();
+        wrapper.set_code(Code::new("wasm_code".as_bytes().to_owned(), None));
+        wrapper.set_data(Data::new("transaction data".as_bytes().to_owned()));
+        // The wrapper expires before the next block
+        wrapper.add_wrapper_extension(WrapperExtension::ExpiryHeight(
+            shell.state.in_mem().get_last_block_height(),
+        ));
+        wrapper.add_section(Section::Authorization(Authorization::new(
+            wrapper.sechashes(),
+            [(0, keypair)].into_iter().collect(),
+            None,
+        )));
+
+        let result = shell.mempool_validate(
+            wrapper.to_bytes().as_ref(),
+            MempoolTxType::NewTransaction,
+        );
+        assert_eq!(result.code, ResultCode::ExpiredTx.into());
+    }
+
     /// Check that a tx requiring more gas than the block limit gets rejected
     #[test]
     fn test_exceeding_max_block_gas_tx() {
@@ -9,7 +9,7 @@ use namada::gas::TxGasMeter;
 use namada::ledger::protocol::{self, ShellParams};
 use namada::proof_of_stake::storage::find_validator_by_raw_hash;
 use namada::state::{DBIter, StorageHasher, TempWlState, DB};
-use namada::tx::data::{TxType, WrapperTx};
+use namada::tx::data::{Fee, TxType, WrapperTx};
 use namada::tx::Tx;
 use namada::vm::wasm::{TxCache, VpCache};
 use namada::vm::WasmCacheAccess;
@@ -119,17 +119,27 @@ where
         let mut temp_state = self.state.with_temp_write_log();
         let mut vp_wasm_cache = self.vp_wasm_cache.clone();
         let mut tx_wasm_cache = self.tx_wasm_cache.clone();
+        // Look up the gas cost per byte of the wrappers once per proposal
+        let wrapper_gas_per_byte =
+            protocol::get_wrapper_gas_per_byte(&self.state)
+                .expect("Failed to read the wrapper gas cost per byte");
+        // The per block limits the wrappers are subject to, as checked by
+        // process_proposal
+        let block_accumulators =
+            RefCell::new(protocol::BlockAccumulators::default());
 
         let txs = txs
             .iter()
             .filter_map(|tx_bytes| {
-                match validate_wrapper_bytes(tx_bytes, block_time, block_proposer, proposer_local_config, &mut temp_state, &mut vp_wasm_cache, &mut tx_wasm_cache, ) {
+                let accumulators = block_accumulators.borrow().clone();
+                match validate_wrapper_bytes(tx_bytes, block_time, block_proposer, proposer_local_config, wrapper_gas_per_byte, &block_accumulators, &mut temp_state, &mut vp_wasm_cache, &mut tx_wasm_cache, ) {
                     Ok(gas) => {
                         temp_state.write_log_mut().commit_tx();
                         Some((tx_bytes.to_owned(), gas))
                     },
                     Err(()) => {
                         temp_state.write_log_mut().drop_tx();
+                        *block_accumulators.borrow_mut() = accumulators;
                         None
                     }
                 }
@@ -262,6 +272,8 @@ fn validate_wrapper_bytes<D, H, CA>(
     block_time: Option<DateTimeUtc>,
     block_proposer: &Address,
     proposer_local_config: Option<&ValidatorLocalConfig>,
+    wrapper_gas_per_byte: u64,
+    block_accumulators: &RefCell<protocol::BlockAccumulators>,
     temp_state: &mut TempWlState<D, H>,
     vp_wasm_cache: &mut VpCache<CA>,
     tx_wasm_cache: &mut TxCache<CA>,
@@ -288,22 +300,35 @@ where
     if let TxType::Wrapper(wrapper) = tx.header().tx_type {
         // Check tx gas limit for tx size
         let mut tx_gas_meter = TxGasMeter::new(wrapper.gas_limit);
-        tx_gas_meter.add_wrapper_gas(tx_bytes).map_err(|_| ())?;
+        tx_gas_meter
+            .add_wrapper_gas(tx_bytes, wrapper_gas_per_byte)
+            .map_err(|_| ())?;
+
+        // An expired wrapper would be rejected by process_proposal
+        protocol::check_wrapper_expiry(
+            &tx,
+            temp_state.in_mem().get_last_block_height().next_height(),
+        )
+        .map_err(|_| ())?;
 
         super::replay_protection_checks(&tx, temp_state).map_err(|_| ())?;
 
         // Check fees and extract the gas limit of this transaction
+        let tx_gas_meter = RefCell::new(tx_gas_meter);
+        let mut shell_params = ShellParams::new(
+            &tx_gas_meter,
+            temp_state,
+            vp_wasm_cache,
+            tx_wasm_cache,
+        );
+        shell_params.block_accumulators = Some(block_accumulators);
         match prepare_proposal_fee_check(
-            &wrapper,
+            &protocol::fee_delegated_wrapper(&tx, &wrapper),
+            &tx.wrapper_fee_token_fallbacks(),
             protocol::get_fee_unshielding_transaction(&tx, &wrapper),
             block_proposer,
             proposer_local_config,
-            &mut ShellParams::new(
-                &RefCell::new(tx_gas_meter),
-                temp_state,
-                vp_wasm_cache,
-                tx_wasm_cache,
-            ),
+            &mut shell_params,
         ) {
             Ok(()) => Ok(u64::from(wrapper.gas_limit)),
             Err(_) => Err(()),
@@ -315,6 +340,7 @@ where
 
 fn prepare_proposal_fee_check<D, H, CA>(
     wrapper: &WrapperTx,
+    fee_token_fallbacks: &[Fee],
     masp_transaction: Option<Transaction>,
     proposer: &Address,
     proposer_local_config: Option<&ValidatorLocalConfig>,
@@ -332,21 +358,25 @@ where
             Some(config) => config
                 .accepted_gas_tokens
                 .get(&wrapper.fee.token)
-                .ok_or(Error::TxApply(protocol::Error::FeeError(format!(
-                    "The provided {} token is not accepted by the block \
-                     proposer for fee payment",
-                    wrapper.fee.token
-                ))))?
+                .ok_or(Error::TxApply(protocol::Error::FeeError(
+                    protocol::FeeValidationError::Other(format!(
+                        "The provided {} token is not accepted by the block \
+                         proposer for fee payment",
+                        wrapper.fee.token
+                    )),
+                )))?
                 .to_owned(),
             None => namada::ledger::parameters::read_gas_cost(
                 shell_params.state,
                 &wrapper.fee.token,
             )
             .expect("Must be able to read gas cost parameter")
-            .ok_or(Error::TxApply(protocol::Error::FeeError(format!(
-                "The provided {} token is not allowed for fee payment",
-                wrapper.fee.token
-            ))))?,
+            .ok_or(Error::TxApply(protocol::Error::FeeError(
+                protocol::FeeValidationError::Other(format!(
+                    "The provided {} token is not allowed for fee payment",
+                    wrapper.fee.token
+                )),
+            )))?,
         }
     };
 
@@ -357,15 +387,23 @@ where
         shell_params,
     )?;
 
-    protocol::transfer_fee(shell_params.state, proposer, wrapper)
-        .map_err(Error::TxApply)
+    let fee_transfer = protocol::transfer_fee(
+        shell_params.state,
+        proposer,
+        wrapper,
+        fee_token_fallbacks,
+        shell_params.fee_unwrap,
+    )
+    .map_err(Error::TxApply)?;
+
+    super::record_proposal_fee_token(&fee_transfer.token, shell_params)
 }
 
 #[cfg(test)]
 // TODO: write tests for validator set update vote extensions in
 // prepare proposals
 mod test_prepare_proposal {
-    use std::collections::BTreeSet;
+    use std::collections::{BTreeMap, BTreeSet};
 
     use namada::core::address;
     use namada::core::ethereum_events::EthereumEvent;
@@ -383,7 +421,7 @@ mod test_prepare_proposal {
     use namada::tx::data::Fee;
     use namada::tx::{Authorization, Code, Data, Section, Signed};
     use namada::vote_ext::{ethereum_events, ethereum_tx_data_variants};
-    use namada::{replay_protection, token};
+    use namada::{parameters, replay_protection, token};
     use namada_sdk::storage::StorageWrite;
 
     use super::*;
@@ -1117,6 +1155,87 @@ mod test_prepare_proposal {
         assert!(result.txs.is_empty());
     }
 
+    // Check that a wrapper introducing a new fee token beyond the limit of
+    // distinct fee tokens per block is not included in the block
+    #[test]
+    fn test_max_fee_tokens_per_block() {
+        let (mut shell, _recv, _, _) = test_utils::setup();
+        let keypair = crate::wallet::defaults::albert_keypair();
+        let native_token = shell.state.in_mem().native_token.clone();
+        let apfel = address::testing::apfel();
+        let apfel_denom = read_denom(&shell.state, &apfel)
+            .expect("unable to read denomination from storage")
+            .expect("unable to find denomination of apfels");
+        for token in [&native_token, &apfel] {
+            let balance_key = token::storage_key::balance_key(
+                token,
+                &Address::from(&keypair.ref_to()),
+            );
+            shell
+                .state
+                .write(&balance_key, Amount::native_whole(1000))
+                .unwrap();
+        }
+        shell
+            .state
+            .write(
+                &parameters::storage::get_gas_cost_key(),
+                BTreeMap::from([
+                    (native_token.clone(), Amount::from(1)),
+                    (apfel.clone(), Amount::from(1)),
+                ]),
+            )
+            .unwrap();
+        let txs: Vec<TxBytes> = [
+            DenominatedAmount::native(1.into()),
+            DenominatedAmount::new(1.into(), apfel_denom),
+        ]
+        .into_iter()
+        .zip([native_token, apfel])
+        .map(|(amount_per_gas_unit, token)| {
+            let mut wrapper =
+                Tx::from_type(TxType::Wrapper(Box::new(WrapperTx::new(
+                    Fee {
+                        amount_per_gas_unit,
+                        token,
+                    },
+                    keypair.ref_to(),
+                    Epoch(0),
+                    GAS_LIMIT_MULTIPLIER.into(),
+                    None,
+                ))));
+            wrapper.header.chain_id = shell.chain_id.clone();
+            wrapper
+                .set_code(Code::new("wasm_code".as_bytes().to_owned(), None));
+            wrapper
+                .set_data(Data::new("transaction data".as_bytes().to_owned()));
+            wrapper.add_section(Section::Authorization(Authorization::new(
+                wrapper.sechashes(),
+                [(0, keypair.clone())].into_iter().collect(),
+                None,
+            )));
+            wrapper.to_bytes().into()
+        })
+        .collect();
+
+        let req = RequestPrepareProposal {
+            txs: txs.clone(),
+            ..Default::default()
+        };
+        let received_txs = shell.prepare_proposal(req.clone()).txs;
+        assert_eq!(received_txs, txs);
+
+        shell
+            .state
+            .write(
+                &parameters::storage::get_max_fee_tokens_per_block_key(),
+                1_u64,
+            )
+            .unwrap();
+        let received_txs = shell.prepare_proposal(req).txs;
+        assert_eq!(received_txs, txs[..1]);
+    }
+
     // Check that a wrapper setting a fee amount lower than the minimum accepted
     // by the validator is not included in the block
     #[test]
@@ -22,6 +22,12 @@ pub struct ValidationMeta {
     pub user_gas: TxBin<BlockGas>,
     /// Space utilized by all txs.
     pub txs_bin: TxBin<BlockSpace>,
+    /// The gas cost per byte of the wrapper txs.
+    pub wrapper_gas_per_byte: u64,
+    /// Resources consumed by the wrappers accepted so far. The accounts
+    /// initialized by the inner txs are only known once these are applied,
+    /// so they are only capped when finalizing the block.
+    pub block_accumulators: RefCell<protocol::BlockAccumulators>,
 }
 
 impl<D, H> From<&WlState<D, H>> for ValidationMeta
@@ -34,10 +40,17 @@ where
             state.pos_queries().get_max_proposal_bytes().get();
         let max_block_gas =
             namada::parameters::get_max_block_gas(state).unwrap();
+        let wrapper_gas_per_byte =
+            protocol::get_wrapper_gas_per_byte(state).unwrap();
 
         let user_gas = TxBin::init(max_block_gas);
         let txs_bin = TxBin::init(max_proposal_bytes);
-        Self { user_gas, txs_bin }
+        Self {
+            user_gas,
+            txs_bin,
+            wrapper_gas_per_byte,
+            block_accumulators: Default::default(),
+        }
     }
 }
 
@@ -390,6 +403,33 @@ where
                             }
                         })
                     }
+                    ProtocolTxType::EthHotKeyRotation => {
+                        ethereum_tx_data_variants::EthHotKeyRotation::try_from(
+                            &tx,
+                        )
+                        .map_err(|err| err.to_string())
+                        .and_then(|rotation| {
+                            protocol::validate_eth_hot_key_rotation(
+                                &self.state,
+                                &rotation,
+                            )
+                            .map(|_| TxResult {
+                                code: ResultCode::Ok.into(),
+                                info: "Process Proposal accepted this \
+                                       transaction"
+                                    .into(),
+                            })
+                            .map_err(|err| err.to_string())
+                        })
+                        .unwrap_or_else(|err| TxResult {
+                            code: ResultCode::InvalidTx.into(),
+                            info: format!(
+                                "Process proposal rejected this proposal \
+                                 because one of the included Ethereum hot key \
+                                 rotations was invalid: {err}"
+                            ),
+                        })
+                    }
                     ProtocolTxType::EthereumEvents
                     | ProtocolTxType::BridgePool
                     | ProtocolTxType::ValidatorSetUpdate => TxResult {
@@ -407,7 +447,9 @@ where
                 let allocated_gas =
                     metadata.user_gas.try_dump(u64::from(wrapper.gas_limit));
                 let mut tx_gas_meter = TxGasMeter::new(wrapper.gas_limit);
-                if tx_gas_meter.add_wrapper_gas(tx_bytes).is_err()
+                if tx_gas_meter
+                    .add_wrapper_gas(tx_bytes, metadata.wrapper_gas_per_byte)
+                    .is_err()
                     || allocated_gas.is_err()
                 {
                     return TxResult {
@@ -453,6 +495,17 @@ where
                     }
                 }
 
+                // Wrapper expiry height, an expired wrapper can't pay its fees
+                if let Err(err) = protocol::check_wrapper_expiry(
+                    &tx,
+                    self.state.in_mem().get_last_block_height().next_height(),
+                ) {
+                    return TxResult {
+                        code: ResultCode::ExpiredTx.into(),
+                        info: err.to_string(),
+                    };
+                }
+
                 // Replay protection checks
                 if let Err(e) = super::replay_protection_checks(&tx, temp_state)
                 {
@@ -463,16 +516,21 @@ where
                 }
 
                 // Check that the fee payer has sufficient balance.
+                let tx_gas_meter = RefCell::new(tx_gas_meter);
+                let mut shell_params = ShellParams::new(
+                    &tx_gas_meter,
+                    temp_state,
+                    vp_wasm_cache,
+                    tx_wasm_cache,
+                );
+                shell_params.block_accumulators =
+                    Some(&metadata.block_accumulators);
                 match process_proposal_fee_check(
-                    &wrapper,
+                    &protocol::fee_delegated_wrapper(&tx, &wrapper),
+                    &tx.wrapper_fee_token_fallbacks(),
                     get_fee_unshielding_transaction(&tx, &wrapper),
                     block_proposer,
-                    &mut ShellParams::new(
-                        &RefCell::new(tx_gas_meter),
-                        temp_state,
-                        vp_wasm_cache,
-                        tx_wasm_cache,
-                    ),
+                    &mut shell_params,
                 ) {
                     Ok(()) => TxResult {
                         code: ResultCode::Ok.into(),
@@ -498,6 +556,7 @@ where
 
 fn process_proposal_fee_check<D, H, CA>(
     wrapper: &WrapperTx,
+    fee_token_fallbacks: &[Fee],
     masp_transaction: Option<Transaction>,
     proposer: &Address,
     shell_params: &mut ShellParams<'_, TempWlState<D, H>, D, H, CA>,
@@ -512,10 +571,12 @@ where
         &wrapper.fee.token,
     )
     .expect("Must be able to read gas cost parameter")
-    .ok_or(Error::TxApply(protocol::Error::FeeError(format!(
-        "The provided {} token is not allowed for fee payment",
-        wrapper.fee.token
-    ))))?;
+    .ok_or(Error::TxApply(protocol::Error::FeeError(
+        protocol::FeeValidationError::Other(format!(
+            "The provided {} token is not allowed for fee payment",
+            wrapper.fee.token
+        )),
+    )))?;
 
     wrapper_fee_check(
         wrapper,
@@ -524,14 +585,28 @@ where
         shell_params,
     )?;
 
-    protocol::transfer_fee(shell_params.state, proposer, wrapper)
-        .map_err(Error::TxApply)
+    let fee_transfer = protocol::transfer_fee(
+        shell_params.state,
+        proposer,
+        wrapper,
+        fee_token_fallbacks,
+        shell_params.fee_unwrap,
+    )
+    .map_err(Error::TxApply)?;
+
+    record_proposal_fee_token(&fee_transfer.token, shell_params)
 }
 
 /// We test the failure cases of [`process_proposal`]. The happy flows
 /// are covered by the e2e tests.
 #[cfg(test)]
 mod test_process_proposal {
+    use std::collections::BTreeMap;
+
+    use masp_primitives::consensus::{
+        BlockHeight as MaspBlockHeight, BranchId,
+    };
+    use masp_primitives::transaction::{TransactionData, TxVersion};
     use namada::core::key::*;
     use namada::core::storage::Epoch;
     use namada::eth_bridge::storage::eth_bridge_queries::{
@@ -540,7 +615,7 @@ mod test_process_proposal {
     use namada::replay_protection;
     use namada::state::StorageWrite;
     use namada::token::{read_denom, Amount, DenominatedAmount};
-    use namada::tx::data::Fee;
+    use namada::tx::data::{Fee, WrapperExtension};
     use namada::tx::{Authorization, Code, Data, Signed};
     use namada::vote_ext::{
         bridge_pool_roots, ethereum_events, validator_set_update,
@@ -1158,7 +1233,7 @@ mod test_process_proposal {
         let hash_key = replay_protection::current_key(&wrapper_unsigned_hash);
         shell
             .state
-            .write_replay_protection_entry(&mut batch, &hash_key)
+            .write_replay_protection_entry(&mut batch, &hash_key, None)
             .expect("Test failed");
 
         // Run validation
@@ -1282,7 +1357,7 @@ mod test_process_proposal {
             replay_protection::current_key(&wrapper.raw_header_hash());
         shell
             .state
-            .write_replay_protection_entry(&mut batch, &hash_key)
+            .write_replay_protection_entry(&mut batch, &hash_key, None)
             .expect("Test failed");
 
         // Run validation
@@ -1471,6 +1546,51 @@ mod test_process_proposal {
         }
     }
 
+    /// Test that a block containing a wrapper past its expiry height is
+    /// rejected, since the wrapper couldn't pay its fees
+    #[test]
+    fn test_expired_wrapper_height() {
+        let (shell, _recv, _, _) = test_utils::setup();
+        let keypair = crate::wallet::defaults::daewon_keypair();
+
+        let mut wrapper =
+            Tx::from_type(TxType::Wrapper(Box::new(WrapperTx::new(
+                Fee {
+                    amount_per_gas_unit: DenominatedAmount::native(1.into()),
+                    token: shell.state.in_mem().native_token.clone(),
+                },
+                keypair.ref_to(),
+                Epoch(0),
+                GAS_LIMIT_MULTIPLIER.into(),
+                None,
+            ))));
+        wrapper.header.chain_id = shell.chain_id.clone();
+        wrapper.set_code(Code::new("wasm_code".as_bytes().to_owned(), None));
+        wrapper.set_data(Data::new("transaction data".as_bytes().to_owned()));
+        wrapper.add_wrapper_extension(WrapperExtension::ExpiryHeight(
+            shell.state.in_mem().get_last_block_height(),
+        ));
+        wrapper.add_section(Section::Authorization(Authorization::new(
+            wrapper.sechashes(),
+            [(0, keypair)].into_iter().collect(),
+            None,
+        )));
+
+        // Run validation
+        let request = ProcessProposal {
+            txs: vec![wrapper.to_bytes()],
+        };
+        match shell.process_proposal(request) {
+            Ok(_) => panic!("Test failed"),
+            Err(TestError::RejectProposal(response)) => {
+                assert_eq!(
+                    response[0].result.code,
+                    u32::from(ResultCode::ExpiredTx)
+                );
+            }
+        }
+    }
+
     /// Check that a tx requiring more gas than the block limit causes a block
     /// rejection
     #[test]
@@ -1608,6 +1728,159 @@ mod test_process_proposal {
         }
     }
 
+    // Check that a wrapper introducing a new fee token beyond the limit of
+    // distinct fee tokens per block causes a block rejection
+    #[test]
+    fn test_max_fee_tokens_per_block() {
+        let (mut shell, _recv, _, _) = test_utils::setup();
+        let keypair = crate::wallet::defaults::albert_keypair();
+        let native_token = shell.state.in_mem().native_token.clone();
+        let apfel = address::testing::apfel();
+        let apfel_denom = read_denom(&shell.state, &apfel)
+            .expect("unable to read denomination from storage")
+            .expect("unable to find denomination of apfels");
+        for token in [&native_token, &apfel] {
+            let balance_key = token::storage_key::balance_key(
+                token,
+                &Address::from(&keypair.ref_to()),
+            );
+            shell
+                .state
+                .write(&balance_key, Amount::native_whole(1000))
+                .unwrap();
+        }
+        shell
+            .state
+            .write(
+                &parameters::storage::get_gas_cost_key(),
+                BTreeMap::from([
+                    (native_token.clone(), Amount::from(1)),
+                    (apfel.clone(), Amount::from(1)),
+                ]),
+            )
+            .unwrap();
+        shell
+            .state
+            .write(
+                &parameters::storage::get_max_fee_tokens_per_block_key(),
+                1_u64,
+            )
+            .unwrap();
+
+        let txs = [
+            DenominatedAmount::native(1.into()),
+            DenominatedAmount::new(1.into(), apfel_denom),
+        ]
+        .into_iter()
+        .zip([native_token, apfel])
+        .map(|(amount_per_gas_unit, token)| {
+            let mut wrapper =
+                Tx::from_type(TxType::Wrapper(Box::new(WrapperTx::new(
+                    Fee {
+                        amount_per_gas_unit,
+                        token,
+                    },
+                    keypair.ref_to(),
+                    Epoch(0),
+                    GAS_LIMIT_MULTIPLIER.into(),
+                    None,
+                ))));
+            wrapper.header.chain_id = shell.chain_id.clone();
+            wrapper
+                .set_code(Code::new("wasm_code".as_bytes().to_owned(), None));
+            wrapper
+                .set_data(Data::new("transaction data".as_bytes().to_owned()));
+            wrapper.add_section(Section::Authorization(Authorization::new(
+                wrapper.sechashes(),
+                [(0, keypair.clone())].into_iter().collect(),
+                None,
+            )));
+            wrapper.to_bytes()
+        })
+        .collect();
+
+        // Run validation
+        let request = ProcessProposal { txs };
+        match shell.process_proposal(request) {
+            Ok(_) => panic!("Test failed"),
+            Err(TestError::RejectProposal(response)) => {
+                assert_eq!(response[0].result.code, u32::from(ResultCode::Ok));
+                assert_eq!(
+                    response[1].result.code,
+                    u32::from(ResultCode::FeeError)
+                );
+            }
+        }
+    }
+
+    // Check that a wrapper unshielding its fees once the limit of fee
+    // unshieldings per block is reached causes a block rejection
+    #[test]
+    fn test_max_fee_unshields_per_block() {
+        let (mut shell, _recv, _, _) = test_utils::setup();
+        let keypair = crate::wallet::defaults::albert_keypair();
+        shell
+            .state
+            .write(
+                &parameters::storage::get_max_fee_unshields_per_block_key(),
+                0_u64,
+            )
+            .unwrap();
+
+        let transaction = TransactionData::from_parts(
+            TxVersion::MASPv5,
+            BranchId::MASP,
+            0,
+            MaspBlockHeight::from_u32(0),
+            None,
+            None,
+        )
+        .freeze()
+        .unwrap();
+        let unshield_hash = Section::MaspTx(transaction.clone()).get_hash();
+        let mut wrapper =
+            Tx::from_type(TxType::Wrapper(Box::new(WrapperTx::new(
+                Fee {
+                    amount_per_gas_unit: DenominatedAmount::native(1.into()),
+                    token: shell.state.in_mem().native_token.clone(),
+                },
+                keypair.ref_to(),
+                Epoch(0),
+                GAS_LIMIT_MULTIPLIER.into(),
+                Some(unshield_hash),
+            ))));
+        wrapper.header.chain_id = shell.chain_id.clone();
+        wrapper.set_code(Code::new("wasm_code".as_bytes().to_owned(), None));
+        wrapper.set_data(Data::new("transaction data".as_bytes().to_owned()));
+        wrapper.add_section(Section::MaspTx(transaction));
+        wrapper.add_section(Section::Authorization(Authorization::new(
+            wrapper.sechashes(),
+            [(0, keypair)].into_iter().collect(),
+            None,
+        )));
+
+        // Run validation
+        let request = ProcessProposal {
+            txs: vec![wrapper.to_bytes()],
+        };
+        match shell.process_proposal(request) {
+            Ok(_) => panic!("Test failed"),
+            Err(TestError::RejectProposal(response)) => {
+                assert_eq!(
+                    response[0].result.code,
+                    u32::from(ResultCode::FeeError)
+                );
+                assert_eq!(
+                    response[0].result.info,
+                    Error::TxApply(
+                        protocol::Error::FeeUnshieldsPerBlockExceeded(0)
+                    )
+                    .to_string()
+                );
+            }
+        }
+    }
+
     // Check that a wrapper setting a fee amount lower than the minimum required
     // causes a block rejection
     #[test]
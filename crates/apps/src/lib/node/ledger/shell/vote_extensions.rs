@@ -158,6 +158,13 @@ where
                         .valset_upd_seen(ext.data.signing_epoch.next());
                     !is_seen
                 }
+                Some(EthereumTxData::EthHotKeyRotation(rotation)) => {
+                    protocol::validate_eth_hot_key_rotation(
+                        &self.state,
+                        &rotation,
+                    )
+                    .is_ok()
+                }
                 _ => false,
             }
         })
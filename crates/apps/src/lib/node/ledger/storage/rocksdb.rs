@@ -41,6 +41,7 @@
 //!     - `address_gen`: established address generator
 //!     - `header`: block's header
 //! - `replay_protection`: hashes of processed tx for replay protection purposes
+//!   mapped to the audit records of the txs, if any
 //!     - `current/{hash}`: a hash included in the current block
 //!     - `{hash}`: a hash included in previous blocks
 
@@ -1025,6 +1026,53 @@ impl DB for RocksDB {
         Ok(false)
     }
 
+    fn read_replay_protection_audit(
+        &self,
+        hash: &namada::core::hash::Hash,
+    ) -> Result<Option<replay_protection::TxAudit>> {
+        let replay_protection_cf =
+            self.get_column_family(REPLAY_PROTECTION_CF)?;
+
+        for key in [
+            replay_protection::current_key(hash),
+            replay_protection::key(hash),
+        ] {
+            match self
+                .0
+                .get_pinned_cf(replay_protection_cf, key.to_string())
+                .map_err(|e| Error::DBError(e.into_string()))?
+            {
+                // Entries written without an audit record have no value
+                Some(bytes) if bytes.is_empty() => return Ok(None),
+                Some(bytes) => {
+                    return decode(bytes).map(Some).map_err(Error::CodingError);
+                }
+                None => continue,
+            }
+        }
+        Ok(None)
+    }
+
+    fn read_replay_protection_expiry(
+        &self,
+        hash: &namada::core::hash::Hash,
+    ) -> Result<Option<BlockHeight>> {
+        let replay_protection_cf =
+            self.get_column_family(REPLAY_PROTECTION_CF)?;
+
+        match self
+            .0
+            .get_pinned_cf(
+                replay_protection_cf,
+                replay_protection::expiry_key(hash).to_string(),
+            )
+            .map_err(|e| Error::DBError(e.into_string()))?
+        {
+            Some(bytes) => decode(bytes).map(Some).map_err(Error::CodingError),
+            None => Ok(None),
+        }
+    }
+
     fn read_diffs_val(
         &self,
         key: &Key,
@@ -1270,6 +1318,7 @@ impl DB for RocksDB {
         &mut self,
         batch: &mut Self::WriteBatch,
         key: &Key,
+        audit: Option<&replay_protection::TxAudit>,
     ) -> Result<()> {
         let replay_protection_cf =
             self.get_column_family(REPLAY_PROTECTION_CF)?;
@@ -1277,7 +1326,7 @@ impl DB for RocksDB {
         self.add_value_bytes_to_batch(
             replay_protection_cf,
             key.to_string(),
-            vec![],
+            audit.map(encode).unwrap_or_default(),
             batch,
         );
 
@@ -1292,7 +1341,7 @@ impl DB for RocksDB {
             self.get_column_family(REPLAY_PROTECTION_CF)?;
         let stripped_prefix = Some(replay_protection::current_prefix());
 
-        for (ref hash_str, _, _) in iter_prefix(
+        for (ref hash_str, value, _) in iter_prefix(
             self,
             replay_protection_cf,
             stripped_prefix.as_ref(),
@@ -1303,13 +1352,70 @@ impl DB for RocksDB {
             let current_key = replay_protection::current_key(&hash);
             let key = replay_protection::key(&hash);
 
-            // Delete the current key and move it to the general bucket
+            // Delete the current key and move it to the general bucket,
+            // together with its audit record
             batch
                 .0
                 .delete_cf(replay_protection_cf, current_key.to_string());
-            batch
-                .0
-                .put_cf(replay_protection_cf, key.to_string(), vec![]);
+            batch.0.put_cf(replay_protection_cf, key.to_string(), value);
+        }
+
+        Ok(())
+    }
+
+    fn write_replay_protection_expiry(
+        &mut self,
+        batch: &mut Self::WriteBatch,
+        hash: &namada::core::hash::Hash,
+        expiry: BlockHeight,
+    ) -> Result<()> {
+        let replay_protection_cf =
+            self.get_column_family(REPLAY_PROTECTION_CF)?;
+
+        self.add_value_to_batch(
+            replay_protection_cf,
+            replay_protection::expiry_key(hash).to_string(),
+            &expiry,
+            batch,
+        );
+        self.add_value_to_batch(
+            replay_protection_cf,
+            replay_protection::expiring_key(expiry, hash).to_string(),
+            &(),
+            batch,
+        );
+
+        Ok(())
+    }
+
+    fn prune_expired_replay_protection_entries(
+        &mut self,
+        batch: &mut Self::WriteBatch,
+        height: BlockHeight,
+    ) -> Result<()> {
+        let replay_protection_cf =
+            self.get_column_family(REPLAY_PROTECTION_CF)?;
+        // Only the hashes expiring at this height are visited
+        let stripped_prefix = Some(replay_protection::expiring_prefix(height));
+
+        for (ref hash_str, _, _) in iter_prefix(
+            self,
+            replay_protection_cf,
+            stripped_prefix.as_ref(),
+            None,
+        ) {
+            let hash = namada::core::hash::Hash::from_str(hash_str)
+                .expect("Failed hash conversion");
+
+            // Delete the entry from whichever bucket holds it
+            for key in [
+                replay_protection::key(&hash),
+                replay_protection::current_key(&hash),
+                replay_protection::expiry_key(&hash),
+                replay_protection::expiring_key(height, &hash),
+            ] {
+                batch.0.delete_cf(replay_protection_cf, key.to_string());
+            }
         }
 
         Ok(())
@@ -2034,6 +2140,7 @@ mod test {
                 db.write_replay_protection_entry(
                     &mut batch,
                     &replay_protection::key(&Hash::sha256(tx)),
+                    None,
                 )
                 .unwrap();
             }
@@ -2042,6 +2149,7 @@ mod test {
                 db.write_replay_protection_entry(
                     &mut batch,
                     &replay_protection::current_key(&Hash::sha256(tx)),
+                    None,
                 )
                 .unwrap();
             }
@@ -2095,6 +2203,7 @@ mod test {
                 db.write_replay_protection_entry(
                     &mut batch,
                     &replay_protection::current_key(&Hash::sha256(tx)),
+                    None,
                 )
                 .unwrap();
             }
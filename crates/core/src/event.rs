@@ -130,6 +130,8 @@ pub enum EventType {
     PgfPayment,
     /// Ethereum Bridge event
     EthereumBridge,
+    /// A replayed transaction was rejected
+    ReplayRejected,
 }
 
 impl Display for EventType {
@@ -140,6 +142,7 @@ impl Display for EventType {
             EventType::Proposal => write!(f, "proposal"),
             EventType::PgfPayment => write!(f, "pgf_payment"),
             EventType::EthereumBridge => write!(f, "ethereum_bridge"),
+            EventType::ReplayRejected => write!(f, "replay_rejected"),
         }?;
         Ok(())
     }
@@ -161,6 +164,7 @@ impl FromStr for EventType {
             }
             // </IBC>
             "ethereum_bridge" => Ok(EventType::EthereumBridge),
+            "replay_rejected" => Ok(EventType::ReplayRejected),
             _ => Err(EventError::InvalidEventType),
         }
     }
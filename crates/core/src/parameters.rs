@@ -8,6 +8,7 @@ use namada_migrations::*;
 
 use super::address::Address;
 use super::chain::ProposalBytes;
+use super::dec::Dec;
 use super::hash::Hash;
 use super::time::DurationSecs;
 use super::token;
@@ -79,3 +80,70 @@ pub struct EpochDuration {
     /// Minimum duration of an epoch
     pub min_duration: DurationSecs,
 }
+
+/// How to handle the fees of a wrapper tx that would overflow the balance of
+/// the block proposer
+#[derive(
+    Clone,
+    Copy,
+    Debug,
+    Default,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    BorshSerialize,
+    BorshDeserialize,
+    BorshDeserializer,
+    BorshSchema,
+)]
+pub enum ProposerOverflowPolicy {
+    /// Reject the fee payment
+    #[default]
+    Reject,
+    /// Charge the whole fees, crediting the proposer up to the maximum
+    /// balance and burning the excess
+    Burn,
+    /// Only charge the part of the fees that fits in the balance of the
+    /// proposer
+    Clamp,
+}
+
+/// The split of the fees of a wrapper tx between the block proposer, a
+/// treasury and a burn. The block proposer receives the remainder of the fees
+/// after the shares of the treasury and of the burn, rounded down, have been
+/// taken.
+#[derive(
+    Clone,
+    Debug,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    BorshSerialize,
+    BorshDeserialize,
+    BorshDeserializer,
+    BorshSchema,
+)]
+pub struct FeeSplit {
+    /// The address receiving the treasury share of the fees, e.g. the PGF
+    /// treasury
+    pub treasury: Address,
+    /// The fraction of the fees sent to the treasury
+    pub treasury_share: Dec,
+    /// The fraction of the fees burned
+    pub burn_share: Dec,
+}
+
+impl FeeSplit {
+    /// Check that the shares are non-negative and don't exceed the whole fees
+    pub fn is_valid(&self) -> bool {
+        let shares = [self.treasury_share, self.burn_share];
+        shares
+            .iter()
+            .all(|share| !share.is_negative() && *share <= Dec::one())
+            && self.treasury_share + self.burn_share <= Dec::one()
+    }
+}
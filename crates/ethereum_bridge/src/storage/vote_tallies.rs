@@ -167,6 +167,16 @@ pub fn is_seen_key(key: &Key) -> bool {
             ] if e == KeysSegments::VALUES.seen)
 }
 
+/// Return true if the storage key is a key to store the `seen_by` votes of
+/// any tally
+pub fn is_seen_by_key(key: &Key) -> bool {
+    matches!(key.segments.first(), Some(DbKeySeg::AddressSeg(ADDRESS)))
+        && matches!(
+            key.segments.last(),
+            Some(DbKeySeg::StringSeg(s)) if s == KeysSegments::VALUES.seen_by
+        )
+}
+
 impl From<&EthereumEvent> for Keys<EthereumEvent> {
     fn from(event: &EthereumEvent) -> Self {
         let hash = event
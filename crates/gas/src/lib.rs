@@ -41,6 +41,10 @@ const STORAGE_OCCUPATION_GAS_PER_BYTE: u64 =
 const PHYSICAL_STORAGE_LATENCY_PER_BYTE: u64 = 1_000_000;
 // This is based on the global average bandwidth
 const NETWORK_TRANSMISSION_GAS_PER_BYTE: u64 = 848;
+/// The default gas cost per byte of a wrapper tx, for the space it requires in
+/// the block and the transmission of its bytes over the network
+pub const WRAPPER_TX_GAS_PER_BYTE: u64 =
+    STORAGE_OCCUPATION_GAS_PER_BYTE + NETWORK_TRANSMISSION_GAS_PER_BYTE;
 
 /// The cost of accessing data from memory (both read and write mode), per byte
 pub const MEMORY_ACCESS_GAS_PER_BYTE: u64 = 104;
@@ -62,6 +66,8 @@ pub const IBC_ACTION_EXECUTE_GAS: u64 = 3_678_745;
 /// The cost to execute an ibc transaction TODO: remove once ibc tx goes back to
 /// wasm
 pub const IBC_TX_GAS: u64 = 111_825_500;
+/// The cost to load and construct the context of a native VP
+pub const NATIVE_VP_INIT_GAS: u64 = 1_250_000;
 /// The cost to verify a masp spend note
 pub const MASP_VERIFY_SPEND_GAS: u64 = 66_822_000;
 /// The cost to verify a masp convert note
@@ -111,7 +117,7 @@ impl Gas {
 
     /// Converts the sub gas units to whole ones. If the sub units are not a
     /// multiple of the `SCALE` than ceil the quotient
-    fn get_whole_gas_units(&self) -> u64 {
+    pub fn get_whole_gas_units(&self) -> u64 {
         let quotient = self.sub / SCALE;
         if self.sub % SCALE == 0 {
             quotient
@@ -299,17 +305,18 @@ impl TxGasMeter {
     ///  - space that the transaction requires in the block
     ///  - cost of downloading (as part of the block) the transaction bytes over
     ///    the network
-    pub fn add_wrapper_gas(&mut self, tx_bytes: &[u8]) -> Result<()> {
+    ///
+    /// The last two are charged at the given `gas_per_byte`.
+    pub fn add_wrapper_gas(
+        &mut self,
+        tx_bytes: &[u8],
+        gas_per_byte: u64,
+    ) -> Result<()> {
         self.consume(WRAPPER_TX_VALIDATION_GAS)?;
 
         let bytes_len = tx_bytes.len() as u64;
         self.consume(
-            bytes_len
-                .checked_mul(
-                    STORAGE_OCCUPATION_GAS_PER_BYTE
-                        + NETWORK_TRANSMISSION_GAS_PER_BYTE,
-                )
-                .ok_or(Error::GasOverflow)?,
+            bytes_len.checked_mul(gas_per_byte).ok_or(Error::GasOverflow)?,
         )
     }
 
@@ -392,6 +399,23 @@ impl VpGasMeter {
             current_gas: Gas::default(),
         }
     }
+
+    /// Cap the gas that the VP can consume on its own, on top of the gas limit
+    /// of the transaction
+    pub fn with_gas_cap(mut self, cap: Gas) -> Self {
+        if let Some(capped_limit) = self.initial_gas.checked_add(cap) {
+            if capped_limit < self.tx_gas_limit {
+                self.tx_gas_limit = capped_limit;
+            }
+        }
+        self
+    }
+
+    /// Get the gas consumed by the VP alone, excluding the gas consumed by the
+    /// transaction before it
+    pub fn get_vp_consumed_gas(&self) -> Gas {
+        self.current_gas
+    }
 }
 
 impl VpsGas {
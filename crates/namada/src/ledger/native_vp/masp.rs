@@ -179,7 +179,10 @@ where
         Ok(())
     }
 
-    // Check that the spend descriptions anchors of a transaction are valid
+    // Check that the spend descriptions anchors of a transaction are valid.
+    // The anchors are only published by the protocol at the end of the block
+    // and are checked against the state prior to the transaction, so a note
+    // can never be spent by the transaction that creates it
     fn valid_spend_descriptions_anchor(
         &self,
         transaction: &Transaction,
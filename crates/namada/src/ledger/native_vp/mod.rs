@@ -84,6 +84,13 @@ where
     /// To avoid unused parameter without "wasm-runtime" feature
     #[cfg(not(feature = "wasm-runtime"))]
     pub cache_access: std::marker::PhantomData<CA>,
+    /// The storage keys read by the VP, collected only when set
+    #[cfg(any(test, feature = "read-set"))]
+    pub read_keys: Option<&'a RefCell<BTreeSet<Key>>>,
+    /// The seed of any randomized behavior of the VP, set by the protocol to
+    /// [`vp_rng_seed`] of the tx and the block height so that it is
+    /// reproducible across nodes
+    pub rng_seed: Hash,
 }
 
 /// Read access to the prior storage (state before tx execution) via
@@ -139,6 +146,24 @@ where
             vp_wasm_cache,
             #[cfg(not(feature = "wasm-runtime"))]
             cache_access: std::marker::PhantomData,
+            #[cfg(any(test, feature = "read-set"))]
+            read_keys: None,
+            rng_seed: Hash::zero(),
+        }
+    }
+
+    /// Record a storage key read by the VP
+    fn record_read(&self, key: &Key) {
+        #[cfg(any(test, feature = "read-set"))]
+        {
+            if let Some(read_keys) = self.read_keys {
+                read_keys.borrow_mut().insert(key.clone());
+            }
+        }
+        #[cfg(not(any(test, feature = "read-set")))]
+        {
+            // This line is here to prevent unused var clippy warning
+            let _ = key;
         }
     }
 
@@ -155,6 +180,14 @@ where
     }
 }
 
+/// Derive the deterministic seed of the randomness available to the VPs
+/// validating the tx with the given hash at the given block height
+pub fn vp_rng_seed(tx_hash: &Hash, height: BlockHeight) -> Hash {
+    let mut bytes = tx_hash.0.to_vec();
+    bytes.extend_from_slice(&height.0.to_be_bytes());
+    Hash::sha256(bytes)
+}
+
 impl<'view, 'a: 'view, S, CA> StorageRead
     for CtxPreStorageRead<'view, 'a, S, CA>
 where
@@ -167,11 +200,13 @@ where
         &self,
         key: &storage::Key,
     ) -> Result<Option<Vec<u8>>, state::StorageError> {
+        self.ctx.record_read(key);
         vp_host_fns::read_pre(self.ctx.gas_meter, self.ctx.state, key)
             .into_storage_result()
     }
 
     fn has_key(&self, key: &storage::Key) -> Result<bool, state::StorageError> {
+        self.ctx.record_read(key);
         vp_host_fns::has_key_pre(self.ctx.gas_meter, self.ctx.state, key)
             .into_storage_result()
     }
@@ -244,11 +279,13 @@ where
         &self,
         key: &storage::Key,
     ) -> Result<Option<Vec<u8>>, state::StorageError> {
+        self.ctx.record_read(key);
         vp_host_fns::read_post(self.ctx.gas_meter, self.ctx.state, key)
             .into_storage_result()
     }
 
     fn has_key(&self, key: &storage::Key) -> Result<bool, state::StorageError> {
+        self.ctx.record_read(key);
         vp_host_fns::has_key_post(self.ctx.gas_meter, self.ctx.state, key)
             .into_storage_result()
     }
@@ -0,0 +1,3763 @@
+//! The fees of the wrapper transactions
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+
+use borsh_ext::BorshSerializeExt;
+use masp_primitives::transaction::Transaction;
+use namada_core::hash::Hash;
+use namada_core::parameters::{FeeSplit, ProposerOverflowPolicy};
+use namada_core::storage::Key;
+use namada_gas::{Gas, TxGasMeter};
+use namada_sdk::tx::TX_TRANSFER_WASM;
+use namada_state::StorageWrite;
+use namada_tx::data::{Fee, GasLimit, WrapperTx};
+use namada_tx::{Section, Signer, Tx};
+use thiserror::Error;
+
+use super::{
+    apply_wasm_tx, get_transfer_hash_from_storage, BlockAccumulators, Error,
+    FeePolicy, FeeUnwrap, Result, ShellParams, WrapperArgs,
+};
+use crate::address::{Address, InternalAddress};
+use crate::key::{common, SigScheme};
+use crate::state::{DBIter, OptionExt, State, StorageHasher, StorageRead, DB};
+use crate::storage::TxIndex;
+use crate::token::{Amount, DenominatedAmount};
+use crate::vm::WasmCacheAccess;
+
+/// The reasons for which the fees of a wrapper tx fail validation or can't be
+/// charged
+#[derive(Error, Debug)]
+pub enum FeeValidationError {
+    /// The balance is insufficient to pay the fees
+    #[error("Insufficient transparent balance to pay fees")]
+    InsufficientBalance {
+        /// The amount that had to be paid
+        required: Amount,
+        /// The available balance
+        available: Amount,
+    },
+    /// The balance of the fee payer was insufficient and has been moved to
+    /// the block proposer anyway
+    #[error(
+        "Transparent balance of wrapper's signer was insufficient to pay fee. \
+         All the available transparent funds have been moved to the block \
+         proposer"
+    )]
+    BalanceDrained {
+        /// The fees that had to be paid
+        required: Amount,
+        /// The balance moved to the block proposer
+        available: Amount,
+    },
+    /// An arithmetic overflow in the computation of the fees
+    #[error("{0}")]
+    Overflow(String),
+    /// The fee amount can't be converted to the denomination of the token
+    #[error("{0}")]
+    ConversionFailed(String),
+    /// Crediting the fees would overflow the balance of the recipient
+    #[error("The transfer would overflow destination balance")]
+    ProposerCreditOverflow,
+    /// The fee payer doesn't exist before the execution of the tx
+    #[error("The fee payer {0} doesn't exist prior to the tx execution")]
+    NonexistentFeePayer(Address),
+    /// The fee payer is restricted to paying the fees in other tokens
+    #[error("The fee payer {payer} is not allowed to pay fees in {token}")]
+    TokenNotAllowedForPayer {
+        /// The fee payer
+        payer: Address,
+        /// The fee token
+        token: Address,
+    },
+    /// Any other invalid fee
+    #[error("{0}")]
+    Other(String),
+}
+
+/// Charge the fees of a wrapper under the [`FeePolicy::Postpaid`] policy, once
+/// its inner tx has been accepted. The fees are charged along with the
+/// uncommitted changes of the inner tx, which are dropped if the fees can't be
+/// paid.
+pub(super) fn charge_postpaid_fee<S, D, H, CA>(
+    wrapper: &WrapperTx,
+    fee_token_fallbacks: &[Fee],
+    masp_transaction: Option<Transaction>,
+    mut shell_params: ShellParams<'_, S, D, H, CA>,
+    changed_keys: &mut BTreeSet<Key>,
+    wrapper_args: Option<&mut WrapperArgs>,
+) -> Result<(Address, Option<Amount>)>
+where
+    S: State<D = D, H = H> + Sync,
+    D: 'static + DB + for<'iter> DBIter<'iter> + Sync,
+    H: 'static + StorageHasher + Sync,
+    CA: 'static + WasmCacheAccess + Sync,
+{
+    charge_fee(
+        wrapper,
+        fee_token_fallbacks,
+        masp_transaction,
+        &mut shell_params,
+        changed_keys,
+        wrapper_args,
+    )
+    .map_err(|e| {
+        shell_params.state.write_log_mut().drop_tx();
+        Error::WrapperRunnerError(e.to_string())
+    })
+}
+
+/// Retrieve the Masp `Transaction` for fee unshielding from the provided
+/// transaction, if present
+pub fn get_fee_unshielding_transaction(
+    tx: &Tx,
+    wrapper: &WrapperTx,
+) -> Option<Transaction> {
+    wrapper
+        .unshield_section_hash
+        .and_then(|ref hash| tx.get_section(hash))
+        .and_then(|section| {
+            if let Section::MaspTx(transaction) = section.as_ref() {
+                Some(transaction.to_owned())
+            } else {
+                None
+            }
+        })
+}
+
+/// The wrapper charging its fees to the fee payer delegated by its signer, if
+/// any. A delegate authorizes the payment with an [`Section::Authorization`]
+/// over the header of the wrapper, signed with a single key other than the one
+/// of the signer, and pays the fees from the implicit account of that key. If
+/// the authorization is missing or invalid, the fees are charged to the signer.
+pub fn fee_delegated_wrapper<'a>(
+    tx: &Tx,
+    wrapper: &'a WrapperTx,
+) -> Cow<'a, WrapperTx> {
+    let header_hash = tx.header_hash();
+    let delegate = tx.sections.iter().find_map(|section| {
+        let Section::Authorization(auth) = section else {
+            return None;
+        };
+        let (Signer::PubKeys(pks), Some(sig)) =
+            (&auth.signer, auth.signatures.get(&0))
+        else {
+            return None;
+        };
+        let [pk] = pks.as_slice() else {
+            return None;
+        };
+        let authorized = *pk != wrapper.pk
+            && auth.targets.contains(&header_hash)
+            && common::SigScheme::verify_signature(
+                pk,
+                &auth.get_raw_hash(),
+                sig,
+            )
+            .is_ok();
+        authorized.then(|| pk.clone())
+    });
+    match delegate {
+        Some(pk) => Cow::Owned(WrapperTx {
+            pk,
+            ..wrapper.clone()
+        }),
+        None => Cow::Borrowed(wrapper),
+    }
+}
+
+/// Charge fee for the provided wrapper transaction. Returns error if:
+/// - The unshielding fails because of gas (other errors are ignored cause we
+///   still try to get the fees amount from the transparent balance and, if it
+///   works, execution can continue)
+/// - Fee amount overflows
+/// - Not enough funds are available to pay the entire amount of the fee
+/// - The accumulated fee amount to be credited to the block proposer overflows
+/// - The fee payer doesn't exist yet, e.g. if initialized by the inner tx
+/// - The fee token is new to the block and the block accumulators already
+///   reached the optional limit of distinct fee tokens per block
+/// - Not enough funds are available to pay the optional gas deposit of the
+///   fee token on top of the fees, in which case the fees are still charged
+///
+/// The fee is charged in the first of the `fee_token_fallbacks` with a
+/// sufficient balance if the balance in the fee token is insufficient. Returns
+/// the token the fees were paid with and the resulting balance of the block
+/// proposer, if the fees were transferred to it.
+pub(super) fn charge_fee<S, D, H, CA>(
+    wrapper: &WrapperTx,
+    fee_token_fallbacks: &[Fee],
+    masp_transaction: Option<Transaction>,
+    shell_params: &mut ShellParams<'_, S, D, H, CA>,
+    changed_keys: &mut BTreeSet<Key>,
+    wrapper_args: Option<&mut WrapperArgs>,
+) -> Result<(Address, Option<Amount>)>
+where
+    S: State<D = D, H = H> + Sync,
+    D: 'static + DB + for<'iter> DBIter<'iter> + Sync,
+    H: 'static + StorageHasher + Sync,
+    CA: 'static + WasmCacheAccess + Sync,
+{
+    // The fees are charged before the inner tx is executed, so the fee payer
+    // can't be an account initialized by it
+    check_fee_payer_exists(&*shell_params.state, &wrapper.fee_payer())?;
+    if let Some(max_fee) =
+        wrapper_args.as_deref().and_then(|args| args.max_fee)
+    {
+        check_max_fee(&*shell_params.state, wrapper, max_fee)?;
+    }
+
+    // Unshield funds if requested
+    let valid_fee_unshielding = if let Some(transaction) = masp_transaction {
+        run_fee_unshielding(wrapper, shell_params, transaction)
+    } else {
+        Ok(false)
+    };
+
+    // Reject a wrapper introducing a new fee token beyond the per block limit
+    // before any fees are moved, discarding the fee unshielding
+    if let Err(err) = record_block_fee_token(
+        &*shell_params.state,
+        wrapper,
+        fee_token_fallbacks,
+        shell_params.block_accumulators,
+    ) {
+        shell_params.state.write_log_mut().drop_tx();
+        return Err(err);
+    }
+
+    // Charge or check fees before propagating any possible error coming from
+    // the fee unshielding. If fee unshielding failed for non-gas reasons but
+    // the fees can still be paid we'll continue with the execution (this is a
+    // different logic from the one we apply in process_proposal)
+    let (fee_token, fee_amount, proposer_fee, proposer_balance) =
+        match wrapper_args.as_deref() {
+            Some(WrapperArgs {
+                block_proposer,
+                is_committed_fee_unshield: _,
+                remaining_block_gas: _,
+                fee_token: _,
+                fee_amount: _,
+                proposer_fee: _,
+                max_fee: _,
+                gas_deposit: _,
+            }) => {
+                let FeeTransfer {
+                    token,
+                    fees,
+                    proposer_token,
+                    proposer_fees,
+                    proposer_balance,
+                } = transfer_fee(
+                    shell_params.state,
+                    block_proposer,
+                    wrapper,
+                    fee_token_fallbacks,
+                    shell_params.fee_unwrap,
+                )?;
+                (
+                    token,
+                    Some(fees),
+                    Some((proposer_token, proposer_fees)),
+                    Some(proposer_balance),
+                )
+            }
+            None => (
+                check_fees(shell_params.state, wrapper, fee_token_fallbacks)?,
+                None,
+                None,
+                None,
+            ),
+        };
+
+    changed_keys
+        .extend(shell_params.state.write_log_mut().get_keys_with_precommit());
+
+    // Commit tx write log even in case of subsequent errors. The postpaid fees
+    // are instead left along with the changes of the inner tx, to be dropped
+    // together if it gets rejected afterwards
+    if shell_params.fee_policy == FeePolicy::Prepaid {
+        shell_params.state.write_log_mut().commit_tx();
+    }
+
+    // Update the flag only after the valid fee payment has been committed. If
+    // fee unshielding went out of gas propagate the error
+    if let Some(args) = wrapper_args {
+        args.fee_token = Some(fee_token.clone());
+        args.fee_amount = fee_amount;
+        args.proposer_fee = proposer_fee;
+        args.is_committed_fee_unshield = valid_fee_unshielding?;
+        // Consume the unshielding budget of the block only now that the
+        // unshielding has been committed
+        if let Some(accumulators) = shell_params
+            .block_accumulators
+            .filter(|_| args.is_committed_fee_unshield)
+        {
+            accumulators.borrow_mut().fee_unshields += 1;
+        }
+
+        // The gas deposit is charged once the fees have been committed, so
+        // that a fee payer that can't afford it still pays them. The postpaid
+        // fees are only charged once the inner tx has been accepted, so
+        // there's nothing left to deposit.
+        if shell_params.fee_policy == FeePolicy::Prepaid {
+            match charge_gas_deposit(
+                shell_params.state,
+                &fee_token,
+                &wrapper.fee_payer(),
+            ) {
+                Ok(gas_deposit) => {
+                    changed_keys.extend(
+                        shell_params
+                            .state
+                            .write_log()
+                            .get_keys_with_precommit(),
+                    );
+                    shell_params.state.write_log_mut().commit_tx();
+                    args.gas_deposit = gas_deposit;
+                }
+                Err(err) => {
+                    shell_params.state.write_log_mut().drop_tx();
+                    return Err(err);
+                }
+            }
+        }
+    }
+
+    Ok((fee_token, proposer_balance))
+}
+
+/// Record the token the fees of the wrapper are about to be paid with in the
+/// optional block accumulators. Fails if the token is new and the per block
+/// limit of distinct fee tokens has already been reached.
+fn record_block_fee_token<S>(
+    state: &S,
+    wrapper: &WrapperTx,
+    fee_token_fallbacks: &[Fee],
+    block_accumulators: Option<&RefCell<BlockAccumulators>>,
+) -> Result<()>
+where
+    S: State + StorageRead,
+{
+    let Some(accumulators) = block_accumulators else {
+        return Ok(());
+    };
+    let max_fee_tokens =
+        namada_parameters::storage::get_max_fee_tokens_per_block(state)
+            .map_err(Error::StorageError)?;
+    // The fees that can't be paid are drained in the fee token of the wrapper
+    let fee_token = fee_payment(state, wrapper, fee_token_fallbacks)
+        .map_or_else(|_| wrapper.fee.token.clone(), |(token, _fees)| token);
+    accumulators
+        .borrow_mut()
+        .record_fee_token(&fee_token, max_fee_tokens)
+}
+
+/// Charge the optional gas deposit of the fee token to the fee payer, on top
+/// of the fees. The deposit is held by the treasury, which forfeits it unless
+/// the inner tx gets accepted. Returns the deposit charged, if any.
+fn charge_gas_deposit<S>(
+    state: &mut S,
+    token: &Address,
+    payer: &Address,
+) -> Result<Option<Amount>>
+where
+    S: State + StorageRead,
+{
+    let Some(deposit) = namada_parameters::read_gas_deposit(state, token)
+        .map_err(Error::StorageError)?
+    else {
+        return Ok(None);
+    };
+    token_transfer(
+        state,
+        token,
+        payer,
+        &Address::Internal(InternalAddress::Pgf),
+        deposit,
+        ProposerOverflowPolicy::Reject,
+    )?;
+    Ok(Some(deposit))
+}
+
+/// Refund the gas deposit charged along with the fees of a wrapper from the
+/// treasury, once its inner tx has been accepted
+pub(super) fn refund_gas_deposit<S>(
+    state: &mut S,
+    token: &Address,
+    payer: &Address,
+    deposit: Amount,
+    changed_keys: &mut BTreeSet<Key>,
+) -> Result<()>
+where
+    S: State + StorageRead,
+{
+    let treasury = Address::Internal(InternalAddress::Pgf);
+    token_transfer(
+        state,
+        token,
+        &treasury,
+        payer,
+        deposit,
+        ProposerOverflowPolicy::Reject,
+    )?;
+    changed_keys.extend([
+        crate::token::storage_key::balance_key(token, &treasury),
+        crate::token::storage_key::balance_key(token, payer),
+    ]);
+    Ok(())
+}
+
+/// Check that the fees of the wrapper, converted as when charging them, don't
+/// exceed the provided maximum
+fn check_max_fee<S>(
+    state: &S,
+    wrapper: &WrapperTx,
+    max_fee: Amount,
+) -> Result<()>
+where
+    S: StorageRead,
+{
+    let fees = wrapper
+        .get_tx_fee()
+        .map_err(|e| FeeValidationError::Overflow(e.to_string()))?;
+    let fees = crate::token::denom_to_amount(fees, &wrapper.fee.token, state)
+        .map_err(|e| FeeValidationError::ConversionFailed(e.to_string()))?;
+    if fees > max_fee {
+        return Err(Error::WrapperFeeAboveMax(fees, max_fee));
+    }
+    Ok(())
+}
+
+/// Check that the fee payer exists in storage before the execution of the tx
+fn check_fee_payer_exists<S>(state: &S, fee_payer: &Address) -> Result<()>
+where
+    S: StorageRead,
+{
+    if crate::account::exists(state, fee_payer).map_err(Error::StorageError)? {
+        Ok(())
+    } else {
+        Err(FeeValidationError::NonexistentFeePayer(fee_payer.clone()).into())
+    }
+}
+
+/// Executes the masp fee unshielding transaction. Returns `true if the unshield
+/// was successful, `false` otherwise and error in case of out-of-gas
+pub fn run_fee_unshielding<S, D, H, CA>(
+    wrapper: &WrapperTx,
+    shell_params: &mut ShellParams<'_, S, D, H, CA>,
+    transaction: Transaction,
+) -> Result<bool>
+where
+    S: State<D = D, H = H> + Sync,
+    D: 'static + DB + for<'iter> DBIter<'iter> + Sync,
+    H: 'static + StorageHasher + Sync,
+    CA: 'static + WasmCacheAccess + Sync,
+{
+    let ShellParams {
+        tx_gas_meter,
+        state,
+        vp_wasm_cache,
+        tx_wasm_cache,
+        block_accumulators,
+        shielded_policy,
+        event_sink,
+        reduce_chunk_size,
+        transfer_hash,
+        vp_observer,
+        fee_unwrap,
+        wrapper_gas_per_byte,
+        fee_policy,
+    } = shell_params;
+
+    // A fee unshielding that already failed in the block is not evaluated
+    // again, so that all its evaluations observe the same outcome
+    let unshielding_hash = Hash::sha256(wrapper.serialize_to_vec());
+    if state.write_log().fee_unshielding_outcome(&unshielding_hash)
+        == Some(false)
+    {
+        return Ok(false);
+    }
+
+    if let Some(policy) = shielded_policy {
+        if let Err(msg) = policy.check_unshielding(wrapper, &transaction) {
+            tracing::error!(
+                "The unshielding tx was rejected by the shielded policy: {}",
+                msg
+            );
+            return Ok(false);
+        }
+    }
+
+    // Skip the unshielding once the budget of the block is exhausted, the fees
+    // must then be paid with the transparent balance. The unshielding is only
+    // counted once it's committed along with the fee payment.
+    if let Some(accumulators) = *block_accumulators {
+        let max_fee_unshields =
+            namada_parameters::storage::get_max_fee_unshields_per_block(
+                &**state,
+            )
+            .map_err(Error::StorageError)?;
+        if let Err(err) =
+            accumulators.borrow().check_fee_unshields(max_fee_unshields)
+        {
+            tracing::warn!("{}, skipping the unshielding", err);
+            return Ok(false);
+        }
+    }
+
+    // The unshielding is subject to a gas limit imposed by a protocol
+    // parameter, instantiate a custom gas meter for this step and
+    // initialize it with the already consumed gas. The gas limit should
+    // actually be the lowest between the protocol parameter and the actual gas
+    // limit of the transaction
+    let min_gas_limit = state
+        .read::<u64>(
+            &namada_parameters::storage::get_fee_unshielding_gas_limit_key(),
+        )
+        .and_then(|limit| {
+            limit.ok_or_err_msg("Missing fee unshielding gas limit in storage")
+        })
+        .map_err(Error::StorageError)?
+        .min(tx_gas_meter.borrow().tx_gas_limit.into());
+    let mut unshield_gas_meter = TxGasMeter::new(GasLimit::from(min_gas_limit));
+    unshield_gas_meter
+        .copy_consumed_gas_from(&tx_gas_meter.borrow())
+        .map_err(|e| Error::GasError(e.to_string()))?;
+    let ref_unshield_gas_meter = RefCell::new(unshield_gas_meter);
+
+    // Only look up the transfer code once for all the unshieldings sharing
+    // these parameters
+    let transfer_code_hash = match *transfer_hash {
+        Some(hash) => hash,
+        None => *transfer_hash.insert(get_transfer_hash_from_storage(*state)?),
+    };
+
+    let fee_unshielding_tx = wrapper
+        .generate_fee_unshielding(
+            transfer_code_hash,
+            Some(TX_TRANSFER_WASM.to_string()),
+            transaction,
+        )
+        .map_err(Error::FeeUnshieldingError)
+        .and_then(|tx| {
+            check_fee_unshielding_code(&tx, transfer_code_hash)?;
+            Ok(tx)
+        });
+    let result = match fee_unshielding_tx {
+        Ok(fee_unshielding_tx) => {
+            // NOTE: A clean tx write log must be provided to this call
+            // for a correct vp validation. Block write log, instead,
+            // should contain any prior changes (if any). This is to simulate
+            // the unshielding tx (to prevent the already written
+            // keys from being passed/triggering VPs) but we cannot
+            // commit the tx write log yet cause the tx could still
+            // be invalid.
+            state.write_log_mut().precommit_tx();
+            match apply_wasm_tx(
+                fee_unshielding_tx,
+                &TxIndex::default(),
+                ShellParams {
+                    tx_gas_meter: &ref_unshield_gas_meter,
+                    state: *state,
+                    vp_wasm_cache,
+                    tx_wasm_cache,
+                    block_accumulators: *block_accumulators,
+                    shielded_policy: *shielded_policy,
+                    event_sink: *event_sink,
+                    reduce_chunk_size: *reduce_chunk_size,
+                    transfer_hash: *transfer_hash,
+                    vp_observer: *vp_observer,
+                    fee_unwrap: *fee_unwrap,
+                    wrapper_gas_per_byte: *wrapper_gas_per_byte,
+                    fee_policy: *fee_policy,
+                },
+            ) {
+                Ok(result) => {
+                    // NOTE: do not commit yet cause this could be
+                    // exploited to get free unshieldings and shielded
+                    // operations
+                    if !result.is_accepted() {
+                        state.write_log_mut().drop_tx_keep_precommit();
+                        tracing::error!(
+                            "The unshielding tx is invalid, some VPs rejected \
+                             it: {:#?}",
+                            result.vps_result.rejected_vps
+                        );
+                    }
+
+                    result.is_accepted()
+                }
+                Err(e) => {
+                    state.write_log_mut().drop_tx_keep_precommit();
+                    tracing::error!(
+                        "The unshielding tx is invalid, wasm run failed: {}",
+                        e
+                    );
+                    if let Error::GasError(_) | Error::VpsGasError { .. } = e {
+                        // Popagate only if it is a gas error
+                        return Err(e);
+                    }
+
+                    false
+                }
+            }
+        }
+        Err(e) => {
+            tracing::error!("{}", e);
+            false
+        }
+    };
+
+    tx_gas_meter
+        .borrow_mut()
+        .copy_consumed_gas_from(&ref_unshield_gas_meter.borrow())
+        .map_err(|e| Error::GasError(e.to_string()))?;
+    state
+        .write_log_mut()
+        .write_fee_unshielding_outcome(unshielding_hash, result);
+
+    Ok(result)
+}
+
+/// Check that the generated fee unshielding tx runs exactly the canonical
+/// transfer code, so that no other code can be substituted for it
+fn check_fee_unshielding_code(tx: &Tx, transfer_code_hash: Hash) -> Result<()> {
+    let code_hash = tx
+        .get_section(tx.code_sechash())
+        .and_then(|section| section.code_sec())
+        .map(|code| code.code.hash());
+    if code_hash == Some(transfer_code_hash) {
+        Ok(())
+    } else {
+        Err(Error::FeeUnshieldingCodeMismatch {
+            expected: transfer_code_hash,
+            found: code_hash,
+        })
+    }
+}
+
+/// The fees transferred to the block proposer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FeeTransfer {
+    /// The token the fees were paid with
+    pub token: Address,
+    /// The amount of the fees charged to the fee payer, in the fee token
+    pub fees: Amount,
+    /// The token credited to the block proposer, the native token if the
+    /// fees were unwrapped
+    pub proposer_token: Address,
+    /// The amount of the share of the fees credited to the block proposer, in
+    /// the proposer token
+    pub proposer_fees: Amount,
+    /// The balance of the block proposer in the fee token after the transfer,
+    /// or in the native token if the fees were unwrapped
+    pub proposer_balance: Amount,
+}
+
+/// Perform the actual transfer of fess from the fee payer to the block
+/// proposer. The token and the amount of the fees are selected like in
+/// [`check_fees`]: if the balance of the fee payer in the fee token is
+/// insufficient, the fee is charged in the first of the `fee_token_fallbacks`
+/// with a sufficient balance. Returns the token the fees were paid with and
+/// the resulting balance of the block proposer.
+///
+/// Fees overflowing the balance of the block proposer are handled according to
+/// the `proposer_overflow_policy` protocol parameter, rejected by default.
+/// When the `fee_split` protocol parameter is set, shares of the fees are sent
+/// to the treasury and burned, see [`split_fee_transfer`]. The fees paid in a
+/// wrapped native token are unwrapped with the optional `fee_unwrap` before
+/// being credited to the block proposer.
+pub fn transfer_fee<S>(
+    state: &mut S,
+    block_proposer: &Address,
+    wrapper: &WrapperTx,
+    fee_token_fallbacks: &[Fee],
+    fee_unwrap: Option<&dyn FeeUnwrap>,
+) -> Result<FeeTransfer>
+where
+    S: State + StorageRead + StorageWrite,
+{
+    let overflow_policy =
+        namada_parameters::storage::get_proposer_overflow_policy(state)
+            .map_err(Error::StorageError)?
+            .unwrap_or_default();
+    let fee_split = read_fee_split(state)?;
+    match fee_payment(state, wrapper, fee_token_fallbacks) {
+        Ok((token, fees)) => split_fee_transfer(
+            state,
+            &token,
+            &wrapper.fee_payer(),
+            block_proposer,
+            fees,
+            overflow_policy,
+            fee_split.as_ref(),
+            fee_unwrap,
+        ),
+        Err(Error::FeeError(FeeValidationError::InsufficientBalance {
+            required: fees,
+            available: balance,
+        })) => {
+            // Balance was insufficient for fee payment, move all the
+            // available funds in the transparent balance of
+            // the fee payer. This shouldn't happen as it should be
+            // prevented from mempool/process_proposal.
+            tracing::error!(
+                "Transfer of tx fee cannot be applied to due to insufficient \
+                 funds. Falling back to transferring the available balance \
+                 which is less than the fee. This shouldn't happen."
+            );
+            split_fee_transfer(
+                state,
+                &wrapper.fee.token,
+                &wrapper.fee_payer(),
+                block_proposer,
+                balance,
+                overflow_policy,
+                fee_split.as_ref(),
+                fee_unwrap,
+            )?;
+            record_fee_anomaly(
+                state,
+                &wrapper.fee.token,
+                &wrapper.fee_payer(),
+                fees.checked_sub(balance).unwrap_or_default(),
+            )?;
+
+            Err(FeeValidationError::BalanceDrained {
+                required: fees,
+                available: balance,
+            }
+            .into())
+        }
+        Err(e) => {
+            // Invalid fee (e.g. overflow). This shouldn't happen as it should
+            // be prevented from mempool/process_proposal.
+            tracing::error!(
+                "Transfer of tx fee cannot be applied to due to an invalid \
+                 fee: {e}. This shouldn't happen."
+            );
+
+            Err(e)
+        }
+    }
+}
+
+/// Read the optional `fee_split` protocol parameter, rejecting shares that
+/// exceed the whole fees
+fn read_fee_split<S>(state: &S) -> Result<Option<FeeSplit>>
+where
+    S: StorageRead,
+{
+    let fee_split = namada_parameters::storage::get_fee_split(state)
+        .map_err(Error::StorageError)?;
+    if let Some(fee_split) = &fee_split {
+        if !fee_split.is_valid() {
+            return Err(FeeValidationError::Other(format!(
+                "Invalid fee split parameter: {fee_split:?}"
+            ))
+            .into());
+        }
+    }
+    Ok(fee_split)
+}
+
+/// Split the `fees` into the shares of the optional `fee_split` sent to the
+/// treasury and burned, and the remainder left to the block proposer, in this
+/// order. The shares of the treasury and of the burn are rounded down.
+fn split_fees(
+    fees: Amount,
+    fee_split: Option<&FeeSplit>,
+) -> Result<(Amount, Amount, Amount)> {
+    let Some(fee_split) = fee_split else {
+        return Ok((Amount::zero(), Amount::zero(), fees));
+    };
+    let treasury_fees = fee_split.treasury_share * fees;
+    let burned_fees = fee_split.burn_share * fees;
+    let proposer_fees = fees
+        .checked_sub(treasury_fees)
+        .and_then(|fees| fees.checked_sub(burned_fees))
+        .ok_or_else(|| {
+            FeeValidationError::Overflow("Fee split underflow".to_string())
+        })?;
+    Ok((treasury_fees, burned_fees, proposer_fees))
+}
+
+/// Transfer the `fees` from the `payer` to the block proposer, minus the
+/// shares of the optional `fee_split` sent to its treasury and burned. The
+/// shares are rounded down and the block proposer receives the remainder, so
+/// that the split amounts always add up to the `fees`. If `fee_unwrap`
+/// unwraps the fee token, the share of the block proposer is burned from the
+/// payer and credited to the proposer in the native token instead. Returns
+/// the fees transferred to the block proposer.
+#[allow(clippy::too_many_arguments)]
+fn split_fee_transfer<WLS>(
+    state: &mut WLS,
+    token: &Address,
+    payer: &Address,
+    block_proposer: &Address,
+    fees: Amount,
+    overflow_policy: ProposerOverflowPolicy,
+    fee_split: Option<&FeeSplit>,
+    fee_unwrap: Option<&dyn FeeUnwrap>,
+) -> Result<FeeTransfer>
+where
+    WLS: State + StorageRead,
+{
+    let (treasury_fees, burned_fees, proposer_fees) =
+        split_fees(fees, fee_split)?;
+    if let Some(fee_split) = fee_split {
+        if !treasury_fees.is_zero() {
+            // The treasury is not subject to the proposer overflow policy
+            token_transfer(
+                state,
+                token,
+                payer,
+                &fee_split.treasury,
+                treasury_fees,
+                ProposerOverflowPolicy::Reject,
+            )?;
+        }
+        if !burned_fees.is_zero() {
+            token_burn(state, token, payer, burned_fees)?;
+        }
+    }
+
+    let (proposer_token, proposer_fees, proposer_balance) = match fee_unwrap
+        .and_then(|fee_unwrap| fee_unwrap.unwrap_fee(token, proposer_fees))
+    {
+        Some((native_token, native_fees)) => {
+            token_burn(state, token, payer, proposer_fees)?;
+            let proposer_balance =
+                token_mint(state, &native_token, block_proposer, native_fees)?;
+            (native_token, native_fees, proposer_balance)
+        }
+        None => {
+            let proposer_balance = token_transfer(
+                state,
+                token,
+                payer,
+                block_proposer,
+                proposer_fees,
+                overflow_policy,
+            )?;
+            (token.clone(), proposer_fees, proposer_balance)
+        }
+    };
+    record_block_fees(state, token, block_proposer, fees)?;
+    Ok(FeeTransfer {
+        token: token.clone(),
+        fees,
+        proposer_token,
+        proposer_fees,
+        proposer_balance,
+    })
+}
+
+/// Accumulate the `fees` charged in the current block under the block fees
+/// key of the block proposer, so that the fees it collected in the block can
+/// be queried directly. The key is written in the tx write log, together with
+/// the fee transfer, and pruned at the next block.
+fn record_block_fees<WLS>(
+    state: &mut WLS,
+    token: &Address,
+    block_proposer: &Address,
+    fees: Amount,
+) -> Result<()>
+where
+    WLS: State + StorageRead,
+{
+    update_block_fees(state, token, block_proposer, |prev_fees| {
+        prev_fees.checked_add(fees)
+    })
+}
+
+/// Deduct the `refund` of the fees charged in the current block from the
+/// block fees key of the block proposer, so that it only accounts for the
+/// fees that were kept
+fn deduct_block_fees<WLS>(
+    state: &mut WLS,
+    token: &Address,
+    block_proposer: &Address,
+    refund: Amount,
+) -> Result<()>
+where
+    WLS: State + StorageRead,
+{
+    update_block_fees(state, token, block_proposer, |prev_fees| {
+        prev_fees.checked_sub(refund)
+    })
+}
+
+/// Update the fees recorded in the current block under the block fees key of
+/// the block proposer
+fn update_block_fees<WLS>(
+    state: &mut WLS,
+    token: &Address,
+    block_proposer: &Address,
+    update: impl FnOnce(Amount) -> Option<Amount>,
+) -> Result<()>
+where
+    WLS: State + StorageRead,
+{
+    let height = state.in_mem().get_block_height().0;
+    let key = crate::token::storage_key::block_fees_key(
+        token,
+        block_proposer,
+        height,
+    );
+    let prev_fees: Amount = state
+        .read(&key)
+        .map_err(Error::StorageError)?
+        .unwrap_or_default();
+    let fees = update(prev_fees).ok_or_else(|| {
+        FeeValidationError::Overflow("Block fees out of range".to_string())
+    })?;
+    state
+        .write_log_mut()
+        .write(&key, fees.serialize_to_vec())
+        .map_err(|e| FeeValidationError::Other(e.to_string()))?;
+    Ok(())
+}
+
+/// Accumulate the shortfall of the fees that the payer failed to pay under its
+/// fee anomaly marker, for operators to review. The marker is written by the
+/// protocol, so that it's kept even though the wrapper tx fails.
+fn record_fee_anomaly<S>(
+    state: &mut S,
+    token: &Address,
+    payer: &Address,
+    shortfall: Amount,
+) -> Result<()>
+where
+    S: StorageRead + StorageWrite,
+{
+    let key = crate::token::storage_key::fee_anomaly_key(token, payer);
+    let prev_shortfall: Amount = state
+        .read(&key)
+        .map_err(Error::StorageError)?
+        .unwrap_or_default();
+    let shortfall = prev_shortfall
+        .checked_add(shortfall)
+        .ok_or_else(|| {
+            FeeValidationError::Overflow("Fee shortfall overflow".to_string())
+        })?;
+    state.write(&key, shortfall).map_err(Error::StorageError)
+}
+
+/// Refund the fees of the gas left unused by a wrapper tx back to the fee
+/// payer, if enabled by the `refund_unused_gas` protocol parameter. Must be
+/// called after the inner tx has been executed and the `fees` have been
+/// charged in the `fee_token`, with the share credited to the block proposer
+/// in the token of the `proposer_fee`. The refund is the share of the charged
+/// fees proportional to the unused gas, split like the fees by the optional
+/// `fee_split`: the shares of the block proposer and of the treasury are
+/// transferred back from them and the burned share is minted back. The share
+/// of the block proposer is taken back in the token it was credited and capped
+/// by its balance. The refund is deducted from the fees recorded for the
+/// block. Returns the refunded amount, in the fee token.
+pub fn refund_unused_gas<S>(
+    state: &mut S,
+    block_proposer: &Address,
+    wrapper: &WrapperTx,
+    fee_token: &Address,
+    fees: Amount,
+    proposer_fee: (&Address, Amount),
+    gas_used: Gas,
+) -> Result<Amount>
+where
+    S: State + StorageRead,
+{
+    if !namada_parameters::storage::get_refund_unused_gas(state)
+        .map_err(Error::StorageError)?
+        .unwrap_or_default()
+    {
+        return Ok(Amount::zero());
+    }
+
+    let gas_limit = u64::from(wrapper.gas_limit);
+    let unused_gas = gas_limit
+        .checked_sub(gas_used.get_whole_gas_units())
+        .unwrap_or_default();
+    if unused_gas == 0 {
+        return Ok(Amount::zero());
+    }
+    let refund = fees
+        .checked_mul(Amount::from(unused_gas))
+        .and_then(|refund| refund.checked_div(Amount::from(gas_limit)))
+        .ok_or_else(|| {
+            FeeValidationError::Overflow("Refund overflow".to_string())
+        })?;
+
+    let fee_split = namada_parameters::storage::get_fee_split(state)
+        .map_err(Error::StorageError)?;
+    let (treasury_refund, burned_refund) = match &fee_split {
+        Some(fee_split) => (
+            fee_split.treasury_share * refund,
+            fee_split.burn_share * refund,
+        ),
+        None => (Amount::zero(), Amount::zero()),
+    };
+    let proposer_refund = refund
+        .checked_sub(treasury_refund)
+        .and_then(|refund| refund.checked_sub(burned_refund))
+        .ok_or_else(|| {
+            FeeValidationError::Overflow("Refund split underflow".to_string())
+        })?;
+    // The share of the block proposer is taken back in the token it was
+    // credited, which differs from the fee token if the fees were unwrapped
+    let (proposer_token, proposer_fees) = proposer_fee;
+    let credited_refund = if proposer_token == fee_token {
+        proposer_refund
+    } else {
+        let (_, _, proposer_share) = split_fees(fees, fee_split.as_ref())?;
+        if proposer_share.is_zero() {
+            Amount::zero()
+        } else {
+            proposer_fees
+                .checked_mul(proposer_refund)
+                .and_then(|refund| refund.checked_div(proposer_share))
+                .ok_or_else(|| {
+                    FeeValidationError::Overflow("Refund overflow".to_string())
+                })?
+        }
+    };
+    let proposer_balance =
+        crate::token::read_balance(state, proposer_token, block_proposer)
+            .map_err(Error::StorageError)?;
+    if credited_refund > proposer_balance {
+        tracing::warn!(
+            "The block proposer balance of {} is insufficient to refund the \
+             unused gas fees of {}, refunding the available balance",
+            proposer_balance.to_string_native(),
+            credited_refund.to_string_native()
+        );
+    }
+    let capped_refund = credited_refund.min(proposer_balance);
+    // The fee payer gets back the matching share of the fee token
+    let proposer_refund = if capped_refund == credited_refund {
+        proposer_refund
+    } else {
+        proposer_refund
+            .checked_mul(capped_refund)
+            .and_then(|refund| refund.checked_div(credited_refund))
+            .ok_or_else(|| {
+                FeeValidationError::Overflow("Refund overflow".to_string())
+            })?
+    };
+
+    let fee_payer = wrapper.fee_payer();
+    // The refund can't exceed the fees previously charged to the payer
+    if proposer_token == fee_token {
+        token_transfer(
+            state,
+            fee_token,
+            block_proposer,
+            &fee_payer,
+            proposer_refund,
+            ProposerOverflowPolicy::Reject,
+        )?;
+    } else {
+        token_burn(state, proposer_token, block_proposer, capped_refund)?;
+        token_mint(state, fee_token, &fee_payer, proposer_refund)?;
+    }
+    if let Some(fee_split) = &fee_split {
+        if !treasury_refund.is_zero() {
+            token_transfer(
+                state,
+                fee_token,
+                &fee_split.treasury,
+                &fee_payer,
+                treasury_refund,
+                ProposerOverflowPolicy::Reject,
+            )?;
+        }
+    }
+    if !burned_refund.is_zero() {
+        token_mint(state, fee_token, &fee_payer, burned_refund)?;
+    }
+
+    let refund = proposer_refund
+        .checked_add(treasury_refund)
+        .and_then(|refund| refund.checked_add(burned_refund))
+        .ok_or_else(|| {
+            FeeValidationError::Overflow("Refund overflow".to_string())
+        })?;
+    deduct_block_fees(state, fee_token, block_proposer, refund)?;
+    Ok(refund)
+}
+
+/// Transfer `token` from `src` to `dest`. Returns an `Err` if `src` has
+/// insufficient balance or if the transfer the `dest` would overflow (This can
+/// only happen if the total supply doesn't fit in `token::Amount`). Contrary to
+/// `crate::token::transfer` this function updates the tx write log and
+/// not the block write log. Returns the resulting balance of `dest`, which is
+/// left unchanged when it's the same as `src`.
+///
+/// A transfer overflowing the balance of `dest` is handled according to the
+/// given `overflow_policy`.
+fn token_transfer<WLS>(
+    state: &mut WLS,
+    token: &Address,
+    src: &Address,
+    dest: &Address,
+    amount: Amount,
+    overflow_policy: ProposerOverflowPolicy,
+) -> Result<Amount>
+where
+    WLS: State + StorageRead,
+{
+    let src_key = crate::token::storage_key::balance_key(token, src);
+    let src_balance = crate::token::read_balance(state, token, src)
+        .expect("Token balance read in protocol must not fail");
+    match src_balance.checked_sub(amount) {
+        Some(new_src_balance) => {
+            if src == dest {
+                return Ok(src_balance);
+            }
+            let dest_key = crate::token::storage_key::balance_key(token, dest);
+            let dest_balance = crate::token::read_balance(state, token, dest)
+                .expect("Token balance read in protocol must not fail");
+            // The part of the amount that fits in the balance of the
+            // destination
+            let credit = Amount::max()
+                .checked_sub(dest_balance)
+                .unwrap_or_default()
+                .min(amount);
+            let new_src_balance = match overflow_policy {
+                _ if credit == amount => new_src_balance,
+                ProposerOverflowPolicy::Reject => {
+                    return Err(
+                        FeeValidationError::ProposerCreditOverflow.into()
+                    );
+                }
+                ProposerOverflowPolicy::Burn => {
+                    let excess = amount.checked_sub(credit).unwrap_or_default();
+                    burn_supply(state, token, excess)?;
+                    new_src_balance
+                }
+                ProposerOverflowPolicy::Clamp => src_balance
+                    .checked_sub(credit)
+                    .expect("The credit can't exceed the amount"),
+            };
+            let new_dest_balance = dest_balance
+                .checked_add(credit)
+                .expect("The credit must fit in the balance");
+            state
+                .write_log_mut()
+                .write(&src_key, new_src_balance.serialize_to_vec())
+                .map_err(|e| FeeValidationError::Other(e.to_string()))?;
+            match state
+                .write_log_mut()
+                .write(&dest_key, new_dest_balance.serialize_to_vec())
+            {
+                Ok(_) => Ok(new_dest_balance),
+                Err(e) => Err(FeeValidationError::Other(e.to_string()).into()),
+            }
+        }
+        None => Err(FeeValidationError::InsufficientBalance {
+            required: amount,
+            available: src_balance,
+        }
+        .into()),
+    }
+}
+
+/// Transfer a denominated `amount` of `token` from `src` to `dest` like
+/// [`token_transfer`], converting it to a raw amount with the denomination of
+/// the token in storage. Fails if the token has no denomination or if the
+/// amount is more precise than it.
+pub fn token_transfer_denominated<WLS>(
+    state: &mut WLS,
+    token: &Address,
+    src: &Address,
+    dest: &Address,
+    amount: DenominatedAmount,
+    overflow_policy: ProposerOverflowPolicy,
+) -> Result<Amount>
+where
+    WLS: State + StorageRead,
+{
+    let amount = crate::token::denom_to_amount(amount, token, state)
+        .map_err(|e| FeeValidationError::ConversionFailed(e.to_string()))?;
+    token_transfer(state, token, src, dest, amount, overflow_policy)
+}
+
+/// Burn `amount` of `token` from the balance of `src` in the tx write log,
+/// decreasing the total supply accordingly
+fn token_burn<WLS>(
+    state: &mut WLS,
+    token: &Address,
+    src: &Address,
+    amount: Amount,
+) -> Result<()>
+where
+    WLS: State + StorageRead,
+{
+    let src_key = crate::token::storage_key::balance_key(token, src);
+    let src_balance = crate::token::read_balance(state, token, src)
+        .expect("Token balance read in protocol must not fail");
+    let new_src_balance = src_balance.checked_sub(amount).ok_or(
+        FeeValidationError::InsufficientBalance {
+            required: amount,
+            available: src_balance,
+        },
+    )?;
+    state
+        .write_log_mut()
+        .write(&src_key, new_src_balance.serialize_to_vec())
+        .map_err(|e| FeeValidationError::Other(e.to_string()))?;
+    burn_supply(state, token, amount)
+}
+
+/// Mint `amount` of `token` to the balance of `dest` in the tx write log,
+/// increasing the total supply accordingly. Returns the resulting balance of
+/// `dest`.
+fn token_mint<WLS>(
+    state: &mut WLS,
+    token: &Address,
+    dest: &Address,
+    amount: Amount,
+) -> Result<Amount>
+where
+    WLS: State + StorageRead,
+{
+    let dest_key = crate::token::storage_key::balance_key(token, dest);
+    let dest_balance = crate::token::read_balance(state, token, dest)
+        .expect("Token balance read in protocol must not fail");
+    let new_dest_balance = dest_balance
+        .checked_add(amount)
+        .ok_or(FeeValidationError::ProposerCreditOverflow)?;
+    let minted_key = crate::token::storage_key::minted_balance_key(token);
+    let supply = crate::token::read_total_supply(state, token)
+        .map_err(Error::StorageError)?;
+    let new_supply = supply.checked_add(amount).ok_or_else(|| {
+        FeeValidationError::Overflow("Total supply overflow".to_string())
+    })?;
+    state
+        .write_log_mut()
+        .write(&dest_key, new_dest_balance.serialize_to_vec())
+        .map_err(|e| FeeValidationError::Other(e.to_string()))?;
+    state
+        .write_log_mut()
+        .write(&minted_key, new_supply.serialize_to_vec())
+        .map_err(|e| FeeValidationError::Other(e.to_string()))?;
+    Ok(new_dest_balance)
+}
+
+/// Decrease the total supply of `token` by `amount` in the tx write log, for
+/// the fees burned by the fee split or when overflowing the balance of the
+/// block proposer
+fn burn_supply<WLS>(
+    state: &mut WLS,
+    token: &Address,
+    amount: Amount,
+) -> Result<()>
+where
+    WLS: State + StorageRead,
+{
+    let minted_key = crate::token::storage_key::minted_balance_key(token);
+    let supply = crate::token::read_total_supply(state, token)
+        .map_err(Error::StorageError)?;
+    state
+        .write_log_mut()
+        .write(
+            &minted_key,
+            supply.checked_sub(amount).unwrap_or_default().serialize_to_vec(),
+        )
+        .map_err(|e| FeeValidationError::Other(e.to_string()))?;
+    Ok(())
+}
+
+/// The outcome of the evaluation of the fees of a wrapper tx, shared by the
+/// paths checking and charging the fees so that they can't diverge
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeeDecision {
+    /// The fee payer can pay the fee amount, either the whole amount or its
+    /// whole balance, falling short of the fees by less than the tolerance
+    /// set for the fee token
+    Pay(Amount),
+    /// The transparent balance of the fee payer is insufficient to pay the
+    /// fee amount
+    InsufficientBalance {
+        /// The fee amount
+        fees: Amount,
+        /// The transparent balance of the fee payer
+        balance: Amount,
+    },
+}
+
+/// Evaluate the fees of the wrapper against the balance of the fee payer and
+/// the optional ceiling and tolerance set for the fee token. Errors on invalid
+/// fees, including a fee token outside of the optional allowlist of the fee
+/// payer.
+pub fn evaluate_fee<S>(state: &S, wrapper: &WrapperTx) -> Result<FeeDecision>
+where
+    S: State + StorageRead,
+{
+    let balance = crate::token::read_balance(
+        state,
+        &wrapper.fee.token,
+        &wrapper.fee_payer(),
+    )
+    .map_err(Error::StorageError)?;
+
+    check_fee_denom(
+        state,
+        &wrapper.fee.token,
+        wrapper.fee.amount_per_gas_unit,
+    )?;
+    check_fee_token_allowed(state, &wrapper.fee_payer(), &wrapper.fee.token)?;
+    let fees = wrapper
+        .get_tx_fee()
+        .map_err(|e| FeeValidationError::Overflow(e.to_string()))?;
+
+    let fees = crate::token::denom_to_amount(fees, &wrapper.fee.token, state)
+        .map_err(|e| FeeValidationError::ConversionFailed(e.to_string()))?;
+    let max_fee_amount = namada_parameters::storage::get_max_fee_amount(
+        state,
+        &wrapper.fee.token,
+    )
+    .map_err(Error::StorageError)?;
+    if let Some(max_fee_amount) = max_fee_amount {
+        if fees > max_fee_amount {
+            return Err(Error::FeeTooHigh(fees, max_fee_amount));
+        }
+    }
+    if balance.checked_sub(fees).is_some() {
+        return Ok(FeeDecision::Pay(fees));
+    }
+    // Absorb the rounding mismatches between the clients and the protocol
+    let tolerance =
+        namada_parameters::read_fee_tolerance(state, &wrapper.fee.token)
+            .map_err(Error::StorageError)?
+            .unwrap_or_default();
+    match fees.checked_sub(balance) {
+        Some(shortfall) if shortfall < tolerance => {
+            Ok(FeeDecision::Pay(balance))
+        }
+        _ => Ok(FeeDecision::InsufficientBalance { fees, balance }),
+    }
+}
+
+/// Check that the gas price is denominated like the fee token. A gas price
+/// with a different denomination is implicitly priced in another token, so
+/// it's rejected rather than rescaled. A token without a denomination is left
+/// to fail the conversion of the fees.
+fn check_fee_denom<S>(
+    state: &S,
+    token: &Address,
+    amount_per_gas_unit: DenominatedAmount,
+) -> Result<()>
+where
+    S: StorageRead,
+{
+    let gas_price_denom = amount_per_gas_unit.denom();
+    match crate::token::read_denom(state, token)
+        .map_err(Error::StorageError)?
+    {
+        Some(token_denom) if token_denom != gas_price_denom => {
+            Err(Error::FeeTokenMismatch {
+                token: token.clone(),
+                gas_price_denom,
+                token_denom,
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Check that the fee payer is allowed to pay the fees in the token, if it's
+/// restricted to some tokens only
+fn check_fee_token_allowed<S>(
+    state: &S,
+    payer: &Address,
+    token: &Address,
+) -> Result<()>
+where
+    S: StorageRead,
+{
+    match namada_parameters::read_fee_token_allowlist(state, payer)
+        .map_err(Error::StorageError)?
+    {
+        Some(allowlist) if !allowlist.contains(token) => {
+            Err(FeeValidationError::TokenNotAllowedForPayer {
+                payer: payer.clone(),
+                token: token.clone(),
+            }
+            .into())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Check if the fee payer has enough transparent balance to pay fees, in the
+/// fee token or in one of the `fee_token_fallbacks` of the wrapper, and that
+/// the fees don't exceed the optional ceiling set for the token and that the
+/// gas price is denominated like the fee token. Returns the token the fees
+/// would be paid with.
+pub fn check_fees<S>(
+    state: &S,
+    wrapper: &WrapperTx,
+    fee_token_fallbacks: &[Fee],
+) -> Result<Address>
+where
+    S: State + StorageRead,
+{
+    fee_payment(state, wrapper, fee_token_fallbacks)
+        .map(|(fee_token, _fees)| fee_token)
+}
+
+/// The token and the amount the fees of the wrapper would be paid with, as
+/// checked by [`check_fees`]
+fn fee_payment<S>(
+    state: &S,
+    wrapper: &WrapperTx,
+    fee_token_fallbacks: &[Fee],
+) -> Result<(Address, Amount)>
+where
+    S: State + StorageRead,
+{
+    match evaluate_fee(state, wrapper)? {
+        FeeDecision::Pay(fees) => Ok((wrapper.fee.token.clone(), fees)),
+        FeeDecision::InsufficientBalance { fees, balance } => {
+            for candidate in
+                fee_fallback_candidates(state, wrapper, fee_token_fallbacks)?
+            {
+                if let FeeDecision::Pay(fees) = evaluate_fee(state, &candidate)?
+                {
+                    return Ok((candidate.fee.token, fees));
+                }
+            }
+            Err(FeeValidationError::InsufficientBalance {
+                required: fees,
+                available: balance,
+            }
+            .into())
+        }
+    }
+}
+
+/// Check the fees of the wrapper like [`check_fees`], after simulating its
+/// optional fee unshielding like [`charge_fee`] does, so that the funds it
+/// unshields are accounted for. The unshielding is run with
+/// [`run_fee_unshielding`], within the fee unshielding gas limit, and its
+/// changes are discarded afterwards together with the rest of the write log
+/// of the simulation, so that nothing is committed. The fee unshieldings
+/// counted by the block accumulators are left untouched as well. Returns the
+/// token the fees would be paid with.
+pub fn check_fees_with_unshielding<S, D, H, CA>(
+    wrapper: &WrapperTx,
+    fee_token_fallbacks: &[Fee],
+    fee_unshield_transaction: Option<Transaction>,
+    shell_params: &mut ShellParams<'_, S, D, H, CA>,
+) -> Result<Address>
+where
+    S: State<D = D, H = H> + Sync,
+    D: 'static + DB + for<'iter> DBIter<'iter> + Sync,
+    H: 'static + StorageHasher + Sync,
+    CA: 'static + WasmCacheAccess + Sync,
+{
+    let Some(transaction) = fee_unshield_transaction else {
+        return check_fees(&*shell_params.state, wrapper, fee_token_fallbacks);
+    };
+
+    let write_log = shell_params.state.write_log().clone();
+    let block_accumulators = shell_params.block_accumulators.take();
+    let valid_fee_unshielding =
+        run_fee_unshielding(wrapper, shell_params, transaction);
+    // As when charging the fees, check them before propagating any error
+    // coming from the fee unshielding
+    let result = check_fees(&*shell_params.state, wrapper, fee_token_fallbacks)
+        .and_then(|fee_token| valid_fee_unshielding.map(|_valid| fee_token));
+    shell_params.block_accumulators = block_accumulators;
+    *shell_params.state.write_log_mut() = write_log;
+    result
+}
+
+/// The fees of a candidate block of wrappers, as estimated by
+/// [`estimate_block_fees`]
+#[derive(Debug, Default)]
+pub struct BlockFeeEstimate {
+    /// The total fees that the wrappers would pay, by fee token
+    pub fees: BTreeMap<Address, Amount>,
+    /// The share of the fees that the block proposer would receive, by fee
+    /// token, once the shares of the `fee_split` parameter are deducted
+    pub proposer_fees: BTreeMap<Address, Amount>,
+    /// The txs that could not pay their fees, by index, with the error that
+    /// the fee checks failed with
+    pub failures: Vec<(usize, Error)>,
+}
+
+/// Estimate the fees that a candidate block of wrapper txs would pay, e.g. for
+/// a proposer to estimate its revenue and drop the infeasible wrappers. The
+/// fees of each wrapper are charged to its delegated fee payer, if any, and
+/// checked with [`check_fees`], in order. They are debited from the fee payer
+/// on a scratch write log, so that the wrappers of the same payer compete for
+/// its balance, and split according to the `fee_split` parameter. The fee
+/// transfers and the inner txs are not run and the write log is restored
+/// afterwards.
+pub fn estimate_block_fees<S>(
+    txs: &[Tx],
+    state: &mut S,
+) -> Result<BlockFeeEstimate>
+where
+    S: State + StorageRead,
+{
+    let fee_split = read_fee_split(&*state)?;
+    let write_log = state.write_log().clone();
+    let mut estimate = BlockFeeEstimate::default();
+    let result = txs.iter().enumerate().try_for_each(
+        |(index, tx)| -> Result<()> {
+            let Some(wrapper) = tx.header().wrapper() else {
+                estimate.failures.push((index, Error::TxTypeError));
+                return Ok(());
+            };
+            let wrapper = fee_delegated_wrapper(tx, &wrapper);
+            let fee_token_fallbacks = tx.wrapper_fee_token_fallbacks();
+            let (fee_token, fees) = match fee_payment(
+                &*state,
+                &wrapper,
+                &fee_token_fallbacks,
+            ) {
+                Ok(payment) => payment,
+                Err(err) => {
+                    estimate.failures.push((index, err));
+                    return Ok(());
+                }
+            };
+            let fee_payer = wrapper.fee_payer();
+            let balance =
+                crate::token::read_balance(&*state, &fee_token, &fee_payer)
+                    .map_err(Error::StorageError)?;
+            state
+                .write(
+                    &crate::token::storage_key::balance_key(
+                        &fee_token, &fee_payer,
+                    ),
+                    balance.checked_sub(fees).unwrap_or_default(),
+                )
+                .map_err(Error::StorageError)?;
+            let (_treasury_fees, _burned_fees, proposer_fees) =
+                split_fees(fees, fee_split.as_ref())?;
+            add_estimated_fees(&mut estimate.fees, &fee_token, fees)?;
+            add_estimated_fees(
+                &mut estimate.proposer_fees,
+                &fee_token,
+                proposer_fees,
+            )
+        },
+    );
+    *state.write_log_mut() = write_log;
+    result.map(|()| estimate)
+}
+
+/// Add the `fees` to the total estimated for the token
+fn add_estimated_fees(
+    totals: &mut BTreeMap<Address, Amount>,
+    token: &Address,
+    fees: Amount,
+) -> Result<()> {
+    let total = totals.entry(token.clone()).or_default();
+    *total = total.checked_add(fees).ok_or_else(|| {
+        FeeValidationError::Overflow("The total fees overflowed".to_string())
+    })?;
+    Ok(())
+}
+
+/// The wrapper with its fee replaced by each of the `fee_token_fallbacks`, in
+/// the order set by the signer of the wrapper, which is the deterministic order
+/// in which the fee tokens are tried by both [`check_fees`] and
+/// [`transfer_fee`]. Each fallback is charged at its own amount per gas unit.
+/// The tokens not allowed for fee payment or for the fee payer, not matching
+/// the denomination of their gas price or for which the amount per gas unit is
+/// below the minimum gas price are skipped.
+fn fee_fallback_candidates<S>(
+    state: &S,
+    wrapper: &WrapperTx,
+    fee_token_fallbacks: &[Fee],
+) -> Result<Vec<WrapperTx>>
+where
+    S: StorageRead,
+{
+    let mut candidates = vec![];
+    for fee in fee_token_fallbacks {
+        let Some(minimum_gas_price) =
+            namada_parameters::read_gas_cost(state, &fee.token)
+                .map_err(Error::StorageError)?
+        else {
+            continue;
+        };
+        match check_fee_denom(state, &fee.token, fee.amount_per_gas_unit) {
+            Err(Error::FeeTokenMismatch { .. }) => continue,
+            res => res?,
+        }
+        match check_fee_token_allowed(state, &wrapper.fee_payer(), &fee.token) {
+            Err(Error::FeeError(
+                FeeValidationError::TokenNotAllowedForPayer { .. },
+            )) => continue,
+            res => res?,
+        }
+        match crate::token::denom_to_amount(
+            fee.amount_per_gas_unit,
+            &fee.token,
+            state,
+        ) {
+            Ok(amount_per_gas_unit)
+                if amount_per_gas_unit >= minimum_gas_price =>
+            {
+                candidates.push(WrapperTx {
+                    fee: fee.clone(),
+                    ..wrapper.clone()
+                });
+            }
+            _ => continue,
+        }
+    }
+    Ok(candidates)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::Debug;
+
+    use masp_primitives::asset_type::AssetType;
+    use masp_primitives::consensus::{
+        BlockHeight as MaspBlockHeight, BranchId,
+    };
+    use masp_primitives::transaction::components::{transparent, TxOut};
+    use masp_primitives::transaction::{
+        TransactionData, TransparentAddress, TxVersion,
+    };
+    use namada_core::dec::Dec;
+    use namada_core::key::RefTo;
+    use namada_core::storage::{BlockHeight, Epoch, KeySeg};
+    use namada_core::{address, key};
+    use namada_ethereum_bridge::storage::vp;
+    use namada_ethereum_bridge::test_utils;
+    use namada_state::testing::TestState;
+    use namada_state::StateRead;
+    use namada_test_utils::tx_data::TxWriteData;
+    use namada_test_utils::TestWasms;
+    use namada_tx::data::{Fee, TxType};
+    use namada_tx::Authorization;
+
+    use super::*;
+    use crate::ledger::gas::GasMetering;
+    use crate::ledger::protocol::tests::{
+        batch_wrapper, credit, setup_batch_storage, signed_wrapper,
+        wasm_caches,
+    };
+    use crate::ledger::protocol::{
+        apply_wrapper_tx, dispatch_tx, DispatchArgs, ShieldedPolicy,
+    };
+    use crate::token::Denomination;
+
+    /// A shielded policy capping the transparent value of the unshieldings
+    #[derive(Debug)]
+    struct UnshieldCap(u64);
+
+    impl ShieldedPolicy for UnshieldCap {
+        fn check_unshielding(
+            &self,
+            _wrapper: &WrapperTx,
+            transaction: &Transaction,
+        ) -> std::result::Result<(), String> {
+            let value: u64 = transaction
+                .transparent_bundle()
+                .map(|bundle| bundle.vout.iter().map(|out| out.value).sum())
+                .unwrap_or_default();
+            if value > self.0 {
+                Err(format!(
+                    "Unshielded value {} exceeds the cap of {}",
+                    value, self.0
+                ))
+            } else {
+                Ok(())
+            }
+        }
+    }
+
+    #[test]
+    /// Tests that a fee unshielding exceeding the cap of the shielded policy
+    /// is rejected
+    fn test_shielded_policy_unshield_cap() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let (mut vp_cache, mut tx_cache) = wasm_caches();
+        let keypair = key::testing::keypair_1();
+
+        let wrapper =
+            signed_wrapper(&keypair, address::testing::nam(), 1, 1_000);
+        let transaction = TransactionData::from_parts(
+            TxVersion::MASPv5,
+            BranchId::MASP,
+            0,
+            MaspBlockHeight::from_u32(0),
+            Some(transparent::Bundle {
+                vin: vec![],
+                vout: vec![TxOut {
+                    asset_type: AssetType::new(b"test").unwrap(),
+                    value: 1_001,
+                    address: TransparentAddress([0; 20]),
+                }],
+                authorization: transparent::Authorized,
+            }),
+            None,
+        )
+        .freeze()
+        .unwrap();
+
+        let policy = UnshieldCap(1_000);
+        assert!(policy.check_unshielding(&wrapper, &transaction).is_err());
+        assert!(
+            UnshieldCap(1_001)
+                .check_unshielding(&wrapper, &transaction)
+                .is_ok()
+        );
+
+        let gas_meter = RefCell::new(TxGasMeter::new(u64::MAX));
+        let mut shell_params = ShellParams::new(
+            &gas_meter,
+            state.restrict_writes_to_write_log(),
+            &mut vp_cache,
+            &mut tx_cache,
+        );
+        shell_params.shielded_policy = Some(&policy);
+        let valid_unshielding =
+            run_fee_unshielding(&wrapper, &mut shell_params, transaction)
+                .unwrap();
+        assert!(!valid_unshielding);
+        // the unshielding was not applied
+        assert!(state.write_log().get_keys().is_empty());
+        assert_eq!(gas_meter.into_inner().get_tx_consumed_gas(), 0.into());
+    }
+
+    #[test]
+    /// Tests that once the fee unshielding budget of the block is exhausted
+    /// the unshieldings are skipped and the fees are paid transparently
+    fn test_max_fee_unshields_per_block() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let (mut vp_cache, mut tx_cache) = wasm_caches();
+        let nam = address::testing::nam();
+        let keypair = key::testing::keypair_1();
+        let fee_payer = Address::from(&keypair.ref_to());
+        let block_proposer = address::testing::established_address_1();
+        credit(&mut state, &nam, &fee_payer, Amount::from(1_000_000));
+        let max_fee_unshields_key =
+            namada_parameters::storage::get_max_fee_unshields_per_block_key();
+        state.write(&max_fee_unshields_key, 2_u64).unwrap();
+        // the transfer code is missing, so the fee unshielding fails
+        state
+            .write(
+                &Key::wasm_code_name(TX_TRANSFER_WASM.to_string()),
+                Hash::sha256(b"transfer"),
+            )
+            .unwrap();
+        state.commit_tx();
+
+        let wrapper = signed_wrapper(&keypair, nam.clone(), 1, 1_000);
+        let transaction = TransactionData::from_parts(
+            TxVersion::MASPv5,
+            BranchId::MASP,
+            0,
+            MaspBlockHeight::from_u32(0),
+            None,
+            None,
+        )
+        .freeze()
+        .unwrap();
+
+        // the budget was exhausted by the previous txs of the block
+        let accumulators = RefCell::new(BlockAccumulators {
+            fee_unshields: 2,
+            ..Default::default()
+        });
+        let gas_meter = RefCell::new(TxGasMeter::new(u64::MAX));
+        let mut shell_params = ShellParams::new(
+            &gas_meter,
+            state.restrict_writes_to_write_log(),
+            &mut vp_cache,
+            &mut tx_cache,
+        );
+        shell_params.block_accumulators = Some(&accumulators);
+        let mut wrapper_args = WrapperArgs::new(&block_proposer);
+        let (fee_token, _) = charge_fee(
+            &wrapper,
+            &[],
+            Some(transaction.clone()),
+            &mut shell_params,
+            &mut BTreeSet::default(),
+            Some(&mut wrapper_args),
+        )
+        .unwrap();
+        assert_eq!(fee_token, nam);
+        assert!(!wrapper_args.is_committed_fee_unshield);
+        assert_eq!(accumulators.borrow().fee_unshields, 2);
+        assert_eq!(gas_meter.into_inner().get_tx_consumed_gas(), 0.into());
+        // the fees were paid with the transparent balance
+        assert_eq!(
+            namada_token::read_balance(&state, &nam, &fee_payer).unwrap(),
+            Amount::from(999_000)
+        );
+        assert_eq!(
+            namada_token::read_balance(&state, &nam, &block_proposer).unwrap(),
+            Amount::from(1_000)
+        );
+
+        // a failed unshielding doesn't consume the budget of the block
+        let wrapper = signed_wrapper(&keypair, nam.clone(), 1, 2_000);
+        let accumulators = RefCell::new(BlockAccumulators {
+            fee_unshields: 1,
+            ..Default::default()
+        });
+        let gas_meter = RefCell::new(TxGasMeter::new(u64::MAX));
+        let mut shell_params = ShellParams::new(
+            &gas_meter,
+            state.restrict_writes_to_write_log(),
+            &mut vp_cache,
+            &mut tx_cache,
+        );
+        shell_params.block_accumulators = Some(&accumulators);
+        let mut wrapper_args = WrapperArgs::new(&block_proposer);
+        let (fee_token, _) = charge_fee(
+            &wrapper,
+            &[],
+            Some(transaction),
+            &mut shell_params,
+            &mut BTreeSet::default(),
+            Some(&mut wrapper_args),
+        )
+        .unwrap();
+        assert_eq!(fee_token, nam);
+        assert!(!wrapper_args.is_committed_fee_unshield);
+        assert_eq!(accumulators.borrow().fee_unshields, 1);
+        assert!(accumulators.borrow().check_fee_unshields(Some(2)).is_ok());
+        assert_eq!(
+            namada_token::read_balance(&state, &nam, &fee_payer).unwrap(),
+            Amount::from(997_000)
+        );
+    }
+
+    #[test]
+    /// Tests that the generated fee unshielding tx runs the transfer code
+    /// stored in storage and that a substituted code is rejected
+    fn test_fee_unshielding_code() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let transfer_hash = Hash::sha256(b"transfer");
+        state
+            .write(
+                &Key::wasm_code_name(TX_TRANSFER_WASM.to_string()),
+                transfer_hash,
+            )
+            .unwrap();
+        state.commit_tx();
+
+        let wrapper = signed_wrapper(
+            &key::testing::keypair_1(),
+            address::testing::nam(),
+            1,
+            1_000,
+        );
+        let transaction = TransactionData::from_parts(
+            TxVersion::MASPv5,
+            BranchId::MASP,
+            0,
+            MaspBlockHeight::from_u32(0),
+            None,
+            None,
+        )
+        .freeze()
+        .unwrap();
+
+        let stored_hash = get_transfer_hash_from_storage(&state).unwrap();
+        let mut tx = wrapper
+            .generate_fee_unshielding(
+                stored_hash,
+                Some(TX_TRANSFER_WASM.to_string()),
+                transaction,
+            )
+            .unwrap();
+        let code_hash = tx
+            .get_section(tx.code_sechash())
+            .and_then(|section| section.code_sec())
+            .map(|code| code.code.hash());
+        assert_eq!(code_hash, Some(transfer_hash));
+        check_fee_unshielding_code(&tx, stored_hash).unwrap();
+
+        let substituted_hash = Hash::sha256(b"substituted");
+        tx.set_code(namada_tx::Code::from_hash(substituted_hash, None));
+        assert!(matches!(
+            check_fee_unshielding_code(&tx, stored_hash).unwrap_err(),
+            Error::FeeUnshieldingCodeMismatch { expected, found }
+                if expected == transfer_hash
+                    && found == Some(substituted_hash)
+        ));
+    }
+
+    #[test]
+    /// Tests that the transfer code of the fee unshieldings is looked up once
+    /// per shell parameters and that a missing one is reported as an error
+    fn test_fee_unshielding_transfer_hash() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let (mut vp_cache, mut tx_cache) = wasm_caches();
+        let keypair = key::testing::keypair_1();
+
+        let wrapper =
+            signed_wrapper(&keypair, address::testing::nam(), 1, 1_000);
+        let transaction = TransactionData::from_parts(
+            TxVersion::MASPv5,
+            BranchId::MASP,
+            0,
+            MaspBlockHeight::from_u32(0),
+            None,
+            None,
+        )
+        .freeze()
+        .unwrap();
+
+        // the transfer code is missing from storage
+        let gas_meter = RefCell::new(TxGasMeter::new(u64::MAX));
+        let mut shell_params = ShellParams::new(
+            &gas_meter,
+            state.restrict_writes_to_write_log(),
+            &mut vp_cache,
+            &mut tx_cache,
+        );
+        let result = run_fee_unshielding(
+            &wrapper,
+            &mut shell_params,
+            transaction.clone(),
+        );
+        assert!(matches!(result.unwrap_err(), Error::MissingTransferHash));
+        assert!(shell_params.transfer_hash.is_none());
+
+        let transfer_hash = Hash::sha256(b"transfer");
+        state
+            .write(
+                &Key::wasm_code_name(TX_TRANSFER_WASM.to_string()),
+                transfer_hash,
+            )
+            .unwrap();
+        state.commit_tx();
+        let gas_meter = RefCell::new(TxGasMeter::new(u64::MAX));
+        let mut shell_params = ShellParams::new(
+            &gas_meter,
+            state.restrict_writes_to_write_log(),
+            &mut vp_cache,
+            &mut tx_cache,
+        );
+        run_fee_unshielding(&wrapper, &mut shell_params, transaction.clone())
+            .unwrap();
+        assert_eq!(shell_params.transfer_hash, Some(transfer_hash));
+
+        // a hash already provided is used without looking it up again
+        let provided_hash = Hash::sha256(b"provided");
+        shell_params.transfer_hash = Some(provided_hash);
+        run_fee_unshielding(&wrapper, &mut shell_params, transaction).unwrap();
+        assert_eq!(shell_params.transfer_hash, Some(provided_hash));
+    }
+
+    #[test]
+    /// Tests that the outcome of a fee unshielding is recorded on its first
+    /// evaluation in the block and that a failed one is not evaluated again
+    fn test_fee_unshielding_outcome() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let (mut vp_cache, mut tx_cache) = wasm_caches();
+        // the transfer code is not stored, so the unshielding fails
+        state
+            .write(
+                &Key::wasm_code_name(TX_TRANSFER_WASM.to_string()),
+                Hash::sha256(b"transfer"),
+            )
+            .unwrap();
+        state.commit_tx();
+
+        let wrapper = signed_wrapper(
+            &key::testing::keypair_1(),
+            address::testing::nam(),
+            1,
+            1_000,
+        );
+        let transaction = TransactionData::from_parts(
+            TxVersion::MASPv5,
+            BranchId::MASP,
+            0,
+            MaspBlockHeight::from_u32(0),
+            None,
+            None,
+        )
+        .freeze()
+        .unwrap();
+        let unshielding_hash = Hash::sha256(wrapper.serialize_to_vec());
+        assert_eq!(
+            state.write_log().fee_unshielding_outcome(&unshielding_hash),
+            None
+        );
+
+        let gas_meter = RefCell::new(TxGasMeter::new(u64::MAX));
+        let mut shell_params = ShellParams::new(
+            &gas_meter,
+            state.restrict_writes_to_write_log(),
+            &mut vp_cache,
+            &mut tx_cache,
+        );
+        let valid_unshielding = run_fee_unshielding(
+            &wrapper,
+            &mut shell_params,
+            transaction.clone(),
+        )
+        .unwrap();
+        assert!(!valid_unshielding);
+        assert_eq!(
+            state.write_log().fee_unshielding_outcome(&unshielding_hash),
+            Some(false)
+        );
+
+        // the recorded outcome is reused without running the unshielding
+        let gas_meter = RefCell::new(TxGasMeter::new(u64::MAX));
+        let mut shell_params = ShellParams::new(
+            &gas_meter,
+            state.restrict_writes_to_write_log(),
+            &mut vp_cache,
+            &mut tx_cache,
+        );
+        let valid_unshielding =
+            run_fee_unshielding(&wrapper, &mut shell_params, transaction)
+                .unwrap();
+        assert!(!valid_unshielding);
+        assert!(shell_params.transfer_hash.is_none());
+        assert_eq!(gas_meter.into_inner().get_tx_consumed_gas(), 0.into());
+    }
+
+    #[test]
+    /// Tests that checking the fees with a fee unshielding doesn't commit any
+    /// of its changes
+    fn test_check_fees_with_unshielding() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let (mut vp_cache, mut tx_cache) = wasm_caches();
+        let nam = address::testing::nam();
+        let keypair = key::testing::keypair_1();
+        let fee_payer = Address::from(&keypair.ref_to());
+        state
+            .write(
+                &Key::wasm_code_name(TX_TRANSFER_WASM.to_string()),
+                Hash::sha256(b"transfer"),
+            )
+            .unwrap();
+        state.commit_tx();
+
+        let wrapper = signed_wrapper(&keypair, nam.clone(), 1, 1_000);
+        let transaction = TransactionData::from_parts(
+            TxVersion::MASPv5,
+            BranchId::MASP,
+            0,
+            MaspBlockHeight::from_u32(0),
+            None,
+            None,
+        )
+        .freeze()
+        .unwrap();
+
+        // without a valid unshielding, the transparent balance is checked
+        let accumulators = RefCell::new(BlockAccumulators::default());
+        let gas_meter = RefCell::new(TxGasMeter::new(u64::MAX));
+        let mut shell_params = ShellParams::new(
+            &gas_meter,
+            state.restrict_writes_to_write_log(),
+            &mut vp_cache,
+            &mut tx_cache,
+        );
+        shell_params.block_accumulators = Some(&accumulators);
+        let result = check_fees_with_unshielding(
+            &wrapper,
+            &[],
+            Some(transaction.clone()),
+            &mut shell_params,
+        );
+        assert!(matches!(
+            result.unwrap_err(),
+            Error::FeeError(FeeValidationError::InsufficientBalance { .. })
+        ));
+        assert!(shell_params.block_accumulators.is_some());
+        assert_eq!(accumulators.borrow().fee_unshields, 0);
+        assert!(state.write_log().get_keys_with_precommit().is_empty());
+
+        credit(&mut state, &nam, &fee_payer, Amount::from(1_000_000));
+        state.commit_tx();
+        let gas_meter = RefCell::new(TxGasMeter::new(u64::MAX));
+        let mut shell_params = ShellParams::new(
+            &gas_meter,
+            state.restrict_writes_to_write_log(),
+            &mut vp_cache,
+            &mut tx_cache,
+        );
+        for transaction in [None, Some(transaction)] {
+            assert_eq!(
+                check_fees_with_unshielding(
+                    &wrapper,
+                    &[],
+                    transaction,
+                    &mut shell_params
+                )
+                .unwrap(),
+                nam
+            );
+        }
+        assert!(state.write_log().get_keys_with_precommit().is_empty());
+        // nothing was charged
+        assert_eq!(
+            namada_token::read_balance(&state, &nam, &fee_payer).unwrap(),
+            Amount::from(1_000_000)
+        );
+    }
+
+    #[test]
+    /// Tests that the result of a wrapper tx reports the denomination of the
+    /// fee token
+    fn test_wrapper_fee_denom() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let (mut vp_cache, mut tx_cache) = wasm_caches();
+        let btc = address::testing::btc();
+        let keypair = key::testing::keypair_1();
+        let fee_payer = Address::from(&keypair.ref_to());
+        let block_proposer = address::testing::established_address_1();
+        namada_token::write_denom(&mut state, &btc, 8.into()).unwrap();
+        credit(&mut state, &btc, &fee_payer, Amount::from(1_000_000));
+
+        let tx_no_op = TestWasms::TxNoOp.read_bytes();
+        let code_hash = Hash::sha256(&tx_no_op);
+        let code_len = (tx_no_op.len() as u64).serialize_to_vec();
+        state
+            .write_log_mut()
+            .write(&Key::wasm_code(&code_hash), tx_no_op.serialize_to_vec())
+            .unwrap();
+        state
+            .write_log_mut()
+            .write(&Key::wasm_code_len(&code_hash), code_len)
+            .unwrap();
+        state.commit_tx();
+        state.commit_block().unwrap();
+
+        let mut tx = Tx::from_type(TxType::Wrapper(Box::new(WrapperTx::new(
+            Fee {
+                amount_per_gas_unit: DenominatedAmount::new(
+                    1.into(),
+                    8.into(),
+                ),
+                token: btc.clone(),
+            },
+            keypair.ref_to(),
+            Epoch(0),
+            GasLimit::from(1_000),
+            None,
+        ))));
+        tx.set_code(namada_tx::Code::new(tx_no_op, None));
+        tx.set_data(namada_tx::Data::new(vec![]));
+
+        let gas_meter = RefCell::new(TxGasMeter::new(u64::MAX));
+        let result = dispatch_tx(
+            tx,
+            &[],
+            TxIndex::default(),
+            &gas_meter,
+            state.restrict_writes_to_write_log(),
+            &mut vp_cache,
+            &mut tx_cache,
+            None,
+            &DispatchArgs::default(),
+            Some(&mut WrapperArgs::new(&block_proposer)),
+        )
+        .unwrap();
+        let stored_denom = namada_token::read_denom(&state, &btc).unwrap();
+        assert_eq!(stored_denom, Some(8.into()));
+        assert_eq!(result.fee_denom, stored_denom);
+        assert_eq!(result.fee_token, Some(btc));
+    }
+
+    #[test]
+    /// Tests that a fee payer initialized by the inner tx is rejected, since
+    /// the fees are charged before its execution
+    fn test_fee_payer_initialized_in_tx() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let fee_payer = address::testing::established_address_1();
+
+        // the account is only initialized by the inner tx
+        assert!(matches!(
+            check_fee_payer_exists(&state, &fee_payer).unwrap_err(),
+            Error::FeeError(FeeValidationError::NonexistentFeePayer(addr))
+                if addr == fee_payer
+        ));
+
+        let vp_hash = Hash::sha256(&TestWasms::VpAlwaysTrue.read_bytes());
+        state
+            .write_log_mut()
+            .write(
+                &Key::validity_predicate(&fee_payer),
+                vp_hash.serialize_to_vec(),
+            )
+            .unwrap();
+        state.commit_tx();
+        check_fee_payer_exists(&state, &fee_payer).unwrap();
+
+        // implicit accounts always exist
+        let implicit = Address::from(&key::testing::keypair_1().ref_to());
+        check_fee_payer_exists(&state, &implicit).unwrap();
+    }
+
+    #[test]
+    /// Tests that the fees of a block of wrappers are estimated without
+    /// modifying the state, and that the wrappers of a payer compete for its
+    /// balance
+    fn test_estimate_block_fees() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let nam = address::testing::nam();
+        let keypair_1 = key::testing::keypair_1();
+        let keypair_2 = key::testing::keypair_2();
+        let keypair_3 = key::testing::keypair_3();
+        let payer_1 = Address::from(&keypair_1.ref_to());
+        let payer_2 = Address::from(&keypair_2.ref_to());
+        // Each wrapper costs 1_000, the first payer can only afford one
+        for (payer, balance) in [(&payer_1, 1_500), (&payer_2, 1_000)] {
+            credit(&mut state, &nam, payer, Amount::from(balance));
+        }
+        state
+            .write(
+                &namada_parameters::storage::get_fee_split_key(),
+                FeeSplit {
+                    treasury: Address::Internal(InternalAddress::Pgf),
+                    treasury_share: Dec::new(3, 1).unwrap(),
+                    burn_share: Dec::new(15, 2).unwrap(),
+                },
+            )
+            .unwrap();
+        state.commit_tx();
+        state.commit_block().unwrap();
+
+        let wrapper_tx = |keypair| {
+            let wrapper = signed_wrapper(keypair, nam.clone(), 1, 1_000);
+            Tx::from_type(TxType::Wrapper(Box::new(wrapper)))
+        };
+        // The third wrapper has no balance and delegates its fees to the
+        // second payer
+        let mut delegated_tx = wrapper_tx(&keypair_3);
+        delegated_tx.add_section(Section::Authorization(Authorization::new(
+            vec![delegated_tx.header_hash()],
+            [(0, keypair_2.clone())].into_iter().collect(),
+            None,
+        )));
+        let txs = [
+            wrapper_tx(&keypair_1),
+            wrapper_tx(&keypair_1),
+            delegated_tx,
+            Tx::from_type(TxType::Raw),
+        ];
+
+        let estimate = estimate_block_fees(&txs, &mut state).unwrap();
+        assert_eq!(
+            estimate.fees,
+            BTreeMap::from([(nam.clone(), Amount::from(2_000))])
+        );
+        // The treasury and the burn take 450 of each fee
+        assert_eq!(
+            estimate.proposer_fees,
+            BTreeMap::from([(nam.clone(), Amount::from(1_100))])
+        );
+        assert_eq!(estimate.failures.len(), 2);
+        assert!(matches!(
+            estimate.failures[0],
+            (
+                1,
+                Error::FeeError(FeeValidationError::InsufficientBalance {
+                    ..
+                })
+            )
+        ));
+        assert!(matches!(estimate.failures[1], (3, Error::TxTypeError)));
+        // Nothing was debited
+        assert!(state.write_log().get_keys().is_empty());
+        assert_eq!(
+            namada_token::read_balance(&state, &nam, &payer_1).unwrap(),
+            Amount::from(1_500)
+        );
+    }
+
+    #[test]
+    /// Tests that the fees charged in a block are accumulated per block
+    /// proposer and block height, and pruned at the next block
+    fn test_block_fees() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let nam = address::testing::nam();
+        let keypair = key::testing::keypair_1();
+        let fee_payer = Address::from(&keypair.ref_to());
+        let block_proposer = address::testing::established_address_1();
+        credit(&mut state, &nam, &fee_payer, Amount::from(1_000_000));
+        state.commit_tx();
+
+        let wrapper = |gas_limit: u64| {
+            signed_wrapper(&keypair, nam.clone(), 100, gas_limit)
+        };
+        let height = state.in_mem().get_block_height().0;
+        let mut total_fees = Amount::zero();
+        for gas_limit in [1_000, 2_500] {
+            let wrapper = wrapper(gas_limit);
+            transfer_fee(&mut state, &block_proposer, &wrapper, &[], None)
+                .unwrap();
+            let fees = crate::token::denom_to_amount(
+                wrapper.get_tx_fee().unwrap(),
+                &nam,
+                &state,
+            )
+            .unwrap();
+            total_fees = total_fees.checked_add(fees).unwrap();
+        }
+        assert_eq!(total_fees, Amount::from(350_000));
+
+        let read_block_fees = |height: BlockHeight| -> Option<Amount> {
+            state
+                .read(&namada_token::storage_key::block_fees_key(
+                    &nam,
+                    &block_proposer,
+                    height,
+                ))
+                .unwrap()
+        };
+        assert_eq!(read_block_fees(height), Some(total_fees));
+        assert_eq!(read_block_fees(height.next_height()), None);
+
+        state.commit_tx();
+        state.in_mem_mut().block.height = height.next_height();
+        namada_token::prune_block_fees(&mut state).unwrap();
+        let block_fees_key = namada_token::storage_key::block_fees_key(
+            &nam,
+            &block_proposer,
+            height,
+        );
+        assert_eq!(state.read::<Amount>(&block_fees_key).unwrap(), None);
+    }
+
+    #[test]
+    /// Tests that a wrapper whose fee exceeds the maximum fee of the wrapper
+    /// args is rejected before any fee is transferred
+    fn test_wrapper_max_fee() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let (mut vp_cache, mut tx_cache) = wasm_caches();
+        let nam = address::testing::nam();
+        let keypair = key::testing::keypair_1();
+        let fee_payer = Address::from(&keypair.ref_to());
+        let block_proposer = address::testing::established_address_1();
+        credit(&mut state, &nam, &fee_payer, Amount::native_whole(1_000));
+        state.commit_tx();
+        state.commit_block().unwrap();
+
+        let gas_limit = 20_000_000;
+        let wrapper = signed_wrapper(&keypair, nam.clone(), 1, gas_limit);
+        let fees = Amount::from(gas_limit);
+        let tx = Tx::from_type(TxType::Wrapper(Box::new(wrapper.clone())));
+        let gas_meter = RefCell::new(TxGasMeter::new(u64::MAX));
+
+        let max_fee = fees.checked_sub(Amount::from(1)).unwrap();
+        let err = apply_wrapper_tx(
+            tx.clone(),
+            &wrapper,
+            None,
+            &[],
+            ShellParams::new(
+                &gas_meter,
+                state.restrict_writes_to_write_log(),
+                &mut vp_cache,
+                &mut tx_cache,
+            ),
+            Some(&mut WrapperArgs {
+                max_fee: Some(max_fee),
+                ..WrapperArgs::new(&block_proposer)
+            }),
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            Error::WrapperFeeAboveMax(fee, max) if fee == fees && max == max_fee
+        ));
+        assert_eq!(
+            namada_token::read_balance(&state, &nam, &fee_payer).unwrap(),
+            Amount::native_whole(1_000)
+        );
+
+        for max_fee in [Some(fees), None] {
+            apply_wrapper_tx(
+                tx.clone(),
+                &wrapper,
+                None,
+                &[],
+                ShellParams::new(
+                    &gas_meter,
+                    state.restrict_writes_to_write_log(),
+                    &mut vp_cache,
+                    &mut tx_cache,
+                ),
+                Some(&mut WrapperArgs {
+                    max_fee,
+                    ..WrapperArgs::new(&block_proposer)
+                }),
+            )
+            .unwrap();
+        }
+        let charged = fees.checked_add(fees).unwrap();
+        assert_eq!(
+            namada_token::read_balance(&state, &nam, &fee_payer).unwrap(),
+            Amount::native_whole(1_000).checked_sub(charged).unwrap()
+        );
+    }
+
+    #[test]
+    /// Tests that a wrapper introducing a new fee token beyond the limit of
+    /// distinct fee tokens per block is rejected
+    fn test_max_fee_tokens_per_block() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let (mut vp_cache, mut tx_cache) = wasm_caches();
+        let nam = address::testing::nam();
+        let btc = address::testing::btc();
+        let eth = address::testing::eth();
+        let keypair = key::testing::keypair_1();
+        let fee_payer = Address::from(&keypair.ref_to());
+        let block_proposer = address::testing::established_address_1();
+        let initial_balance = Amount::native_whole(1_000);
+        for token in [&nam, &btc, &eth] {
+            namada_token::write_denom(&mut state, token, 6.into()).unwrap();
+            credit(&mut state, token, &fee_payer, initial_balance);
+        }
+        state
+            .write(
+                &namada_parameters::storage::get_gas_cost_key(),
+                BTreeMap::from([
+                    (nam.clone(), Amount::from(1)),
+                    (btc.clone(), Amount::from(1)),
+                    (eth.clone(), Amount::from(1)),
+                ]),
+            )
+            .unwrap();
+        state
+            .write(
+                &namada_parameters::storage::get_max_fee_tokens_per_block_key(),
+                2_u64,
+            )
+            .unwrap();
+        state.commit_tx();
+        state.commit_block().unwrap();
+
+        let block_accumulators = RefCell::new(BlockAccumulators::default());
+        let mut apply = |token: &Address, gas_limit: u64| {
+            let wrapper = signed_wrapper(&keypair, token.clone(), 1, gas_limit);
+            let tx = Tx::from_type(TxType::Wrapper(Box::new(wrapper.clone())));
+            let gas_meter = RefCell::new(TxGasMeter::new(u64::MAX));
+            let mut shell_params = ShellParams::new(
+                &gas_meter,
+                state.restrict_writes_to_write_log(),
+                &mut vp_cache,
+                &mut tx_cache,
+            );
+            shell_params.block_accumulators = Some(&block_accumulators);
+            apply_wrapper_tx(
+                tx,
+                &wrapper,
+                None,
+                &[],
+                shell_params,
+                Some(&mut WrapperArgs::new(&block_proposer)),
+            )
+            .map(|(_, fee_token, _)| fee_token)
+        };
+
+        // up to the limit of distinct tokens
+        assert_eq!(apply(&nam, 1_000).unwrap(), nam);
+        assert_eq!(apply(&btc, 1_000).unwrap(), btc);
+        // beyond the limit
+        assert!(matches!(
+            apply(&eth, 1_000).unwrap_err(),
+            Error::FeeTokensPerBlockExceeded(2)
+        ));
+        // a token already paid in the block is still accepted
+        assert_eq!(apply(&nam, 2_000).unwrap(), nam);
+
+        assert_eq!(
+            block_accumulators.into_inner().fee_tokens,
+            BTreeSet::from([nam, btc])
+        );
+        // the rejected wrapper wasn't charged
+        assert_eq!(
+            namada_token::read_balance(&state, &eth, &fee_payer).unwrap(),
+            initial_balance
+        );
+    }
+
+    #[test]
+    /// Tests that a denominated amount is transferred in the raw units of the
+    /// denomination of the token
+    fn test_token_transfer_denominated() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let btc = address::testing::btc();
+        let src = address::testing::established_address_1();
+        let dest = address::testing::established_address_2();
+        credit(&mut state, &btc, &src, Amount::from(1_000_000_000));
+
+        // the token has no denomination yet
+        let amount = DenominatedAmount::new(15.into(), 1.into());
+        assert!(matches!(
+            token_transfer_denominated(
+                &mut state,
+                &btc,
+                &src,
+                &dest,
+                amount,
+                ProposerOverflowPolicy::Reject,
+            )
+            .unwrap_err(),
+            Error::FeeError(FeeValidationError::ConversionFailed(_))
+        ));
+
+        namada_token::write_denom(&mut state, &btc, 8.into()).unwrap();
+        let dest_balance = token_transfer_denominated(
+            &mut state,
+            &btc,
+            &src,
+            &dest,
+            amount,
+            ProposerOverflowPolicy::Reject,
+        )
+        .unwrap();
+        assert_eq!(dest_balance, Amount::from(150_000_000));
+        assert_eq!(
+            namada_token::read_balance(&state, &btc, &src).unwrap(),
+            Amount::from(850_000_000)
+        );
+
+        // more precise than the denomination of the token
+        assert!(matches!(
+            token_transfer_denominated(
+                &mut state,
+                &btc,
+                &src,
+                &dest,
+                DenominatedAmount::new(1.into(), 9.into()),
+                ProposerOverflowPolicy::Reject,
+            )
+            .unwrap_err(),
+            Error::FeeError(FeeValidationError::ConversionFailed(_))
+        ));
+    }
+
+    #[test]
+    /// Tests that the fees are charged to the delegate authorized by the
+    /// wrapper, falling back to its signer on an invalid authorization
+    fn test_fee_delegation() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let (mut vp_cache, mut tx_cache) = wasm_caches();
+        let nam = address::testing::nam();
+        let keypair = key::testing::keypair_1();
+        let delegate_keypair = key::testing::keypair_2();
+        let signer = Address::from(&keypair.ref_to());
+        let delegate = Address::from(&delegate_keypair.ref_to());
+        let block_proposer = address::testing::established_address_1();
+        for owner in [&signer, &delegate] {
+            credit(&mut state, &nam, owner, Amount::native_whole(1_000));
+        }
+        state.commit_tx();
+        state.commit_block().unwrap();
+
+        let gas_limit = 20_000_000;
+        let wrapper = signed_wrapper(&keypair, nam.clone(), 1, gas_limit);
+        let mut tx = Tx::from_type(TxType::Wrapper(Box::new(wrapper.clone())));
+        assert!(matches!(
+            fee_delegated_wrapper(&tx, &wrapper),
+            Cow::Borrowed(_)
+        ));
+
+        // an authorization whose signature doesn't match its key
+        let authorization = Authorization::new(
+            vec![tx.header_hash()],
+            [(0, delegate_keypair.clone())].into_iter().collect(),
+            None,
+        );
+        tx.add_section(Section::Authorization(Authorization {
+            signer: Signer::PubKeys(vec![key::testing::keypair_3().ref_to()]),
+            ..authorization
+        }));
+        // an authorization of the inner tx only
+        tx.add_section(Section::Authorization(Authorization::new(
+            vec![tx.raw_header_hash()],
+            [(0, delegate_keypair.clone())].into_iter().collect(),
+            None,
+        )));
+        assert_eq!(fee_delegated_wrapper(&tx, &wrapper).pk, keypair.ref_to());
+
+        tx.add_section(Section::Authorization(Authorization::new(
+            vec![tx.header_hash()],
+            [(0, delegate_keypair.clone())].into_iter().collect(),
+            None,
+        )));
+        assert_eq!(
+            fee_delegated_wrapper(&tx, &wrapper).pk,
+            delegate_keypair.ref_to()
+        );
+
+        let gas_meter = RefCell::new(TxGasMeter::new(u64::MAX));
+        let (changed_keys, _, _) = apply_wrapper_tx(
+            tx,
+            &wrapper,
+            None,
+            &[],
+            ShellParams::new(
+                &gas_meter,
+                state.restrict_writes_to_write_log(),
+                &mut vp_cache,
+                &mut tx_cache,
+            ),
+            Some(&mut WrapperArgs::new(&block_proposer)),
+        )
+        .unwrap();
+        let fees = Amount::from(gas_limit);
+        assert_eq!(
+            namada_token::read_balance(&state, &nam, &delegate).unwrap(),
+            Amount::native_whole(1_000).checked_sub(fees).unwrap()
+        );
+        assert_eq!(
+            namada_token::read_balance(&state, &nam, &signer).unwrap(),
+            Amount::native_whole(1_000)
+        );
+        assert!(changed_keys.contains(
+            &namada_token::storage_key::balance_key(&nam, &delegate)
+        ));
+        assert!(!changed_keys
+            .contains(&namada_token::storage_key::balance_key(&nam, &signer)));
+    }
+
+    #[test]
+    /// Tests that the fee transfer reports the resulting balance of the block
+    /// proposer, also when the proposer pays its own fees
+    fn test_transfer_fee_proposer_balance() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let nam = address::testing::nam();
+        let keypair = key::testing::keypair_1();
+        let fee_payer = Address::from(&keypair.ref_to());
+        let block_proposer = address::testing::established_address_1();
+        for owner in [&fee_payer, &block_proposer] {
+            credit(&mut state, &nam, owner, Amount::from(1_000_000));
+        }
+        state.commit_tx();
+
+        let wrapper = signed_wrapper(&keypair, nam.clone(), 100, 1_000);
+        let fee_transfer =
+            transfer_fee(&mut state, &block_proposer, &wrapper, &[], None)
+                .unwrap();
+        assert_eq!(
+            fee_transfer,
+            FeeTransfer {
+                token: nam.clone(),
+                fees: Amount::from(100_000),
+                proposer_token: nam.clone(),
+                proposer_fees: Amount::from(100_000),
+                proposer_balance: Amount::from(1_100_000),
+            }
+        );
+        assert_eq!(
+            namada_token::read_balance(&state, &nam, &block_proposer).unwrap(),
+            fee_transfer.proposer_balance
+        );
+
+        // the fees paid to itself leave the balance unchanged
+        let fee_transfer =
+            transfer_fee(&mut state, &fee_payer, &wrapper, &[], None).unwrap();
+        assert_eq!(fee_transfer.proposer_balance, Amount::from(900_000));
+        assert_eq!(
+            namada_token::read_balance(&state, &nam, &fee_payer).unwrap(),
+            fee_transfer.proposer_balance
+        );
+    }
+
+    #[test]
+    /// Tests the handling of the fees overflowing the balance of the block
+    /// proposer under each policy
+    fn test_proposer_overflow_policy() {
+        let nam = address::testing::nam();
+        let keypair = key::testing::keypair_1();
+        let fee_payer = Address::from(&keypair.ref_to());
+        let block_proposer = address::testing::established_address_1();
+        let wrapper = signed_wrapper(&keypair, nam.clone(), 100, 1_000);
+        // only 40_000 of the fees of 100_000 fit in the proposer balance
+        let proposer_balance =
+            Amount::max().checked_sub(Amount::from(40_000)).unwrap();
+        let supply = Amount::max();
+        let policy_key =
+            namada_parameters::storage::get_proposer_overflow_policy_key();
+
+        for policy in [
+            None,
+            Some(ProposerOverflowPolicy::Reject),
+            Some(ProposerOverflowPolicy::Burn),
+            Some(ProposerOverflowPolicy::Clamp),
+        ] {
+            let (mut state, _) = test_utils::setup_default_storage();
+            state
+                .write(
+                    &namada_token::storage_key::balance_key(&nam, &fee_payer),
+                    Amount::from(1_000_000),
+                )
+                .unwrap();
+            state
+                .write(
+                    &namada_token::storage_key::balance_key(
+                        &nam,
+                        &block_proposer,
+                    ),
+                    proposer_balance,
+                )
+                .unwrap();
+            state
+                .write(
+                    &namada_token::storage_key::minted_balance_key(&nam),
+                    supply,
+                )
+                .unwrap();
+            if let Some(policy) = policy {
+                state.write(&policy_key, policy).unwrap();
+            }
+            state.commit_tx();
+
+            let result =
+                transfer_fee(&mut state, &block_proposer, &wrapper, &[], None);
+            let read_balance = |owner| {
+                namada_token::read_balance(&state, &nam, owner).unwrap()
+            };
+            match policy.unwrap_or_default() {
+                ProposerOverflowPolicy::Reject => {
+                    assert!(matches!(
+                        result.unwrap_err(),
+                        Error::FeeError(
+                            FeeValidationError::ProposerCreditOverflow
+                        )
+                    ));
+                    assert_eq!(
+                        read_balance(&fee_payer),
+                        Amount::from(1_000_000)
+                    );
+                    assert_eq!(read_balance(&block_proposer), proposer_balance);
+                }
+                ProposerOverflowPolicy::Burn => {
+                    let result = result.unwrap();
+                    assert_eq!(result.proposer_balance, Amount::max());
+                    assert_eq!(read_balance(&fee_payer), Amount::from(900_000));
+                    assert_eq!(read_balance(&block_proposer), Amount::max());
+                    // the excess was burned
+                    assert_eq!(
+                        namada_token::read_total_supply(&state, &nam).unwrap(),
+                        supply.checked_sub(Amount::from(60_000)).unwrap()
+                    );
+                }
+                ProposerOverflowPolicy::Clamp => {
+                    let result = result.unwrap();
+                    assert_eq!(result.proposer_balance, Amount::max());
+                    assert_eq!(read_balance(&fee_payer), Amount::from(960_000));
+                    assert_eq!(read_balance(&block_proposer), Amount::max());
+                    assert_eq!(
+                        namada_token::read_total_supply(&state, &nam).unwrap(),
+                        supply
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    /// Tests that the fees are split between the block proposer, the treasury
+    /// and a burn without leaving any dust
+    fn test_fee_split() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let nam = address::testing::nam();
+        let keypair = key::testing::keypair_1();
+        let fee_payer = Address::from(&keypair.ref_to());
+        let block_proposer = address::testing::established_address_1();
+        let treasury = Address::Internal(InternalAddress::Pgf);
+        credit(&mut state, &nam, &fee_payer, Amount::from(1_000_000));
+        let supply = namada_token::read_total_supply(&state, &nam).unwrap();
+        let split_key = namada_parameters::storage::get_fee_split_key();
+        state
+            .write(
+                &split_key,
+                FeeSplit {
+                    treasury: treasury.clone(),
+                    treasury_share: Dec::new(3, 1).unwrap(),
+                    burn_share: Dec::new(15, 2).unwrap(),
+                },
+            )
+            .unwrap();
+        state.commit_tx();
+
+        // fees of 7_007 that can't be split evenly
+        let wrapper = signed_wrapper(&keypair, nam.clone(), 7, 1_001);
+        let fee_transfer =
+            transfer_fee(&mut state, &block_proposer, &wrapper, &[], None)
+                .unwrap();
+        let read_balance =
+            |owner| namada_token::read_balance(&state, &nam, owner).unwrap();
+        // the shares of the treasury and of the burn are rounded down
+        assert_eq!(read_balance(&treasury), Amount::from(2_102));
+        assert_eq!(
+            namada_token::read_total_supply(&state, &nam).unwrap(),
+            supply.checked_sub(Amount::from(1_051)).unwrap()
+        );
+        // and the block proposer receives the remainder
+        assert_eq!(fee_transfer.proposer_balance, Amount::from(3_854));
+        assert_eq!(read_balance(&block_proposer), Amount::from(3_854));
+        assert_eq!(read_balance(&fee_payer), Amount::from(992_993));
+
+        // shares exceeding the whole fees are rejected
+        state
+            .write(
+                &split_key,
+                FeeSplit {
+                    treasury,
+                    treasury_share: Dec::new(6, 1).unwrap(),
+                    burn_share: Dec::new(6, 1).unwrap(),
+                },
+            )
+            .unwrap();
+        state.commit_tx();
+        assert!(matches!(
+            transfer_fee(&mut state, &block_proposer, &wrapper, &[], None)
+                .unwrap_err(),
+            Error::FeeError(FeeValidationError::Other(_))
+        ));
+    }
+
+    /// Unwraps a mock wrapped native token one to one
+    #[derive(Debug)]
+    struct MockFeeUnwrap {
+        wrapped: Address,
+        native: Address,
+    }
+
+    impl FeeUnwrap for MockFeeUnwrap {
+        fn unwrap_fee(
+            &self,
+            token: &Address,
+            amount: Amount,
+        ) -> Option<(Address, Amount)> {
+            (token == &self.wrapped).then(|| (self.native.clone(), amount))
+        }
+    }
+
+    #[test]
+    /// Tests that the fees paid in a wrapped native token are credited to the
+    /// block proposer in the native token
+    fn test_transfer_fee_unwrap() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let nam = address::testing::nam();
+        let wnam = address::testing::established_address_2();
+        let keypair = key::testing::keypair_1();
+        let fee_payer = Address::from(&keypair.ref_to());
+        let block_proposer = address::testing::established_address_1();
+        namada_token::write_denom(&mut state, &wnam, 6.into()).unwrap();
+        credit(&mut state, &wnam, &fee_payer, Amount::from(1_000_000));
+        state.commit_tx();
+        let nam_supply = namada_token::read_total_supply(&state, &nam).unwrap();
+
+        let wrapper = signed_wrapper(&keypair, wnam.clone(), 100, 1_000);
+        let fee_unwrap = MockFeeUnwrap {
+            wrapped: wnam.clone(),
+            native: nam.clone(),
+        };
+        let fee_transfer = transfer_fee(
+            &mut state,
+            &block_proposer,
+            &wrapper,
+            &[],
+            Some(&fee_unwrap),
+        )
+        .unwrap();
+        assert_eq!(
+            fee_transfer,
+            FeeTransfer {
+                token: wnam.clone(),
+                fees: Amount::from(100_000),
+                proposer_token: nam.clone(),
+                proposer_fees: Amount::from(100_000),
+                proposer_balance: Amount::from(100_000),
+            }
+        );
+        let read_balance = |token, owner| {
+            namada_token::read_balance(&state, token, owner).unwrap()
+        };
+        // the proposer received the native token
+        assert_eq!(read_balance(&nam, &block_proposer), Amount::from(100_000));
+        assert_eq!(read_balance(&wnam, &block_proposer), Amount::zero());
+        // the wrapped fees were burned
+        assert_eq!(read_balance(&wnam, &fee_payer), Amount::from(900_000));
+        assert_eq!(
+            namada_token::read_total_supply(&state, &wnam).unwrap(),
+            Amount::from(900_000)
+        );
+        assert_eq!(
+            namada_token::read_total_supply(&state, &nam).unwrap(),
+            nam_supply.checked_add(Amount::from(100_000)).unwrap()
+        );
+    }
+
+    #[test]
+    /// Tests the refund of the fees of the unused gas to the fee payer
+    fn test_refund_unused_gas() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let nam = address::testing::nam();
+        let keypair = key::testing::keypair_1();
+        let fee_payer = Address::from(&keypair.ref_to());
+        let block_proposer = address::testing::established_address_1();
+        credit(&mut state, &nam, &fee_payer, Amount::from(1_000_000));
+        state.commit_tx();
+
+        let wrapper = signed_wrapper(&keypair, nam.clone(), 100, 1_000);
+        let FeeTransfer { fees, .. } =
+            transfer_fee(&mut state, &block_proposer, &wrapper, &[], None)
+                .unwrap();
+        assert_eq!(
+            namada_token::read_balance(&state, &nam, &block_proposer).unwrap(),
+            fees
+        );
+
+        // disabled by default
+        let gas_used = Gas::from_whole_units(400);
+        assert_eq!(
+            refund_unused_gas(
+                &mut state,
+                &block_proposer,
+                &wrapper,
+                &nam,
+                fees,
+                (&nam, fees),
+                gas_used
+            )
+            .unwrap(),
+            Amount::zero()
+        );
+
+        state
+            .write(
+                &namada_parameters::storage::get_refund_unused_gas_key(),
+                true,
+            )
+            .unwrap();
+        // the 600 gas units left unused are refunded
+        assert_eq!(
+            refund_unused_gas(
+                &mut state,
+                &block_proposer,
+                &wrapper,
+                &nam,
+                fees,
+                (&nam, fees),
+                gas_used
+            )
+            .unwrap(),
+            Amount::from(60_000)
+        );
+        assert_eq!(
+            namada_token::read_balance(&state, &nam, &block_proposer).unwrap(),
+            Amount::from(40_000)
+        );
+        assert_eq!(
+            namada_token::read_balance(&state, &nam, &fee_payer).unwrap(),
+            Amount::from(960_000)
+        );
+
+        // the refund never exceeds the proposer balance
+        assert_eq!(
+            refund_unused_gas(
+                &mut state,
+                &block_proposer,
+                &wrapper,
+                &nam,
+                fees,
+                (&nam, fees),
+                0.into()
+            )
+            .unwrap(),
+            Amount::from(40_000)
+        );
+        assert_eq!(
+            namada_token::read_balance(&state, &nam, &block_proposer).unwrap(),
+            Amount::zero()
+        );
+        assert_eq!(
+            namada_token::read_balance(&state, &nam, &fee_payer).unwrap(),
+            Amount::from(1_000_000)
+        );
+    }
+
+    #[test]
+    /// Tests that the refund of the unused gas is split like the fees and
+    /// deducted from the fees recorded for the block
+    fn test_refund_unused_gas_fee_split() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let nam = address::testing::nam();
+        let keypair = key::testing::keypair_1();
+        let fee_payer = Address::from(&keypair.ref_to());
+        let block_proposer = address::testing::established_address_1();
+        let treasury = Address::Internal(InternalAddress::Pgf);
+        credit(&mut state, &nam, &fee_payer, Amount::from(1_000_000));
+        let supply = namada_token::read_total_supply(&state, &nam).unwrap();
+        state
+            .write(
+                &namada_parameters::storage::get_fee_split_key(),
+                FeeSplit {
+                    treasury: treasury.clone(),
+                    treasury_share: Dec::new(3, 1).unwrap(),
+                    burn_share: Dec::new(15, 2).unwrap(),
+                },
+            )
+            .unwrap();
+        state
+            .write(
+                &namada_parameters::storage::get_refund_unused_gas_key(),
+                true,
+            )
+            .unwrap();
+        state.commit_tx();
+
+        let wrapper = signed_wrapper(&keypair, nam.clone(), 100, 1_000);
+        let FeeTransfer {
+            fees,
+            proposer_fees,
+            ..
+        } = transfer_fee(&mut state, &block_proposer, &wrapper, &[], None)
+            .unwrap();
+        assert_eq!(fees, Amount::from(100_000));
+
+        // the 600 gas units left unused are refunded
+        let refund = refund_unused_gas(
+            &mut state,
+            &block_proposer,
+            &wrapper,
+            &nam,
+            fees,
+            (&nam, proposer_fees),
+            Gas::from_whole_units(400),
+        )
+        .unwrap();
+        assert_eq!(refund, Amount::from(60_000));
+        let read_balance =
+            |owner| namada_token::read_balance(&state, &nam, owner).unwrap();
+        assert_eq!(read_balance(&fee_payer), Amount::from(960_000));
+        assert_eq!(read_balance(&treasury), Amount::from(12_000));
+        assert_eq!(read_balance(&block_proposer), Amount::from(22_000));
+        assert_eq!(
+            namada_token::read_total_supply(&state, &nam).unwrap(),
+            supply.checked_sub(Amount::from(6_000)).unwrap()
+        );
+        let block_fees: Amount = state
+            .read(&crate::token::storage_key::block_fees_key(
+                &nam,
+                &block_proposer,
+                state.in_mem().get_block_height().0,
+            ))
+            .unwrap()
+            .unwrap();
+        assert_eq!(block_fees, Amount::from(40_000));
+    }
+
+    #[test]
+    /// Tests that the share of the block proposer of the refund is taken back
+    /// in the native token it was credited when the fees were unwrapped
+    fn test_refund_unused_gas_unwrap() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let nam = address::testing::nam();
+        let wnam = address::testing::established_address_2();
+        let keypair = key::testing::keypair_1();
+        let fee_payer = Address::from(&keypair.ref_to());
+        let block_proposer = address::testing::established_address_1();
+        namada_token::write_denom(&mut state, &wnam, 6.into()).unwrap();
+        credit(&mut state, &wnam, &fee_payer, Amount::from(1_000_000));
+        state
+            .write(
+                &namada_parameters::storage::get_refund_unused_gas_key(),
+                true,
+            )
+            .unwrap();
+        state.commit_tx();
+        let nam_supply = namada_token::read_total_supply(&state, &nam).unwrap();
+
+        let wrapper = signed_wrapper(&keypair, wnam.clone(), 100, 1_000);
+        let fee_unwrap = MockFeeUnwrap {
+            wrapped: wnam.clone(),
+            native: nam.clone(),
+        };
+        let FeeTransfer {
+            fees,
+            proposer_token,
+            proposer_fees,
+            ..
+        } = transfer_fee(
+            &mut state,
+            &block_proposer,
+            &wrapper,
+            &[],
+            Some(&fee_unwrap),
+        )
+        .unwrap();
+
+        // the 600 gas units left unused are refunded
+        let refund = refund_unused_gas(
+            &mut state,
+            &block_proposer,
+            &wrapper,
+            &wnam,
+            fees,
+            (&proposer_token, proposer_fees),
+            Gas::from_whole_units(400),
+        )
+        .unwrap();
+        assert_eq!(refund, Amount::from(60_000));
+        let read_balance = |token, owner| {
+            namada_token::read_balance(&state, token, owner).unwrap()
+        };
+        assert_eq!(read_balance(&nam, &block_proposer), Amount::from(40_000));
+        assert_eq!(read_balance(&wnam, &block_proposer), Amount::zero());
+        assert_eq!(read_balance(&wnam, &fee_payer), Amount::from(960_000));
+        assert_eq!(
+            namada_token::read_total_supply(&state, &wnam).unwrap(),
+            Amount::from(960_000)
+        );
+        assert_eq!(
+            namada_token::read_total_supply(&state, &nam).unwrap(),
+            nam_supply.checked_add(Amount::from(40_000)).unwrap()
+        );
+    }
+
+    #[test]
+    /// Tests that the fees are paid in the first fallback fee token with
+    /// sufficient balance, at its own amount per gas unit, when the balance in
+    /// the fee token is insufficient
+    fn test_fee_token_fallbacks() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let nam = address::testing::nam();
+        let btc = address::testing::btc();
+        let apfel = address::testing::apfel();
+        let keypair = key::testing::keypair_1();
+        let fee_payer = Address::from(&keypair.ref_to());
+        let block_proposer = address::testing::established_address_1();
+        namada_token::write_denom(&mut state, &btc, 6.into()).unwrap();
+        let gas_cost_key = namada_parameters::storage::get_gas_cost_key();
+        state
+            .write(
+                &gas_cost_key,
+                BTreeMap::from([
+                    (nam.clone(), Amount::from(1)),
+                    (btc.clone(), Amount::from(1)),
+                ]),
+            )
+            .unwrap();
+        credit(&mut state, &nam, &fee_payer, Amount::from(1_000_000));
+        state.commit_tx();
+
+        let wrapper = signed_wrapper(&keypair, btc.clone(), 100, 1_000);
+        // without fallbacks the fee payer can't pay
+        assert!(check_fees(&state, &wrapper, &[]).is_err());
+
+        // the token missing from the gas cost table is skipped and the fees
+        // are charged at the gas price of the fallback, not of the wrapper
+        let fallback = |token: &Address, amount_per_gas_unit: u64| Fee {
+            amount_per_gas_unit: DenominatedAmount::native(
+                amount_per_gas_unit.into(),
+            ),
+            token: token.clone(),
+        };
+        let fee_token_fallbacks = [fallback(&apfel, 100), fallback(&nam, 50)];
+        assert_eq!(
+            check_fees(&state, &wrapper, &fee_token_fallbacks).unwrap(),
+            nam
+        );
+        let fee_transfer = transfer_fee(
+            &mut state,
+            &block_proposer,
+            &wrapper,
+            &fee_token_fallbacks,
+            None,
+        )
+        .unwrap();
+        assert_eq!(fee_transfer.token, nam);
+        assert_eq!(fee_transfer.fees, Amount::from(50_000));
+        assert_eq!(
+            namada_token::read_balance(&state, &nam, &block_proposer).unwrap(),
+            Amount::from(50_000)
+        );
+        assert_eq!(
+            namada_token::read_balance(&state, &nam, &fee_payer).unwrap(),
+            Amount::from(950_000)
+        );
+
+        // a fallback token whose minimum gas price is not met is skipped
+        state
+            .write(
+                &gas_cost_key,
+                BTreeMap::from([
+                    (nam.clone(), Amount::from(1_000)),
+                    (btc, Amount::from(1)),
+                ]),
+            )
+            .unwrap();
+        assert!(check_fees(&state, &wrapper, &fee_token_fallbacks).is_err());
+        // unless the fallback offers a gas price above it
+        assert_eq!(
+            check_fees(&state, &wrapper, &[fallback(&nam, 1_000)]).unwrap(),
+            nam
+        );
+    }
+
+    #[test]
+    /// Tests that the fee errors can be told apart without parsing their
+    /// messages
+    fn test_fee_validation_errors() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let nam = address::testing::nam();
+        let keypair = key::testing::keypair_1();
+        let fee_payer = Address::from(&keypair.ref_to());
+        credit(&mut state, &nam, &fee_payer, Amount::from(1_000));
+        state.commit_tx();
+
+        let mut wrapper = signed_wrapper(&keypair, nam, 100, 1_000);
+        let err = check_fees(&state, &wrapper, &[]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Error while processing transaction's fees: Insufficient \
+             transparent balance to pay fees"
+        );
+        assert!(matches!(
+            err,
+            Error::FeeError(FeeValidationError::InsufficientBalance {
+                required,
+                available,
+            }) if required == Amount::from(100_000)
+                && available == Amount::from(1_000)
+        ));
+
+        wrapper.fee.amount_per_gas_unit =
+            DenominatedAmount::native(Amount::max());
+        assert!(matches!(
+            check_fees(&state, &wrapper, &[]),
+            Err(Error::FeeError(FeeValidationError::Overflow(_)))
+        ));
+    }
+
+    #[test]
+    /// Tests that a fee payer restricted to some fee tokens can't pay the fees
+    /// in another token
+    fn test_fee_token_allowlist() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let nam = address::testing::nam();
+        let btc = address::testing::btc();
+        let keypair = key::testing::keypair_1();
+        let fee_payer = Address::from(&keypair.ref_to());
+        for token in [&nam, &btc] {
+            credit(&mut state, token, &fee_payer, Amount::from(1_000_000));
+        }
+        let allowlist_key =
+            namada_parameters::storage::get_fee_token_allowlist_key(&fee_payer);
+        state
+            .write(&allowlist_key, BTreeSet::from([nam.clone()]))
+            .unwrap();
+        state.commit_tx();
+        // the allowlist of the payer is a protocol parameter of its own
+        assert_eq!(
+            namada_parameters::storage::is_fee_token_allowlist_key(
+                &allowlist_key
+            ),
+            Some(&fee_payer)
+        );
+        assert!(namada_parameters::storage::is_protocol_parameter_key(
+            &allowlist_key
+        ));
+
+        let wrapper = |token: &Address| {
+            signed_wrapper(&keypair, token.clone(), 1, 1_000)
+        };
+        assert_eq!(check_fees(&state, &wrapper(&nam), &[]).unwrap(), nam);
+        assert!(matches!(
+            check_fees(&state, &wrapper(&btc), &[]).unwrap_err(),
+            Error::FeeError(FeeValidationError::TokenNotAllowedForPayer {
+                payer,
+                token,
+            }) if payer == fee_payer && token == btc
+        ));
+    }
+
+    #[test]
+    /// Tests that the postpaid fees of a wrapper are only charged if its inner
+    /// tx is accepted
+    fn test_postpaid_fees() {
+        let tx_write = TestWasms::TxWriteStorageKey.read_bytes();
+        let vp_always_true = TestWasms::VpAlwaysTrue.read_bytes();
+        let vp_always_false = TestWasms::VpAlwaysFalse.read_bytes();
+        let (mut state, keypair) = setup_batch_storage(&[
+            &tx_write,
+            &vp_always_true,
+            &vp_always_false,
+        ]);
+        let (mut vp_cache, mut tx_cache) = wasm_caches();
+        let nam = address::testing::nam();
+        let fee_payer = Address::from(&keypair.ref_to());
+        let block_proposer = address::testing::established_address_1();
+        let initial_balance = Amount::from(1_000_000);
+
+        let accepting = address::testing::established_address_2();
+        let rejecting = address::testing::established_address_3();
+        let mut wrappers = vec![];
+        for (owner, vp) in
+            [(&rejecting, &vp_always_false), (&accepting, &vp_always_true)]
+        {
+            state
+                .write_log_mut()
+                .write(
+                    &Key::validity_predicate(owner),
+                    Hash::sha256(vp).serialize_to_vec(),
+                )
+                .unwrap();
+            let key = Key::from(owner.to_db_key())
+                .push(&"test".to_string())
+                .unwrap();
+            let mut tx = batch_wrapper(&keypair);
+            tx.set_code(namada_tx::Code::new(tx_write.clone(), None));
+            tx.set_data(namada_tx::Data::new(
+                TxWriteData {
+                    key,
+                    value: "test".as_bytes().to_vec(),
+                }
+                .serialize_to_vec(),
+            ));
+            wrappers.push(tx);
+        }
+        state.commit_tx();
+        state.commit_block().unwrap();
+
+        let dispatch_args = DispatchArgs {
+            fee_policy: FeePolicy::Postpaid,
+            ..Default::default()
+        };
+        let mut balances = vec![];
+        for tx in wrappers {
+            let gas_meter = RefCell::new(TxGasMeter::new(u64::MAX));
+            let mut wrapper_args = WrapperArgs::new(&block_proposer);
+            let result = dispatch_tx(
+                tx,
+                &[],
+                TxIndex::default(),
+                &gas_meter,
+                state.restrict_writes_to_write_log(),
+                &mut vp_cache,
+                &mut tx_cache,
+                None,
+                &dispatch_args,
+                Some(&mut wrapper_args),
+            )
+            .unwrap();
+            assert_eq!(result.proposer_balance.is_some(), result.is_accepted());
+            assert_eq!(wrapper_args.fee_token.is_some(), result.is_accepted());
+            if result.is_accepted() {
+                state.commit_tx();
+            } else {
+                state.drop_tx();
+            }
+            balances.push(
+                namada_token::read_balance(&state, &nam, &fee_payer).unwrap(),
+            );
+        }
+
+        let fees = Amount::from(1_000);
+        assert_eq!(
+            balances,
+            vec![initial_balance, initial_balance.checked_sub(fees).unwrap()]
+        );
+    }
+
+    #[test]
+    /// Tests that the gas deposit is refunded to the fee payer if the inner tx
+    /// is accepted and forfeited to the treasury otherwise
+    fn test_gas_deposit() {
+        let tx_write = TestWasms::TxWriteStorageKey.read_bytes();
+        let vp_always_true = TestWasms::VpAlwaysTrue.read_bytes();
+        let vp_always_false = TestWasms::VpAlwaysFalse.read_bytes();
+        let (mut state, keypair) = setup_batch_storage(&[
+            &tx_write,
+            &vp_always_true,
+            &vp_always_false,
+        ]);
+        let (mut vp_cache, mut tx_cache) = wasm_caches();
+        let nam = address::testing::nam();
+        let fee_payer = Address::from(&keypair.ref_to());
+        let treasury = Address::Internal(InternalAddress::Pgf);
+        let block_proposer = address::testing::established_address_1();
+        let deposit = Amount::from(500);
+        state
+            .write(
+                &namada_parameters::storage::get_gas_deposit_key(),
+                BTreeMap::from([(nam.clone(), deposit)]),
+            )
+            .unwrap();
+
+        let accepting = address::testing::established_address_2();
+        let rejecting = address::testing::established_address_3();
+        let mut wrappers = vec![];
+        for (owner, vp) in
+            [(&accepting, &vp_always_true), (&rejecting, &vp_always_false)]
+        {
+            state
+                .write_log_mut()
+                .write(
+                    &Key::validity_predicate(owner),
+                    Hash::sha256(vp).serialize_to_vec(),
+                )
+                .unwrap();
+            let key = Key::from(owner.to_db_key())
+                .push(&"test".to_string())
+                .unwrap();
+            let mut tx = batch_wrapper(&keypair);
+            tx.set_code(namada_tx::Code::new(tx_write.clone(), None));
+            tx.set_data(namada_tx::Data::new(
+                TxWriteData {
+                    key,
+                    value: "test".as_bytes().to_vec(),
+                }
+                .serialize_to_vec(),
+            ));
+            wrappers.push(tx);
+        }
+        state.commit_tx();
+        state.commit_block().unwrap();
+
+        let balance = |state: &TestState, owner: &Address| {
+            namada_token::read_balance(state, &nam, owner).unwrap()
+        };
+        let fees = Amount::from(1_000);
+        let mut payer_balance = balance(&state, &fee_payer);
+        let mut treasury_balance = balance(&state, &treasury);
+        for tx in wrappers {
+            let gas_meter = RefCell::new(TxGasMeter::new(u64::MAX));
+            let mut wrapper_args = WrapperArgs::new(&block_proposer);
+            let result = dispatch_tx(
+                tx,
+                &[],
+                TxIndex::default(),
+                &gas_meter,
+                state.restrict_writes_to_write_log(),
+                &mut vp_cache,
+                &mut tx_cache,
+                None,
+                &DispatchArgs::default(),
+                Some(&mut wrapper_args),
+            )
+            .unwrap();
+            assert_eq!(wrapper_args.gas_deposit, Some(deposit));
+            payer_balance = payer_balance.checked_sub(fees).unwrap();
+            if result.is_accepted() {
+                state.commit_tx();
+            } else {
+                // the deposit is forfeited
+                state.drop_tx();
+                payer_balance = payer_balance.checked_sub(deposit).unwrap();
+                treasury_balance =
+                    treasury_balance.checked_add(deposit).unwrap();
+            }
+            assert_eq!(balance(&state, &fee_payer), payer_balance);
+            assert_eq!(balance(&state, &treasury), treasury_balance);
+        }
+    }
+
+    #[test]
+    /// Tests that a fee payer that can pay the fees but not the gas deposit
+    /// is still charged the fees
+    fn test_gas_deposit_insufficient_balance() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let (mut vp_cache, mut tx_cache) = wasm_caches();
+        let nam = address::testing::nam();
+        let keypair = key::testing::keypair_1();
+        let fee_payer = Address::from(&keypair.ref_to());
+        let block_proposer = address::testing::established_address_1();
+        let fees = Amount::from(1_000);
+        credit(&mut state, &nam, &fee_payer, fees);
+        state
+            .write(
+                &namada_parameters::storage::get_gas_deposit_key(),
+                BTreeMap::from([(nam.clone(), Amount::from(500))]),
+            )
+            .unwrap();
+        state.commit_tx();
+
+        let wrapper = signed_wrapper(&keypair, nam.clone(), 1, 1_000);
+        let tx = Tx::from_type(TxType::Wrapper(Box::new(wrapper.clone())));
+        let gas_meter = RefCell::new(TxGasMeter::new(u64::MAX));
+        let mut wrapper_args = WrapperArgs::new(&block_proposer);
+        let result = apply_wrapper_tx(
+            tx,
+            &wrapper,
+            None,
+            &[],
+            ShellParams::new(
+                &gas_meter,
+                state.restrict_writes_to_write_log(),
+                &mut vp_cache,
+                &mut tx_cache,
+            ),
+            Some(&mut wrapper_args),
+        );
+        assert!(result.is_err());
+        assert_eq!(wrapper_args.gas_deposit, None);
+        // the fees were committed nonetheless
+        state.drop_tx();
+        assert_eq!(
+            namada_token::read_balance(&state, &nam, &fee_payer).unwrap(),
+            Amount::zero()
+        );
+        assert_eq!(
+            namada_token::read_balance(&state, &nam, &block_proposer).unwrap(),
+            fees
+        );
+    }
+
+    #[test]
+    /// Tests that wrappers declaring a fee above the ceiling set for the fee
+    /// token are rejected
+    fn test_max_fee_amount() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let nam = address::testing::nam();
+        let keypair = key::testing::keypair_1();
+        let fee_payer = Address::from(&keypair.ref_to());
+        credit(&mut state, &nam, &fee_payer, Amount::native_whole(1_000));
+        state
+            .write(
+                &namada_parameters::storage::get_max_fee_amount_key(),
+                BTreeMap::from([(nam.clone(), Amount::from(100_000))]),
+            )
+            .unwrap();
+
+        let wrapper = |amount_per_gas_unit: u64| {
+            WrapperTx::new(
+                Fee {
+                    amount_per_gas_unit: DenominatedAmount::native(
+                        amount_per_gas_unit.into(),
+                    ),
+                    token: nam.clone(),
+                },
+                keypair.ref_to(),
+                Epoch(0),
+                GasLimit::from(1_000),
+                None,
+            )
+        };
+
+        // a fee under the ceiling is accepted
+        check_fees(&state, &wrapper(100), &[]).unwrap();
+        // a fee over the ceiling is rejected
+        assert!(matches!(
+            check_fees(&state, &wrapper(101), &[]).unwrap_err(),
+            Error::FeeTooHigh(fee, max)
+                if fee == Amount::from(101_000) && max == Amount::from(100_000)
+        ));
+    }
+
+    #[test]
+    /// Tests that a gas price denominated differently from the fee token is
+    /// rejected
+    fn test_fee_token_mismatch() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let btc = address::testing::btc();
+        let keypair = key::testing::keypair_1();
+        namada_token::write_denom(&mut state, &btc, 8.into()).unwrap();
+        credit(
+            &mut state,
+            &btc,
+            &Address::from(&keypair.ref_to()),
+            Amount::from(1_000_000),
+        );
+        state.commit_tx();
+
+        let wrapper = |amount_per_gas_unit| {
+            WrapperTx::new(
+                Fee {
+                    amount_per_gas_unit,
+                    token: btc.clone(),
+                },
+                keypair.ref_to(),
+                Epoch(0),
+                GasLimit::from(1_000),
+                None,
+            )
+        };
+        // a gas price in the native denomination implies another token
+        assert!(matches!(
+            check_fees(
+                &state,
+                &wrapper(DenominatedAmount::native(1.into())),
+                &[]
+            )
+            .unwrap_err(),
+            Error::FeeTokenMismatch {
+                token,
+                gas_price_denom: Denomination(6),
+                token_denom: Denomination(8),
+            } if token == btc
+        ));
+        // the gas price denominated like the fee token is accepted
+        assert_eq!(
+            check_fees(
+                &state,
+                &wrapper(DenominatedAmount::new(1.into(), 8.into())),
+                &[]
+            )
+            .unwrap(),
+            btc
+        );
+    }
+
+    #[test]
+    /// Tests that the paths checking and charging the fees of a wrapper tx
+    /// reach consistent decisions
+    fn test_fee_check_and_charge_consistency() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let nam = address::testing::nam();
+        let keypair = key::testing::keypair_1();
+        let fee_payer = Address::from(&keypair.ref_to());
+        let block_proposer = address::testing::established_address_1();
+        credit(&mut state, &nam, &fee_payer, Amount::from(150_000));
+        state
+            .write(
+                &namada_parameters::storage::get_max_fee_amount_key(),
+                BTreeMap::from([(nam.clone(), Amount::from(200_000))]),
+            )
+            .unwrap();
+
+        let wrapper = |amount_per_gas_unit: u64| {
+            WrapperTx::new(
+                Fee {
+                    amount_per_gas_unit: DenominatedAmount::native(
+                        amount_per_gas_unit.into(),
+                    ),
+                    token: nam.clone(),
+                },
+                keypair.ref_to(),
+                Epoch(0),
+                GasLimit::from(1_000),
+                None,
+            )
+        };
+
+        // payable, too high for the balance and too high for the ceiling
+        for (amount_per_gas_unit, expected) in [
+            (100, Ok(FeeDecision::Pay(Amount::from(100_000)))),
+            (
+                180,
+                Ok(FeeDecision::InsufficientBalance {
+                    fees: Amount::from(180_000),
+                    balance: Amount::from(150_000),
+                }),
+            ),
+            (300, Err(())),
+        ] {
+            let wrapper = wrapper(amount_per_gas_unit);
+            let decision = evaluate_fee(&state, &wrapper).map_err(|_| ());
+            assert_eq!(decision, expected);
+
+            let checked = check_fees(&state, &wrapper, &[]).is_ok();
+            let charged =
+                transfer_fee(&mut state, &block_proposer, &wrapper, &[], None)
+                    .is_ok();
+            state.drop_tx();
+            assert_eq!(checked, charged);
+            assert_eq!(checked, matches!(decision, Ok(FeeDecision::Pay(_))));
+        }
+    }
+
+    #[test]
+    /// Tests that fees short of the required amount by less than the tolerance
+    /// set for the fee token are treated as paid
+    fn test_fee_tolerance() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let nam = address::testing::nam();
+        let keypair = key::testing::keypair_1();
+        let fee_payer = Address::from(&keypair.ref_to());
+        let block_proposer = address::testing::established_address_1();
+        credit(&mut state, &nam, &fee_payer, Amount::from(150_000));
+        state
+            .write(
+                &namada_parameters::storage::get_fee_tolerance_key(),
+                BTreeMap::from([(nam.clone(), Amount::from(2))]),
+            )
+            .unwrap();
+
+        let wrapper = |fees: u64| {
+            signed_wrapper(&keypair, nam.clone(), fees, 1)
+        };
+
+        // one unit short, within the tolerance
+        let wrapper_one_short = wrapper(150_001);
+        assert_eq!(
+            evaluate_fee(&state, &wrapper_one_short).unwrap(),
+            FeeDecision::Pay(Amount::from(150_000))
+        );
+        assert_eq!(check_fees(&state, &wrapper_one_short, &[]).unwrap(), nam);
+
+        // two units short, beyond the tolerance
+        let wrapper_two_short = wrapper(150_002);
+        assert_eq!(
+            evaluate_fee(&state, &wrapper_two_short).unwrap(),
+            FeeDecision::InsufficientBalance {
+                fees: Amount::from(150_002),
+                balance: Amount::from(150_000),
+            }
+        );
+        assert!(matches!(
+            check_fees(&state, &wrapper_two_short, &[]).unwrap_err(),
+            Error::FeeError(FeeValidationError::InsufficientBalance { .. })
+        ));
+
+        // the whole balance pays the fees within the tolerance
+        transfer_fee(
+            &mut state,
+            &block_proposer,
+            &wrapper_one_short,
+            &[],
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            namada_token::read_balance(&state, &nam, &fee_payer).unwrap(),
+            Amount::zero()
+        );
+    }
+
+    #[test]
+    /// Tests that a fee anomaly marker with the shortfall is written for the
+    /// payer when the insufficient balance fallback of the fee payment
+    /// triggers
+    fn test_fee_anomaly_marker() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let nam = address::testing::nam();
+        let keypair = key::testing::keypair_1();
+        let fee_payer = Address::from(&keypair.ref_to());
+        let block_proposer = address::testing::established_address_1();
+        credit(&mut state, &nam, &fee_payer, Amount::from(60_000));
+        state.commit_block().unwrap();
+
+        let wrapper = signed_wrapper(&keypair, nam.clone(), 100, 1_000);
+        assert!(
+            transfer_fee(&mut state, &block_proposer, &wrapper, &[], None)
+                .is_err()
+        );
+        // the marker survives the failure of the wrapper tx
+        state.drop_tx();
+
+        let anomaly_key =
+            namada_token::storage_key::fee_anomaly_key(&nam, &fee_payer);
+        let shortfall: Option<Amount> = state.read(&anomaly_key).unwrap();
+        assert_eq!(shortfall, Some(Amount::from(40_000)));
+        // no marker for the block proposer
+        let proposer_key =
+            namada_token::storage_key::fee_anomaly_key(&nam, &block_proposer);
+        assert!(!state.has_key(&proposer_key).unwrap());
+    }
+}
@@ -1,43 +1,58 @@
 //! The ledger's protocol
+
+mod fees;
+mod protocol_txs;
+mod vps;
+
 use std::cell::RefCell;
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::Debug;
-
-use borsh_ext::BorshSerializeExt;
-use eyre::{eyre, WrapErr};
+use std::sync::mpsc::Sender;
+
+use borsh::BorshDeserialize;
+use eyre::eyre;
+pub use fees::{
+    check_fees, check_fees_with_unshielding, estimate_block_fees, evaluate_fee,
+    fee_delegated_wrapper, get_fee_unshielding_transaction, refund_unused_gas,
+    run_fee_unshielding, token_transfer_denominated, transfer_fee,
+    BlockFeeEstimate, FeeDecision, FeeTransfer, FeeValidationError,
+};
+use fees::{charge_fee, charge_postpaid_fee, refund_gas_deposit};
 use masp_primitives::transaction::Transaction;
-use namada_core::booleans::BoolResultUnitExt;
+use namada_core::collections::HashMap;
 use namada_core::hash::Hash;
 use namada_core::storage::Key;
-use namada_gas::TxGasMeter;
-use namada_sdk::tx::TX_TRANSFER_WASM;
-use namada_state::StorageWrite;
-use namada_tx::data::protocol::ProtocolTxType;
+use namada_gas::{Gas, TxGasMeter, WRAPPER_TX_GAS_PER_BYTE};
+use namada_sdk::tx::{TX_TRANSFER_WASM, TX_UPDATE_STEWARD_COMMISSION};
+use namada_tx::data::pgf::UpdateStewardCommission;
 use namada_tx::data::{
-    GasLimit, TxResult, TxType, VpStatusFlags, VpsResult, WrapperTx,
+    TxResult, TxType, VpErrorClass, VpRejection, VpRejectionReason,
+    VpStatusFlags, WrapperTx,
 };
 use namada_tx::{Section, Tx};
 use namada_vote_ext::EthereumTxData;
-use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use protocol_txs::apply_protocol_tx;
+pub use protocol_txs::{
+    simulate_protocol_tx, validate_eth_hot_key_rotation, ProtocolTxHandler,
+    ProtocolTxHandlers,
+};
 use thiserror::Error;
+pub use vps::{execute_vps_streaming, VpObserver};
+use vps::{check_vps, CheckVps};
 
 use crate::address::{Address, InternalAddress};
-use crate::ledger::gas::{GasMetering, VpGasMeter};
-use crate::ledger::governance::GovernanceVp;
-use crate::ledger::native_vp::ethereum_bridge::bridge_pool_vp::BridgePoolVp;
-use crate::ledger::native_vp::ethereum_bridge::nut::NonUsableTokens;
-use crate::ledger::native_vp::ethereum_bridge::vp::EthBridge;
-use crate::ledger::native_vp::ibc::Ibc;
-use crate::ledger::native_vp::masp::MaspVp;
-use crate::ledger::native_vp::multitoken::MultitokenVp;
-use crate::ledger::native_vp::parameters::{self, ParametersVp};
-use crate::ledger::native_vp::{self, NativeVp};
-use crate::ledger::pgf::PgfVp;
-use crate::ledger::pos::{self, PosVP};
-use crate::state::{DBIter, State, StorageHasher, StorageRead, WlState, DB};
+use crate::ledger::events::{EmitEvents, Event, EventLevel, EventType};
+use crate::ledger::gas::GasMetering;
+use crate::ledger::native_vp;
+use crate::ledger::native_vp::parameters;
+use crate::ledger::pos;
+use crate::replay_protection::TxAudit;
+use crate::state::{
+    DBIter, ReplayOrigin, State, StorageHasher, StorageRead, WlState, DB,
+};
 use crate::storage;
 use crate::storage::TxIndex;
-use crate::token::Amount;
+use crate::token::{Amount, Denomination};
 use crate::vm::wasm::{TxCache, VpCache};
 use crate::vm::{self, wasm, WasmCacheAccess};
 
@@ -63,13 +78,11 @@ pub enum Error {
     #[error("Gas error: {0}")]
     GasError(String),
     #[error("Error while processing transaction's fees: {0}")]
-    FeeError(String),
+    FeeError(#[from] FeeValidationError),
     #[error("Invalid transaction section signature: {0}")]
     InvalidSectionSignature(String),
-    #[error(
-        "The decrypted transaction {0} has already been applied in this block"
-    )]
-    ReplayAttempt(Hash),
+    #[error("The transaction {0} has already been applied in {1}")]
+    ReplayAttempt(Hash, ReplayOrigin),
     #[error("Error executing VP for addresses: {0:?}")]
     VpRunnerError(vm::wasm::run::Error),
     #[error("The address {0} doesn't exist")]
@@ -80,6 +93,8 @@ pub enum Error {
     PosNativeVpError(pos::vp::Error),
     #[error("PoS native VP panicked")]
     PosNativeVpRuntime,
+    #[error("The native VP of {addr} panicked")]
+    VpPanic { addr: Address },
     #[error("Parameters native VP: {0}")]
     ParametersNativeVpError(parameters::Error),
     #[error("IBC Token native VP: {0}")]
@@ -98,16 +113,185 @@ pub enum Error {
     MaspNativeVpError(native_vp::masp::Error),
     #[error("Access to an internal address {0:?} is forbidden")]
     AccessForbidden(InternalAddress),
+    #[error(
+        "Access to an internal address {0:?} exceeds its limit in the block"
+    )]
+    AccessRateLimited(InternalAddress),
+    #[error(
+        "The transaction would exceed the limit of {0} accounts initialized \
+         per block"
+    )]
+    AccountsPerBlockExceeded(u64),
+    #[error(
+        "The fee token would exceed the limit of {0} distinct fee tokens per \
+         block"
+    )]
+    FeeTokensPerBlockExceeded(u64),
+    #[error("The limit of {0} fee unshieldings per block was reached")]
+    FeeUnshieldsPerBlockExceeded(u64),
+    #[error(
+        "The wrapper gas limit of {0} exceeds the remaining block gas of {1}"
+    )]
+    ExceedsBlockGas(u64, u64),
+    #[error(
+        "The wrapper gas limit of {0} is too low to cover the gas of the \
+         wrapper itself"
+    )]
+    GasLimitTooLow(u64),
+    #[error(
+        "The wrapper expiry height {0} is below the current block height {1}"
+    )]
+    ExpiryHeightInPast(storage::BlockHeight, storage::BlockHeight),
+    #[error("Transaction rejected by a pre-dispatch hook: {0}")]
+    PreHookRejected(String),
+    #[error("The transaction violated a state invariant: {0}")]
+    InvariantViolated(String),
+    #[error("The declared fee of {0} exceeds the maximum of {1} for the token")]
+    FeeTooHigh(Amount, Amount),
+    #[error("The wrapper fee of {0} exceeds the maximum fee of {1} accepted")]
+    WrapperFeeAboveMax(Amount, Amount),
+    #[error(
+        "The gas price with denomination {} doesn't match the fee token \
+         {token} with denomination {}",
+        .gas_price_denom.0,
+        .token_denom.0
+    )]
+    FeeTokenMismatch {
+        token: Address,
+        gas_price_denom: Denomination,
+        token_denom: Denomination,
+    },
+    #[error("Protocol tx modified the key {0} outside of its storage scope")]
+    ProtocolTxOutOfScope(Key),
+    #[error(
+        "The transaction changed storage keys without triggering any verifier"
+    )]
+    NoVerifiers,
+    #[error(
+        "The transaction triggers {count} verifiers, more than the maximum \
+         of {max}"
+    )]
+    TooManyVerifiers {
+        count: u64,
+        max: u64,
+    },
+    #[error("The VP of {0} exceeded the gas ceiling of {1} per VP")]
+    VpGasCeilingExceeded(Address, u64),
+    #[error("The tx carries the section {0} that nothing references")]
+    UnknownSection(Hash),
+    #[error("Gas error: {msg}, VPs not evaluated: {skipped_vps:?}")]
+    VpsGasError {
+        msg: String,
+        skipped_vps: BTreeSet<Address>,
+    },
+    #[error("Protocol tx {0} is not implemented")]
+    UnimplementedProtocolTx(String),
+    #[error(
+        "Protocol tx {tx} is signed by {signers} validators, below the \
+         minimum of {min_signers}"
+    )]
+    InsufficientProtocolTxSigners {
+        tx: String,
+        signers: u64,
+        min_signers: u64,
+    },
+    #[error("Invalid data for tx {0}: {1}")]
+    InvalidTxData(String, String),
+    #[error("Protocol tx {0} carries no data")]
+    MissingProtocolTxData(String),
+    #[error("Invalid data for protocol tx {0}: {1}")]
+    InvalidProtocolTxData(String, String),
+    #[error("Invalid batch: {0}")]
+    InvalidBatch(String),
+    #[error("The hash of the transfer wasm code is missing from storage")]
+    MissingTransferHash,
+    #[error("Inner tx {index} ({hash}) of the batch failed: {error}")]
+    BatchTxError {
+        index: usize,
+        hash: Hash,
+        error: Box<Error>,
+    },
+    #[error(
+        "The fee unshielding tx runs the code {found:?} instead of the \
+         transfer code {expected}"
+    )]
+    FeeUnshieldingCodeMismatch {
+        expected: Hash,
+        found: Option<Hash>,
+    },
 }
 
 impl Error {
-    /// Determine if the error originates from an invalid transaction
-    /// section signature. This is required for replay protection.
-    const fn invalid_section_signature_flag(&self) -> VpStatusFlags {
-        if matches!(self, Self::InvalidSectionSignature(_)) {
-            VpStatusFlags::INVALID_SIGNATURE
-        } else {
-            VpStatusFlags::empty()
+    /// Determine the status flags of a VP rejecting the tx with this error,
+    /// e.g. if the error originates from an invalid transaction section
+    /// signature, which is required for replay protection.
+    const fn vp_status_flags(&self) -> VpStatusFlags {
+        match self {
+            Self::InvalidSectionSignature(_) => {
+                VpStatusFlags::INVALID_SIGNATURE
+            }
+            Self::VpPanic { .. } => VpStatusFlags::VP_PANIC,
+            _ => VpStatusFlags::empty(),
+        }
+    }
+
+    /// The class of a VP error for clients to filter by
+    const fn vp_error_class(&self) -> VpErrorClass {
+        match self {
+            Self::InvalidSectionSignature(_) => VpErrorClass::Signature,
+            Self::GasError(_)
+            | Self::VpGasCeilingExceeded(..)
+            | Self::VpsGasError { .. } => VpErrorClass::Gas,
+            Self::AccessForbidden(_) | Self::AccessRateLimited(_) => {
+                VpErrorClass::Access
+            }
+            Self::VpRunnerError(wasm::run::Error::VpError(_))
+            | Self::MissingAddress(_)
+            | Self::IbcNativeVpError(_)
+            | Self::PosNativeVpError(_)
+            | Self::ParametersNativeVpError(_)
+            | Self::MultitokenNativeVpError(_)
+            | Self::GovernanceNativeVpError(_)
+            | Self::PgfNativeVpError(_)
+            | Self::EthBridgeNativeVpError(_)
+            | Self::BridgePoolNativeVpError(_)
+            | Self::NutNativeVpError(_)
+            | Self::MaspNativeVpError(_) => VpErrorClass::Logic,
+            _ => VpErrorClass::Runtime,
+        }
+    }
+
+    /// The reason of the rejection of a tx by a VP with this error
+    fn vp_rejection_reason(&self) -> VpRejectionReason {
+        use VpRejectionReason as Reason;
+
+        let msg = self.to_string();
+        match self {
+            Self::PosNativeVpError(_) => Reason::Pos(msg),
+            Self::IbcNativeVpError(_) => Reason::Ibc(msg),
+            Self::ParametersNativeVpError(_) => Reason::Parameters(msg),
+            Self::GovernanceNativeVpError(_) => Reason::Governance(msg),
+            Self::MultitokenNativeVpError(_) => Reason::Multitoken(msg),
+            Self::EthBridgeNativeVpError(_) => Reason::EthBridge(msg),
+            Self::BridgePoolNativeVpError(_) => Reason::BridgePool(msg),
+            Self::PgfNativeVpError(_) => Reason::Pgf(msg),
+            Self::NutNativeVpError(_) => Reason::Nut(msg),
+            Self::MaspNativeVpError(_) => Reason::Masp(msg),
+            Self::AccessForbidden(_) => Reason::AccessForbidden(msg),
+            Self::VpGasCeilingExceeded(..) => Reason::GasCeilingExceeded(msg),
+            Self::PosNativeVpRuntime | Self::VpPanic { .. } => {
+                Reason::Panicked(msg)
+            }
+            _ => Reason::Other(msg),
+        }
+    }
+
+    /// The rejection of a tx by the VP of the given address with this error
+    fn vp_rejection(&self, addr: &Address) -> VpRejection {
+        VpRejection {
+            addr: addr.clone(),
+            class: self.vp_error_class(),
+            reason: self.vp_rejection_reason(),
         }
     }
 }
@@ -126,6 +310,15 @@ where
     pub state: &'a mut S,
     pub vp_wasm_cache: &'a mut VpCache<CA>,
     pub tx_wasm_cache: &'a mut TxCache<CA>,
+    pub block_accumulators: Option<&'a RefCell<BlockAccumulators>>,
+    pub shielded_policy: Option<&'a dyn ShieldedPolicy>,
+    pub event_sink: Option<&'a RefCell<Vec<Event>>>,
+    pub reduce_chunk_size: Option<usize>,
+    pub transfer_hash: Option<Hash>,
+    pub vp_observer: Option<VpObserver<'a>>,
+    pub fee_unwrap: Option<&'a dyn FeeUnwrap>,
+    pub wrapper_gas_per_byte: Option<u64>,
+    pub fee_policy: FeePolicy,
 }
 
 impl<'a, S, D, H, CA> ShellParams<'a, S, D, H, CA>
@@ -147,7 +340,139 @@ where
             state,
             vp_wasm_cache,
             tx_wasm_cache,
+            block_accumulators: None,
+            shielded_policy: None,
+            event_sink: None,
+            reduce_chunk_size: None,
+            transfer_hash: None,
+            vp_observer: None,
+            fee_unwrap: None,
+            wrapper_gas_per_byte: None,
+            fee_policy: FeePolicy::default(),
+        }
+    }
+
+    /// Set the block accumulators and the optional extensions of the
+    /// dispatch of a tx
+    fn with_dispatch_args(
+        self,
+        block_accumulators: Option<&'a RefCell<BlockAccumulators>>,
+        dispatch_args: &DispatchArgs<'a, D, H>,
+    ) -> Self {
+        Self {
+            block_accumulators,
+            shielded_policy: dispatch_args.shielded_policy,
+            event_sink: dispatch_args.event_sink,
+            reduce_chunk_size: dispatch_args.reduce_chunk_size,
+            transfer_hash: dispatch_args.transfer_hash,
+            vp_observer: dispatch_args.vp_observer,
+            fee_unwrap: dispatch_args.fee_unwrap,
+            wrapper_gas_per_byte: dispatch_args.wrapper_gas_per_byte,
+            fee_policy: dispatch_args.fee_policy,
+            ..self
+        }
+    }
+}
+
+/// Accumulators of the resources consumed by the transactions applied so far
+/// in the current block
+#[derive(Debug, Default, Clone)]
+pub struct BlockAccumulators {
+    /// Number of accounts initialized by the accepted transactions
+    pub initialized_accounts: u64,
+    /// Number of fee unshieldings executed
+    pub fee_unshields: u64,
+    /// Number of txs that triggered the VP of each internal address with a
+    /// per block limit
+    pub internal_accesses: BTreeMap<InternalAddress, u64>,
+    /// Distinct tokens the fees were paid in
+    pub fee_tokens: BTreeSet<Address>,
+}
+
+impl BlockAccumulators {
+    /// Check that `new_accounts` more initialized accounts fit in the optional
+    /// per block limit
+    pub fn check_initialized_accounts(
+        &self,
+        new_accounts: usize,
+        max_accounts_per_block: Option<u64>,
+    ) -> Result<()> {
+        let Some(max) = max_accounts_per_block else {
+            return Ok(());
+        };
+        match self.initialized_accounts.checked_add(new_accounts as u64) {
+            Some(total) if total <= max => Ok(()),
+            _ => Err(Error::AccountsPerBlockExceeded(max)),
+        }
+    }
+
+    /// Check that one more fee unshielding fits in the optional per block
+    /// limit
+    pub fn check_fee_unshields(
+        &self,
+        max_fee_unshields_per_block: Option<u64>,
+    ) -> Result<()> {
+        match max_fee_unshields_per_block {
+            Some(max) if self.fee_unshields >= max => {
+                Err(Error::FeeUnshieldsPerBlockExceeded(max))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Count an access to each of the internal addresses among the
+    /// `verifiers` that have a limit in `limits`. Fails if any limit would be
+    /// exceeded, in which case no access is counted.
+    pub fn count_internal_accesses(
+        &mut self,
+        verifiers: &BTreeSet<Address>,
+        limits: &BTreeMap<InternalAddress, u64>,
+    ) -> Result<()> {
+        let mut accesses = Vec::new();
+        for verifier in verifiers {
+            let Address::Internal(internal_addr) = verifier else {
+                continue;
+            };
+            let Some(limit) = limits.get(internal_addr) else {
+                continue;
+            };
+            let count = self
+                .internal_accesses
+                .get(internal_addr)
+                .copied()
+                .unwrap_or_default();
+            if count >= *limit {
+                return Err(Error::AccessRateLimited(internal_addr.clone()));
+            }
+            accesses.push(internal_addr);
+        }
+        for internal_addr in accesses {
+            *self
+                .internal_accesses
+                .entry(internal_addr.clone())
+                .or_default() += 1;
+        }
+        Ok(())
+    }
+
+    /// Record the token the fees of a wrapper are paid in. Fails if the token
+    /// is new and the optional per block limit of distinct fee tokens has
+    /// already been reached.
+    pub fn record_fee_token(
+        &mut self,
+        token: &Address,
+        max_fee_tokens_per_block: Option<u64>,
+    ) -> Result<()> {
+        if self.fee_tokens.contains(token) {
+            return Ok(());
+        }
+        if let Some(max) = max_fee_tokens_per_block {
+            if self.fee_tokens.len() as u64 >= max {
+                return Err(Error::FeeTokensPerBlockExceeded(max));
+            }
         }
+        self.fee_tokens.insert(token.clone());
+        Ok(())
     }
 }
 
@@ -160,6 +485,199 @@ pub struct WrapperArgs<'a> {
     pub block_proposer: &'a Address,
     /// Flag if the wrapper transaction committed the fee unshielding operation
     pub is_committed_fee_unshield: bool,
+    /// The gas left in the block, if the block gas is being tracked
+    pub remaining_block_gas: Option<u64>,
+    /// The token the fees were charged in, set once the fee payment has been
+    /// committed
+    pub fee_token: Option<Address>,
+    /// The amount of the fees charged in the fee token, set once the fee
+    /// payment has been committed
+    pub fee_amount: Option<Amount>,
+    /// The token and the amount of the share of the fees credited to the
+    /// block proposer, set once the fee payment has been committed
+    pub proposer_fee: Option<(Address, Amount)>,
+    /// The maximum fee a single wrapper may be charged, if any
+    pub max_fee: Option<Amount>,
+    /// The refundable gas deposit charged along with the fees, set once the
+    /// fee payment has been committed
+    pub gas_deposit: Option<Amount>,
+}
+
+impl<'a> WrapperArgs<'a> {
+    /// Create the arguments of a wrapper included by the given block
+    /// proposer, without tracking the block gas nor capping the fee
+    pub fn new(block_proposer: &'a Address) -> Self {
+        Self {
+            block_proposer,
+            is_committed_fee_unshield: false,
+            remaining_block_gas: None,
+            fee_token: None,
+            fee_amount: None,
+            proposer_fee: None,
+            max_fee: None,
+            gas_deposit: None,
+        }
+    }
+
+    /// The token and the amount of the fees charged, once the fee payment has
+    /// been committed
+    pub fn charged_fee(&self) -> Option<(Address, Amount)> {
+        self.fee_token.clone().zip(self.fee_amount)
+    }
+}
+
+/// When the fees of a wrapper are charged
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum FeePolicy {
+    /// The fees are charged before applying the inner tx and committed even if
+    /// it gets rejected
+    #[default]
+    Prepaid,
+    /// The fees are only charged once the inner tx has been accepted, along
+    /// with its changes. The wrapper gas is still accounted for regardless of
+    /// the outcome of the inner tx
+    Postpaid,
+}
+
+/// A hook invoked on every transaction before it gets dispatched, e.g. to run
+/// custom compliance checks. Returning an error aborts the dispatch with the
+/// provided message.
+pub trait TxPreHook<S> {
+    /// Validate the transaction against the current state
+    fn before_dispatch(
+        &self,
+        tx: &Tx,
+        state: &S,
+    ) -> std::result::Result<(), String>;
+}
+
+/// An invariant of the state checked after applying a transaction, e.g. the
+/// conservation of the total supply of the tokens it touched, to catch the
+/// bugs of the VPs letting the balances inflate. Returning an error rejects
+/// the transaction with the provided message.
+pub trait StateInvariant<S> {
+    /// Check the invariant against the state, including the uncommitted
+    /// changes of the transaction with the given result
+    fn check(
+        &self,
+        result: &TxResult,
+        state: &S,
+    ) -> std::result::Result<(), String>;
+}
+
+/// An external policy on the shielded pool, e.g. enforcing regulatory or risk
+/// limits, consulted before accepting a fee unshielding. Returning an error
+/// rejects the unshielding with the provided message.
+pub trait ShieldedPolicy: Debug {
+    /// Validate the MASP transaction of the fee unshielding of the given
+    /// wrapper
+    fn check_unshielding(
+        &self,
+        wrapper: &WrapperTx,
+        transaction: &Transaction,
+    ) -> std::result::Result<(), String>;
+}
+
+/// The unwrapping of the fees paid in a wrapped native token, e.g. wNAM, into
+/// the native token, so that the block proposer is credited the native token
+pub trait FeeUnwrap: Debug {
+    /// The native token and amount the given `amount` of the fee `token`
+    /// unwraps to, `None` if the token is not a wrapped native token
+    fn unwrap_fee(
+        &self,
+        token: &Address,
+        amount: Amount,
+    ) -> Option<(Address, Amount)>;
+}
+
+/// Event emitted when a transaction is rejected as a replay of a transaction
+/// already applied
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplayRejected {
+    /// The hash of the replayed transaction
+    pub tx_hash: Hash,
+}
+
+impl From<ReplayRejected> for Event {
+    fn from(replay: ReplayRejected) -> Self {
+        Self {
+            event_type: EventType::ReplayRejected,
+            level: EventLevel::Tx,
+            attributes: HashMap::from([(
+                "hash".to_string(),
+                replay.tx_hash.to_string(),
+            )]),
+        }
+    }
+}
+
+/// Optional extensions to the behavior of [`dispatch_tx`]
+pub struct DispatchArgs<'a, D, H>
+where
+    D: 'static + DB + for<'iter> DBIter<'iter> + Sync,
+    H: 'static + StorageHasher + Sync,
+{
+    /// Hooks run in order before dispatching the transaction
+    pub pre_hooks: &'a [&'a dyn TxPreHook<WlState<D, H>>],
+    /// Invariants checked in order on the state after applying the
+    /// transaction, if accepted
+    pub invariants: &'a [&'a dyn StateInvariant<WlState<D, H>>],
+    /// Policy applied to the fee unshieldings, if any
+    pub shielded_policy: Option<&'a dyn ShieldedPolicy>,
+    /// Sink collecting the events emitted while applying the transaction
+    pub event_sink: Option<&'a RefCell<Vec<Event>>>,
+    /// Reject the transaction if it carries sections that nothing references,
+    /// instead of ignoring them
+    pub strict_sections: bool,
+    /// The number of verifiers run serially within each parallel task, if
+    /// any, otherwise the granularity is left to the thread pool
+    pub reduce_chunk_size: Option<usize>,
+    /// Validate the data of the transaction against the schema registered
+    /// for its code, if any, before executing it
+    pub validate_tx_data: bool,
+    /// The hash of the transfer wasm code run by the fee unshieldings, if
+    /// already loaded for the block, otherwise it's read from storage when
+    /// needed
+    pub transfer_hash: Option<Hash>,
+    /// Callback notified of the result of each VP as soon as it completes
+    pub vp_observer: Option<VpObserver<'a>>,
+    /// Unwrapping of the fees paid in a wrapped native token, if any
+    pub fee_unwrap: Option<&'a dyn FeeUnwrap>,
+    /// The handlers of the protocol txs, if any, otherwise the default ones
+    pub protocol_tx_handlers: Option<&'a ProtocolTxHandlers<D, H>>,
+    /// The gas cost per byte of the wrapper txs, if already loaded for the
+    /// block, otherwise it's read from storage when needed
+    pub wrapper_gas_per_byte: Option<u64>,
+    /// When the fees of the wrappers are charged
+    pub fee_policy: FeePolicy,
+    /// Flag the accepted txs that changed no keys and emitted no events in
+    /// their result, as they may be a waste or the sign of a bug
+    pub flag_noop_txs: bool,
+}
+
+impl<'a, D, H> Default for DispatchArgs<'a, D, H>
+where
+    D: 'static + DB + for<'iter> DBIter<'iter> + Sync,
+    H: 'static + StorageHasher + Sync,
+{
+    fn default() -> Self {
+        Self {
+            pre_hooks: &[],
+            invariants: &[],
+            shielded_policy: None,
+            event_sink: None,
+            strict_sections: false,
+            reduce_chunk_size: None,
+            validate_tx_data: false,
+            transfer_hash: None,
+            vp_observer: None,
+            fee_unwrap: None,
+            protocol_tx_handlers: None,
+            wrapper_gas_per_byte: None,
+            fee_policy: FeePolicy::default(),
+            flag_noop_txs: false,
+        }
+    }
 }
 
 /// Dispatch a given transaction to be applied based on its type. Some storage
@@ -178,451 +696,985 @@ pub fn dispatch_tx<'a, D, H, CA>(
     state: &'a mut WlState<D, H>,
     vp_wasm_cache: &'a mut VpCache<CA>,
     tx_wasm_cache: &'a mut TxCache<CA>,
-    wrapper_args: Option<&mut WrapperArgs>,
+    block_accumulators: Option<&'a RefCell<BlockAccumulators>>,
+    dispatch_args: &DispatchArgs<'_, D, H>,
+    mut wrapper_args: Option<&mut WrapperArgs>,
 ) -> Result<TxResult>
 where
     D: 'static + DB + for<'iter> DBIter<'iter> + Sync,
     H: 'static + StorageHasher + Sync,
     CA: 'static + WasmCacheAccess + Sync,
 {
-    match tx.header().tx_type {
+    check_before_dispatch(&tx, state, dispatch_args)?;
+
+    // The replay protection entries of the tx that record its audit
+    let audit_hashes = match tx.header().tx_type {
+        TxType::Raw => vec![tx.raw_header_hash()],
+        TxType::Wrapper(_) => vec![tx.raw_header_hash(), tx.header_hash()],
+        TxType::Protocol(_) => vec![],
+    };
+    let mut result = match tx.header().tx_type {
         // Raw trasaction type is allowed only for governance proposals
         TxType::Raw => apply_wasm_tx(
             tx,
             &tx_index,
-            ShellParams {
-                tx_gas_meter,
-                state,
-                vp_wasm_cache,
-                tx_wasm_cache,
-            },
+            ShellParams::new(tx_gas_meter, state, vp_wasm_cache, tx_wasm_cache)
+                .with_dispatch_args(block_accumulators, dispatch_args),
         ),
         TxType::Protocol(protocol_tx) => {
-            apply_protocol_tx(protocol_tx.tx, tx.data(), state)
+            let default_handlers;
+            let handlers = match dispatch_args.protocol_tx_handlers {
+                Some(handlers) => handlers,
+                None => {
+                    default_handlers = ProtocolTxHandlers::default();
+                    &default_handlers
+                }
+            };
+            apply_protocol_tx(
+                protocol_tx.tx,
+                tx.data(),
+                handlers,
+                state,
+            )
         }
         TxType::Wrapper(ref wrapper) => {
             let fee_unshielding_transaction =
                 get_fee_unshielding_transaction(&tx, wrapper);
-            let changed_keys = apply_wrapper_tx(
+            // The fees of a postpaid wrapper are only charged once its inner
+            // tx has been accepted
+            let postpaid_fee = match dispatch_args.fee_policy {
+                FeePolicy::Prepaid => None,
+                FeePolicy::Postpaid => Some((
+                    fee_delegated_wrapper(&tx, wrapper).into_owned(),
+                    tx.wrapper_fee_token_fallbacks(),
+                    fee_unshielding_transaction.clone(),
+                )),
+            };
+            let (changed_keys, fee_token, proposer_balance) = apply_wrapper_tx(
                 tx.clone(),
                 wrapper,
                 fee_unshielding_transaction,
                 tx_bytes,
-                ShellParams {
+                ShellParams::new(
                     tx_gas_meter,
                     state,
                     vp_wasm_cache,
                     tx_wasm_cache,
-                },
-                wrapper_args,
+                )
+                .with_dispatch_args(block_accumulators, dispatch_args),
+                wrapper_args.as_deref_mut(),
             )
             .map_err(|e| Error::WrapperRunnerError(e.to_string()))?;
+            // The denomination was already resolved when charging the fees,
+            // report it so that clients can display them in human units
+            let fee_denom = crate::token::read_denom(&*state, &fee_token)
+                .map_err(Error::StorageError)?;
+            // The gas deposit is refunded once the inner tx has been accepted
+            let gas_deposit = wrapper_args
+                .as_deref()
+                .and_then(|args| args.gas_deposit)
+                .map(|deposit| {
+                    let payer = fee_delegated_wrapper(&tx, wrapper).fee_payer();
+                    (fee_token.clone(), payer, deposit)
+                });
             let mut inner_res = apply_wasm_tx(
                 tx,
                 &tx_index,
-                ShellParams {
+                ShellParams::new(
                     tx_gas_meter,
                     state,
                     vp_wasm_cache,
                     tx_wasm_cache,
-                },
+                )
+                .with_dispatch_args(block_accumulators, dispatch_args),
             )?;
 
-            inner_res.wrapper_changed_keys = changed_keys;
+            inner_res.merge_wrapper(
+                changed_keys,
+                fee_token,
+                fee_denom,
+                proposer_balance,
+                tx_bytes.len() as u64,
+            );
+            if let Some((token, payer, deposit)) =
+                gas_deposit.filter(|_| inner_res.is_accepted())
+            {
+                refund_gas_deposit(
+                    state,
+                    &token,
+                    &payer,
+                    deposit,
+                    &mut inner_res.wrapper_changed_keys,
+                )?;
+            }
+            if let Some((fee_wrapper, fee_token_fallbacks, masp_transaction)) =
+                postpaid_fee.filter(|_| inner_res.is_accepted())
+            {
+                let shell_params = ShellParams::new(
+                    tx_gas_meter,
+                    state,
+                    vp_wasm_cache,
+                    tx_wasm_cache,
+                )
+                .with_dispatch_args(block_accumulators, dispatch_args);
+                let (_, proposer_balance) = charge_postpaid_fee(
+                    &fee_wrapper,
+                    &fee_token_fallbacks,
+                    masp_transaction,
+                    shell_params,
+                    &mut inner_res.wrapper_changed_keys,
+                    wrapper_args.as_deref_mut(),
+                )?;
+                inner_res.proposer_balance = proposer_balance;
+            }
             Ok(inner_res)
         }
+    }?;
+    check_invariants(&result, state, dispatch_args)?;
+    if dispatch_args.flag_noop_txs {
+        result.is_noop = result.is_accepted()
+            && result.changed_keys.is_empty()
+            && result.ibc_events.is_empty()
+            && result.eth_bridge_events.is_empty();
     }
+    // Audit the tx now that its outcome and its fees are final
+    write_tx_audit(state, audit_hashes, &result, wrapper_args.as_deref());
+    Ok(result)
 }
 
-/// Load the wasm hash for a transfer from storage.
-///
-/// # Panics
-/// If the transaction hash is not found in storage
-pub fn get_transfer_hash_from_storage<S>(storage: &S) -> Hash
+/// Record the audit of an applied tx along with the given replay protection
+/// entries, only the committed ones are persisted. The fees are those
+/// recorded in the `wrapper_args`, so the audit must only be written once
+/// they have been charged.
+fn write_tx_audit<D, H>(
+    state: &mut WlState<D, H>,
+    hashes: impl IntoIterator<Item = Hash>,
+    result: &TxResult,
+    wrapper_args: Option<&WrapperArgs>,
+) where
+    D: 'static + DB + for<'iter> DBIter<'iter> + Sync,
+    H: 'static + StorageHasher + Sync,
+{
+    let audit = TxAudit {
+        gas_used: result.gas_used.into(),
+        fee_charged: wrapper_args.and_then(WrapperArgs::charged_fee),
+        accepted: result.is_accepted(),
+    };
+    for hash in hashes {
+        state.write_log_mut().write_tx_audit(hash, audit.clone());
+    }
+}
+
+/// Deduct the fees refunded to the fee payer of the wrapper from the audit of
+/// the tx, see [`refund_unused_gas`]
+pub fn audit_fee_refund<S>(state: &mut S, tx: &Tx, refund: Amount)
 where
-    S: StorageRead,
+    S: State,
 {
-    let transfer_code_name_key =
-        Key::wasm_code_name(TX_TRANSFER_WASM.to_string());
-    storage
-        .read(&transfer_code_name_key)
-        .expect("Could not read the storage")
-        .expect("Expected tx transfer hash in storage")
+    for hash in [tx.raw_header_hash(), tx.header_hash()] {
+        if let Some((_token, fees)) = state
+            .write_log_mut()
+            .tx_audit_mut(&hash)
+            .and_then(|audit| audit.fee_charged.as_mut())
+        {
+            *fees = fees.checked_sub(refund).unwrap_or_default();
+        }
+    }
 }
 
-/// Performs the required operation on a wrapper transaction:
-///  - replay protection
-///  - fee payment
-///  - gas accounting
-///
-/// Returns the set of changed storage keys.
-pub(crate) fn apply_wrapper_tx<S, D, H, CA>(
+/// Dispatch a given transaction like [`dispatch_tx`] without persisting any
+/// of its effects. The write log, including the fees charged by a wrapper and
+/// its replay protection entry, the block accumulators and the events sink
+/// are rolled back once the transaction has been applied, even on failure.
+/// The returned result still reports the would-be changed keys, gas used and
+/// VPs results.
+#[allow(clippy::too_many_arguments)]
+pub fn dispatch_tx_dry_run<'a, D, H, CA>(
     tx: Tx,
-    wrapper: &WrapperTx,
-    fee_unshield_transaction: Option<Transaction>,
-    tx_bytes: &[u8],
-    mut shell_params: ShellParams<'_, S, D, H, CA>,
+    tx_bytes: &'a [u8],
+    tx_index: TxIndex,
+    tx_gas_meter: &'a RefCell<TxGasMeter>,
+    state: &'a mut WlState<D, H>,
+    vp_wasm_cache: &'a mut VpCache<CA>,
+    tx_wasm_cache: &'a mut TxCache<CA>,
+    block_accumulators: Option<&'a RefCell<BlockAccumulators>>,
+    dispatch_args: &DispatchArgs<'_, D, H>,
     wrapper_args: Option<&mut WrapperArgs>,
-) -> Result<BTreeSet<Key>>
+) -> Result<TxResult>
 where
-    S: State<D = D, H = H> + Sync,
     D: 'static + DB + for<'iter> DBIter<'iter> + Sync,
     H: 'static + StorageHasher + Sync,
     CA: 'static + WasmCacheAccess + Sync,
 {
-    let mut changed_keys = BTreeSet::default();
-
-    // Write wrapper tx hash to storage
-    shell_params
-        .state
-        .write_log_mut()
-        .write_tx_hash(tx.header_hash())
-        .expect("Error while writing tx hash to storage");
-
-    // Charge fee before performing any fallible operations
-    charge_fee(
-        wrapper,
-        fee_unshield_transaction,
-        &mut shell_params,
-        &mut changed_keys,
+    let write_log = state.write_log().clone();
+    let accumulators =
+        block_accumulators.map(|accumulators| accumulators.borrow().clone());
+    let events_len = dispatch_args.event_sink.map(|sink| sink.borrow().len());
+    let result = dispatch_tx(
+        tx,
+        tx_bytes,
+        tx_index,
+        tx_gas_meter,
+        &mut *state,
+        vp_wasm_cache,
+        tx_wasm_cache,
+        block_accumulators,
+        dispatch_args,
         wrapper_args,
-    )?;
-
-    // Account for gas
-    shell_params
-        .tx_gas_meter
-        .borrow_mut()
-        .add_wrapper_gas(tx_bytes)
-        .map_err(|err| Error::GasError(err.to_string()))?;
-
-    Ok(changed_keys)
-}
-
-/// Retrieve the Masp `Transaction` for fee unshielding from the provided
-/// transaction, if present
-pub fn get_fee_unshielding_transaction(
-    tx: &Tx,
-    wrapper: &WrapperTx,
-) -> Option<Transaction> {
-    wrapper
-        .unshield_section_hash
-        .and_then(|ref hash| tx.get_section(hash))
-        .and_then(|section| {
-            if let Section::MaspTx(transaction) = section.as_ref() {
-                Some(transaction.to_owned())
-            } else {
-                None
-            }
-        })
+    );
+    *state.write_log_mut() = write_log;
+    if let (Some(block_accumulators), Some(accumulators)) =
+        (block_accumulators, accumulators)
+    {
+        *block_accumulators.borrow_mut() = accumulators;
+    }
+    if let (Some(sink), Some(len)) = (dispatch_args.event_sink, events_len) {
+        sink.borrow_mut().truncate(len);
+    }
+    result
 }
 
-/// Charge fee for the provided wrapper transaction. Returns error if:
-/// - The unshielding fails because of gas (other errors are ignored cause we
-///   still try to get the fees amount from the transparent balance and, if it
-///   works, execution can continue)
-/// - Fee amount overflows
-/// - Not enough funds are available to pay the entire amount of the fee
-/// - The accumulated fee amount to be credited to the block proposer overflows
-fn charge_fee<S, D, H, CA>(
-    wrapper: &WrapperTx,
-    masp_transaction: Option<Transaction>,
-    shell_params: &mut ShellParams<'_, S, D, H, CA>,
-    changed_keys: &mut BTreeSet<Key>,
+/// Dispatch a given transaction like [`dispatch_tx`], streaming the outcome of
+/// each VP to `vp_results` as soon as it completes, along with the address of
+/// its verifier, e.g. to render the progress of a transaction triggering many
+/// VPs. The VPs run in parallel, so the outcomes are sent in the order the VPs
+/// complete, which is not deterministic, while the aggregated result of all
+/// the VPs is still returned at the end. The outcomes of the VPs of all the
+/// inner transactions of a batch are streamed through the same channel.
+#[allow(clippy::too_many_arguments)]
+pub fn dispatch_tx_streaming<'a, D, H, CA>(
+    tx: Tx,
+    tx_bytes: &'a [u8],
+    tx_index: TxIndex,
+    tx_gas_meter: &'a RefCell<TxGasMeter>,
+    state: &'a mut WlState<D, H>,
+    vp_wasm_cache: &'a mut VpCache<CA>,
+    tx_wasm_cache: &'a mut TxCache<CA>,
+    block_accumulators: Option<&'a RefCell<BlockAccumulators>>,
+    dispatch_args: &DispatchArgs<'_, D, H>,
     wrapper_args: Option<&mut WrapperArgs>,
-) -> Result<()>
+    vp_results: Sender<(Address, bool)>,
+) -> Result<TxResult>
 where
-    S: State<D = D, H = H> + Sync,
     D: 'static + DB + for<'iter> DBIter<'iter> + Sync,
     H: 'static + StorageHasher + Sync,
     CA: 'static + WasmCacheAccess + Sync,
 {
-    // Unshield funds if requested
-    let valid_fee_unshielding = if let Some(transaction) = masp_transaction {
-        run_fee_unshielding(wrapper, shell_params, transaction)
-    } else {
-        Ok(false)
+    let vp_observer = dispatch_args.vp_observer;
+    let observe = |addr: &Address,
+                   accepted: bool,
+                   error: Option<&str>,
+                   gas: u64| {
+        if let Some(VpObserver(observer)) = vp_observer {
+            observer(addr, accepted, error, gas);
+        }
+        // The outcomes are only informative, so they are dropped once the
+        // receiver has been disconnected
+        let _ = vp_results.send((addr.clone(), accepted));
     };
+    let dispatch_args = DispatchArgs {
+        vp_observer: Some(VpObserver(&observe)),
+        ..*dispatch_args
+    };
+    dispatch_tx(
+        tx,
+        tx_bytes,
+        tx_index,
+        tx_gas_meter,
+        state,
+        vp_wasm_cache,
+        tx_wasm_cache,
+        block_accumulators,
+        &dispatch_args,
+        wrapper_args,
+    )
+}
 
-    // Charge or check fees before propagating any possible error coming from
-    // the fee unshielding. If fee unshielding failed for non-gas reasons but
-    // the fees can still be paid we'll continue with the execution (this is a
-    // different logic from the one we apply in process_proposal)
-    match wrapper_args {
-        Some(WrapperArgs {
-            block_proposer,
-            is_committed_fee_unshield: _,
-        }) => transfer_fee(shell_params.state, block_proposer, wrapper)?,
-        None => check_fees(shell_params.state, wrapper)?,
-    }
-
-    changed_keys
-        .extend(shell_params.state.write_log_mut().get_keys_with_precommit());
-
-    // Commit tx write log even in case of subsequent errors
-    shell_params.state.write_log_mut().commit_tx();
+/// The outcome of a transaction applied with [`apply_tx`]
+#[derive(Debug)]
+pub enum TxOutcome {
+    /// The transaction was applied and accepted by all the VPs
+    Accepted(TxResult),
+    /// The transaction was applied but rejected by some VPs
+    Rejected {
+        /// The VPs that rejected the transaction
+        vps: BTreeSet<Address>,
+        /// The result of the transaction
+        result: TxResult,
+    },
+    /// The transaction had already been applied in this block and was
+    /// skipped. The fees of its wrapper, if any, have still been charged
+    Replayed(Hash),
+    /// The wrapper of the transaction failed to pay for its fees or gas, so
+    /// that its inner transaction was not applied
+    FeeError(String),
+    /// The transaction failed for any other reason
+    Failed(Error),
+}
 
-    // Update the flag only after the valid fee payment has been committed. If
-    // fee unshielding went out of gas propagate the error
-    if let Some(args) = wrapper_args {
-        args.is_committed_fee_unshield = valid_fee_unshielding?;
+impl TxOutcome {
+    /// Check if the tx has been applied and accepted by all the VPs
+    pub fn is_accepted(&self) -> bool {
+        matches!(self, Self::Accepted(_))
     }
+}
 
-    Ok(())
+impl From<Result<TxResult>> for TxOutcome {
+    fn from(result: Result<TxResult>) -> Self {
+        match result {
+            Ok(result) if result.is_accepted() => Self::Accepted(result),
+            Ok(result) => Self::Rejected {
+                vps: result.vps_result.rejected_vps.clone(),
+                result,
+            },
+            Err(Error::ReplayAttempt(hash, _)) => Self::Replayed(hash),
+            Err(Error::WrapperRunnerError(msg)) => Self::FeeError(msg),
+            Err(err) => Self::Failed(err),
+        }
+    }
 }
 
-/// Executes the masp fee unshielding transaction. Returns `true if the unshield
-/// was successful, `false` otherwise and error in case of out-of-gas
-pub fn run_fee_unshielding<S, D, H, CA>(
-    wrapper: &WrapperTx,
-    shell_params: &mut ShellParams<'_, S, D, H, CA>,
-    transaction: Transaction,
-) -> Result<bool>
+/// Apply a given transaction like [`dispatch_tx`], classifying its result in
+/// a [`TxOutcome`] rather than signaling the replays and the wrapper failures
+/// through errors.
+#[allow(clippy::too_many_arguments)]
+pub fn apply_tx<'a, D, H, CA>(
+    tx: Tx,
+    tx_bytes: &'a [u8],
+    tx_index: TxIndex,
+    tx_gas_meter: &'a RefCell<TxGasMeter>,
+    state: &'a mut WlState<D, H>,
+    vp_wasm_cache: &'a mut VpCache<CA>,
+    tx_wasm_cache: &'a mut TxCache<CA>,
+    block_accumulators: Option<&'a RefCell<BlockAccumulators>>,
+    dispatch_args: &DispatchArgs<'_, D, H>,
+    wrapper_args: Option<&mut WrapperArgs>,
+) -> TxOutcome
 where
-    S: State<D = D, H = H> + Sync,
     D: 'static + DB + for<'iter> DBIter<'iter> + Sync,
     H: 'static + StorageHasher + Sync,
     CA: 'static + WasmCacheAccess + Sync,
 {
-    let ShellParams {
+    dispatch_tx(
+        tx,
+        tx_bytes,
+        tx_index,
         tx_gas_meter,
         state,
         vp_wasm_cache,
         tx_wasm_cache,
-    } = shell_params;
-
-    // The unshielding is subject to a gas limit imposed by a protocol
-    // parameter, instantiate a custom gas meter for this step and
-    // initialize it with the already consumed gas. The gas limit should
-    // actually be the lowest between the protocol parameter and the actual gas
-    // limit of the transaction
-    let min_gas_limit = state
-        .read::<u64>(
-            &namada_parameters::storage::get_fee_unshielding_gas_limit_key(),
-        )
-        .expect("Error reading the storage")
-        .expect("Missing fee unshielding gas limit in storage")
-        .min(tx_gas_meter.borrow().tx_gas_limit.into());
-    let mut unshield_gas_meter = TxGasMeter::new(GasLimit::from(min_gas_limit));
-    unshield_gas_meter
-        .copy_consumed_gas_from(&tx_gas_meter.borrow())
-        .map_err(|e| Error::GasError(e.to_string()))?;
-    let ref_unshield_gas_meter = RefCell::new(unshield_gas_meter);
-
-    let result = match wrapper.generate_fee_unshielding(
-        get_transfer_hash_from_storage(*state),
-        Some(TX_TRANSFER_WASM.to_string()),
-        transaction,
-    ) {
-        Ok(fee_unshielding_tx) => {
-            // NOTE: A clean tx write log must be provided to this call
-            // for a correct vp validation. Block write log, instead,
-            // should contain any prior changes (if any). This is to simulate
-            // the unshielding tx (to prevent the already written
-            // keys from being passed/triggering VPs) but we cannot
-            // commit the tx write log yet cause the tx could still
-            // be invalid.
-            state.write_log_mut().precommit_tx();
-            match apply_wasm_tx(
-                fee_unshielding_tx,
-                &TxIndex::default(),
-                ShellParams {
-                    tx_gas_meter: &ref_unshield_gas_meter,
-                    state: *state,
-                    vp_wasm_cache,
-                    tx_wasm_cache,
-                },
-            ) {
-                Ok(result) => {
-                    // NOTE: do not commit yet cause this could be
-                    // exploited to get free unshieldings and shielded
-                    // operations
-                    if !result.is_accepted() {
-                        state.write_log_mut().drop_tx_keep_precommit();
-                        tracing::error!(
-                            "The unshielding tx is invalid, some VPs rejected \
-                             it: {:#?}",
-                            result.vps_result.rejected_vps
-                        );
-                    }
-
-                    result.is_accepted()
-                }
-                Err(e) => {
-                    state.write_log_mut().drop_tx_keep_precommit();
-                    tracing::error!(
-                        "The unshielding tx is invalid, wasm run failed: {}",
-                        e
-                    );
-                    if let Error::GasError(_) = e {
-                        // Popagate only if it is a gas error
-                        return Err(e);
-                    }
-
-                    false
-                }
-            }
+        block_accumulators,
+        dispatch_args,
+        wrapper_args,
+    )
+    .into()
+}
+
+/// Dispatch a batch of inner transactions under a single wrapper. The wrapper
+/// is applied once, charging the fees and the wrapper gas of the whole batch,
+/// then the inner transactions are applied in order, sharing the same gas
+/// meter. The changes of the batch are all-or-nothing: if any inner
+/// transaction fails or is rejected, the write log is restored to its
+/// precommit state prior to the batch, which retains the fees only.
+///
+/// The signed wrapper must commit to the inner transactions by carrying their
+/// headers, in order, as header sections. The pre-hooks are run once on the
+/// wrapper, while the other checks of the dispatch arguments are run on each
+/// inner transaction.
+///
+/// The returned result combines the results of the inner transactions and
+/// reports them in `batch_results`, up to the first one that was rejected.
+/// The failure of an inner transaction is reported as
+/// [`Error::BatchTxError`]. The replay protection entries of the inner
+/// transactions are written once the whole batch has been accepted.
+#[allow(clippy::too_many_arguments)]
+pub fn dispatch_batch<'a, D, H, CA>(
+    wrapper_tx: Tx,
+    inner_txs: Vec<Tx>,
+    tx_bytes: &'a [u8],
+    tx_index: TxIndex,
+    tx_gas_meter: &'a RefCell<TxGasMeter>,
+    state: &'a mut WlState<D, H>,
+    vp_wasm_cache: &'a mut VpCache<CA>,
+    tx_wasm_cache: &'a mut TxCache<CA>,
+    block_accumulators: Option<&'a RefCell<BlockAccumulators>>,
+    dispatch_args: &DispatchArgs<'_, D, H>,
+    mut wrapper_args: Option<&mut WrapperArgs>,
+) -> Result<TxResult>
+where
+    D: 'static + DB + for<'iter> DBIter<'iter> + Sync,
+    H: 'static + StorageHasher + Sync,
+    CA: 'static + WasmCacheAccess + Sync,
+{
+    let header = wrapper_tx.header();
+    let TxType::Wrapper(ref wrapper) = header.tx_type else {
+        return Err(Error::InvalidBatch(
+            "The batch must be dispatched with a wrapper tx".to_string(),
+        ));
+    };
+    if inner_txs.is_empty() {
+        return Err(Error::InvalidBatch("The batch is empty".to_string()));
+    }
+    check_batch_commitment(&wrapper_tx, &inner_txs)?;
+    run_pre_hooks(&wrapper_tx, state, dispatch_args)?;
+    let mut inner_hashes = BTreeSet::new();
+    for tx in &inner_txs {
+        check_tx_contents(tx, state, dispatch_args)?;
+        let tx_hash = tx.raw_header_hash();
+        if !inner_hashes.insert(tx_hash) {
+            return Err(Error::ReplayAttempt(
+                tx_hash,
+                ReplayOrigin::CurrentBlock,
+            ));
         }
-        Err(e) => {
-            tracing::error!("{}", e);
-            false
+        // The inner txs are not wrappers, so the allowlist has to be checked
+        // here rather than when running their code
+        let code_hash = tx
+            .get_section(tx.code_sechash())
+            .and_then(|section| section.code_sec())
+            .map(|code| code.code.hash())
+            .ok_or_else(|| {
+                Error::MissingSection(tx.code_sechash().to_string())
+            })?;
+        if !crate::parameters::is_tx_allowed(&*state, &code_hash)
+            .map_err(Error::StorageError)?
+        {
+            return Err(Error::TxRunnerError(wasm::run::Error::DisallowedTx));
+        }
+    }
+
+    let fee_unshielding_transaction =
+        get_fee_unshielding_transaction(&wrapper_tx, wrapper);
+    let (wrapper_changed_keys, fee_token, proposer_balance) = apply_wrapper_tx(
+        wrapper_tx.clone(),
+        wrapper,
+        fee_unshielding_transaction,
+        tx_bytes,
+        ShellParams::new(tx_gas_meter, state, vp_wasm_cache, tx_wasm_cache)
+            .with_dispatch_args(block_accumulators, dispatch_args),
+        wrapper_args.as_deref_mut(),
+    )
+    .map_err(|e| Error::WrapperRunnerError(e.to_string()))?;
+    let fee_denom = crate::token::read_denom(&*state, &fee_token)
+        .map_err(Error::StorageError)?;
+
+    // The accounts initialized by the members are only counted if the whole
+    // batch is accepted
+    let initialized_accounts_before = block_accumulators
+        .map(|accumulators| accumulators.borrow().initialized_accounts);
+    let rollback = |state: &mut WlState<D, H>| {
+        state.write_log_mut().drop_tx();
+        if let (Some(accumulators), Some(count)) =
+            (block_accumulators, initialized_accounts_before)
+        {
+            accumulators.borrow_mut().initialized_accounts = count;
         }
     };
 
-    tx_gas_meter
-        .borrow_mut()
-        .copy_consumed_gas_from(&ref_unshield_gas_meter.borrow())
-        .map_err(|e| Error::GasError(e.to_string()))?;
+    let mut batch_res = TxResult::default();
+    #[cfg(any(test, feature = "debug-results"))]
+    {
+        batch_res.wasm_cache_read_write = Some(CA::is_read_write());
+    }
+    batch_res.merge_wrapper(
+        wrapper_changed_keys,
+        fee_token,
+        fee_denom,
+        proposer_balance,
+        tx_bytes.len() as u64,
+    );
+    // The inner txs share the gas meter of the wrapper, the gas of each one is
+    // the increase of the consumed gas
+    let mut consumed_gas =
+        u64::from(tx_gas_meter.borrow().get_tx_consumed_gas());
+    for (index, tx) in inner_txs.into_iter().enumerate() {
+        let tx_hash = tx.raw_header_hash();
+        let inner_res = match apply_wasm_tx(
+            tx,
+            &tx_index,
+            ShellParams::new(tx_gas_meter, state, vp_wasm_cache, tx_wasm_cache)
+                .with_dispatch_args(block_accumulators, dispatch_args),
+        ) {
+            Ok(inner_res) => inner_res,
+            Err(error) => {
+                rollback(state);
+                return Err(Error::BatchTxError {
+                    index,
+                    hash: tx_hash,
+                    error: Box::new(error),
+                });
+            }
+        };
 
-    Ok(result)
+        let accepted = inner_res.is_accepted();
+        batch_res.gas_used = inner_res.gas_used;
+        let Some((inner_gas, inner_gas_used)) = u64::from(inner_res.gas_used)
+            .checked_sub(consumed_gas)
+            .and_then(|inner_gas| {
+                let total = batch_res.inner_gas_used.checked_add(inner_gas)?;
+                Some((inner_gas, total))
+            })
+        else {
+            rollback(state);
+            return Err(Error::GasError(
+                namada_gas::Error::GasOverflow.to_string(),
+            ));
+        };
+        consumed_gas = u64::from(inner_res.gas_used);
+        batch_res.per_inner_gas.push(inner_gas);
+        batch_res.inner_gas_used = inner_gas_used;
+        batch_res.changed_keys.extend(inner_res.changed_keys.iter().cloned());
+        batch_res
+            .vps_result
+            .accepted_vps
+            .extend(inner_res.vps_result.accepted_vps.iter().cloned());
+        batch_res
+            .vps_result
+            .rejected_vps
+            .extend(inner_res.vps_result.rejected_vps.iter().cloned());
+        batch_res
+            .vps_result
+            .errors
+            .extend(inner_res.vps_result.errors.iter().cloned());
+        batch_res.vps_result.status_flags |= inner_res.vps_result.status_flags;
+        batch_res
+            .initialized_accounts
+            .extend(inner_res.initialized_accounts.iter().cloned());
+        batch_res
+            .ibc_events
+            .extend(inner_res.ibc_events.iter().cloned());
+        batch_res
+            .eth_bridge_events
+            .extend(inner_res.eth_bridge_events.iter().cloned());
+        #[cfg(any(test, feature = "debug-results"))]
+        batch_res.read_keys.extend(inner_res.read_keys.iter().cloned());
+        batch_res.written_bytes += inner_res.written_bytes;
+        batch_res
+            .tokens_touched
+            .extend(inner_res.tokens_touched.iter().cloned());
+
+        if !accepted {
+            rollback(state);
+            batch_res.batch_results.push((
+                tx_hash,
+                Err(format!(
+                    "Rejected by the VPs of {:?}",
+                    inner_res.vps_result.rejected_vps
+                )),
+            ));
+            return Ok(batch_res);
+        }
+        // Keep the changes of the member apart from those of the next ones,
+        // only the whole batch gets committed
+        state.write_log_mut().precommit_tx();
+        batch_res.batch_results.push((tx_hash, Ok(inner_res)));
+    }
+
+    if let Err(err) = check_invariants(&batch_res, state, dispatch_args) {
+        rollback(state);
+        return Err(err);
+    }
+
+    // The gas deposit is refunded once the whole batch has been accepted
+    if let (Some(fee_token), Some(deposit)) = (
+        &batch_res.fee_token,
+        wrapper_args.as_deref().and_then(|args| args.gas_deposit),
+    ) {
+        let refunded = refund_gas_deposit(
+            state,
+            fee_token,
+            &fee_delegated_wrapper(&wrapper_tx, wrapper).fee_payer(),
+            deposit,
+            &mut batch_res.wrapper_changed_keys,
+        );
+        if let Err(err) = refunded {
+            rollback(state);
+            return Err(err);
+        }
+    }
+
+    // The postpaid fees are charged once the whole batch has been accepted
+    if dispatch_args.fee_policy == FeePolicy::Postpaid {
+        let charged = charge_postpaid_fee(
+            &fee_delegated_wrapper(&wrapper_tx, wrapper),
+            &wrapper_tx.wrapper_fee_token_fallbacks(),
+            get_fee_unshielding_transaction(&wrapper_tx, wrapper),
+            ShellParams::new(tx_gas_meter, state, vp_wasm_cache, tx_wasm_cache)
+                .with_dispatch_args(block_accumulators, dispatch_args),
+            &mut batch_res.wrapper_changed_keys,
+            wrapper_args.as_deref_mut(),
+        );
+        match charged {
+            Ok((_, proposer_balance)) => {
+                batch_res.proposer_balance = proposer_balance;
+            }
+            Err(err) => {
+                rollback(state);
+                return Err(err);
+            }
+        }
+    }
+
+    // Protect the inner txs from replays now that the whole batch has been
+    // accepted
+    for hash in &inner_hashes {
+        state
+            .write_log_mut()
+            .write_tx_hash(*hash)
+            .expect("Error while writing tx hash to storage");
+    }
+
+    // Audit the batch now that its fees have been charged
+    write_tx_audit(
+        state,
+        inner_hashes.into_iter().chain([wrapper_tx.header_hash()]),
+        &batch_res,
+        wrapper_args.as_deref(),
+    );
+
+    Ok(batch_res)
 }
 
-/// Perform the actual transfer of fess from the fee payer to the block
-/// proposer.
-pub fn transfer_fee<S>(
-    state: &mut S,
-    block_proposer: &Address,
-    wrapper: &WrapperTx,
+/// Check that the headers carried by the wrapper of a batch are those of its
+/// inner transactions, in order, so that the wrapper signature commits to them
+fn check_batch_commitment(wrapper_tx: &Tx, inner_txs: &[Tx]) -> Result<()> {
+    let committed = wrapper_tx
+        .sections
+        .iter()
+        .filter(|section| matches!(section, Section::Header(_)))
+        .map(Section::get_hash);
+    if !committed.eq(inner_txs.iter().map(Tx::raw_header_hash)) {
+        return Err(Error::InvalidBatch(
+            "The inner txs don't match the headers committed to by the \
+             wrapper"
+                .to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Run the pre-hooks and the optional checks of the dispatch arguments on the
+/// transaction
+fn check_before_dispatch<D, H>(
+    tx: &Tx,
+    state: &WlState<D, H>,
+    dispatch_args: &DispatchArgs<'_, D, H>,
 ) -> Result<()>
 where
-    S: State + StorageRead + StorageWrite,
+    D: 'static + DB + for<'iter> DBIter<'iter> + Sync,
+    H: 'static + StorageHasher + Sync,
 {
-    let balance = crate::token::read_balance(
-        state,
-        &wrapper.fee.token,
-        &wrapper.fee_payer(),
-    )
-    .unwrap();
-
-    match wrapper.get_tx_fee() {
-        Ok(fees) => {
-            let fees =
-                crate::token::denom_to_amount(fees, &wrapper.fee.token, state)
-                    .map_err(|e| Error::FeeError(e.to_string()))?;
-            if balance.checked_sub(fees).is_some() {
-                token_transfer(
-                    state,
-                    &wrapper.fee.token,
-                    &wrapper.fee_payer(),
-                    block_proposer,
-                    fees,
-                )
-                .map_err(|e| Error::FeeError(e.to_string()))
-            } else {
-                // Balance was insufficient for fee payment, move all the
-                // available funds in the transparent balance of
-                // the fee payer. This shouldn't happen as it should be
-                // prevented from mempool/process_proposal.
-                tracing::error!(
-                    "Transfer of tx fee cannot be applied to due to \
-                     insufficient funds. Falling back to transferring the \
-                     available balance which is less than the fee. This \
-                     shouldn't happen."
-                );
-                token_transfer(
-                    state,
-                    &wrapper.fee.token,
-                    &wrapper.fee_payer(),
-                    block_proposer,
-                    balance,
-                )
-                .map_err(|e| Error::FeeError(e.to_string()))?;
-
-                Err(Error::FeeError(
-                    "Transparent balance of wrapper's signer was insufficient \
-                     to pay fee. All the available transparent funds have \
-                     been moved to the block proposer"
-                        .to_string(),
-                ))
-            }
-        }
-        Err(e) => {
-            // Fee overflow. This shouldn't happen as it should be prevented
-            // from mempool/process_proposal.
-            tracing::error!(
-                "Transfer of tx fee cannot be applied to due to fee overflow. \
-                 This shouldn't happen."
-            );
+    check_protocol_tx_data(tx)?;
+    run_pre_hooks(tx, state, dispatch_args)?;
+    check_tx_contents(tx, state, dispatch_args)
+}
 
-            Err(Error::FeeError(format!("{}", e)))
-        }
+/// Run the pre-hooks of the dispatch arguments on the transaction
+fn run_pre_hooks<D, H>(
+    tx: &Tx,
+    state: &WlState<D, H>,
+    dispatch_args: &DispatchArgs<'_, D, H>,
+) -> Result<()>
+where
+    D: 'static + DB + for<'iter> DBIter<'iter> + Sync,
+    H: 'static + StorageHasher + Sync,
+{
+    for hook in dispatch_args.pre_hooks {
+        hook.before_dispatch(tx, state)
+            .map_err(Error::PreHookRejected)?;
+    }
+    Ok(())
+}
+
+/// Run the optional checks of the dispatch arguments on the sections and the
+/// data of the transaction
+fn check_tx_contents<D, H>(
+    tx: &Tx,
+    state: &WlState<D, H>,
+    dispatch_args: &DispatchArgs<'_, D, H>,
+) -> Result<()>
+where
+    D: 'static + DB + for<'iter> DBIter<'iter> + Sync,
+    H: 'static + StorageHasher + Sync,
+{
+    if dispatch_args.strict_sections {
+        check_referenced_sections(tx)?;
+    }
+    if dispatch_args.validate_tx_data {
+        check_tx_data_schema(tx, state)?;
     }
+    Ok(())
 }
 
-/// Transfer `token` from `src` to `dest`. Returns an `Err` if `src` has
-/// insufficient balance or if the transfer the `dest` would overflow (This can
-/// only happen if the total supply doesn't fit in `token::Amount`). Contrary to
-/// `crate::token::transfer` this function updates the tx write log and
-/// not the block write log.
-fn token_transfer<WLS>(
-    state: &mut WLS,
-    token: &Address,
-    src: &Address,
-    dest: &Address,
-    amount: Amount,
+/// Check the invariants of the dispatch arguments on the state after applying
+/// a transaction. The rejected transactions are skipped, as their changes get
+/// dropped anyway.
+fn check_invariants<D, H>(
+    result: &TxResult,
+    state: &WlState<D, H>,
+    dispatch_args: &DispatchArgs<'_, D, H>,
 ) -> Result<()>
 where
-    WLS: State + StorageRead,
+    D: 'static + DB + for<'iter> DBIter<'iter> + Sync,
+    H: 'static + StorageHasher + Sync,
 {
-    let src_key = crate::token::storage_key::balance_key(token, src);
-    let src_balance = crate::token::read_balance(state, token, src)
-        .expect("Token balance read in protocol must not fail");
-    match src_balance.checked_sub(amount) {
-        Some(new_src_balance) => {
-            if src == dest {
-                return Ok(());
+    if !result.is_accepted() {
+        return Ok(());
+    }
+    for invariant in dispatch_args.invariants {
+        invariant
+            .check(result, state)
+            .map_err(Error::InvariantViolated)?;
+    }
+    Ok(())
+}
+
+/// Check that the data of a protocol transaction is present and matches its
+/// type, before any state is touched
+fn check_protocol_tx_data(tx: &Tx) -> Result<()> {
+    let TxType::Protocol(protocol_tx) = tx.header().tx_type else {
+        return Ok(());
+    };
+    let tx_type = format!("{:?}", protocol_tx.tx);
+    let data = tx
+        .data()
+        .ok_or_else(|| Error::MissingProtocolTxData(tx_type.clone()))?;
+    EthereumTxData::deserialize(&protocol_tx.tx, &data)
+        .map_err(|err| Error::InvalidProtocolTxData(tx_type, err.to_string()))?;
+    Ok(())
+}
+
+/// Check that every section of the transaction is referenced by its header,
+/// a signature, a MASP builder or its data, which embeds the hashes of the
+/// sections it uses. The signatures and the MASP builders reference other
+/// sections themselves.
+fn check_referenced_sections(tx: &Tx) -> Result<()> {
+    let header = tx.header();
+    let mut referenced =
+        BTreeSet::from([header.code_hash, header.data_hash, header.memo_hash]);
+    for section in &tx.sections {
+        match section {
+            Section::Authorization(auth) => {
+                referenced.extend(auth.targets.iter().copied())
             }
-            let dest_key = crate::token::storage_key::balance_key(token, dest);
-            let dest_balance = crate::token::read_balance(state, token, dest)
-                .expect("Token balance read in protocol must not fail");
-            match dest_balance.checked_add(amount) {
-                Some(new_dest_balance) => {
-                    state
-                        .write_log_mut()
-                        .write(&src_key, new_src_balance.serialize_to_vec())
-                        .map_err(|e| Error::FeeError(e.to_string()))?;
-                    match state
-                        .write_log_mut()
-                        .write(&dest_key, new_dest_balance.serialize_to_vec())
-                    {
-                        Ok(_) => Ok(()),
-                        Err(e) => Err(Error::FeeError(e.to_string())),
-                    }
-                }
-                None => Err(Error::FeeError(
-                    "The transfer would overflow destination balance"
-                        .to_string(),
-                )),
+            Section::MaspBuilder(builder) => {
+                referenced.insert(builder.target);
             }
+            _ => {}
+        }
+    }
+    let data = tx.data().unwrap_or_default();
+    for section in &tx.sections {
+        if matches!(
+            section,
+            Section::Authorization(_) | Section::MaspBuilder(_)
+        ) {
+            continue;
+        }
+        let hash = section.get_hash();
+        let in_data = data.windows(hash.0.len()).any(|bytes| bytes == hash.0);
+        if !referenced.contains(&hash) && !in_data {
+            return Err(Error::UnknownSection(hash));
         }
-        None => Err(Error::FeeError("Insufficient source balance".to_string())),
     }
+    Ok(())
+}
+
+/// Validate the data of a tx by decoding it
+type TxDataSchema = fn(&[u8]) -> std::io::Result<()>;
+
+/// The schemas of the data of the well-known txs, keyed by the name of their
+/// wasm code
+const TX_DATA_SCHEMAS: &[(&str, TxDataSchema)] = &[(
+    TX_UPDATE_STEWARD_COMMISSION,
+    decode_tx_data::<UpdateStewardCommission>,
+)];
+
+/// Check that the tx data can be decoded into the given type
+fn decode_tx_data<T: BorshDeserialize>(data: &[u8]) -> std::io::Result<()> {
+    T::try_from_slice(data).map(|_| ())
 }
 
-/// Check if the fee payer has enough transparent balance to pay fees
-pub fn check_fees<S>(state: &S, wrapper: &WrapperTx) -> Result<()>
+/// Check the data of the transaction against the schema registered for the
+/// hash of its code, if any
+fn check_tx_data_schema<S>(tx: &Tx, storage: &S) -> Result<()>
 where
-    S: State + StorageRead,
+    S: StorageRead,
 {
-    let balance = crate::token::read_balance(
-        state,
-        &wrapper.fee.token,
-        &wrapper.fee_payer(),
-    )
-    .unwrap();
+    let Some(code_hash) = tx
+        .get_section(tx.code_sechash())
+        .and_then(|section| section.code_sec())
+        .map(|code| code.code.hash())
+    else {
+        return Ok(());
+    };
 
-    let fees = wrapper
-        .get_tx_fee()
-        .map_err(|e| Error::FeeError(e.to_string()))?;
+    for (code_name, schema) in TX_DATA_SCHEMAS {
+        let registered_hash: Option<Hash> = storage
+            .read(&Key::wasm_code_name(code_name.to_string()))
+            .map_err(Error::StorageError)?;
+        if registered_hash == Some(code_hash) {
+            let data = tx.data().unwrap_or_default();
+            return schema(&data).map_err(|err| {
+                Error::InvalidTxData(code_name.to_string(), err.to_string())
+            });
+        }
+    }
+    Ok(())
+}
 
-    let fees = crate::token::denom_to_amount(fees, &wrapper.fee.token, state)
-        .map_err(|e| Error::FeeError(e.to_string()))?;
-    if balance.checked_sub(fees).is_some() {
-        Ok(())
-    } else {
-        Err(Error::FeeError(
-            "Insufficient transparent balance to pay fees".to_string(),
-        ))
+/// Load the wasm hash for a transfer from storage. Returns an error if the
+/// hash is not found in storage.
+pub fn get_transfer_hash_from_storage<S>(storage: &S) -> Result<Hash>
+where
+    S: StorageRead,
+{
+    let transfer_code_name_key =
+        Key::wasm_code_name(TX_TRANSFER_WASM.to_string());
+    storage
+        .read(&transfer_code_name_key)
+        .map_err(Error::StorageError)?
+        .ok_or(Error::MissingTransferHash)
+}
+
+/// Load the gas cost per byte of the wrapper txs from storage, falling back to
+/// the default cost when the parameter is missing
+pub fn get_wrapper_gas_per_byte<S>(storage: &S) -> Result<u64>
+where
+    S: StorageRead,
+{
+    Ok(namada_parameters::storage::get_wrapper_gas_per_byte(storage)
+        .map_err(Error::StorageError)?
+        .unwrap_or(WRAPPER_TX_GAS_PER_BYTE))
+}
+
+/// Check that the expiry height set by the extensions of a wrapper, if any,
+/// is not below the given block height. An expired wrapper can't pay its fees,
+/// so it must be rejected from the mempool and from the blocks too.
+pub fn check_wrapper_expiry(
+    tx: &Tx,
+    height: storage::BlockHeight,
+) -> Result<()> {
+    match tx.wrapper_expiry_height() {
+        Some(expiry) if expiry < height => {
+            Err(Error::ExpiryHeightInPast(expiry, height))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Performs the required operation on a wrapper transaction:
+///  - replay protection
+///  - fee payment, unless deferred by the [`FeePolicy::Postpaid`] policy
+///  - gas accounting
+///
+/// Returns the set of changed storage keys, the fee token and the resulting
+/// balance of the block proposer, if the fees were transferred to it.
+pub(crate) fn apply_wrapper_tx<S, D, H, CA>(
+    tx: Tx,
+    wrapper: &WrapperTx,
+    fee_unshield_transaction: Option<Transaction>,
+    tx_bytes: &[u8],
+    mut shell_params: ShellParams<'_, S, D, H, CA>,
+    mut wrapper_args: Option<&mut WrapperArgs>,
+) -> Result<(BTreeSet<Key>, Address, Option<Amount>)>
+where
+    S: State<D = D, H = H> + Sync,
+    D: 'static + DB + for<'iter> DBIter<'iter> + Sync,
+    H: 'static + StorageHasher + Sync,
+    CA: 'static + WasmCacheAccess + Sync,
+{
+    // The gas cost per byte is read at most once per wrapper
+    let gas_per_byte = match shell_params.wrapper_gas_per_byte {
+        Some(gas_per_byte) => gas_per_byte,
+        None => get_wrapper_gas_per_byte(&*shell_params.state)?,
+    };
+
+    // Reject a gas limit that can never succeed before any fee logic
+    let gas_limit = u64::from(wrapper.gas_limit);
+    if gas_limit == 0
+        || TxGasMeter::new(wrapper.gas_limit)
+            .add_wrapper_gas(tx_bytes, gas_per_byte)
+            .is_err()
+    {
+        return Err(Error::GasLimitTooLow(gas_limit));
+    }
+
+    // Reject a wrapper that could not fit in the block before charging any
+    // fee
+    if let Some(remaining_block_gas) = wrapper_args
+        .as_deref()
+        .and_then(|args| args.remaining_block_gas)
+    {
+        if gas_limit > remaining_block_gas {
+            return Err(Error::ExceedsBlockGas(gas_limit, remaining_block_gas));
+        }
+    }
+
+    // An expiry in the past would let the tx dodge the replay protection once
+    // its entries are pruned
+    check_wrapper_expiry(&tx, shell_params.state.in_mem().block.height)?;
+
+    let mut changed_keys = BTreeSet::default();
+
+    // Write wrapper tx hash to storage
+    shell_params
+        .state
+        .write_log_mut()
+        .write_tx_hash(tx.header_hash())
+        .expect("Error while writing tx hash to storage");
+    // Only the entry of the wrapper expires. Anyone could wrap the inner tx
+    // again with a later expiry, so the entry of the inner tx is permanent.
+    if let Some(expiry) = tx.wrapper_expiry_height() {
+        shell_params
+            .state
+            .write_log_mut()
+            .write_tx_expiry(tx.header_hash(), expiry);
     }
+
+    let (fee_token, proposer_balance) = match shell_params.fee_policy {
+        // Charge fee before performing any fallible operations
+        FeePolicy::Prepaid => charge_fee(
+            &fee_delegated_wrapper(&tx, wrapper),
+            &tx.wrapper_fee_token_fallbacks(),
+            fee_unshield_transaction,
+            &mut shell_params,
+            &mut changed_keys,
+            wrapper_args.as_deref_mut(),
+        )?,
+        // The fees are charged once the inner tx has been accepted, only
+        // commit the replay protection of the wrapper
+        FeePolicy::Postpaid => {
+            changed_keys.extend(
+                shell_params.state.write_log_mut().get_keys_with_precommit(),
+            );
+            shell_params.state.write_log_mut().commit_tx();
+            (wrapper.fee.token.clone(), None)
+        }
+    };
+
+    // Account for gas
+    shell_params
+        .tx_gas_meter
+        .borrow_mut()
+        .add_wrapper_gas(tx_bytes, gas_per_byte)
+        .map_err(|err| Error::GasError(err.to_string()))?;
+
+    // Audit the wrapper until the inner tx, which updates the record, has been
+    // applied
+    let gas_used = shell_params.tx_gas_meter.borrow().get_tx_consumed_gas();
+    shell_params.state.write_log_mut().write_tx_audit(
+        tx.header_hash(),
+        TxAudit {
+            gas_used: gas_used.into(),
+            fee_charged: wrapper_args
+                .as_deref()
+                .and_then(WrapperArgs::charged_fee),
+            accepted: false,
+        },
+    );
+
+    Ok((changed_keys, fee_token, proposer_balance))
 }
 
 /// Apply a transaction going via the wasm environment. Gas will be metered and
@@ -643,15 +1695,47 @@ where
         state,
         vp_wasm_cache,
         tx_wasm_cache,
+        block_accumulators,
+        shielded_policy: _,
+        event_sink,
+        reduce_chunk_size,
+        transfer_hash: _,
+        vp_observer,
+        fee_unwrap: _,
+        wrapper_gas_per_byte: _,
+        fee_policy: _,
     } = shell_params;
 
     let tx_hash = tx.raw_header_hash();
-    if state.write_log().has_replay_protection_entry(&tx_hash) {
-        // If the same transaction has already been applied in this block, skip
-        // execution and return
-        return Err(Error::ReplayAttempt(tx_hash));
+    // If the same transaction has already been applied, in this block or in a
+    // committed one, skip execution and return
+    if let Some(origin) = state
+        .replay_protection_entry_origin(&tx_hash)
+        .map_err(Error::StateError)?
+    {
+        if let Some(event_sink) = event_sink {
+            event_sink.borrow_mut().emit(ReplayRejected { tx_hash });
+        }
+        return Err(Error::ReplayAttempt(tx_hash, origin));
     }
 
+    // Read the limits before running the tx so that its write log cannot
+    // affect them
+    let (max_accounts_per_block, internal_access_limits) =
+        match block_accumulators {
+            Some(_) => (
+                namada_parameters::storage::get_max_accounts_per_block(
+                    &*state,
+                )
+                .map_err(Error::StorageError)?,
+                namada_parameters::storage::get_internal_access_limits(
+                    &*state,
+                )
+                .map_err(Error::StorageError)?,
+            ),
+            None => (None, None),
+        };
+
     let verifiers = execute_tx(
         &tx,
         tx_index,
@@ -661,6 +1745,14 @@ where
         tx_wasm_cache,
     )?;
 
+    let initialized_accounts = state.write_log().get_initialized_accounts();
+    if let Some(accumulators) = block_accumulators {
+        accumulators.borrow().check_initialized_accounts(
+            initialized_accounts.len(),
+            max_accounts_per_block,
+        )?;
+    }
+
     let vps_result = check_vps(CheckVps {
         tx: &tx,
         tx_index,
@@ -668,96 +1760,54 @@ where
         tx_gas_meter: &mut tx_gas_meter.borrow_mut(),
         verifiers_from_tx: &verifiers,
         vp_wasm_cache,
+        reduce_chunk_size,
+        vp_observer,
+        block_accumulators,
+        internal_access_limits: internal_access_limits.as_ref(),
     })?;
 
+    // Only the accounts of accepted txs end up in the block
+    if let Some(accumulators) = block_accumulators {
+        if vps_result.rejected_vps.is_empty() {
+            accumulators.borrow_mut().initialized_accounts +=
+                initialized_accounts.len() as u64;
+        }
+    }
+
     let gas_used = tx_gas_meter.borrow().get_tx_consumed_gas();
-    let initialized_accounts = state.write_log().get_initialized_accounts();
     let changed_keys = state.write_log().get_keys();
+    let tokens_touched = changed_keys
+        .iter()
+        .filter_map(crate::token::storage_key::is_any_token_balance_key)
+        .map(|[token, _owner]| token.clone())
+        .collect();
+    let written_bytes = state.write_log().get_written_bytes();
     let ibc_events = state.write_log_mut().take_ibc_events();
+    let eth_bridge_events = state.write_log_mut().take_eth_bridge_events();
+    #[cfg(any(test, feature = "debug-results"))]
+    let read_keys = {
+        let mut read_keys = state.write_log_mut().take_read_keys();
+        read_keys.extend(vps_result.read_keys.iter().cloned());
+        read_keys
+    };
 
     Ok(TxResult {
         gas_used,
-        wrapper_changed_keys: Default::default(),
         changed_keys,
         vps_result,
         initialized_accounts,
         ibc_events,
-        eth_bridge_events: BTreeSet::default(),
+        eth_bridge_events,
+        #[cfg(any(test, feature = "debug-results"))]
+        read_keys,
+        #[cfg(any(test, feature = "debug-results"))]
+        wasm_cache_read_write: Some(CA::is_read_write()),
+        written_bytes,
+        tokens_touched,
+        ..Default::default()
     })
 }
 
-/// Apply a derived transaction to storage based on some protocol transaction.
-/// The logic here must be completely deterministic and will be executed by all
-/// full nodes every time a protocol transaction is included in a block. Storage
-/// is updated natively rather than via the wasm environment, so gas does not
-/// need to be metered and validity predicates are bypassed. A [`TxResult`]
-/// containing changed keys and the like should be returned in the normal way.
-pub(crate) fn apply_protocol_tx<D, H>(
-    tx: ProtocolTxType,
-    data: Option<Vec<u8>>,
-    state: &mut WlState<D, H>,
-) -> Result<TxResult>
-where
-    D: 'static + DB + for<'iter> DBIter<'iter> + Sync,
-    H: 'static + StorageHasher + Sync,
-{
-    use namada_ethereum_bridge::protocol::transactions;
-    use namada_vote_ext::{ethereum_events, validator_set_update};
-
-    let Some(data) = data else {
-        return Err(Error::ProtocolTxError(eyre!(
-            "Protocol tx data must be present"
-        )));
-    };
-    let ethereum_tx_data = EthereumTxData::deserialize(&tx, &data)
-        .wrap_err_with(|| {
-            format!(
-                "Attempt made to apply an unsupported protocol transaction! - \
-                 {tx:?}",
-            )
-        })
-        .map_err(Error::ProtocolTxError)?;
-
-    match ethereum_tx_data {
-        EthereumTxData::EthEventsVext(
-            namada_vote_ext::ethereum_events::SignedVext(ext),
-        ) => {
-            let ethereum_events::VextDigest { events, .. } =
-                ethereum_events::VextDigest::singleton(ext);
-            transactions::ethereum_events::apply_derived_tx(state, events)
-                .map_err(Error::ProtocolTxError)
-        }
-        EthereumTxData::BridgePoolVext(ext) => {
-            transactions::bridge_pool_roots::apply_derived_tx(state, ext.into())
-                .map_err(Error::ProtocolTxError)
-        }
-        EthereumTxData::ValSetUpdateVext(ext) => {
-            // NOTE(feature = "abcipp"): with ABCI++, we can write the
-            // complete proof to storage in one go. the decided vote extension
-            // digest must already have >2/3 of the voting power behind it.
-            // with ABCI+, multiple vote extension protocol txs may be needed
-            // to reach a complete proof.
-            let signing_epoch = ext.data.signing_epoch;
-            transactions::validator_set_update::aggregate_votes(
-                state,
-                validator_set_update::VextDigest::singleton(ext),
-                signing_epoch,
-            )
-            .map_err(Error::ProtocolTxError)
-        }
-        EthereumTxData::EthereumEvents(_)
-        | EthereumTxData::BridgePool(_)
-        | EthereumTxData::ValidatorSetUpdate(_) => {
-            // TODO(namada#198): implement this
-            tracing::warn!(
-                "Attempt made to apply an unimplemented protocol transaction, \
-                 no actions will be taken"
-            );
-            Ok(TxResult::default())
-        }
-    }
-}
-
 /// Execute a transaction code. Returns verifiers requested by the transaction.
 #[allow(clippy::too_many_arguments)]
 fn execute_tx<S, D, H, CA>(
@@ -789,495 +1839,1945 @@ where
     })
 }
 
-/// Arguments to [`check_vps`].
-struct CheckVps<'a, S, CA>
-where
-    S: State,
-    CA: 'static + WasmCacheAccess + Sync,
-{
-    tx: &'a Tx,
-    tx_index: &'a TxIndex,
-    state: &'a S,
-    tx_gas_meter: &'a mut TxGasMeter,
-    verifiers_from_tx: &'a BTreeSet<Address>,
-    vp_wasm_cache: &'a mut VpCache<CA>,
-}
+#[cfg(test)]
+mod tests {
+    use borsh_ext::BorshSerializeExt;
+    use namada_core::collections::HashMap;
+    use namada_core::ethereum_structs::EthBridgeEvent;
+    use namada_core::keccak::keccak_hash;
+    use namada_core::key::RefTo;
+    use namada_core::storage::{BlockHeight, Epoch, KeySeg};
+    use namada_core::token::DenominatedAmount;
+    use namada_core::{address, key};
+    use namada_ethereum_bridge::test_utils;
+    use namada_sdk::testing::arb_masp_transfer_tx;
+    use namada_state::testing::TestState;
+    use namada_state::{StateRead, StorageWrite};
+    use namada_test_utils::tx_data::TxWriteData;
+    use namada_test_utils::TestWasms;
+    use namada_tx::data::protocol::ProtocolTxType;
+    use namada_tx::data::{Fee, GasLimit, WrapperExtension};
+    use namada_vote_ext::ethereum_events::EthereumEventsVext;
+    use proptest::prelude::*;
 
-/// Check the acceptance of a transaction by validity predicates
-fn check_vps<S, CA>(
-    CheckVps {
-        tx,
-        tx_index,
-        state,
-        tx_gas_meter,
-        verifiers_from_tx,
-        vp_wasm_cache,
-    }: CheckVps<'_, S, CA>,
-) -> Result<VpsResult>
-where
-    S: State + Sync,
-    CA: 'static + WasmCacheAccess + Sync,
-{
-    let (verifiers, keys_changed) = state
-        .write_log()
-        .verifiers_and_changed_keys(verifiers_from_tx);
+    use super::*;
+    use crate::vm::WasmCacheRwAccess;
+
+    /// The VP and tx wasm caches of a test
+    pub(super) fn wasm_caches()
+    -> (VpCache<WasmCacheRwAccess>, TxCache<WasmCacheRwAccess>) {
+        let (vp_cache, _) = wasm::compilation_cache::common::testing::cache();
+        let (tx_cache, _) = wasm::compilation_cache::common::testing::cache();
+        (vp_cache, tx_cache)
+    }
 
-    let vps_result = execute_vps(
-        verifiers,
-        keys_changed,
-        tx,
-        tx_index,
-        state,
-        tx_gas_meter,
-        vp_wasm_cache,
-    )?;
-    tracing::debug!("Total VPs gas cost {:?}", vps_result.gas_used);
+    /// Credit the amount of the token to the owner
+    pub(super) fn credit<S>(
+        state: &mut S,
+        token: &Address,
+        owner: &Address,
+        amount: Amount,
+    ) where
+        S: StorageRead + StorageWrite,
+    {
+        namada_token::credit_tokens(state, token, owner, amount).unwrap();
+    }
 
-    tx_gas_meter
-        .add_vps_gas(&vps_result.gas_used)
-        .map_err(|err| Error::GasError(err.to_string()))?;
+    /// A wrapper signed with the keypair, paying the amount per gas unit of
+    /// the token
+    pub(super) fn signed_wrapper(
+        keypair: &key::common::SecretKey,
+        token: Address,
+        amount_per_gas_unit: u64,
+        gas_limit: u64,
+    ) -> WrapperTx {
+        WrapperTx::new(
+            Fee {
+                amount_per_gas_unit: DenominatedAmount::native(
+                    amount_per_gas_unit.into(),
+                ),
+                token,
+            },
+            keypair.ref_to(),
+            Epoch(0),
+            GasLimit::from(gas_limit),
+            None,
+        )
+    }
 
-    Ok(vps_result)
-}
+    #[test]
+    /// Tests that the accounts initialized across the txs of a block are
+    /// capped by the `max_accounts_per_block` parameter.
+    fn test_max_accounts_per_block() {
+        let max_accounts_per_block = Some(5);
+        let mut accumulators = BlockAccumulators::default();
+
+        // two txs initializing two accounts each fit in the block
+        for _ in 0..2 {
+            accumulators
+                .check_initialized_accounts(2, max_accounts_per_block)
+                .unwrap();
+            accumulators.initialized_accounts += 2;
+        }
+        // a third one doesn't
+        assert!(matches!(
+            accumulators
+                .check_initialized_accounts(2, max_accounts_per_block)
+                .unwrap_err(),
+            Error::AccountsPerBlockExceeded(5)
+        ));
+        // but one filling the remaining slot does
+        accumulators
+            .check_initialized_accounts(1, max_accounts_per_block)
+            .unwrap();
+        // without the parameter there's no limit
+        accumulators.check_initialized_accounts(100, None).unwrap();
+    }
 
-/// Execute verifiers' validity predicates
-#[allow(clippy::too_many_arguments)]
-fn execute_vps<S, CA>(
-    verifiers: BTreeSet<Address>,
-    keys_changed: BTreeSet<storage::Key>,
-    tx: &Tx,
-    tx_index: &TxIndex,
-    state: &S,
-    tx_gas_meter: &TxGasMeter,
-    vp_wasm_cache: &mut VpCache<CA>,
-) -> Result<VpsResult>
-where
-    S: State + Sync,
-    CA: 'static + WasmCacheAccess + Sync,
-{
-    let vps_result = verifiers
-        .par_iter()
-        .try_fold(VpsResult::default, |mut result, addr| {
-            let gas_meter =
-                RefCell::new(VpGasMeter::new_from_tx_meter(tx_gas_meter));
-            let tx_accepted = match &addr {
-                Address::Implicit(_) | Address::Established(_) => {
-                    let (vp_hash, gas) = state
-                        .validity_predicate(addr)
-                        .map_err(Error::StateError)?;
-                    gas_meter
-                        .borrow_mut()
-                        .consume(gas)
-                        .map_err(|err| Error::GasError(err.to_string()))?;
-                    let Some(vp_code_hash) = vp_hash else {
-                        return Err(Error::MissingAddress(addr.clone()));
-                    };
-
-                    wasm::run::vp(
-                        vp_code_hash,
-                        tx,
-                        tx_index,
-                        addr,
-                        state,
-                        &gas_meter,
-                        &keys_changed,
-                        &verifiers,
-                        vp_wasm_cache.clone(),
-                    )
-                    .map_err(|err| match err {
-                        wasm::run::Error::GasError(msg) => Error::GasError(msg),
-                        wasm::run::Error::InvalidSectionSignature(msg) => {
-                            Error::InvalidSectionSignature(msg)
-                        }
-                        _ => Error::VpRunnerError(err),
-                    })
-                }
-                Address::Internal(internal_addr) => {
-                    let ctx = native_vp::Ctx::new(
-                        addr,
-                        state,
-                        tx,
-                        tx_index,
-                        &gas_meter,
-                        &keys_changed,
-                        &verifiers,
-                        vp_wasm_cache.clone(),
-                    );
-
-                    match internal_addr {
-                        InternalAddress::PoS => {
-                            let pos = PosVP { ctx };
-                            pos.validate_tx(tx, &keys_changed, &verifiers)
-                                .map_err(Error::PosNativeVpError)
-                        }
-                        InternalAddress::Ibc => {
-                            let ibc = Ibc { ctx };
-                            ibc.validate_tx(tx, &keys_changed, &verifiers)
-                                .map_err(Error::IbcNativeVpError)
-                        }
-                        InternalAddress::Parameters => {
-                            let parameters = ParametersVp { ctx };
-                            parameters
-                                .validate_tx(tx, &keys_changed, &verifiers)
-                                .map_err(Error::ParametersNativeVpError)
-                        }
-                        InternalAddress::PosSlashPool => Err(
-                            Error::AccessForbidden((*internal_addr).clone()),
-                        ),
-                        InternalAddress::Governance => {
-                            let governance = GovernanceVp { ctx };
-                            governance
-                                .validate_tx(tx, &keys_changed, &verifiers)
-                                .map_err(Error::GovernanceNativeVpError)
-                        }
-                        InternalAddress::Multitoken => {
-                            let multitoken = MultitokenVp { ctx };
-                            multitoken
-                                .validate_tx(tx, &keys_changed, &verifiers)
-                                .map_err(Error::MultitokenNativeVpError)
-                        }
-                        InternalAddress::EthBridge => {
-                            let bridge = EthBridge { ctx };
-                            bridge
-                                .validate_tx(tx, &keys_changed, &verifiers)
-                                .map_err(Error::EthBridgeNativeVpError)
-                        }
-                        InternalAddress::EthBridgePool => {
-                            let bridge_pool = BridgePoolVp { ctx };
-                            bridge_pool
-                                .validate_tx(tx, &keys_changed, &verifiers)
-                                .map_err(Error::BridgePoolNativeVpError)
-                        }
-                        InternalAddress::Pgf => {
-                            let pgf_vp = PgfVp { ctx };
-                            pgf_vp
-                                .validate_tx(tx, &keys_changed, &verifiers)
-                                .map_err(Error::PgfNativeVpError)
-                        }
-                        InternalAddress::Nut(_) => {
-                            let non_usable_tokens = NonUsableTokens { ctx };
-                            non_usable_tokens
-                                .validate_tx(tx, &keys_changed, &verifiers)
-                                .map_err(Error::NutNativeVpError)
-                        }
-                        internal_addr @ (InternalAddress::IbcToken(_)
-                        | InternalAddress::Erc20(_)) => {
-                            // The address should be a part of a multitoken
-                            // key
-                            verifiers
-                                .contains(&Address::Internal(
-                                    InternalAddress::Multitoken,
-                                ))
-                                .ok_or_else(|| {
-                                    Error::AccessForbidden(
-                                        internal_addr.clone(),
-                                    )
-                                })
-                        }
-                        InternalAddress::Masp => {
-                            let masp = MaspVp { ctx };
-                            masp.validate_tx(tx, &keys_changed, &verifiers)
-                                .map_err(Error::MaspNativeVpError)
-                        }
-                        InternalAddress::TempStorage => Err(
-                            // Temp storage changes must never be committed
-                            Error::AccessForbidden((*internal_addr).clone()),
-                        ),
-                    }
-                }
-            };
+    #[test]
+    /// Tests that the txs triggering the VP of an internal address are capped
+    /// per block by the `internal_access_limits` parameter
+    fn test_internal_access_limits() {
+        let ibc = Address::Internal(InternalAddress::Ibc);
+        let masp = Address::Internal(InternalAddress::Masp);
+        let limits = BTreeMap::from([(InternalAddress::Ibc, 2)]);
+        let mut accumulators = BlockAccumulators::default();
+
+        for _ in 0..2 {
+            accumulators
+                .count_internal_accesses(
+                    &BTreeSet::from([ibc.clone(), masp.clone()]),
+                    &limits,
+                )
+                .unwrap();
+        }
+        assert!(matches!(
+            accumulators
+                .count_internal_accesses(&BTreeSet::from([ibc]), &limits)
+                .unwrap_err(),
+            Error::AccessRateLimited(InternalAddress::Ibc)
+        ));
+        // only the limited addresses are counted
+        assert_eq!(
+            accumulators.internal_accesses,
+            BTreeMap::from([(InternalAddress::Ibc, 2)])
+        );
+        accumulators
+            .count_internal_accesses(&BTreeSet::from([masp]), &limits)
+            .unwrap();
+
+        // the limit is enforced before running the VPs of a tx
+        let (mut state, _) = test_utils::setup_default_storage();
+        let (mut vp_cache, _) =
+            wasm::compilation_cache::common::testing::cache();
+        let mut tx = Tx::from_type(TxType::Raw);
+        tx.set_code(namada_tx::Code::new(vec![], None));
+        tx.set_data(namada_tx::Data::new(vec![]));
+        let key = Key::from(
+            Address::Internal(InternalAddress::TempStorage).to_db_key(),
+        )
+        .push(&"temp".to_owned())
+        .unwrap();
+        state.write_log_mut().write(&key, vec![0]).unwrap();
+        let accumulators = RefCell::new(BlockAccumulators::default());
+        let result = check_vps(CheckVps {
+            tx: &tx,
+            tx_index: &TxIndex::default(),
+            state: &*state,
+            tx_gas_meter: &mut TxGasMeter::new(u64::MAX),
+            verifiers_from_tx: &BTreeSet::new(),
+            vp_wasm_cache: &mut vp_cache,
+            reduce_chunk_size: None,
+            vp_observer: None,
+            block_accumulators: Some(&accumulators),
+            internal_access_limits: Some(&BTreeMap::from([(
+                InternalAddress::TempStorage,
+                0,
+            )])),
+        });
+        assert!(matches!(
+            result.unwrap_err(),
+            Error::AccessRateLimited(InternalAddress::TempStorage)
+        ));
+    }
+
+    #[test]
+    /// Tests that the txs dispatched in a block are rejected once the accounts
+    /// they initialize exceed the `max_accounts_per_block` parameter
+    fn test_max_accounts_per_block_dispatch() {
+        let tx_no_op = TestWasms::TxNoOp.read_bytes();
+        let (mut state, _) = setup_batch_storage(&[&tx_no_op]);
+        let (mut vp_cache, mut tx_cache) = wasm_caches();
+        state
+            .write(
+                &namada_parameters::storage::get_max_accounts_per_block_key(),
+                1_u64,
+            )
+            .unwrap();
+        state.commit_tx();
+        state.commit_block().unwrap();
 
-            tx_accepted.map_or_else(
-                |err| {
-                    result
-                        .status_flags
-                        .insert(err.invalid_section_signature_flag());
-                    result.rejected_vps.insert(addr.clone());
-                    result.errors.push((addr.clone(), err.to_string()));
-                },
-                |()| {
-                    result.accepted_vps.insert(addr.clone());
-                },
+        let block_accumulators = RefCell::new(BlockAccumulators::default());
+        let mut results = vec![];
+        for entropy in 0..2_u8 {
+            // the tx initializes an account
+            let address_gen = state.in_mem().address_gen.clone();
+            state.write_log_mut().init_account(
+                &address_gen,
+                Hash::sha256(&tx_no_op),
+                &[entropy],
             );
+            let mut tx = Tx::from_type(TxType::Raw);
+            tx.set_code(namada_tx::Code::new(tx_no_op.clone(), None));
+            tx.set_data(namada_tx::Data::new(vec![entropy]));
+            let gas_meter = RefCell::new(TxGasMeter::new(u64::MAX));
+            let result = dispatch_tx(
+                tx,
+                &[],
+                TxIndex::default(),
+                &gas_meter,
+                state.restrict_writes_to_write_log(),
+                &mut vp_cache,
+                &mut tx_cache,
+                Some(&block_accumulators),
+                &DispatchArgs::default(),
+                None,
+            );
+            if result.is_ok() {
+                state.commit_tx();
+            } else {
+                state.write_log_mut().drop_tx();
+            }
+            results.push(result);
+        }
 
-            // Execution of VPs can (and must) be short-circuited
-            // only in case of a gas overflow to prevent the
-            // transaction from consuming resources that have not
-            // been acquired in the corresponding wrapper tx. For
-            // all the other errors we keep evaluating the vps. This
-            // allows to display a consistent VpsResult across all
-            // nodes and find any invalid signatures
-            result
-                .gas_used
-                .set(gas_meter.into_inner())
-                .map_err(|err| Error::GasError(err.to_string()))?;
+        let result = results[0].as_ref().unwrap();
+        assert!(result.is_accepted());
+        assert_eq!(result.initialized_accounts.len(), 1);
+        assert!(matches!(results[1], Err(Error::AccountsPerBlockExceeded(1))));
+        assert_eq!(block_accumulators.borrow().initialized_accounts, 1);
+    }
 
-            Ok(result)
-        })
-        .try_reduce(VpsResult::default, |a, b| {
-            merge_vp_results(a, b, tx_gas_meter)
-        })?;
+    #[test]
+    /// Tests that the wrapped txs dispatched in a block are rejected once they
+    /// trigger the VP of an internal address more often than allowed by the
+    /// `internal_access_limits` parameter
+    fn test_internal_access_limits_dispatch() {
+        let tx_write = TestWasms::TxWriteStorageKey.read_bytes();
+        let (mut state, keypair) = setup_batch_storage(&[&tx_write]);
+        let (mut vp_cache, mut tx_cache) = wasm_caches();
+        let block_proposer = address::testing::established_address_1();
+        state
+            .write(
+                &namada_parameters::storage::get_internal_access_limits_key(),
+                BTreeMap::from([(InternalAddress::Pgf, 1_u64)]),
+            )
+            .unwrap();
+        state.commit_tx();
+        state.commit_block().unwrap();
 
-    Ok(vps_result)
-}
+        let pgf = Address::Internal(InternalAddress::Pgf);
+        let key = Key::from(pgf.to_db_key())
+            .push(&"test".to_string())
+            .unwrap();
+        let block_accumulators = RefCell::new(BlockAccumulators::default());
+        let mut results = vec![];
+        for value in 0..2_u8 {
+            let mut tx = batch_wrapper(&keypair);
+            tx.set_code(namada_tx::Code::new(tx_write.clone(), None));
+            tx.set_data(namada_tx::Data::new(
+                TxWriteData {
+                    key: key.clone(),
+                    value: vec![value],
+                }
+                .serialize_to_vec(),
+            ));
+            let gas_meter = RefCell::new(TxGasMeter::new(u64::MAX));
+            results.push(dispatch_tx(
+                tx,
+                &[],
+                TxIndex::default(),
+                &gas_meter,
+                state.restrict_writes_to_write_log(),
+                &mut vp_cache,
+                &mut tx_cache,
+                Some(&block_accumulators),
+                &DispatchArgs::default(),
+                Some(&mut WrapperArgs::new(&block_proposer)),
+            ));
+            state.commit_tx();
+        }
 
-/// Merge VP results from parallel runs
-fn merge_vp_results(
-    a: VpsResult,
-    mut b: VpsResult,
-    tx_gas_meter: &TxGasMeter,
-) -> Result<VpsResult> {
-    let mut accepted_vps = a.accepted_vps;
-    let mut rejected_vps = a.rejected_vps;
-    accepted_vps.extend(b.accepted_vps);
-    rejected_vps.extend(b.rejected_vps);
-    let mut errors = a.errors;
-    errors.append(&mut b.errors);
-    let status_flags = a.status_flags | b.status_flags;
-    let mut gas_used = a.gas_used;
-
-    gas_used
-        .merge(b.gas_used, tx_gas_meter)
-        .map_err(|err| Error::GasError(err.to_string()))?;
+        // the first tx triggers the PGF VP, the second one is over the limit
+        let result = results[0].as_ref().unwrap();
+        assert!(
+            result.vps_result.accepted_vps.contains(&pgf)
+                || result.vps_result.rejected_vps.contains(&pgf)
+        );
+        assert!(matches!(
+            results[1],
+            Err(Error::AccessRateLimited(InternalAddress::Pgf))
+        ));
+        assert_eq!(
+            block_accumulators.borrow().internal_accesses,
+            BTreeMap::from([(InternalAddress::Pgf, 1)])
+        );
+    }
 
-    Ok(VpsResult {
-        accepted_vps,
-        rejected_vps,
-        gas_used,
-        errors,
-        status_flags,
-    })
-}
+    /// A pre-dispatch hook rejecting txs running the given code
+    struct RejectCodeHook(Hash);
+
+    impl<S> TxPreHook<S> for RejectCodeHook {
+        fn before_dispatch(
+            &self,
+            tx: &Tx,
+            _state: &S,
+        ) -> std::result::Result<(), String> {
+            let code_hash = tx
+                .get_section(tx.code_sechash())
+                .and_then(|section| Section::code_sec(section.as_ref()))
+                .map(|code_sec| code_sec.code.hash());
+            if code_hash == Some(self.0) {
+                Err(format!("Code {} is not allowed", self.0))
+            } else {
+                Ok(())
+            }
+        }
+    }
 
-#[cfg(test)]
-mod tests {
-    use eyre::Result;
-    use namada_core::collections::HashMap;
-    use namada_core::ethereum_events::testing::DAI_ERC20_ETH_ADDRESS;
-    use namada_core::ethereum_events::{EthereumEvent, TransferToNamada};
-    use namada_core::keccak::keccak_hash;
-    use namada_core::storage::BlockHeight;
-    use namada_core::voting_power::FractionalVotingPower;
-    use namada_core::{address, key};
-    use namada_ethereum_bridge::protocol::transactions::votes::{
-        EpochedVotingPower, Votes,
-    };
-    use namada_ethereum_bridge::storage::eth_bridge_queries::EthBridgeQueries;
-    use namada_ethereum_bridge::storage::proof::EthereumProof;
-    use namada_ethereum_bridge::storage::{vote_tallies, vp};
-    use namada_ethereum_bridge::test_utils;
-    use namada_tx::{SignableEthMessage, Signed};
-    use namada_vote_ext::bridge_pool_roots::BridgePoolRootVext;
-    use namada_vote_ext::ethereum_events::EthereumEventsVext;
+    /// A pre-hook counting the txs it was run on
+    #[derive(Default)]
+    struct CountHook(std::cell::Cell<usize>);
+
+    impl<S> TxPreHook<S> for CountHook {
+        fn before_dispatch(
+            &self,
+            _tx: &Tx,
+            _state: &S,
+        ) -> std::result::Result<(), String> {
+            self.0.set(self.0.get() + 1);
+            Ok(())
+        }
+    }
 
-    use super::*;
+    /// An invariant checking that the balances of the given holders add up to
+    /// the total supply of each token touched by a tx
+    struct SupplyConservation(Vec<Address>);
 
-    fn apply_eth_tx<D, H>(
-        tx: EthereumTxData,
-        state: &mut WlState<D, H>,
-    ) -> Result<TxResult>
+    impl<S> StateInvariant<S> for SupplyConservation
     where
-        D: 'static + DB + for<'iter> DBIter<'iter> + Sync,
-        H: 'static + StorageHasher + Sync,
+        S: StorageRead,
     {
-        let (data, tx) = tx.serialize();
-        let tx_result = apply_protocol_tx(tx, Some(data), state)?;
-        Ok(tx_result)
+        fn check(
+            &self,
+            result: &TxResult,
+            state: &S,
+        ) -> std::result::Result<(), String> {
+            for token in &result.tokens_touched {
+                let supply = namada_token::read_total_supply(state, token)
+                    .map_err(|e| e.to_string())?;
+                let mut balances = Amount::zero();
+                for holder in &self.0 {
+                    let balance =
+                        namada_token::read_balance(state, token, holder)
+                            .map_err(|e| e.to_string())?;
+                    balances = balances
+                        .checked_add(balance)
+                        .ok_or_else(|| "Balances overflow".to_string())?;
+                }
+                if balances != supply {
+                    return Err(format!(
+                        "The balances of {token} add up to {balances} for a \
+                         total supply of {supply}"
+                    ));
+                }
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    /// Tests that the access mode of the wasm caches used to apply a tx is
+    /// recorded in its result
+    fn test_apply_wasm_tx_cache_access_mode() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let (mut vp_cache, _vp_dir) =
+            wasm::compilation_cache::common::testing::cache();
+        let (mut tx_cache, _tx_dir) =
+            wasm::compilation_cache::common::testing::cache();
+
+        let tx_no_op = TestWasms::TxNoOp.read_bytes();
+        let code_hash = Hash::sha256(&tx_no_op);
+        let code_len = (tx_no_op.len() as u64).serialize_to_vec();
+        state
+            .write_log_mut()
+            .write(&Key::wasm_code(&code_hash), tx_no_op.serialize_to_vec())
+            .unwrap();
+        state
+            .write_log_mut()
+            .write(&Key::wasm_code_len(&code_hash), code_len)
+            .unwrap();
+        let mut tx = Tx::from_type(TxType::Raw);
+        tx.set_code(namada_tx::Code::new(tx_no_op, None));
+        tx.set_data(namada_tx::Data::new(vec![]));
+
+        // read/write access
+        let gas_meter = RefCell::new(TxGasMeter::new(u64::MAX));
+        let result = apply_wasm_tx(
+            tx.clone(),
+            &TxIndex::default(),
+            ShellParams::new(
+                &gas_meter,
+                state.restrict_writes_to_write_log(),
+                &mut vp_cache,
+                &mut tx_cache,
+            ),
+        )
+        .unwrap();
+        assert_eq!(result.wasm_cache_read_write, Some(true));
+
+        // read-only access
+        let gas_meter = RefCell::new(TxGasMeter::new(u64::MAX));
+        let result = apply_wasm_tx(
+            tx,
+            &TxIndex::default(),
+            ShellParams::new(
+                &gas_meter,
+                state.restrict_writes_to_write_log(),
+                &mut vp_cache.read_only(),
+                &mut tx_cache.read_only(),
+            ),
+        )
+        .unwrap();
+        assert_eq!(result.wasm_cache_read_write, Some(false));
+    }
+
+    #[test]
+    /// Tests that the result of a wrapper tx reports the byte size the wrapper
+    /// gas was charged for
+    fn test_wrapper_tx_bytes() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let (mut vp_cache, mut tx_cache) = wasm_caches();
+        let btc = address::testing::btc();
+        let keypair = key::testing::keypair_1();
+        let fee_payer = Address::from(&keypair.ref_to());
+        let block_proposer = address::testing::established_address_1();
+        namada_token::write_denom(&mut state, &btc, 8.into()).unwrap();
+        credit(&mut state, &btc, &fee_payer, Amount::from(100_000_000));
+
+        let tx_no_op = TestWasms::TxNoOp.read_bytes();
+        let code_hash = Hash::sha256(&tx_no_op);
+        let code_len = (tx_no_op.len() as u64).serialize_to_vec();
+        state
+            .write_log_mut()
+            .write(&Key::wasm_code(&code_hash), tx_no_op.serialize_to_vec())
+            .unwrap();
+        state
+            .write_log_mut()
+            .write(&Key::wasm_code_len(&code_hash), code_len)
+            .unwrap();
+        state.commit_tx();
+        state.commit_block().unwrap();
+
+        // enough gas for the wrapper bytes at the default cost
+        let tx_bytes = [0; 10];
+        let mut tx = Tx::from_type(TxType::Wrapper(Box::new(WrapperTx::new(
+            Fee {
+                amount_per_gas_unit: DenominatedAmount::new(
+                    1.into(),
+                    8.into(),
+                ),
+                token: btc,
+            },
+            keypair.ref_to(),
+            Epoch(0),
+            GasLimit::from(20_000_000),
+            None,
+        ))));
+        tx.set_code(namada_tx::Code::new(tx_no_op, None));
+        tx.set_data(namada_tx::Data::new(vec![]));
+
+        let gas_meter = RefCell::new(TxGasMeter::new(u64::MAX));
+        let result = dispatch_tx(
+            tx,
+            &tx_bytes,
+            TxIndex::default(),
+            &gas_meter,
+            state.restrict_writes_to_write_log(),
+            &mut vp_cache,
+            &mut tx_cache,
+            None,
+            &DispatchArgs::default(),
+            Some(&mut WrapperArgs::new(&block_proposer)),
+        )
+        .unwrap();
+        assert_eq!(result.wrapper_tx_bytes, Some(tx_bytes.len() as u64));
     }
 
     #[test]
-    /// Tests that if the same [`ProtocolTxType::EthEventsVext`] is applied
-    /// twice within the same block, it doesn't result in voting power being
-    /// double counted.
-    fn test_apply_protocol_tx_duplicate_eth_events_vext() -> Result<()> {
-        let validator_a = address::testing::established_address_2();
-        let validator_b = address::testing::established_address_3();
-        let validator_a_stake = Amount::native_whole(100);
-        let validator_b_stake = Amount::native_whole(100);
-        let total_stake = validator_a_stake + validator_b_stake;
-        let (mut state, _) = test_utils::setup_storage_with_validators(
-            HashMap::from_iter(vec![
-                (validator_a.clone(), validator_a_stake),
-                (validator_b, validator_b_stake),
-            ]),
+    /// Tests that a dry-run dispatch reports the effects of a wrapper without
+    /// persisting its fees, its replay protection entry, the block
+    /// accumulators or the events
+    fn test_dispatch_tx_dry_run() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let (mut vp_cache, mut tx_cache) = wasm_caches();
+        let nam = address::testing::nam();
+        let keypair = key::testing::keypair_1();
+        let fee_payer = Address::from(&keypair.ref_to());
+        let block_proposer = address::testing::established_address_1();
+        credit(&mut state, &nam, &fee_payer, Amount::from(1_000_000));
+
+        let tx_no_op = TestWasms::TxNoOp.read_bytes();
+        let code_hash = Hash::sha256(&tx_no_op);
+        let code_len = (tx_no_op.len() as u64).serialize_to_vec();
+        state
+            .write_log_mut()
+            .write(&Key::wasm_code(&code_hash), tx_no_op.serialize_to_vec())
+            .unwrap();
+        state
+            .write_log_mut()
+            .write(&Key::wasm_code_len(&code_hash), code_len)
+            .unwrap();
+        state.commit_tx();
+        state.commit_block().unwrap();
+
+        let mut tx = Tx::from_type(TxType::Wrapper(Box::new(signed_wrapper(
+            &keypair,
+            nam.clone(),
+            1,
+            1_000,
+        ))));
+        tx.set_code(namada_tx::Code::new(tx_no_op, None));
+        tx.set_data(namada_tx::Data::new(vec![]));
+        let wrapper_hash = tx.header_hash();
+
+        let block_accumulators = RefCell::new(BlockAccumulators::default());
+        let gas_meter = RefCell::new(TxGasMeter::new(u64::MAX));
+        let result = dispatch_tx_dry_run(
+            tx,
+            &[],
+            TxIndex::default(),
+            &gas_meter,
+            state.restrict_writes_to_write_log(),
+            &mut vp_cache,
+            &mut tx_cache,
+            Some(&block_accumulators),
+            &DispatchArgs::default(),
+            Some(&mut WrapperArgs::new(&block_proposer)),
+        )
+        .unwrap();
+        assert!(result.wrapper_changed_keys.contains(
+            &namada_token::storage_key::balance_key(&nam, &fee_payer)
+        ));
+        assert_eq!(result.fee_token, Some(nam.clone()));
+        assert!(result.gas_used > Gas::default());
+
+        // Nothing was persisted
+        assert_eq!(
+            namada_token::read_balance(&state, &nam, &fee_payer).unwrap(),
+            Amount::from(1_000_000)
+        );
+        assert_eq!(
+            namada_token::read_balance(&state, &nam, &block_proposer).unwrap(),
+            Amount::zero()
         );
-        let event = EthereumEvent::TransfersToNamada {
-            nonce: 0.into(),
-            transfers: vec![TransferToNamada {
-                amount: Amount::from(100),
-                asset: DAI_ERC20_ETH_ADDRESS,
-                receiver: address::testing::established_address_4(),
-            }],
+        assert!(!state.write_log().has_replay_protection_entry(&wrapper_hash));
+        assert!(block_accumulators.borrow().fee_tokens.is_empty());
+
+        // The events of a rejected replay are dropped too
+        let mut tx = Tx::from_type(TxType::Raw);
+        tx.set_code(namada_tx::Code::new(vec![], None));
+        tx.set_data(namada_tx::Data::new(vec![]));
+        state
+            .write_log_mut()
+            .write_tx_hash(tx.raw_header_hash())
+            .unwrap();
+        let event_sink = RefCell::new(vec![]);
+        let dispatch_args = DispatchArgs {
+            event_sink: Some(&event_sink),
+            ..Default::default()
         };
-        let vext = EthereumEventsVext {
-            block_height: BlockHeight(100),
-            validator_addr: address::testing::established_address_2(),
-            ethereum_events: vec![event.clone()],
+        let result = dispatch_tx_dry_run(
+            tx,
+            &[],
+            TxIndex::default(),
+            &gas_meter,
+            state.restrict_writes_to_write_log(),
+            &mut vp_cache,
+            &mut tx_cache,
+            Some(&block_accumulators),
+            &dispatch_args,
+            None,
+        );
+        assert!(matches!(result.unwrap_err(), Error::ReplayAttempt(..)));
+        assert!(event_sink.borrow().is_empty());
+    }
+
+    #[test]
+    /// Tests that the written bytes reflect the size of the balances written
+    /// by a transfer
+    fn test_written_bytes() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let (mut vp_cache, mut tx_cache) = wasm_caches();
+        let nam = address::testing::nam();
+        let src = address::testing::established_address_1();
+        let dest = address::testing::established_address_2();
+        namada_token::credit_tokens(&mut state, &nam, &src, 1_000.into())
+            .unwrap();
+        state.commit_tx();
+
+        let tx_no_op = TestWasms::TxNoOp.read_bytes();
+        let vp_always_true = TestWasms::VpAlwaysTrue.read_bytes();
+        for code in [&tx_no_op, &vp_always_true] {
+            let code_hash = Hash::sha256(code);
+            let code_len = (code.len() as u64).serialize_to_vec();
+            state
+                .write_log_mut()
+                .write(&Key::wasm_code(&code_hash), code.serialize_to_vec())
+                .unwrap();
+            state
+                .write_log_mut()
+                .write(&Key::wasm_code_len(&code_hash), code_len)
+                .unwrap();
+        }
+        // the owners of the balances accept the transfer
+        let vp_hash = Hash::sha256(&vp_always_true);
+        for owner in [&src, &dest] {
+            state
+                .write_log_mut()
+                .write(
+                    &Key::validity_predicate(owner),
+                    vp_hash.serialize_to_vec(),
+                )
+                .unwrap();
+        }
+        state.commit_tx();
+        state.commit_block().unwrap();
+
+        namada_token::transfer(&mut state, &nam, &src, &dest, 400.into())
+            .unwrap();
+        let balance_bytes = |owner: &Address| {
+            namada_token::read_balance(&state, &nam, owner)
+                .unwrap()
+                .serialize_to_vec()
+                .len() as u64
         };
-        let signing_key = key::testing::keypair_1();
-        let signed = vext.sign(&signing_key);
-        let tx = EthereumTxData::EthEventsVext(
-            namada_vote_ext::ethereum_events::SignedVext(signed),
+        let expected = balance_bytes(&src) + balance_bytes(&dest);
+        assert_eq!(state.write_log().get_written_bytes(), expected);
+
+        let mut tx = Tx::from_type(TxType::Raw);
+        tx.set_code(namada_tx::Code::new(tx_no_op, None));
+        tx.set_data(namada_tx::Data::new(vec![]));
+        let gas_meter = RefCell::new(TxGasMeter::new(u64::MAX));
+        let result = apply_wasm_tx(
+            tx,
+            &TxIndex::default(),
+            ShellParams::new(
+                &gas_meter,
+                state.restrict_writes_to_write_log(),
+                &mut vp_cache,
+                &mut tx_cache,
+            ),
+        )
+        .unwrap();
+        assert_eq!(result.written_bytes, expected);
+    }
+
+    #[test]
+    /// Tests that the outcomes of the VPs of a dispatched tx are streamed
+    /// through the channel and match the aggregated result
+    fn test_dispatch_tx_streaming() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let (mut vp_cache, mut tx_cache) = wasm_caches();
+        let nam = address::testing::nam();
+        let src = address::testing::established_address_1();
+        let dest = address::testing::established_address_2();
+        namada_token::credit_tokens(&mut state, &nam, &src, 1_000.into())
+            .unwrap();
+        state.commit_tx();
+
+        let tx_no_op = TestWasms::TxNoOp.read_bytes();
+        let vp_always_true = TestWasms::VpAlwaysTrue.read_bytes();
+        for code in [&tx_no_op, &vp_always_true] {
+            let code_hash = Hash::sha256(code);
+            let code_len = (code.len() as u64).serialize_to_vec();
+            state
+                .write_log_mut()
+                .write(&Key::wasm_code(&code_hash), code.serialize_to_vec())
+                .unwrap();
+            state
+                .write_log_mut()
+                .write(&Key::wasm_code_len(&code_hash), code_len)
+                .unwrap();
+        }
+        let vp_hash = Hash::sha256(&vp_always_true);
+        for owner in [&src, &dest] {
+            state
+                .write_log_mut()
+                .write(
+                    &Key::validity_predicate(owner),
+                    vp_hash.serialize_to_vec(),
+                )
+                .unwrap();
+        }
+        state.commit_tx();
+        state.commit_block().unwrap();
+
+        namada_token::transfer(&mut state, &nam, &src, &dest, 400.into())
+            .unwrap();
+        let mut tx = Tx::from_type(TxType::Raw);
+        tx.set_code(namada_tx::Code::new(tx_no_op, None));
+        tx.set_data(namada_tx::Data::new(vec![]));
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let gas_meter = RefCell::new(TxGasMeter::new(u64::MAX));
+        let result = dispatch_tx_streaming(
+            tx,
+            &[],
+            TxIndex::default(),
+            &gas_meter,
+            state.restrict_writes_to_write_log(),
+            &mut vp_cache,
+            &mut tx_cache,
+            None,
+            &DispatchArgs::default(),
+            None,
+            sender,
+        )
+        .unwrap();
+        let streamed: Vec<_> = receiver.try_iter().collect();
+        let vps_result = &result.vps_result;
+        assert_eq!(
+            streamed.len(),
+            vps_result.accepted_vps.len() + vps_result.rejected_vps.len()
         );
+        for (addr, accepted) in &streamed {
+            assert_eq!(*accepted, vps_result.accepted_vps.contains(addr));
+        }
+        assert!(streamed.iter().any(|(addr, _)| *addr == src));
+        assert!(streamed.iter().any(|(addr, _)| *addr == dest));
+    }
 
-        apply_eth_tx(tx.clone(), &mut state)?;
-        apply_eth_tx(tx, &mut state)?;
+    #[test]
+    /// Tests that the tokens with a balance changed by a tx are reported
+    fn test_tokens_touched() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let (mut vp_cache, mut tx_cache) = wasm_caches();
+        let nam = address::testing::nam();
+        let btc = address::testing::btc();
+        let src = address::testing::established_address_1();
+        let dest = address::testing::established_address_2();
+        for token in [&nam, &btc] {
+            namada_token::credit_tokens(&mut state, token, &src, 1_000.into())
+                .unwrap();
+        }
+        state.commit_tx();
 
-        let eth_msg_keys = vote_tallies::Keys::from(&event);
-        let seen_by: Votes = state.read(&eth_msg_keys.seen_by())?.unwrap();
-        assert_eq!(seen_by, Votes::from([(validator_a, BlockHeight(100))]));
+        let tx_no_op = TestWasms::TxNoOp.read_bytes();
+        let vp_always_true = TestWasms::VpAlwaysTrue.read_bytes();
+        for code in [&tx_no_op, &vp_always_true] {
+            let code_hash = Hash::sha256(code);
+            let code_len = (code.len() as u64).serialize_to_vec();
+            state
+                .write_log_mut()
+                .write(&Key::wasm_code(&code_hash), code.serialize_to_vec())
+                .unwrap();
+            state
+                .write_log_mut()
+                .write(&Key::wasm_code_len(&code_hash), code_len)
+                .unwrap();
+        }
+        // the owners of the balances accept the transfers
+        let vp_hash = Hash::sha256(&vp_always_true);
+        for owner in [&src, &dest] {
+            state
+                .write_log_mut()
+                .write(
+                    &Key::validity_predicate(owner),
+                    vp_hash.serialize_to_vec(),
+                )
+                .unwrap();
+        }
+        state.commit_tx();
+        state.commit_block().unwrap();
 
-        // the vote should have only be applied once
-        let voting_power: EpochedVotingPower =
-            state.read(&eth_msg_keys.voting_power())?.unwrap();
-        let expected = EpochedVotingPower::from([(
-            0.into(),
-            FractionalVotingPower::HALF * total_stake,
-        )]);
-        assert_eq!(voting_power, expected);
+        let mut tx = Tx::from_type(TxType::Raw);
+        tx.set_code(namada_tx::Code::new(tx_no_op, None));
+        tx.set_data(namada_tx::Data::new(vec![]));
 
-        Ok(())
+        // a single token transfer
+        namada_token::transfer(&mut state, &nam, &src, &dest, 400.into())
+            .unwrap();
+        let gas_meter = RefCell::new(TxGasMeter::new(u64::MAX));
+        let result = apply_wasm_tx(
+            tx.clone(),
+            &TxIndex::default(),
+            ShellParams::new(
+                &gas_meter,
+                state.restrict_writes_to_write_log(),
+                &mut vp_cache,
+                &mut tx_cache,
+            ),
+        )
+        .unwrap();
+        assert_eq!(result.tokens_touched, BTreeSet::from([nam.clone()]));
+
+        // a multi-token transfer
+        namada_token::transfer(&mut state, &btc, &src, &dest, 400.into())
+            .unwrap();
+        let gas_meter = RefCell::new(TxGasMeter::new(u64::MAX));
+        let result = apply_wasm_tx(
+            tx,
+            &TxIndex::default(),
+            ShellParams::new(
+                &gas_meter,
+                state.restrict_writes_to_write_log(),
+                &mut vp_cache,
+                &mut tx_cache,
+            ),
+        )
+        .unwrap();
+        assert_eq!(result.tokens_touched, BTreeSet::from([nam, btc]));
+    }
+
+    #[test]
+    /// Tests that the audit record of a dispatched wrapper is committed along
+    /// with the replay protection entries, with the fees actually charged
+    fn test_replay_protection_audit() {
+        let tx_no_op = TestWasms::TxNoOp.read_bytes();
+        let (mut state, keypair) = setup_batch_storage(&[&tx_no_op]);
+        let (mut vp_cache, mut tx_cache) = wasm_caches();
+        let block_proposer = address::testing::established_address_1();
+
+        let mut tx = batch_wrapper(&keypair);
+        tx.set_code(namada_tx::Code::new(tx_no_op, None));
+        tx.set_data(namada_tx::Data::new(vec![]));
+        let wrapper_hash = tx.header_hash();
+        let inner_hash = tx.raw_header_hash();
+        let gas_meter = RefCell::new(TxGasMeter::new(u64::MAX));
+        let result = dispatch_tx(
+            tx,
+            &[],
+            TxIndex::default(),
+            &gas_meter,
+            state.restrict_writes_to_write_log(),
+            &mut vp_cache,
+            &mut tx_cache,
+            None,
+            &DispatchArgs::default(),
+            Some(&mut WrapperArgs::new(&block_proposer)),
+        )
+        .unwrap();
+        assert!(result.is_accepted());
+        state.write_log_mut().write_tx_hash(inner_hash).unwrap();
+        state.commit_tx();
+        state.commit_block().unwrap();
+
+        // the fees of 1_000 charged in the native token
+        let audit = TxAudit {
+            gas_used: result.gas_used.into(),
+            fee_charged: Some((address::testing::nam(), Amount::from(1_000))),
+            accepted: true,
+        };
+        for hash in [wrapper_hash, inner_hash] {
+            assert_eq!(
+                state.read_replay_protection_audit(&hash).unwrap(),
+                Some(audit.clone())
+            );
+        }
+    }
+
+    #[test]
+    /// Tests that the Ethereum bridge events emitted by a tx are reported
+    fn test_apply_wasm_tx_eth_bridge_events() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let (mut vp_cache, mut tx_cache) = wasm_caches();
+
+        let tx_no_op = TestWasms::TxNoOp.read_bytes();
+        let code_hash = Hash::sha256(&tx_no_op);
+        let code_len = (tx_no_op.len() as u64).serialize_to_vec();
+        state
+            .write_log_mut()
+            .write(&Key::wasm_code(&code_hash), tx_no_op.serialize_to_vec())
+            .unwrap();
+        state
+            .write_log_mut()
+            .write(&Key::wasm_code_len(&code_hash), code_len)
+            .unwrap();
+        state.commit_tx();
+        state.commit_block().unwrap();
+
+        let mut tx = Tx::from_type(TxType::Raw);
+        tx.set_code(namada_tx::Code::new(tx_no_op, None));
+        tx.set_data(namada_tx::Data::new(vec![]));
+        let event =
+            EthBridgeEvent::new_bridge_pool_relayed(keccak_hash(b"transfer"));
+        state.write_log_mut().emit_eth_bridge_event(event.clone());
+        let gas_meter = RefCell::new(TxGasMeter::new(u64::MAX));
+        let result = apply_wasm_tx(
+            tx,
+            &TxIndex::default(),
+            ShellParams::new(
+                &gas_meter,
+                state.restrict_writes_to_write_log(),
+                &mut vp_cache,
+                &mut tx_cache,
+            ),
+        )
+        .unwrap();
+        assert_eq!(result.eth_bridge_events, BTreeSet::from([event]));
+        assert!(state.write_log_mut().take_eth_bridge_events().is_empty());
     }
 
     #[test]
-    /// Tests that if the same [`ProtocolTxType::BridgePoolVext`] is applied
-    /// twice within the same block, it doesn't result in voting power being
-    /// double counted.
-    fn test_apply_protocol_tx_duplicate_bp_roots_vext() -> Result<()> {
-        let validator_a = address::testing::established_address_2();
-        let validator_b = address::testing::established_address_3();
-        let validator_a_stake = Amount::native_whole(100);
-        let validator_b_stake = Amount::native_whole(100);
-        let total_stake = validator_a_stake + validator_b_stake;
-        let (mut state, keys) = test_utils::setup_storage_with_validators(
-            HashMap::from_iter(vec![
-                (validator_a.clone(), validator_a_stake),
-                (validator_b, validator_b_stake),
-            ]),
+    /// Tests that a wrapper with a zero gas limit is rejected before charging
+    /// the fees
+    fn test_wrapper_gas_limit_too_low() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let (mut vp_cache, mut tx_cache) = wasm_caches();
+        let nam = address::testing::nam();
+        let keypair = key::testing::keypair_1();
+        let fee_payer = Address::from(&keypair.ref_to());
+        let block_proposer = address::testing::established_address_1();
+        credit(&mut state, &nam, &fee_payer, Amount::native_whole(1_000));
+        state.commit_tx();
+        state.commit_block().unwrap();
+
+        let wrapper = signed_wrapper(&keypair, nam.clone(), 1, 0);
+        let tx = Tx::from_type(TxType::Wrapper(Box::new(wrapper.clone())));
+
+        let gas_meter = RefCell::new(TxGasMeter::new(u64::MAX));
+        let result = apply_wrapper_tx(
+            tx,
+            &wrapper,
+            None,
+            &[],
+            ShellParams::new(
+                &gas_meter,
+                state.restrict_writes_to_write_log(),
+                &mut vp_cache,
+                &mut tx_cache,
+            ),
+            Some(&mut WrapperArgs::new(&block_proposer)),
+        );
+        assert!(matches!(result.unwrap_err(), Error::GasLimitTooLow(0)));
+        // no fee was charged nor the tx hash written
+        assert_eq!(
+            namada_token::read_balance(&state, &nam, &fee_payer).unwrap(),
+            Amount::native_whole(1_000)
         );
-        vp::bridge_pool::init_storage(&mut state);
+        assert!(state.write_log().get_keys().is_empty());
+        assert_eq!(gas_meter.into_inner().get_tx_consumed_gas(), 0.into());
+    }
 
-        let root = state.ethbridge_queries().get_bridge_pool_root();
-        let nonce = state.ethbridge_queries().get_bridge_pool_nonce();
-        test_utils::commit_bridge_pool_root_at_height(
-            &mut state,
-            &root,
-            100.into(),
+    #[test]
+    /// Tests that a wrapper can't set an expiry height below the current
+    /// block height to dodge the replay protection
+    fn test_wrapper_expiry_height() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let (mut vp_cache, mut tx_cache) = wasm_caches();
+        let nam = address::testing::nam();
+        let keypair = key::testing::keypair_1();
+        let fee_payer = Address::from(&keypair.ref_to());
+        let block_proposer = address::testing::established_address_1();
+        credit(&mut state, &nam, &fee_payer, Amount::native_whole(1_000));
+        state.commit_tx();
+        state.in_mem_mut().begin_block(BlockHeight(10)).unwrap();
+
+        let mut apply_wrapper = |expiry_height| {
+            let wrapper = signed_wrapper(&keypair, nam.clone(), 1, 1_000);
+            let mut tx =
+                Tx::from_type(TxType::Wrapper(Box::new(wrapper.clone())));
+            tx.add_wrapper_extension(WrapperExtension::ExpiryHeight(
+                BlockHeight(expiry_height),
+            ));
+            let hash = tx.header_hash();
+            let gas_meter = RefCell::new(TxGasMeter::new(u64::MAX));
+            let result = apply_wrapper_tx(
+                tx,
+                &wrapper,
+                None,
+                &[],
+                ShellParams::new(
+                    &gas_meter,
+                    state.restrict_writes_to_write_log(),
+                    &mut vp_cache,
+                    &mut tx_cache,
+                ),
+                Some(&mut WrapperArgs::new(&block_proposer)),
+            );
+            let written = state.write_log().has_replay_protection_entry(&hash);
+            (result, written)
+        };
+
+        let (result, written) = apply_wrapper(9);
+        assert!(matches!(
+            result.unwrap_err(),
+            Error::ExpiryHeightInPast(BlockHeight(9), BlockHeight(10))
+        ));
+        assert!(!written);
+
+        // the tx can still be applied at its expiry height
+        let (result, written) = apply_wrapper(10);
+        assert!(result.is_ok());
+        assert!(written);
+    }
+
+    #[test]
+    /// Tests that the gas of the wrapper bytes is charged at the cost per byte
+    /// set in storage, unless already loaded for the block
+    fn test_wrapper_gas_per_byte() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let (mut vp_cache, mut tx_cache) = wasm_caches();
+        let nam = address::testing::nam();
+        let keypair = key::testing::keypair_1();
+        let fee_payer = Address::from(&keypair.ref_to());
+        let block_proposer = address::testing::established_address_1();
+        credit(&mut state, &nam, &fee_payer, Amount::native_whole(1_000));
+        assert_eq!(
+            get_wrapper_gas_per_byte(&state).unwrap(),
+            WRAPPER_TX_GAS_PER_BYTE
         );
-        let to_sign = keccak_hash([root.0, nonce.to_bytes()].concat());
-        let signing_key = key::testing::keypair_1();
-        let hot_key =
-            &keys[&address::testing::established_address_2()].eth_bridge;
-        let sig = Signed::<_, SignableEthMessage>::new(hot_key, to_sign).sig;
-        let vext = BridgePoolRootVext {
-            block_height: BlockHeight(100),
-            validator_addr: address::testing::established_address_2(),
-            sig,
+        state
+            .write(
+                &namada_parameters::storage::get_wrapper_gas_per_byte_key(),
+                10 * WRAPPER_TX_GAS_PER_BYTE,
+            )
+            .unwrap();
+        state.commit_tx();
+        state.commit_block().unwrap();
+        assert_eq!(
+            get_wrapper_gas_per_byte(&state).unwrap(),
+            10 * WRAPPER_TX_GAS_PER_BYTE
+        );
+
+        // enough gas for the wrapper bytes at the default cost only
+        let gas_limit = 20_000_000;
+        let tx_bytes = [0; 10];
+        let wrapper = signed_wrapper(&keypair, nam.clone(), 1, gas_limit);
+        let tx = Tx::from_type(TxType::Wrapper(Box::new(wrapper.clone())));
+
+        for cached_gas_per_byte in [None, Some(WRAPPER_TX_GAS_PER_BYTE)] {
+            let gas_meter = RefCell::new(TxGasMeter::new(gas_limit));
+            let mut shell_params = ShellParams::new(
+                &gas_meter,
+                state.restrict_writes_to_write_log(),
+                &mut vp_cache,
+                &mut tx_cache,
+            );
+            shell_params.wrapper_gas_per_byte = cached_gas_per_byte;
+            let result = apply_wrapper_tx(
+                tx.clone(),
+                &wrapper,
+                None,
+                &tx_bytes,
+                shell_params,
+                Some(&mut WrapperArgs::new(&block_proposer)),
+            );
+            match cached_gas_per_byte {
+                None => assert!(matches!(
+                    result.unwrap_err(),
+                    Error::GasLimitTooLow(limit) if limit == gas_limit
+                )),
+                Some(_) => assert!(result.is_ok()),
+            }
         }
-        .sign(&signing_key);
-        let tx = EthereumTxData::BridgePoolVext(vext);
-        apply_eth_tx(tx.clone(), &mut state)?;
-        apply_eth_tx(tx, &mut state)?;
+    }
 
-        let bp_root_keys = vote_tallies::Keys::from((
-            &vote_tallies::BridgePoolRoot(EthereumProof::new((root, nonce))),
-            100.into(),
+    #[test]
+    /// Tests that a wrapper whose gas limit exceeds the gas left in the block
+    /// is rejected before charging the fees
+    fn test_wrapper_exceeds_block_gas() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let (mut vp_cache, mut tx_cache) = wasm_caches();
+        let nam = address::testing::nam();
+        let keypair = key::testing::keypair_1();
+        let fee_payer = Address::from(&keypair.ref_to());
+        let block_proposer = address::testing::established_address_1();
+        credit(&mut state, &nam, &fee_payer, Amount::native_whole(1_000));
+        state.commit_tx();
+        state.commit_block().unwrap();
+
+        let wrapper = signed_wrapper(&keypair, nam.clone(), 1, 1_000);
+        let tx = Tx::from_type(TxType::Wrapper(Box::new(wrapper.clone())));
+
+        // a near-full block
+        let gas_meter = RefCell::new(TxGasMeter::new(u64::MAX));
+        let result = apply_wrapper_tx(
+            tx,
+            &wrapper,
+            None,
+            &[],
+            ShellParams::new(
+                &gas_meter,
+                state.restrict_writes_to_write_log(),
+                &mut vp_cache,
+                &mut tx_cache,
+            ),
+            Some(&mut WrapperArgs {
+                remaining_block_gas: Some(999),
+                ..WrapperArgs::new(&block_proposer)
+            }),
+        );
+        assert!(matches!(
+            result.unwrap_err(),
+            Error::ExceedsBlockGas(1_000, 999)
         ));
-        let root_seen_by: Votes = state.read(&bp_root_keys.seen_by())?.unwrap();
+        // no fee was charged
         assert_eq!(
-            root_seen_by,
-            Votes::from([(validator_a, BlockHeight(100))])
+            namada_token::read_balance(&state, &nam, &fee_payer).unwrap(),
+            Amount::native_whole(1_000)
         );
-        // the vote should have only be applied once
-        let voting_power: EpochedVotingPower =
-            state.read(&bp_root_keys.voting_power())?.unwrap();
-        let expected = EpochedVotingPower::from([(
-            0.into(),
-            FractionalVotingPower::HALF * total_stake,
-        )]);
-        assert_eq!(voting_power, expected);
+        assert!(state.write_log().get_keys().is_empty());
+    }
 
-        Ok(())
+    #[test]
+    /// Tests that the rejection of a replayed tx emits an event
+    fn test_replay_rejected_event() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let (mut vp_cache, mut tx_cache) = wasm_caches();
+
+        let mut tx = Tx::from_type(TxType::Raw);
+        tx.set_code(namada_tx::Code::new(vec![], None));
+        tx.set_data(namada_tx::Data::new(vec![]));
+        let tx_hash = tx.raw_header_hash();
+        // the tx was already applied in this block
+        state.write_log_mut().write_tx_hash(tx_hash).unwrap();
+
+        let event_sink = RefCell::new(vec![]);
+        let gas_meter = RefCell::new(TxGasMeter::new(u64::MAX));
+        let mut shell_params = ShellParams::new(
+            &gas_meter,
+            state.restrict_writes_to_write_log(),
+            &mut vp_cache,
+            &mut tx_cache,
+        );
+        shell_params.event_sink = Some(&event_sink);
+        let result = apply_wasm_tx(tx, &TxIndex::default(), shell_params);
+        assert!(matches!(
+            result.unwrap_err(),
+            Error::ReplayAttempt(hash, ReplayOrigin::CurrentBlock)
+                if hash == tx_hash
+        ));
+
+        let events = event_sink.into_inner();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].event_type, EventType::ReplayRejected);
+        assert_eq!(events[0]["hash"], tx_hash.to_string());
+    }
+
+    #[test]
+    /// Tests that a replay of a tx committed in a previous block is told apart
+    /// from a replay within the current block
+    fn test_replay_origin() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let (mut vp_cache, mut tx_cache) = wasm_caches();
+
+        let mut tx = Tx::from_type(TxType::Raw);
+        tx.set_code(namada_tx::Code::new(vec![], None));
+        tx.set_data(namada_tx::Data::new(vec![]));
+        let tx_hash = tx.raw_header_hash();
+        // the tx was applied in a committed block
+        state.write_log_mut().write_tx_hash(tx_hash).unwrap();
+        state.commit_block().unwrap();
+        assert!(!state.write_log().has_replay_protection_entry(&tx_hash));
+
+        let gas_meter = RefCell::new(TxGasMeter::new(u64::MAX));
+        let err = apply_wasm_tx(
+            tx,
+            &TxIndex::default(),
+            ShellParams::new(
+                &gas_meter,
+                state.restrict_writes_to_write_log(),
+                &mut vp_cache,
+                &mut tx_cache,
+            ),
+        )
+        .unwrap_err();
+        assert!(matches!(
+            err,
+            Error::ReplayAttempt(hash, ReplayOrigin::CommittedBlock)
+                if hash == tx_hash
+        ));
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "The transaction {tx_hash} has already been applied in a \
+                 committed block"
+            )
+        );
     }
 
     #[test]
-    fn test_native_vp_out_of_gas() {
-        let (mut state, _validators) = test_utils::setup_default_storage();
+    /// Tests that a pre-dispatch hook can reject a tx before it gets applied
+    fn test_dispatch_tx_pre_hook() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let (mut vp_cache, mut tx_cache) = wasm_caches();
+
+        let mut tx = Tx::from_type(TxType::Raw);
+        tx.set_code(namada_tx::Code::new(vec![0xde, 0xad], None));
+        tx.set_data(namada_tx::Data::new(vec![]));
+        let code_hash = namada_tx::data::hash_tx(&[0xde, 0xad]);
+
+        let hook = RejectCodeHook(code_hash);
+        let pre_hooks: [&dyn TxPreHook<_>; 1] = [&hook];
+        let dispatch_args = DispatchArgs {
+            pre_hooks: &pre_hooks,
+            ..Default::default()
+        };
+        let gas_meter = RefCell::new(TxGasMeter::new(u64::MAX));
 
-        // some random token address
-        let token_address = Address::Established([0xff; 20].into());
+        let result = dispatch_tx(
+            tx,
+            &[],
+            TxIndex::default(),
+            &gas_meter,
+            state.restrict_writes_to_write_log(),
+            &mut vp_cache,
+            &mut tx_cache,
+            None,
+            &dispatch_args,
+            None,
+        );
+        assert!(matches!(
+            result.unwrap_err(),
+            Error::PreHookRejected(msg) if msg.contains(&code_hash.to_string())
+        ));
+    }
 
-        let src_address = Address::Established([0xab; 20].into());
-        let dst_address = Address::Established([0xba; 20].into());
+    #[test]
+    /// Tests that a protocol tx with missing or malformed data is rejected
+    /// before being applied
+    fn test_dispatch_tx_protocol_tx_data() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let (mut vp_cache, mut tx_cache) = wasm_caches();
+
+        let mut tx = Tx::from_type(TxType::Protocol(Box::new(
+            namada_tx::data::protocol::ProtocolTx {
+                pk: key::testing::keypair_1().ref_to(),
+                tx: ProtocolTxType::EthEventsVext,
+            },
+        )));
+        let mut dispatch = |tx: Tx| {
+            let gas_meter = RefCell::new(TxGasMeter::new(u64::MAX));
+            dispatch_tx(
+                tx,
+                &[],
+                TxIndex::default(),
+                &gas_meter,
+                state.restrict_writes_to_write_log(),
+                &mut vp_cache,
+                &mut tx_cache,
+                None,
+                &DispatchArgs::default(),
+                None,
+            )
+        };
 
-        // supply an address with 1000 of said token
-        namada_token::credit_tokens(
-            &mut state,
-            &token_address,
-            &src_address,
-            1000.into(),
+        assert!(matches!(
+            dispatch(tx.clone()).unwrap_err(),
+            Error::MissingProtocolTxData(_)
+        ));
+        tx.set_data(namada_tx::Data::new(vec![0xff]));
+        assert!(matches!(
+            dispatch(tx).unwrap_err(),
+            Error::InvalidProtocolTxData(tx_type, _)
+                if tx_type == "EthEventsVext"
+        ));
+    }
+
+    #[test]
+    /// Tests that a tx violating a state invariant is rejected after being
+    /// applied, while the one preserving it is accepted
+    fn test_dispatch_tx_invariants() {
+        /// Transfer some tokens between the holders
+        fn apply_transfer<D, H>(
+            state: &mut WlState<D, H>,
+            _data: EthereumTxData,
+        ) -> eyre::Result<TxResult>
+        where
+            D: 'static + DB + for<'iter> DBIter<'iter> + Sync,
+            H: 'static + StorageHasher + Sync,
+        {
+            let btc = address::testing::btc();
+            let src = address::testing::established_address_1();
+            let dest = address::testing::established_address_2();
+            namada_token::transfer(state, &btc, &src, &dest, 400.into())?;
+            Ok(TxResult {
+                changed_keys: BTreeSet::from([
+                    namada_token::storage_key::balance_key(&btc, &src),
+                    namada_token::storage_key::balance_key(&btc, &dest),
+                ]),
+                tokens_touched: BTreeSet::from([btc]),
+                ..Default::default()
+            })
+        }
+
+        /// Credit some tokens without minting them
+        fn apply_inflation<D, H>(
+            state: &mut WlState<D, H>,
+            _data: EthereumTxData,
+        ) -> eyre::Result<TxResult>
+        where
+            D: 'static + DB + for<'iter> DBIter<'iter> + Sync,
+            H: 'static + StorageHasher + Sync,
+        {
+            let btc = address::testing::btc();
+            let dest = address::testing::established_address_2();
+            let key = namada_token::storage_key::balance_key(&btc, &dest);
+            let balance = namada_token::read_balance(state, &btc, &dest)?;
+            let balance = balance.checked_add(500.into()).unwrap();
+            state.write_log_mut().write(&key, balance.serialize_to_vec())?;
+            Ok(TxResult {
+                changed_keys: BTreeSet::from([key]),
+                tokens_touched: BTreeSet::from([btc]),
+                ..Default::default()
+            })
+        }
+
+        let (mut state, _) = test_utils::setup_default_storage();
+        let (mut vp_cache, mut tx_cache) = wasm_caches();
+        let btc = address::testing::btc();
+        let src = address::testing::established_address_1();
+        let dest = address::testing::established_address_2();
+        namada_token::credit_tokens(&mut state, &btc, &src, 1_000.into())
+            .unwrap();
+        state.commit_tx();
+
+        let vext = EthereumEventsVext {
+            block_height: BlockHeight(100),
+            validator_addr: address::testing::established_address_2(),
+            ethereum_events: vec![],
+        }
+        .sign(&key::testing::keypair_1());
+        let (data, tx_type) = EthereumTxData::EthEventsVext(
+            namada_vote_ext::ethereum_events::SignedVext(vext),
         )
-        .unwrap();
+        .serialize();
+        let mut tx = Tx::from_type(TxType::Protocol(Box::new(
+            namada_tx::data::protocol::ProtocolTx {
+                pk: key::testing::keypair_1().ref_to(),
+                tx: tx_type.clone(),
+            },
+        )));
+        tx.set_data(namada_tx::Data::new(data));
+
+        let invariant = SupplyConservation(vec![src, dest]);
+        let invariants: [&dyn StateInvariant<_>; 1] = [&invariant];
+        let mut handlers = ProtocolTxHandlers::default();
+        let mut dispatch =
+            |state: &mut TestState, handler: ProtocolTxHandler<_, _>| {
+                handlers.register(tx_type.clone(), handler);
+                let gas_meter = RefCell::new(TxGasMeter::new(u64::MAX));
+                dispatch_tx(
+                    tx.clone(),
+                    &[],
+                    TxIndex::default(),
+                    &gas_meter,
+                    state.restrict_writes_to_write_log(),
+                    &mut vp_cache,
+                    &mut tx_cache,
+                    None,
+                    &DispatchArgs {
+                        invariants: &invariants,
+                        protocol_tx_handlers: Some(&handlers),
+                        ..Default::default()
+                    },
+                    None,
+                )
+            };
 
-        // commit storage changes. this will act as the
-        // initial state of the chain
+        let result = dispatch(&mut state, apply_transfer).unwrap();
+        assert!(result.is_accepted());
         state.commit_tx();
-        state.commit_block().unwrap();
 
-        // "execute" a dummy tx, by manually performing its state changes
-        let (dummy_tx, changed_keys, verifiers) = {
-            let mut tx = Tx::from_type(TxType::Raw);
-            tx.set_code(namada_tx::Code::new(vec![], None));
-            tx.set_data(namada_tx::Data::new(vec![]));
-
-            // transfer half of the supply of src to dst
-            namada_token::transfer(
-                &mut state,
-                &token_address,
-                &src_address,
-                &dst_address,
-                500.into(),
-            )
+        let err = dispatch(&mut state, apply_inflation).unwrap_err();
+        assert!(matches!(
+            err,
+            Error::InvariantViolated(msg) if msg.contains(&btc.to_string())
+        ));
+    }
+
+    #[test]
+    /// Tests that a tx carrying a section that nothing references is only
+    /// rejected in strict mode
+    fn test_dispatch_tx_strict_sections() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let (mut vp_cache, mut tx_cache) = wasm_caches();
+
+        let tx_no_op = TestWasms::TxNoOp.read_bytes();
+        let code_hash = Hash::sha256(&tx_no_op);
+        let code_len = (tx_no_op.len() as u64).serialize_to_vec();
+        state
+            .write_log_mut()
+            .write(&Key::wasm_code(&code_hash), tx_no_op.serialize_to_vec())
             .unwrap();
+        state
+            .write_log_mut()
+            .write(&Key::wasm_code_len(&code_hash), code_len)
+            .unwrap();
+        state.commit_tx();
+        state.commit_block().unwrap();
 
-            let changed_keys = {
-                let mut set = BTreeSet::new();
-                set.insert(namada_token::storage_key::balance_key(
-                    &token_address,
-                    &src_address,
-                ));
-                set.insert(namada_token::storage_key::balance_key(
-                    &token_address,
-                    &dst_address,
+        let mut tx = Tx::from_type(TxType::Raw);
+        tx.set_code(namada_tx::Code::new(tx_no_op, None));
+        tx.set_data(namada_tx::Data::new(vec![]));
+        // nothing references this header
+        let header = Section::Header(tx.header());
+        let header_hash = header.get_hash();
+        tx.add_section(header);
+
+        for strict_sections in [true, false] {
+            let dispatch_args = DispatchArgs {
+                strict_sections,
+                ..Default::default()
+            };
+            let gas_meter = RefCell::new(TxGasMeter::new(u64::MAX));
+            let result = dispatch_tx(
+                tx.clone(),
+                &[],
+                TxIndex::default(),
+                &gas_meter,
+                state.restrict_writes_to_write_log(),
+                &mut vp_cache,
+                &mut tx_cache,
+                None,
+                &dispatch_args,
+                None,
+            );
+            if strict_sections {
+                assert!(matches!(
+                    result.unwrap_err(),
+                    Error::UnknownSection(hash) if hash == header_hash
                 ));
-                set
+            } else {
+                assert!(result.unwrap().is_accepted());
+            }
+        }
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(8))]
+
+        /// Tests that the sections of a shielded transfer built by the SDK,
+        /// including its MASP builder, are all referenced, before and after
+        /// signing the wrapper
+        #[test]
+        fn test_masp_transfer_sections_referenced(
+            (mut tx, _) in arb_masp_transfer_tx()
+        ) {
+            assert!(
+                tx.sections
+                    .iter()
+                    .any(|section| matches!(section, Section::MaspBuilder(_)))
+            );
+            assert!(check_referenced_sections(&tx).is_ok());
+
+            tx.sign_wrapper(key::testing::keypair_1());
+            assert!(check_referenced_sections(&tx).is_ok());
+        }
+    }
+
+    #[test]
+    /// Tests that an accepted tx changing nothing is only flagged as a no-op
+    /// when requested
+    fn test_dispatch_tx_flag_noop() {
+        let tx_no_op = TestWasms::TxNoOp.read_bytes();
+        let (mut state, _) = setup_batch_storage(&[&tx_no_op]);
+        let (mut vp_cache, mut tx_cache) = wasm_caches();
+
+        let mut tx = Tx::from_type(TxType::Raw);
+        tx.set_code(namada_tx::Code::new(tx_no_op, None));
+        tx.set_data(namada_tx::Data::new(vec![]));
+
+        for flag_noop_txs in [false, true] {
+            let dispatch_args = DispatchArgs {
+                flag_noop_txs,
+                ..Default::default()
             };
+            let gas_meter = RefCell::new(TxGasMeter::new(u64::MAX));
+            let result = dispatch_tx(
+                tx.clone(),
+                &[],
+                TxIndex::default(),
+                &gas_meter,
+                state.restrict_writes_to_write_log(),
+                &mut vp_cache,
+                &mut tx_cache,
+                None,
+                &dispatch_args,
+                None,
+            )
+            .unwrap();
+            assert!(result.is_accepted());
+            assert!(result.changed_keys.is_empty());
+            assert_eq!(result.is_noop, flag_noop_txs);
+        }
+    }
+
+    #[test]
+    /// Tests that malformed data of a tx with a registered schema is rejected
+    /// before the execution, when requested
+    fn test_dispatch_tx_validate_tx_data() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let (mut vp_cache, mut tx_cache) = wasm_caches();
+
+        // register the no-op code as the steward commission tx
+        let tx_no_op = TestWasms::TxNoOp.read_bytes();
+        let code_hash = Hash::sha256(&tx_no_op);
+        let code_len = (tx_no_op.len() as u64).serialize_to_vec();
+        state
+            .write_log_mut()
+            .write(&Key::wasm_code(&code_hash), tx_no_op.serialize_to_vec())
+            .unwrap();
+        state
+            .write_log_mut()
+            .write(&Key::wasm_code_len(&code_hash), code_len)
+            .unwrap();
+        state
+            .write_log_mut()
+            .write(
+                &Key::wasm_code_name(TX_UPDATE_STEWARD_COMMISSION.to_string()),
+                code_hash.serialize_to_vec(),
+            )
+            .unwrap();
+        state.commit_tx();
+        state.commit_block().unwrap();
 
-            let verifiers = {
-                let mut set = BTreeSet::new();
-                set.insert(Address::Internal(InternalAddress::Multitoken));
-                set
+        let mut tx = Tx::from_type(TxType::Raw);
+        tx.set_code(namada_tx::Code::new(tx_no_op, None));
+        tx.set_data(namada_tx::Data::new(vec![0xff; 3]));
+
+        for validate_tx_data in [true, false] {
+            let dispatch_args = DispatchArgs {
+                validate_tx_data,
+                ..Default::default()
             };
+            let gas_meter = RefCell::new(TxGasMeter::new(u64::MAX));
+            let result = dispatch_tx(
+                tx.clone(),
+                &[],
+                TxIndex::default(),
+                &gas_meter,
+                state.restrict_writes_to_write_log(),
+                &mut vp_cache,
+                &mut tx_cache,
+                None,
+                &dispatch_args,
+                None,
+            );
+            if validate_tx_data {
+                assert!(matches!(
+                    result.unwrap_err(),
+                    Error::InvalidTxData(code_name, _)
+                        if code_name == TX_UPDATE_STEWARD_COMMISSION
+                ));
+                // nothing was executed
+                assert_eq!(
+                    gas_meter.borrow().get_tx_consumed_gas(),
+                    Gas::default()
+                );
+            } else {
+                assert!(result.unwrap().is_accepted());
+            }
+        }
 
-            (tx, changed_keys, verifiers)
+        // well-formed data passes the validation
+        let data = UpdateStewardCommission {
+            steward: address::testing::established_address_1(),
+            commission: HashMap::new(),
+        };
+        tx.set_data(namada_tx::Data::new(data.serialize_to_vec()));
+        let dispatch_args = DispatchArgs {
+            validate_tx_data: true,
+            ..Default::default()
         };
+        let gas_meter = RefCell::new(TxGasMeter::new(u64::MAX));
+        let result = dispatch_tx(
+            tx,
+            &[],
+            TxIndex::default(),
+            &gas_meter,
+            state.restrict_writes_to_write_log(),
+            &mut vp_cache,
+            &mut tx_cache,
+            None,
+            &dispatch_args,
+            None,
+        );
+        assert!(result.unwrap().is_accepted());
+    }
 
-        // temp vp cache
-        let (mut vp_cache, _) =
-            wasm::compilation_cache::common::testing::cache();
+    #[test]
+    /// Tests that each way of applying a tx maps to the right outcome
+    fn test_apply_tx_outcome() {
+        let tx_no_op = TestWasms::TxNoOp.read_bytes();
+        let tx_write = TestWasms::TxWriteStorageKey.read_bytes();
+        let vp_always_false = TestWasms::VpAlwaysFalse.read_bytes();
+        let (mut state, _) =
+            setup_batch_storage(&[&tx_no_op, &tx_write, &vp_always_false]);
+        let (mut vp_cache, mut tx_cache) = wasm_caches();
+        let rejecting = address::testing::established_address_3();
+        state
+            .write_log_mut()
+            .write(
+                &Key::validity_predicate(&rejecting),
+                Hash::sha256(&vp_always_false).serialize_to_vec(),
+            )
+            .unwrap();
+        state.commit_tx();
+        state.commit_block().unwrap();
 
-        // gas meter with no gas left
-        let gas_meter = TxGasMeter::new(0);
+        let mut apply = |state: &mut TestState,
+                         tx: Tx,
+                         dispatch_args: &DispatchArgs<'_, _, _>,
+                         wrapper_args: Option<&mut WrapperArgs>| {
+            let gas_meter = RefCell::new(TxGasMeter::new(u64::MAX));
+            let outcome = apply_tx(
+                tx,
+                &[],
+                TxIndex::default(),
+                &gas_meter,
+                state.restrict_writes_to_write_log(),
+                &mut vp_cache,
+                &mut tx_cache,
+                None,
+                dispatch_args,
+                wrapper_args,
+            );
+            state.write_log_mut().drop_tx();
+            outcome
+        };
 
-        let result = execute_vps(
-            verifiers,
-            changed_keys,
-            &dummy_tx,
-            &TxIndex::default(),
-            &state,
+        // accepted by all the VPs
+        let mut tx = Tx::from_type(TxType::Raw);
+        tx.set_code(namada_tx::Code::new(tx_no_op.clone(), None));
+        tx.set_data(namada_tx::Data::new(vec![]));
+        let outcome =
+            apply(&mut state, tx.clone(), &DispatchArgs::default(), None);
+        assert!(outcome.is_accepted());
+
+        // already applied in this block
+        let tx_hash = tx.raw_header_hash();
+        state.write_log_mut().write_tx_hash(tx_hash).unwrap();
+        let outcome =
+            apply(&mut state, tx.clone(), &DispatchArgs::default(), None);
+        assert!(matches!(
+            outcome,
+            TxOutcome::Replayed(hash) if hash == tx_hash
+        ));
+
+        // rejected by a VP
+        let key = Key::from(rejecting.to_db_key())
+            .push(&"test".to_string())
+            .unwrap();
+        let mut write_tx = Tx::from_type(TxType::Raw);
+        write_tx.set_code(namada_tx::Code::new(tx_write, None));
+        write_tx.set_data(namada_tx::Data::new(
+            TxWriteData {
+                key,
+                value: "test".as_bytes().to_vec(),
+            }
+            .serialize_to_vec(),
+        ));
+        let outcome =
+            apply(&mut state, write_tx, &DispatchArgs::default(), None);
+        assert!(matches!(
+            outcome,
+            TxOutcome::Rejected { vps, result }
+                if vps == BTreeSet::from([rejecting.clone()])
+                    && vps == result.vps_result.rejected_vps
+        ));
+
+        // the wrapper can't pay for the fees
+        let block_proposer = address::testing::established_address_1();
+        let mut wrapper = batch_wrapper(&key::testing::keypair_2());
+        wrapper.set_code(namada_tx::Code::new(tx_no_op.clone(), None));
+        wrapper.set_data(namada_tx::Data::new(vec![]));
+        let outcome = apply(
+            &mut state,
+            wrapper,
+            &DispatchArgs::default(),
+            Some(&mut WrapperArgs::new(&block_proposer)),
+        );
+        assert!(matches!(outcome, TxOutcome::FeeError(_)));
+
+        // any other failure
+        let hook = RejectCodeHook(Hash::sha256(&tx_no_op));
+        let pre_hooks: [&dyn TxPreHook<_>; 1] = [&hook];
+        let dispatch_args = DispatchArgs {
+            pre_hooks: &pre_hooks,
+            ..Default::default()
+        };
+        let outcome = apply(&mut state, tx, &dispatch_args, None);
+        assert!(matches!(
+            outcome,
+            TxOutcome::Failed(Error::PreHookRejected(_))
+        ));
+    }
+
+    /// Setup the storage with funds for the fees of a wrapper signed with
+    /// the returned keypair and with the given tx and VP codes
+    pub(super) fn setup_batch_storage(
+        codes: &[&[u8]],
+    ) -> (TestState, key::common::SecretKey) {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let keypair = key::testing::keypair_1();
+        credit(
+            &mut state,
+            &address::testing::nam(),
+            &Address::from(&keypair.ref_to()),
+            Amount::from(1_000_000),
+        );
+        for code in codes {
+            let code_hash = Hash::sha256(code);
+            let code_len = (code.len() as u64).serialize_to_vec();
+            state
+                .write_log_mut()
+                .write(
+                    &Key::wasm_code(&code_hash),
+                    code.to_vec().serialize_to_vec(),
+                )
+                .unwrap();
+            state
+                .write_log_mut()
+                .write(&Key::wasm_code_len(&code_hash), code_len)
+                .unwrap();
+        }
+        state.commit_tx();
+        state.commit_block().unwrap();
+        (state, keypair)
+    }
+
+    pub(super) fn batch_wrapper(keypair: &key::common::SecretKey) -> Tx {
+        Tx::from_type(TxType::Wrapper(Box::new(signed_wrapper(
+            &keypair,
+            address::testing::nam(),
+            1,
+            1_000,
+        ))))
+    }
+
+    /// A wrapper committing to the headers of the given inner txs
+    fn committed_batch_wrapper(
+        keypair: &key::common::SecretKey,
+        inner_txs: &[Tx],
+    ) -> Tx {
+        let mut wrapper = batch_wrapper(keypair);
+        for tx in inner_txs {
+            wrapper.add_section(Section::Header(tx.header()));
+        }
+        wrapper
+    }
+
+    #[test]
+    /// Tests that the inner txs of a batch are applied under a single wrapper
+    fn test_dispatch_batch() {
+        let tx_no_op = TestWasms::TxNoOp.read_bytes();
+        let (mut state, keypair) = setup_batch_storage(&[&tx_no_op]);
+        let (mut vp_cache, mut tx_cache) = wasm_caches();
+        let block_proposer = address::testing::established_address_1();
+
+        let inner_txs: Vec<Tx> = (0..2_u8)
+            .map(|i| {
+                let mut tx = Tx::from_type(TxType::Raw);
+                tx.set_code(namada_tx::Code::new(tx_no_op.clone(), None));
+                tx.set_data(namada_tx::Data::new(vec![i]));
+                tx
+            })
+            .collect();
+        let inner_hashes: Vec<Hash> =
+            inner_txs.iter().map(Tx::raw_header_hash).collect();
+
+        // the wrapper must commit to the inner txs in order
+        for wrapper in [
+            batch_wrapper(&keypair),
+            committed_batch_wrapper(&keypair, &inner_txs[..1]),
+            committed_batch_wrapper(
+                &keypair,
+                &[inner_txs[1].clone(), inner_txs[0].clone()],
+            ),
+        ] {
+            let gas_meter = RefCell::new(TxGasMeter::new(u64::MAX));
+            let result = dispatch_batch(
+                wrapper,
+                inner_txs.clone(),
+                &[],
+                TxIndex::default(),
+                &gas_meter,
+                state.restrict_writes_to_write_log(),
+                &mut vp_cache,
+                &mut tx_cache,
+                None,
+                &DispatchArgs::default(),
+                Some(&mut WrapperArgs::new(&block_proposer)),
+            );
+            assert!(matches!(result.unwrap_err(), Error::InvalidBatch(_)));
+        }
+
+        // the same inner tx can't be batched twice
+        let duplicated = vec![inner_txs[0].clone(), inner_txs[0].clone()];
+        let gas_meter = RefCell::new(TxGasMeter::new(u64::MAX));
+        let result = dispatch_batch(
+            committed_batch_wrapper(&keypair, &duplicated),
+            duplicated,
+            &[],
+            TxIndex::default(),
             &gas_meter,
+            state.restrict_writes_to_write_log(),
             &mut vp_cache,
+            &mut tx_cache,
+            None,
+            &DispatchArgs::default(),
+            Some(&mut WrapperArgs::new(&block_proposer)),
         );
-        assert!(matches!(result.unwrap_err(), Error::GasError(_)));
+        assert!(matches!(
+            result.unwrap_err(),
+            Error::ReplayAttempt(hash, ReplayOrigin::CurrentBlock)
+                if hash == inner_hashes[0]
+        ));
+
+        let hook = CountHook::default();
+        let pre_hooks: [&dyn TxPreHook<_>; 1] = [&hook];
+        let dispatch_args = DispatchArgs {
+            pre_hooks: &pre_hooks,
+            ..Default::default()
+        };
+        let gas_meter = RefCell::new(TxGasMeter::new(u64::MAX));
+        let result = dispatch_batch(
+            committed_batch_wrapper(&keypair, &inner_txs),
+            inner_txs,
+            &[],
+            TxIndex::default(),
+            &gas_meter,
+            state.restrict_writes_to_write_log(),
+            &mut vp_cache,
+            &mut tx_cache,
+            None,
+            &dispatch_args,
+            Some(&mut WrapperArgs::new(&block_proposer)),
+        )
+        .unwrap();
+        assert!(result.is_accepted());
+        // the pre-hooks were run once for the whole batch
+        assert_eq!(hook.0.get(), 1);
+        // the inner txs are protected from replays
+        for hash in &inner_hashes {
+            assert!(state.write_log().has_replay_protection_entry(hash));
+        }
+        assert_eq!(result.fee_token, Some(address::testing::nam()));
+        assert_eq!(result.gas_used, gas_meter.borrow().get_tx_consumed_gas());
+        // the gas of the batch is broken down by inner tx, the rest being the
+        // gas of the wrapper
+        assert_eq!(result.per_inner_gas.len(), 2);
+        assert!(result.per_inner_gas.iter().all(|gas| *gas > 0));
+        assert_eq!(
+            result.per_inner_gas.iter().sum::<u64>(),
+            result.inner_gas_used
+        );
+        assert!(result.inner_gas_used < u64::from(result.gas_used));
+        assert_eq!(
+            result
+                .batch_results
+                .iter()
+                .map(|(hash, res)| (*hash, res.is_ok()))
+                .collect::<Vec<_>>(),
+            inner_hashes
+                .into_iter()
+                .map(|hash| (hash, true))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    /// Tests that the changes of a batch are rolled back when one of its
+    /// inner txs is rejected and that the rejected member is reported
+    fn test_dispatch_batch_rollback() {
+        let tx_write = TestWasms::TxWriteStorageKey.read_bytes();
+        let vp_always_true = TestWasms::VpAlwaysTrue.read_bytes();
+        let vp_always_false = TestWasms::VpAlwaysFalse.read_bytes();
+        let (mut state, keypair) = setup_batch_storage(&[
+            &tx_write,
+            &vp_always_true,
+            &vp_always_false,
+        ]);
+        let (mut vp_cache, mut tx_cache) = wasm_caches();
+        let block_proposer = address::testing::established_address_1();
+
+        // the first member writes to an accepting account, the second one to
+        // a rejecting account
+        let accepting = address::testing::established_address_2();
+        let rejecting = address::testing::established_address_3();
+        let mut inner_txs = vec![];
+        for (owner, vp) in
+            [(&accepting, &vp_always_true), (&rejecting, &vp_always_false)]
+        {
+            state
+                .write_log_mut()
+                .write(
+                    &Key::validity_predicate(owner),
+                    Hash::sha256(vp).serialize_to_vec(),
+                )
+                .unwrap();
+            let key = Key::from(owner.to_db_key())
+                .push(&"test".to_string())
+                .unwrap();
+            let mut tx = Tx::from_type(TxType::Raw);
+            tx.set_code(namada_tx::Code::new(tx_write.clone(), None));
+            tx.set_data(namada_tx::Data::new(
+                TxWriteData {
+                    key: key.clone(),
+                    value: "test".as_bytes().to_vec(),
+                }
+                .serialize_to_vec(),
+            ));
+            inner_txs.push((key, tx));
+        }
+        state.commit_tx();
+        state.commit_block().unwrap();
+        let (keys, inner_txs): (Vec<Key>, Vec<Tx>) =
+            inner_txs.into_iter().unzip();
+        let inner_hashes: Vec<Hash> =
+            inner_txs.iter().map(Tx::raw_header_hash).collect();
+
+        let gas_meter = RefCell::new(TxGasMeter::new(u64::MAX));
+        let result = dispatch_batch(
+            committed_batch_wrapper(&keypair, &inner_txs),
+            inner_txs,
+            &[],
+            TxIndex::default(),
+            &gas_meter,
+            state.restrict_writes_to_write_log(),
+            &mut vp_cache,
+            &mut tx_cache,
+            None,
+            &DispatchArgs::default(),
+            Some(&mut WrapperArgs::new(&block_proposer)),
+        )
+        .unwrap();
+        assert!(!result.is_accepted());
+        assert_eq!(result.vps_result.rejected_vps, BTreeSet::from([rejecting]));
+        assert_eq!(result.batch_results.len(), 2);
+        assert_eq!(result.batch_results[0].0, inner_hashes[0]);
+        assert!(result.batch_results[0].1.is_ok());
+        assert_eq!(result.batch_results[1].0, inner_hashes[1]);
+        assert!(result.batch_results[1].1.is_err());
+        // the inner txs of the rejected batch can be submitted again
+        for hash in &inner_hashes {
+            assert!(!state.write_log().has_replay_protection_entry(hash));
+        }
+
+        // none of the writes of the batch were kept
+        for key in &keys {
+            assert!(state.write_log().read(key).0.is_none());
+        }
+        // but the fees were charged
+        let balance = namada_token::read_balance(
+            &state,
+            &address::testing::nam(),
+            &Address::from(&keypair.ref_to()),
+        )
+        .unwrap();
+        assert!(balance < Amount::from(1_000_000));
     }
 }
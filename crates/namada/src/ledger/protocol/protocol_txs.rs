@@ -0,0 +1,1175 @@
+//! The native application of the protocol transactions
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Debug;
+
+use eyre::{eyre, WrapErr};
+use namada_core::storage::Key;
+use namada_tx::data::protocol::ProtocolTxType;
+use namada_tx::data::TxResult;
+use namada_vote_ext::{eth_hot_key_rotation, EthereumTxData};
+
+use super::{Error, Result};
+use crate::address::{Address, InternalAddress};
+use crate::ledger::pos::{self, PosQueries};
+use crate::state::{DBIter, StorageHasher, WlState, DB};
+use crate::storage;
+
+/// A handler applying the data of a protocol tx natively to storage
+pub type ProtocolTxHandler<D, H> =
+    fn(&mut WlState<D, H>, EthereumTxData) -> eyre::Result<TxResult>;
+
+/// The registry of the handlers of the protocol txs, keyed by their type. The
+/// default registry holds the handlers of the Ethereum protocol txs.
+pub struct ProtocolTxHandlers<D, H>
+where
+    D: 'static + DB + for<'iter> DBIter<'iter> + Sync,
+    H: 'static + StorageHasher + Sync,
+{
+    handlers: BTreeMap<ProtocolTxType, ProtocolTxHandler<D, H>>,
+}
+
+impl<D, H> ProtocolTxHandlers<D, H>
+where
+    D: 'static + DB + for<'iter> DBIter<'iter> + Sync,
+    H: 'static + StorageHasher + Sync,
+{
+    /// Register the handler of the given type of protocol tx. Returns the
+    /// handler it replaces, if any.
+    pub fn register(
+        &mut self,
+        tx: ProtocolTxType,
+        handler: ProtocolTxHandler<D, H>,
+    ) -> Option<ProtocolTxHandler<D, H>> {
+        self.handlers.insert(tx, handler)
+    }
+
+    /// The handler of the given type of protocol tx, if any
+    pub fn get(&self, tx: &ProtocolTxType) -> Option<ProtocolTxHandler<D, H>> {
+        self.handlers.get(tx).copied()
+    }
+}
+
+impl<D, H> Default for ProtocolTxHandlers<D, H>
+where
+    D: 'static + DB + for<'iter> DBIter<'iter> + Sync,
+    H: 'static + StorageHasher + Sync,
+{
+    fn default() -> Self {
+        let mut handlers = Self {
+            handlers: BTreeMap::new(),
+        };
+        handlers.register(ProtocolTxType::EthEventsVext, apply_eth_events_vext);
+        handlers
+            .register(ProtocolTxType::BridgePoolVext, apply_bridge_pool_vext);
+        handlers.register(
+            ProtocolTxType::ValSetUpdateVext,
+            apply_val_set_update_vext,
+        );
+        handlers
+            .register(ProtocolTxType::EthereumEvents, apply_eth_events_digest);
+        handlers.register(
+            ProtocolTxType::EthHotKeyRotation,
+            apply_eth_hot_key_rotation,
+        );
+        // TODO(namada#198): register the handlers of the complete bridge pool
+        // proofs and validator set updates
+        handlers
+    }
+}
+
+impl<D, H> Debug for ProtocolTxHandlers<D, H>
+where
+    D: 'static + DB + for<'iter> DBIter<'iter> + Sync,
+    H: 'static + StorageHasher + Sync,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_set().entries(self.handlers.keys()).finish()
+    }
+}
+
+/// Apply a derived transaction to storage based on some protocol transaction.
+/// The logic here must be completely deterministic and will be executed by all
+/// full nodes every time a protocol transaction is included in a block. Storage
+/// is updated natively rather than via the wasm environment, so gas does not
+/// need to be metered and validity predicates are bypassed. A [`TxResult`]
+/// containing changed keys and the like should be returned in the normal way.
+/// The transaction is applied by the handler registered for its type. When the
+/// `protocol_tx_min_signers` parameter is set, a digest signed by fewer
+/// validators is rejected, regardless of their voting power.
+pub(crate) fn apply_protocol_tx<D, H>(
+    tx: ProtocolTxType,
+    data: Option<Vec<u8>>,
+    handlers: &ProtocolTxHandlers<D, H>,
+    state: &mut WlState<D, H>,
+) -> Result<TxResult>
+where
+    D: 'static + DB + for<'iter> DBIter<'iter> + Sync,
+    H: 'static + StorageHasher + Sync,
+{
+    let Some(data) = data else {
+        return Err(Error::ProtocolTxError(eyre!(
+            "Protocol tx data must be present"
+        )));
+    };
+    let ethereum_tx_data = EthereumTxData::deserialize(&tx, &data)
+        .wrap_err_with(|| {
+            format!(
+                "Attempt made to apply an unsupported protocol transaction! - \
+                 {tx:?}",
+            )
+        })
+        .map_err(Error::ProtocolTxError)?;
+    let min_signers =
+        namada_parameters::storage::get_protocol_tx_min_signers(&*state)
+            .map_err(Error::StorageError)?;
+    if let (Some(min_signers), Some(signers)) =
+        (min_signers, digest_signers(&ethereum_tx_data))
+    {
+        let signers = signers as u64;
+        if signers < min_signers {
+            return Err(Error::InsufficientProtocolTxSigners {
+                tx: format!("{tx:?}"),
+                signers,
+                min_signers,
+            });
+        }
+    }
+    let Some(handler) = handlers.get(&tx) else {
+        tracing::warn!(
+            "Attempt made to apply an unimplemented protocol transaction, no \
+             actions will be taken"
+        );
+        return Err(Error::UnimplementedProtocolTx(format!("{tx:?}")));
+    };
+
+    state.write_log_mut().start_protocol_journal();
+    let tx_result = handler(state, ethereum_tx_data)
+        .map_err(Error::ProtocolTxError)
+        .and_then(|tx_result| {
+            check_protocol_tx_scope(&tx, &tx_result.changed_keys)?;
+            Ok(tx_result)
+        });
+
+    if let Err(Error::ProtocolTxOutOfScope(_)) = &tx_result {
+        // Refuse to commit any of the changes of the tx
+        state.write_log_mut().revert_protocol_journal();
+    } else {
+        state.write_log_mut().stop_protocol_journal();
+    }
+    tx_result
+}
+
+/// Apply a protocol tx like [`apply_protocol_tx`] without persisting any of
+/// its effects, e.g. to check that a vote extension digest would apply cleanly
+/// before proposing it. The write log is rolled back once the tx has been
+/// applied, even on failure. Returns the result the tx would have produced.
+pub fn simulate_protocol_tx<D, H>(
+    tx: ProtocolTxType,
+    data: Option<Vec<u8>>,
+    handlers: &ProtocolTxHandlers<D, H>,
+    state: &mut WlState<D, H>,
+) -> Result<TxResult>
+where
+    D: 'static + DB + for<'iter> DBIter<'iter> + Sync,
+    H: 'static + StorageHasher + Sync,
+{
+    let write_log = state.write_log().clone();
+    let result = apply_protocol_tx(tx, data, handlers, state);
+    *state.write_log_mut() = write_log;
+    result
+}
+
+/// The number of distinct validators that signed the digest carried by a
+/// protocol tx, counting the least signed event of the Ethereum events.
+/// Returns `None` if the tx carries the vote extension of a single validator
+/// or no events.
+fn digest_signers(data: &EthereumTxData) -> Option<usize> {
+    match data {
+        EthereumTxData::EthereumEvents(digest) => digest
+            .events
+            .iter()
+            .map(|event| {
+                event
+                    .signers
+                    .iter()
+                    .map(|(addr, _)| addr)
+                    .collect::<BTreeSet<_>>()
+                    .len()
+            })
+            .min(),
+        EthereumTxData::BridgePool(multisigned) => Some(
+            multisigned
+                .iter()
+                .map(|ext| &ext.data.validator_addr)
+                .collect::<BTreeSet<_>>()
+                .len(),
+        ),
+        EthereumTxData::ValidatorSetUpdate(digest) => {
+            Some(digest.signatures.len())
+        }
+        EthereumTxData::EthEventsVext(_)
+        | EthereumTxData::BridgePoolVext(_)
+        | EthereumTxData::ValSetUpdateVext(_)
+        | EthereumTxData::EthHotKeyRotation(_) => None,
+    }
+}
+
+/// Apply the Ethereum events seen by some validator
+fn apply_eth_events_vext<D, H>(
+    state: &mut WlState<D, H>,
+    data: EthereumTxData,
+) -> eyre::Result<TxResult>
+where
+    D: 'static + DB + for<'iter> DBIter<'iter> + Sync,
+    H: 'static + StorageHasher + Sync,
+{
+    use namada_ethereum_bridge::protocol::transactions;
+    use namada_ethereum_bridge::storage::vote_tallies;
+    use namada_vote_ext::ethereum_events;
+
+    let EthereumTxData::EthEventsVext(ethereum_events::SignedVext(ext)) = data
+    else {
+        return Err(eyre!("Expected an Ethereum events vote extension"));
+    };
+    let voter = ext.data.validator_addr.clone();
+    let seen_by_keys: BTreeSet<Key> = ext
+        .data
+        .ethereum_events
+        .iter()
+        .map(|event| vote_tallies::Keys::from(event).seen_by())
+        .collect();
+    let ethereum_events::VextDigest { events, .. } =
+        ethereum_events::VextDigest::singleton(ext);
+    let mut tx_result =
+        transactions::ethereum_events::apply_derived_tx(state, events)?;
+    if tx_result
+        .changed_keys
+        .iter()
+        .any(|key| seen_by_keys.contains(key))
+    {
+        tx_result.newly_counted.push(voter);
+    }
+    Ok(tx_result)
+}
+
+/// Apply the signature of some validator over the Ethereum bridge pool root
+fn apply_bridge_pool_vext<D, H>(
+    state: &mut WlState<D, H>,
+    data: EthereumTxData,
+) -> eyre::Result<TxResult>
+where
+    D: 'static + DB + for<'iter> DBIter<'iter> + Sync,
+    H: 'static + StorageHasher + Sync,
+{
+    use namada_ethereum_bridge::protocol::transactions;
+    use namada_ethereum_bridge::storage::vote_tallies;
+
+    let EthereumTxData::BridgePoolVext(ext) = data else {
+        return Err(eyre!("Expected a bridge pool root vote extension"));
+    };
+    let voter = ext.data.validator_addr.clone();
+    let mut tx_result =
+        transactions::bridge_pool_roots::apply_derived_tx(state, ext.into())?;
+    // Only the tally of the signed root is touched by this tx
+    if tx_result
+        .changed_keys
+        .iter()
+        .any(vote_tallies::is_seen_by_key)
+    {
+        tx_result.newly_counted.push(voter);
+    }
+    Ok(tx_result)
+}
+
+/// Apply the validator set update signed by some validator
+fn apply_val_set_update_vext<D, H>(
+    state: &mut WlState<D, H>,
+    data: EthereumTxData,
+) -> eyre::Result<TxResult>
+where
+    D: 'static + DB + for<'iter> DBIter<'iter> + Sync,
+    H: 'static + StorageHasher + Sync,
+{
+    use namada_ethereum_bridge::protocol::transactions;
+    use namada_vote_ext::validator_set_update;
+
+    let EthereumTxData::ValSetUpdateVext(ext) = data else {
+        return Err(eyre!("Expected a validator set update vote extension"));
+    };
+    // NOTE(feature = "abcipp"): with ABCI++, we can write the
+    // complete proof to storage in one go. the decided vote extension
+    // digest must already have >2/3 of the voting power behind it.
+    // with ABCI+, multiple vote extension protocol txs may be needed
+    // to reach a complete proof.
+    let signing_epoch = ext.data.signing_epoch;
+    transactions::validator_set_update::aggregate_votes(
+        state,
+        validator_set_update::VextDigest::singleton(ext),
+        signing_epoch,
+    )
+}
+
+/// Apply the decided digest of the Ethereum events
+fn apply_eth_events_digest<D, H>(
+    state: &mut WlState<D, H>,
+    data: EthereumTxData,
+) -> eyre::Result<TxResult>
+where
+    D: 'static + DB + for<'iter> DBIter<'iter> + Sync,
+    H: 'static + StorageHasher + Sync,
+{
+    use namada_ethereum_bridge::protocol::transactions;
+    use namada_vote_ext::ethereum_events;
+
+    let EthereumTxData::EthereumEvents(ethereum_events::VextDigest {
+        events,
+        ..
+    }) = data
+    else {
+        return Err(eyre!("Expected a digest of Ethereum events"));
+    };
+    // The decided digest already holds the votes of all the validators that
+    // saw each event, apply them in one go
+    transactions::ethereum_events::apply_derived_tx(state, events)
+}
+
+/// Check that the rotation of the Ethereum bridge hot key of a validator was
+/// signed in the current epoch with its current hot key, that the new hot key
+/// can be used on Ethereum and that no other rotation is already pending
+pub fn validate_eth_hot_key_rotation<D, H>(
+    state: &WlState<D, H>,
+    rotation: &eth_hot_key_rotation::SignedRotation,
+) -> eyre::Result<()>
+where
+    D: 'static + DB + for<'iter> DBIter<'iter> + Sync,
+    H: 'static + StorageHasher + Sync,
+{
+    use namada_core::ethereum_events::EthAddress;
+
+    let validator = &rotation.data.validator_addr;
+    let current_epoch = state.in_mem().get_current_epoch().0;
+    if rotation.data.epoch != current_epoch {
+        return Err(eyre!(
+            "The hot key rotation of validator {validator} was signed at \
+             epoch {}, expected {current_epoch}",
+            rotation.data.epoch
+        ));
+    }
+    EthAddress::try_from(&rotation.data.new_hot_key).map_err(|err| {
+        eyre!("Invalid new hot key of validator {validator}: {err}")
+    })?;
+    let queries = state.pos_queries();
+    let hot_key = queries
+        .read_validator_eth_hot_key(validator, Some(current_epoch))
+        .ok_or_else(|| eyre!("Validator {validator} has no hot key"))?;
+    let pipeline_epoch = current_epoch + queries.get_pos_params().pipeline_len;
+    if queries
+        .read_validator_eth_hot_key(validator, Some(pipeline_epoch))
+        .as_ref()
+        != Some(&hot_key)
+    {
+        return Err(eyre!(
+            "A hot key rotation of validator {validator} is already pending"
+        ));
+    }
+    rotation.verify(&hot_key).map_err(|err| {
+        eyre!(
+            "Invalid signature of the hot key rotation of validator \
+             {validator}: {err}"
+        )
+    })
+}
+
+/// Apply the rotation of the Ethereum bridge hot key of some validator. Like
+/// the consensus keys, the new hot key takes effect at the pipeline epoch.
+fn apply_eth_hot_key_rotation<D, H>(
+    state: &mut WlState<D, H>,
+    data: EthereumTxData,
+) -> eyre::Result<TxResult>
+where
+    D: 'static + DB + for<'iter> DBIter<'iter> + Sync,
+    H: 'static + StorageHasher + Sync,
+{
+    use crate::proof_of_stake::storage_key::validator_eth_hot_key_key;
+
+    let EthereumTxData::EthHotKeyRotation(rotation) = data else {
+        return Err(eyre!("Expected an Ethereum hot key rotation"));
+    };
+    validate_eth_hot_key_rotation(state, &rotation)?;
+    let eth_hot_key_rotation::Rotation {
+        validator_addr,
+        epoch,
+        new_hot_key,
+    } = rotation.data;
+    let params = pos::read_pos_params(state)?;
+    pos::validator_eth_hot_key_handle(&validator_addr).set(
+        state,
+        new_hot_key,
+        epoch,
+        params.pipeline_len,
+    )?;
+    let prefix = validator_eth_hot_key_key(&validator_addr);
+    let changed_keys = state
+        .write_log()
+        .get_keys()
+        .into_iter()
+        .filter(|key| key.split_prefix(&prefix).is_some())
+        .collect();
+    Ok(TxResult {
+        changed_keys,
+        ..Default::default()
+    })
+}
+
+/// Check that the keys changed by a protocol tx belong to the storage of the
+/// internal addresses designated to its type, since VPs are bypassed
+fn check_protocol_tx_scope(
+    tx: &ProtocolTxType,
+    changed_keys: &BTreeSet<Key>,
+) -> Result<()> {
+    let scope: &[InternalAddress] = match tx {
+        ProtocolTxType::EthereumEvents | ProtocolTxType::EthEventsVext => &[
+            InternalAddress::EthBridge,
+            InternalAddress::EthBridgePool,
+            InternalAddress::Multitoken,
+        ],
+        ProtocolTxType::BridgePool | ProtocolTxType::BridgePoolVext => {
+            &[InternalAddress::EthBridge, InternalAddress::EthBridgePool]
+        }
+        ProtocolTxType::ValidatorSetUpdate
+        | ProtocolTxType::ValSetUpdateVext => &[InternalAddress::EthBridge],
+        ProtocolTxType::EthHotKeyRotation => &[InternalAddress::PoS],
+    };
+    match changed_keys.iter().find(|key| {
+        !matches!(
+            key.segments.first(),
+            Some(storage::DbKeySeg::AddressSeg(Address::Internal(addr)))
+                if scope.contains(addr)
+        )
+    }) {
+        Some(key) => Err(Error::ProtocolTxOutOfScope(key.clone())),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use borsh_ext::BorshSerializeExt;
+    use eyre::Result;
+    use namada_core::collections::HashMap;
+    use namada_core::ethereum_events::testing::DAI_ERC20_ETH_ADDRESS;
+    use namada_core::ethereum_events::{EthereumEvent, TransferToNamada};
+    use namada_core::keccak::keccak_hash;
+    use namada_core::key::RefTo;
+    use namada_core::storage::{BlockHeight, KeySeg};
+    use namada_core::voting_power::FractionalVotingPower;
+    use namada_core::{address, key};
+    use namada_ethereum_bridge::protocol::transactions::votes::{
+        EpochedVotingPower, Votes,
+    };
+    use namada_ethereum_bridge::protocol::validation::bridge_pool_roots::validate_bp_roots_vext;
+    use namada_ethereum_bridge::protocol::validation::VoteExtensionError;
+    use namada_ethereum_bridge::storage::eth_bridge_queries::EthBridgeQueries;
+    use namada_ethereum_bridge::storage::proof::EthereumProof;
+    use namada_ethereum_bridge::storage::{vote_tallies, vp};
+    use namada_ethereum_bridge::test_utils;
+    use namada_gas::TxGasMeter;
+    use namada_state::testing::TestState;
+    use namada_state::{StateRead, StorageWrite};
+    use namada_tx::data::TxType;
+    use namada_tx::{SignableEthMessage, Signed, Tx};
+    use namada_vote_ext::bridge_pool_roots::BridgePoolRootVext;
+    use namada_vote_ext::ethereum_events::EthereumEventsVext;
+
+    use super::*;
+    use crate::key::common;
+    use crate::ledger::protocol::tests::wasm_caches;
+    use crate::ledger::protocol::{dispatch_tx, DispatchArgs};
+    use crate::state::StorageRead;
+    use crate::storage::TxIndex;
+    use crate::token::Amount;
+
+    fn apply_eth_tx<D, H>(
+        tx: EthereumTxData,
+        state: &mut WlState<D, H>,
+    ) -> Result<TxResult>
+    where
+        D: 'static + DB + for<'iter> DBIter<'iter> + Sync,
+        H: 'static + StorageHasher + Sync,
+    {
+        let (data, tx) = tx.serialize();
+        let tx_result = apply_protocol_tx(
+            tx,
+            Some(data),
+            &ProtocolTxHandlers::default(),
+            state,
+        )?;
+        Ok(tx_result)
+    }
+
+    #[test]
+    /// Tests that if the same [`ProtocolTxType::EthEventsVext`] is applied
+    /// twice within the same block, it doesn't result in voting power being
+    /// double counted.
+    fn test_apply_protocol_tx_duplicate_eth_events_vext() -> Result<()> {
+        let validator_a = address::testing::established_address_2();
+        let validator_b = address::testing::established_address_3();
+        let validator_a_stake = Amount::native_whole(100);
+        let validator_b_stake = Amount::native_whole(100);
+        let total_stake = validator_a_stake + validator_b_stake;
+        let (mut state, _) = test_utils::setup_storage_with_validators(
+            HashMap::from_iter(vec![
+                (validator_a.clone(), validator_a_stake),
+                (validator_b, validator_b_stake),
+            ]),
+        );
+        let event = EthereumEvent::TransfersToNamada {
+            nonce: 0.into(),
+            transfers: vec![TransferToNamada {
+                amount: Amount::from(100),
+                asset: DAI_ERC20_ETH_ADDRESS,
+                receiver: address::testing::established_address_4(),
+            }],
+        };
+        let vext = EthereumEventsVext {
+            block_height: BlockHeight(100),
+            validator_addr: address::testing::established_address_2(),
+            ethereum_events: vec![event.clone()],
+        };
+        let signing_key = key::testing::keypair_1();
+        let signed = vext.sign(&signing_key);
+        let tx = EthereumTxData::EthEventsVext(
+            namada_vote_ext::ethereum_events::SignedVext(signed),
+        );
+
+        apply_eth_tx(tx.clone(), &mut state)?;
+        apply_eth_tx(tx, &mut state)?;
+
+        let eth_msg_keys = vote_tallies::Keys::from(&event);
+        let seen_by: Votes = state.read(&eth_msg_keys.seen_by())?.unwrap();
+        assert_eq!(seen_by, Votes::from([(validator_a, BlockHeight(100))]));
+
+        // the vote should have only be applied once
+        let voting_power: EpochedVotingPower =
+            state.read(&eth_msg_keys.voting_power())?.unwrap();
+        let expected = EpochedVotingPower::from([(
+            0.into(),
+            FractionalVotingPower::HALF * total_stake,
+        )]);
+        assert_eq!(voting_power, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    /// Tests that simulating a protocol tx reports the result it would have
+    /// produced, without changing the state
+    fn test_simulate_protocol_tx() -> Result<()> {
+        let validator_a = address::testing::established_address_2();
+        let validator_b = address::testing::established_address_3();
+        let (mut state, _) = test_utils::setup_storage_with_validators(
+            HashMap::from_iter(vec![
+                (validator_a.clone(), Amount::native_whole(100)),
+                (validator_b, Amount::native_whole(100)),
+            ]),
+        );
+        let event = EthereumEvent::TransfersToNamada {
+            nonce: 0.into(),
+            transfers: vec![TransferToNamada {
+                amount: Amount::from(100),
+                asset: DAI_ERC20_ETH_ADDRESS,
+                receiver: address::testing::established_address_4(),
+            }],
+        };
+        let vext = EthereumEventsVext {
+            block_height: BlockHeight(100),
+            validator_addr: validator_a.clone(),
+            ethereum_events: vec![event.clone()],
+        };
+        let signed = vext.sign(&key::testing::keypair_1());
+        let (data, tx) = EthereumTxData::EthEventsVext(
+            namada_vote_ext::ethereum_events::SignedVext(signed),
+        )
+        .serialize();
+        let handlers = ProtocolTxHandlers::default();
+        let eth_msg_keys = vote_tallies::Keys::from(&event);
+
+        let simulated = simulate_protocol_tx(
+            tx.clone(),
+            Some(data.clone()),
+            &handlers,
+            &mut state,
+        )?;
+        assert!(!simulated.changed_keys.is_empty());
+        assert!(state.read::<Votes>(&eth_msg_keys.seen_by())?.is_none());
+
+        let applied = apply_protocol_tx(tx, Some(data), &handlers, &mut state)?;
+        assert_eq!(simulated.changed_keys, applied.changed_keys);
+        let seen_by: Votes = state.read(&eth_msg_keys.seen_by())?.unwrap();
+        assert_eq!(seen_by, Votes::from([(validator_a, BlockHeight(100))]));
+
+        Ok(())
+    }
+
+    #[test]
+    /// Tests that the events of a decided Ethereum events digest are applied
+    /// with the votes of all their signers
+    fn test_apply_protocol_tx_eth_events_digest() -> Result<()> {
+        let validator_a = address::testing::established_address_2();
+        let validator_b = address::testing::established_address_3();
+        let (mut state, _) = test_utils::setup_storage_with_validators(
+            HashMap::from_iter(vec![
+                (validator_a.clone(), Amount::native_whole(100)),
+                (validator_b.clone(), Amount::native_whole(100)),
+            ]),
+        );
+        let event = EthereumEvent::TransfersToNamada {
+            nonce: 0.into(),
+            transfers: vec![TransferToNamada {
+                amount: Amount::from(100),
+                asset: DAI_ERC20_ETH_ADDRESS,
+                receiver: address::testing::established_address_4(),
+            }],
+        };
+        let signers = BTreeSet::from([
+            (validator_a.clone(), BlockHeight(100)),
+            (validator_b.clone(), BlockHeight(100)),
+        ]);
+        let tx = EthereumTxData::EthereumEvents(
+            namada_vote_ext::ethereum_events::VextDigest {
+                signatures: Default::default(),
+                events: vec![
+                    namada_vote_ext::ethereum_events::MultiSignedEthEvent {
+                        event: event.clone(),
+                        signers,
+                    },
+                ],
+            },
+        );
+
+        let tx_result = apply_eth_tx(tx, &mut state)?;
+
+        let eth_msg_keys = vote_tallies::Keys::from(&event);
+        assert!(tx_result.changed_keys.contains(&eth_msg_keys.seen()));
+        let seen_by: Votes = state.read(&eth_msg_keys.seen_by())?.unwrap();
+        assert_eq!(
+            seen_by,
+            Votes::from([
+                (validator_a, BlockHeight(100)),
+                (validator_b, BlockHeight(100)),
+            ])
+        );
+        // the whole voting power is behind the event, so it was confirmed
+        let seen: bool = state.read(&eth_msg_keys.seen())?.unwrap();
+        assert!(seen);
+
+        Ok(())
+    }
+
+    #[test]
+    /// Tests that a digest signed by fewer validators than the minimum is
+    /// rejected, even if their voting power suffices
+    fn test_apply_protocol_tx_min_signers() {
+        let validator_a = address::testing::established_address_2();
+        let validator_b = address::testing::established_address_3();
+        let (mut state, _) = test_utils::setup_storage_with_validators(
+            HashMap::from_iter(vec![
+                (validator_a.clone(), Amount::native_whole(100)),
+                (validator_b.clone(), Amount::native_whole(100)),
+            ]),
+        );
+        let event = EthereumEvent::TransfersToNamada {
+            nonce: 0.into(),
+            transfers: vec![TransferToNamada {
+                amount: Amount::from(100),
+                asset: DAI_ERC20_ETH_ADDRESS,
+                receiver: address::testing::established_address_4(),
+            }],
+        };
+        // the whole voting power is behind the event
+        let (data, tx) = EthereumTxData::EthereumEvents(
+            namada_vote_ext::ethereum_events::VextDigest {
+                signatures: Default::default(),
+                events: vec![
+                    namada_vote_ext::ethereum_events::MultiSignedEthEvent {
+                        event: event.clone(),
+                        signers: BTreeSet::from([
+                            (validator_a, BlockHeight(100)),
+                            (validator_b, BlockHeight(100)),
+                        ]),
+                    },
+                ],
+            },
+        )
+        .serialize();
+        let handlers = ProtocolTxHandlers::default();
+        let min_signers_key =
+            namada_parameters::storage::get_protocol_tx_min_signers_key();
+        state.write(&min_signers_key, 3_u64).unwrap();
+        let written_keys = state.write_log().get_keys();
+
+        let result = apply_protocol_tx(
+            tx.clone(),
+            Some(data.clone()),
+            &handlers,
+            &mut state,
+        );
+        assert!(matches!(
+            result.unwrap_err(),
+            Error::InsufficientProtocolTxSigners {
+                signers: 2,
+                min_signers: 3,
+                ..
+            }
+        ));
+        assert_eq!(state.write_log().get_keys(), written_keys);
+
+        state.write(&min_signers_key, 2_u64).unwrap();
+        let tx_result =
+            apply_protocol_tx(tx, Some(data), &handlers, &mut state).unwrap();
+        let eth_msg_keys = vote_tallies::Keys::from(&event);
+        assert!(tx_result.changed_keys.contains(&eth_msg_keys.seen()));
+    }
+
+    #[test]
+    /// Tests that applying a complete bridge pool proof, which is not
+    /// implemented, is reported as an error instead of a no-op success
+    fn test_apply_protocol_tx_unimplemented() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let tx = EthereumTxData::BridgePool(
+            namada_vote_ext::bridge_pool_roots::MultiSignedVext::default(),
+        );
+
+        let result = apply_eth_tx(tx, &mut state);
+        assert!(matches!(
+            result.unwrap_err(),
+            Error::UnimplementedProtocolTx(tx) if tx == "BridgePool"
+        ));
+        assert!(state.write_log().get_keys().is_empty());
+    }
+
+    #[test]
+    /// Tests that a protocol tx is applied by the handler registered for its
+    /// type, in place of the default one
+    fn test_protocol_tx_handlers() {
+        /// Write a dummy complete bridge pool proof
+        fn apply_bridge_pool_proof<D, H>(
+            state: &mut WlState<D, H>,
+            _data: EthereumTxData,
+        ) -> eyre::Result<TxResult>
+        where
+            D: 'static + DB + for<'iter> DBIter<'iter> + Sync,
+            H: 'static + StorageHasher + Sync,
+        {
+            let key = Key::from(
+                Address::Internal(InternalAddress::EthBridgePool).to_db_key(),
+            )
+            .push(&"proof".to_owned())?;
+            state.write_log_mut().write(&key, 1u64.serialize_to_vec())?;
+            Ok(TxResult {
+                changed_keys: BTreeSet::from([key]),
+                ..Default::default()
+            })
+        }
+
+        let (mut state, _) = test_utils::setup_default_storage();
+        let (data, tx) = EthereumTxData::BridgePool(
+            namada_vote_ext::bridge_pool_roots::MultiSignedVext::default(),
+        )
+        .serialize();
+
+        let mut handlers = ProtocolTxHandlers::default();
+        assert!(handlers.get(&tx).is_none());
+        assert!(
+            handlers
+                .register(tx.clone(), apply_bridge_pool_proof)
+                .is_none()
+        );
+        let tx_result =
+            apply_protocol_tx(tx, Some(data), &handlers, &mut state).unwrap();
+        assert_eq!(tx_result.changed_keys.len(), 1);
+        assert_eq!(state.write_log().get_keys(), tx_result.changed_keys);
+
+        // the default handlers can be replaced too
+        assert!(
+            handlers
+                .register(
+                    ProtocolTxType::EthEventsVext,
+                    apply_bridge_pool_proof,
+                )
+                .is_some()
+        );
+    }
+
+    #[test]
+    /// Tests that if the same [`ProtocolTxType::BridgePoolVext`] is applied
+    /// twice within the same block, it doesn't result in voting power being
+    /// double counted.
+    fn test_apply_protocol_tx_duplicate_bp_roots_vext() -> Result<()> {
+        let validator_a = address::testing::established_address_2();
+        let validator_b = address::testing::established_address_3();
+        let validator_a_stake = Amount::native_whole(100);
+        let validator_b_stake = Amount::native_whole(100);
+        let total_stake = validator_a_stake + validator_b_stake;
+        let (mut state, keys) = test_utils::setup_storage_with_validators(
+            HashMap::from_iter(vec![
+                (validator_a.clone(), validator_a_stake),
+                (validator_b, validator_b_stake),
+            ]),
+        );
+        vp::bridge_pool::init_storage(&mut state);
+
+        let root = state.ethbridge_queries().get_bridge_pool_root();
+        let nonce = state.ethbridge_queries().get_bridge_pool_nonce();
+        test_utils::commit_bridge_pool_root_at_height(
+            &mut state,
+            &root,
+            100.into(),
+        );
+        let to_sign = keccak_hash([root.0, nonce.to_bytes()].concat());
+        let signing_key = key::testing::keypair_1();
+        let hot_key =
+            &keys[&address::testing::established_address_2()].eth_bridge;
+        let sig = Signed::<_, SignableEthMessage>::new(hot_key, to_sign).sig;
+        let vext = BridgePoolRootVext {
+            block_height: BlockHeight(100),
+            validator_addr: address::testing::established_address_2(),
+            sig,
+        }
+        .sign(&signing_key);
+        let tx = EthereumTxData::BridgePoolVext(vext);
+        apply_eth_tx(tx.clone(), &mut state)?;
+        apply_eth_tx(tx, &mut state)?;
+
+        let bp_root_keys = vote_tallies::Keys::from((
+            &vote_tallies::BridgePoolRoot(EthereumProof::new((root, nonce))),
+            100.into(),
+        ));
+        let root_seen_by: Votes = state.read(&bp_root_keys.seen_by())?.unwrap();
+        assert_eq!(
+            root_seen_by,
+            Votes::from([(validator_a, BlockHeight(100))])
+        );
+        // the vote should have only be applied once
+        let voting_power: EpochedVotingPower =
+            state.read(&bp_root_keys.voting_power())?.unwrap();
+        let expected = EpochedVotingPower::from([(
+            0.into(),
+            FractionalVotingPower::HALF * total_stake,
+        )]);
+        assert_eq!(voting_power, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    /// Tests that the rotation of the Ethereum hot key of a validator must be
+    /// signed with its current hot key, and that the new hot key is used to
+    /// verify the signatures of the validator from the pipeline epoch.
+    fn test_apply_protocol_tx_eth_hot_key_rotation() -> Result<()> {
+        use namada_core::ethereum_events::EthAddress;
+
+        let validator = address::testing::established_address_2();
+        let (mut state, keys) = test_utils::setup_storage_with_validators(
+            HashMap::from_iter(vec![(
+                validator.clone(),
+                Amount::native_whole(100),
+            )]),
+        );
+        let current_epoch = state.in_mem().get_current_epoch().0;
+        let pipeline_epoch =
+            current_epoch + state.pos_queries().get_pos_params().pipeline_len;
+        let old_hot_key = &keys[&validator].eth_bridge;
+        let new_hot_key = common::SecretKey::Secp256k1(
+            key::testing::gen_keypair::<key::secp256k1::SigScheme>(),
+        );
+        let rotation = eth_hot_key_rotation::Rotation {
+            validator_addr: validator.clone(),
+            epoch: current_epoch,
+            new_hot_key: new_hot_key.ref_to(),
+        };
+
+        // the rotation must be signed with the current hot key
+        let tx = EthereumTxData::EthHotKeyRotation(rotation.sign(&new_hot_key));
+        assert!(apply_eth_tx(tx, &mut state).is_err());
+
+        let tx = EthereumTxData::EthHotKeyRotation(rotation.sign(old_hot_key));
+        let tx_result = apply_eth_tx(tx.clone(), &mut state)?;
+        assert!(!tx_result.changed_keys.is_empty());
+        // the rotation is pending, it can't be replayed
+        assert!(apply_eth_tx(tx, &mut state).is_err());
+
+        let hot_key = |epoch| {
+            state
+                .pos_queries()
+                .read_validator_eth_hot_key(&validator, Some(epoch))
+        };
+        assert_eq!(hot_key(current_epoch), Some(old_hot_key.ref_to()));
+        assert_eq!(hot_key(pipeline_epoch), Some(new_hot_key.ref_to()));
+        let hot_key_addr = |epoch| {
+            state
+                .ethbridge_queries()
+                .get_eth_addr_book(&validator, Some(epoch))
+                .map(|addr_book| addr_book.hot_key_addr)
+        };
+        assert_eq!(
+            hot_key_addr(current_epoch),
+            EthAddress::try_from(&old_hot_key.ref_to()).ok()
+        );
+        assert_eq!(
+            hot_key_addr(pipeline_epoch),
+            EthAddress::try_from(&new_hot_key.ref_to()).ok()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    /// Tests that the Bridge pool root signatures of a validator are verified
+    /// against its rotated hot key once the rotation takes effect.
+    fn test_bp_roots_vext_signed_with_rotated_hot_key() -> Result<()> {
+        let validator = address::testing::established_address_2();
+        let (mut state, keys) = test_utils::setup_storage_with_validators(
+            HashMap::from_iter(vec![(
+                validator.clone(),
+                Amount::native_whole(100),
+            )]),
+        );
+        let current_epoch = state.in_mem().get_current_epoch().0;
+        let pipeline_len = state.pos_queries().get_pos_params().pipeline_len;
+        let pipeline_epoch = current_epoch + pipeline_len;
+        let old_hot_key = &keys[&validator].eth_bridge;
+        let new_hot_key = common::SecretKey::Secp256k1(
+            key::testing::gen_keypair::<key::secp256k1::SigScheme>(),
+        );
+        let rotation = eth_hot_key_rotation::Rotation {
+            validator_addr: validator.clone(),
+            epoch: current_epoch,
+            new_hot_key: new_hot_key.ref_to(),
+        };
+        let tx = EthereumTxData::EthHotKeyRotation(rotation.sign(old_hot_key));
+        apply_eth_tx(tx, &mut state)?;
+
+        // advance to the epoch where the rotation takes effect
+        let mut height = state.in_mem().block.height;
+        for epoch in current_epoch.iter_range(pipeline_len) {
+            height = BlockHeight(height.0 + 10);
+            state.in_mem_mut().block.epoch = epoch.next();
+            state.in_mem_mut().block.pred_epochs.new_epoch(height);
+        }
+        assert_eq!(state.in_mem().block.epoch, pipeline_epoch);
+        vp::bridge_pool::init_storage(&mut state);
+        let root = state.ethbridge_queries().get_bridge_pool_root();
+        test_utils::commit_bridge_pool_root_at_height(
+            &mut state,
+            &root,
+            height,
+        );
+
+        let root = state
+            .ethbridge_queries()
+            .get_bridge_pool_root_at_height(height)
+            .expect("Test failed");
+        let nonce = state
+            .ethbridge_queries()
+            .get_bridge_pool_nonce_at_height(height);
+        let to_sign = keccak_hash([root.0, nonce.to_bytes()].concat());
+        let vext = |hot_key: &common::SecretKey| {
+            let sig =
+                Signed::<_, SignableEthMessage>::new(hot_key, to_sign.clone())
+                    .sig;
+            BridgePoolRootVext {
+                block_height: height,
+                validator_addr: validator.clone(),
+                sig,
+            }
+            .sign(&keys[&validator].protocol)
+            .0
+        };
+
+        assert!(
+            validate_bp_roots_vext(&state, &vext(&new_hot_key), height).is_ok()
+        );
+        assert!(matches!(
+            validate_bp_roots_vext(&state, &vext(old_hot_key), height),
+            Err(VoteExtensionError::InvalidBPRootSig)
+        ));
+
+        Ok(())
+    }
+
+    #[test]
+    /// Tests that the signer of a vote extension is reported as newly
+    /// counted only the first time its vote is tallied.
+    fn test_apply_protocol_tx_newly_counted_voters() -> Result<()> {
+        let validator_a = address::testing::established_address_2();
+        let validator_b = address::testing::established_address_3();
+        let (mut state, _) = test_utils::setup_storage_with_validators(
+            HashMap::from_iter(vec![
+                (validator_a.clone(), Amount::native_whole(100)),
+                (validator_b, Amount::native_whole(100)),
+            ]),
+        );
+        let event = EthereumEvent::TransfersToNamada {
+            nonce: 0.into(),
+            transfers: vec![TransferToNamada {
+                amount: Amount::from(100),
+                asset: DAI_ERC20_ETH_ADDRESS,
+                receiver: address::testing::established_address_4(),
+            }],
+        };
+        let vext = EthereumEventsVext {
+            block_height: BlockHeight(100),
+            validator_addr: validator_a.clone(),
+            ethereum_events: vec![event],
+        };
+        let signed = vext.sign(&key::testing::keypair_1());
+        let tx = EthereumTxData::EthEventsVext(
+            namada_vote_ext::ethereum_events::SignedVext(signed),
+        );
+
+        let tx_result = apply_eth_tx(tx.clone(), &mut state)?;
+        assert_eq!(tx_result.newly_counted, vec![validator_a]);
+
+        // the same vote must not be reported again
+        let tx_result = apply_eth_tx(tx, &mut state)?;
+        assert!(tx_result.newly_counted.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    /// Tests that a protocol tx modifying keys outside of the storage of the
+    /// internal addresses designated to its type is rejected.
+    fn test_protocol_tx_out_of_scope_keys() {
+        let event = EthereumEvent::TransfersToNamada {
+            nonce: 0.into(),
+            transfers: vec![],
+        };
+        let seen_by_key = vote_tallies::Keys::from(&event).seen_by();
+        let balance_key = namada_token::storage_key::balance_key(
+            &address::testing::nam(),
+            &address::testing::established_address_1(),
+        );
+
+        let in_scope = BTreeSet::from([seen_by_key.clone()]);
+        check_protocol_tx_scope(&ProtocolTxType::ValSetUpdateVext, &in_scope)
+            .unwrap();
+
+        let out_of_scope = BTreeSet::from([seen_by_key, balance_key.clone()]);
+        assert!(matches!(
+            check_protocol_tx_scope(
+                &ProtocolTxType::ValSetUpdateVext,
+                &out_of_scope
+            )
+            .unwrap_err(),
+            Error::ProtocolTxOutOfScope(key) if key == balance_key
+        ));
+        // balances are in the scope of Ethereum events
+        check_protocol_tx_scope(&ProtocolTxType::EthEventsVext, &out_of_scope)
+            .unwrap();
+    }
+
+    #[test]
+    /// Tests that a dispatched protocol tx modifying keys outside of the
+    /// storage designated to its type is rejected without committing any of
+    /// its changes
+    fn test_dispatch_protocol_tx_out_of_scope() {
+        /// Write a dummy bridge pool proof
+        fn apply_bridge_pool_proof<D, H>(
+            state: &mut WlState<D, H>,
+            _data: EthereumTxData,
+        ) -> eyre::Result<TxResult>
+        where
+            D: 'static + DB + for<'iter> DBIter<'iter> + Sync,
+            H: 'static + StorageHasher + Sync,
+        {
+            let key = Key::from(
+                Address::Internal(InternalAddress::EthBridgePool).to_db_key(),
+            )
+            .push(&"proof".to_owned())?;
+            state.write_log_mut().write(&key, 1u64.serialize_to_vec())?;
+            Ok(TxResult {
+                changed_keys: BTreeSet::from([key]),
+                ..Default::default()
+            })
+        }
+
+        /// Write a dummy bridge pool proof and credit some tokens
+        fn apply_bridge_pool_proof_and_credit<D, H>(
+            state: &mut WlState<D, H>,
+            data: EthereumTxData,
+        ) -> eyre::Result<TxResult>
+        where
+            D: 'static + DB + for<'iter> DBIter<'iter> + Sync,
+            H: 'static + StorageHasher + Sync,
+        {
+            let mut tx_result = apply_bridge_pool_proof(state, data)?;
+            let key = namada_token::storage_key::balance_key(
+                &address::testing::nam(),
+                &address::testing::established_address_1(),
+            );
+            state
+                .write_log_mut()
+                .write(&key, Amount::from(1).serialize_to_vec())?;
+            tx_result.changed_keys.insert(key);
+            Ok(tx_result)
+        }
+
+        let (mut state, _) = test_utils::setup_default_storage();
+        let (mut vp_cache, mut tx_cache) = wasm_caches();
+        let (data, tx_type) = EthereumTxData::BridgePool(
+            namada_vote_ext::bridge_pool_roots::MultiSignedVext::default(),
+        )
+        .serialize();
+        let mut tx = Tx::from_type(TxType::Protocol(Box::new(
+            namada_tx::data::protocol::ProtocolTx {
+                pk: key::testing::keypair_1().ref_to(),
+                tx: tx_type.clone(),
+            },
+        )));
+        tx.set_data(namada_tx::Data::new(data));
+
+        let mut dispatch =
+            |state: &mut TestState, handlers: &ProtocolTxHandlers<_, _>| {
+                let gas_meter = RefCell::new(TxGasMeter::new(u64::MAX));
+                dispatch_tx(
+                    tx.clone(),
+                    &[],
+                    TxIndex::default(),
+                    &gas_meter,
+                    state.restrict_writes_to_write_log(),
+                    &mut vp_cache,
+                    &mut tx_cache,
+                    None,
+                    &DispatchArgs {
+                        protocol_tx_handlers: Some(handlers),
+                        ..Default::default()
+                    },
+                    None,
+                )
+            };
+
+        let mut handlers = ProtocolTxHandlers::default();
+        handlers.register(tx_type.clone(), apply_bridge_pool_proof_and_credit);
+        let err = dispatch(&mut state, &handlers).unwrap_err();
+        let balance_key = namada_token::storage_key::balance_key(
+            &address::testing::nam(),
+            &address::testing::established_address_1(),
+        );
+        assert!(matches!(
+            err,
+            Error::ProtocolTxOutOfScope(key) if key == balance_key
+        ));
+        // none of the changes were kept
+        assert!(state.write_log().get_keys().is_empty());
+
+        handlers.register(tx_type, apply_bridge_pool_proof);
+        let result = dispatch(&mut state, &handlers).unwrap();
+        assert_eq!(result.changed_keys.len(), 1);
+        assert_eq!(state.write_log().get_keys(), result.changed_keys);
+    }
+}
@@ -0,0 +1,2163 @@
+//! The execution of the validity predicates triggered by a transaction
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Debug;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use namada_core::booleans::BoolResultUnitExt;
+use namada_core::collections::HashMap;
+use namada_core::hash::Hash;
+use namada_gas::{Gas, TxGasMeter, NATIVE_VP_INIT_GAS};
+use namada_tx::data::{VpStatusFlags, VpsResult};
+use namada_tx::Tx;
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use rayon::slice::ParallelSlice;
+
+use super::{BlockAccumulators, Error, Result};
+use crate::address::{Address, InternalAddress};
+use crate::ledger::gas::{GasMetering, VpGasMeter};
+use crate::ledger::governance::GovernanceVp;
+use crate::ledger::native_vp::ethereum_bridge::bridge_pool_vp::BridgePoolVp;
+use crate::ledger::native_vp::ethereum_bridge::nut::NonUsableTokens;
+use crate::ledger::native_vp::ethereum_bridge::vp::EthBridge;
+use crate::ledger::native_vp::ibc::Ibc;
+use crate::ledger::native_vp::masp::MaspVp;
+use crate::ledger::native_vp::multitoken::MultitokenVp;
+use crate::ledger::native_vp::parameters::ParametersVp;
+use crate::ledger::native_vp::{self, NativeVp};
+use crate::ledger::pgf::PgfVp;
+use crate::ledger::pos::PosVP;
+use crate::state::State;
+use crate::storage;
+use crate::storage::TxIndex;
+use crate::vm::wasm::run::VpTimings;
+use crate::vm::wasm::VpCache;
+use crate::vm::{wasm, WasmCacheAccess};
+
+/// A callback observing the result of each VP as soon as it completes, e.g.
+/// to export per-VP metrics. It receives the address of the verifier, whether
+/// it accepted the transaction, the error it failed with, if any, and the gas
+/// it consumed. The VPs run in parallel, so the callback may be invoked
+/// concurrently from several threads and must be thread-safe.
+#[derive(Clone, Copy)]
+pub struct VpObserver<'a>(
+    pub &'a (dyn Fn(&Address, bool, Option<&str>, u64) + Sync),
+);
+
+impl Debug for VpObserver<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("VpObserver")
+    }
+}
+
+/// Arguments to [`check_vps`].
+pub(super) struct CheckVps<'a, S, CA>
+where
+    S: State,
+    CA: 'static + WasmCacheAccess + Sync,
+{
+    pub(super) tx: &'a Tx,
+    pub(super) tx_index: &'a TxIndex,
+    pub(super) state: &'a S,
+    pub(super) tx_gas_meter: &'a mut TxGasMeter,
+    pub(super) verifiers_from_tx: &'a BTreeSet<Address>,
+    pub(super) vp_wasm_cache: &'a mut VpCache<CA>,
+    pub(super) reduce_chunk_size: Option<usize>,
+    pub(super) vp_observer: Option<VpObserver<'a>>,
+    pub(super) block_accumulators: Option<&'a RefCell<BlockAccumulators>>,
+    pub(super) internal_access_limits:
+        Option<&'a BTreeMap<InternalAddress, u64>>,
+}
+
+/// Check the acceptance of a transaction by validity predicates. When the
+/// block accumulators are given, a tx triggering the VP of an internal
+/// address that already reached its limit in the block is rejected before
+/// running any VP.
+pub(super) fn check_vps<S, CA>(
+    CheckVps {
+        tx,
+        tx_index,
+        state,
+        tx_gas_meter,
+        verifiers_from_tx,
+        vp_wasm_cache,
+        reduce_chunk_size,
+        vp_observer,
+        block_accumulators,
+        internal_access_limits,
+    }: CheckVps<'_, S, CA>,
+) -> Result<VpsResult>
+where
+    S: State + Sync,
+    CA: 'static + WasmCacheAccess + Sync,
+{
+    // Bound the cost of the VPs evaluation before doing any work on the
+    // verifiers
+    let max_verifiers =
+        namada_parameters::storage::get_max_verifiers_per_tx(state)
+            .map_err(Error::StorageError)?;
+    let check_verifiers_count = |verifiers: &BTreeSet<Address>| {
+        let count = verifiers.len() as u64;
+        if count > max_verifiers {
+            return Err(Error::TooManyVerifiers {
+                count,
+                max: max_verifiers,
+            });
+        }
+        Ok(())
+    };
+    check_verifiers_count(verifiers_from_tx)?;
+    let (verifiers, keys_changed) = state
+        .write_log()
+        .verifiers_and_changed_keys(verifiers_from_tx);
+    check_verifiers_count(&verifiers)?;
+
+    // Changes to the storage of newly initialized accounts need no verifier
+    let initialized_accounts = state.write_log().get_initialized_accounts();
+    let no_verifiers = verifiers.is_empty()
+        && keys_changed.iter().any(|key| {
+            key.fst_address()
+                .map_or(true, |addr| !initialized_accounts.contains(addr))
+        });
+    if no_verifiers {
+        tracing::warn!(
+            "Transaction {} changed storage keys without triggering any \
+             verifier",
+            tx.header_hash()
+        );
+        if namada_parameters::storage::get_reject_unverified_changes(state)
+            .map_err(Error::StorageError)?
+            .unwrap_or_default()
+        {
+            return Err(Error::NoVerifiers);
+        }
+    }
+
+    if let (Some(accumulators), Some(limits)) =
+        (block_accumulators, internal_access_limits)
+    {
+        accumulators
+            .borrow_mut()
+            .count_internal_accesses(&verifiers, limits)?;
+    }
+
+    let vp_gas_budget = namada_parameters::storage::get_vp_gas_budget(state)
+        .map_err(Error::StorageError)?;
+    let max_vp_gas = namada_parameters::storage::get_max_vp_gas(state)
+        .map_err(Error::StorageError)?;
+    let mut vps_result = execute_vps(
+        verifiers,
+        keys_changed,
+        tx,
+        tx_index,
+        state,
+        tx_gas_meter,
+        vp_gas_budget,
+        max_vp_gas,
+        reduce_chunk_size,
+        vp_observer,
+        vp_wasm_cache,
+    )?;
+    tracing::debug!("Total VPs gas cost {:?}", vps_result.gas_used);
+    #[cfg(any(test, feature = "debug-results"))]
+    if !vps_result.read_parameters.is_empty() {
+        tracing::debug!(
+            "Protocol parameters read by the VPs: {:?}",
+            vps_result.read_parameters
+        );
+    }
+    if no_verifiers {
+        vps_result.status_flags.insert(VpStatusFlags::NO_VERIFIERS);
+    }
+
+    tx_gas_meter
+        .add_vps_gas(&vps_result.gas_used)
+        .map_err(|err| Error::GasError(err.to_string()))?;
+
+    Ok(vps_result)
+}
+
+/// Read the VP code hashes of the implicit and established accounts among the
+/// verifiers, together with the gas cost of each read.
+fn prefetch_vp_hashes<S>(
+    state: &S,
+    verifiers: &BTreeSet<Address>,
+) -> Result<HashMap<Address, (Option<Hash>, u64)>>
+where
+    S: State,
+{
+    verifiers
+        .iter()
+        .filter(|addr| {
+            matches!(addr, Address::Implicit(_) | Address::Established(_))
+        })
+        .map(|addr| {
+            let entry =
+                state.validity_predicate(addr).map_err(Error::StateError)?;
+            Ok((addr.clone(), entry))
+        })
+        .collect()
+}
+
+/// Execute verifiers' validity predicates. The VPs gas is bounded by the gas
+/// left in the tx gas meter, and also by the fixed `vp_gas_budget` when given.
+/// The gas of any single VP is further bounded by the optional `max_vp_gas`, a
+/// VP exceeding it gets rejected. When a `reduce_chunk_size` is given, the
+/// verifiers are run serially in chunks of that size (at least one) within
+/// each parallel task. The optional `vp_observer` is notified of the result
+/// of each VP as soon as it completes. The VP code hashes of the accounts
+/// are read up front, before the parallel run. A single verifier is run on
+/// the current thread, bypassing the thread pool. The errors are sorted by
+/// verifier, so that the result doesn't depend on the order the VPs complete.
+/// The VP code hash run for each account is recorded in the result. Each
+/// native VP is charged a fixed gas cost for the setup of its context. With
+/// the `vp-timings` feature, the time spent compiling and executing each wasm
+/// VP is logged.
+#[allow(clippy::too_many_arguments)]
+fn execute_vps<S, CA>(
+    verifiers: BTreeSet<Address>,
+    keys_changed: BTreeSet<storage::Key>,
+    tx: &Tx,
+    tx_index: &TxIndex,
+    state: &S,
+    tx_gas_meter: &TxGasMeter,
+    vp_gas_budget: Option<u64>,
+    max_vp_gas: Option<u64>,
+    reduce_chunk_size: Option<usize>,
+    vp_observer: Option<VpObserver<'_>>,
+    vp_wasm_cache: &mut VpCache<CA>,
+) -> Result<VpsResult>
+where
+    S: State + Sync,
+    CA: 'static + WasmCacheAccess + Sync,
+{
+    let vp_budget_meter = vp_gas_budget.map(|budget| {
+        let budget = Gas::from(budget);
+        let available = tx_gas_meter.get_available_gas();
+        TxGasMeter::new(if budget < available { budget } else { available })
+    });
+    let tx_gas_meter = vp_budget_meter.as_ref().unwrap_or(tx_gas_meter);
+    // Flag the VPs as they start, to report the ones skipped on a gas error
+    let started_vps: BTreeMap<Address, AtomicBool> = verifiers
+        .iter()
+        .map(|addr| (addr.clone(), AtomicBool::new(false)))
+        .collect();
+    // Read the VP code hashes of the accounts before the parallel run, so
+    // that the tasks only have to look them up
+    let vp_hashes = prefetch_vp_hashes(state, &verifiers)?;
+    let rng_seed = native_vp::vp_rng_seed(
+        &tx.header_hash(),
+        state.in_mem().get_block_height().0,
+    );
+    let run_vp = |mut result: VpsResult, addr: &Address| -> Result<VpsResult> {
+        if let Some(started) = started_vps.get(addr) {
+            started.store(true, Ordering::Relaxed);
+        }
+        let gas_meter = VpGasMeter::new_from_tx_meter(tx_gas_meter);
+        let gas_meter = RefCell::new(match max_vp_gas {
+            Some(cap) => gas_meter.with_gas_cap(Gas::from(cap)),
+            None => gas_meter,
+        });
+        let tx_accepted = match &addr {
+            Address::Implicit(_) | Address::Established(_) => {
+                let (vp_hash, gas) = vp_hashes
+                    .get(addr)
+                    .copied()
+                    .expect("The VP hashes of all the accounts are prefetched");
+                gas_meter
+                    .borrow_mut()
+                    .consume(gas)
+                    .map_err(|err| Error::GasError(err.to_string()))?;
+                let Some(vp_code_hash) = vp_hash else {
+                    return Err(Error::MissingAddress(addr.clone()));
+                };
+                tracing::debug!(
+                    "Running VP {} with code hash {}",
+                    addr,
+                    vp_code_hash
+                );
+                #[cfg(any(test, feature = "debug-results"))]
+                result.vp_code_hashes.insert(addr.clone(), vp_code_hash);
+
+                let mut timings =
+                    cfg!(feature = "vp-timings").then(VpTimings::default);
+                let accepted = wasm::run::vp_with_timings(
+                    vp_code_hash,
+                    tx,
+                    tx_index,
+                    addr,
+                    state,
+                    &gas_meter,
+                    &keys_changed,
+                    &verifiers,
+                    vp_wasm_cache.clone(),
+                    timings.as_mut(),
+                )
+                .map_err(|err| match err {
+                    wasm::run::Error::GasError(msg) => Error::GasError(msg),
+                    wasm::run::Error::InvalidSectionSignature(msg) => {
+                        Error::InvalidSectionSignature(msg)
+                    }
+                    _ => Error::VpRunnerError(err),
+                });
+                // The timings differ across nodes, so they are only logged
+                // rather than reported in the result of the VPs
+                if let Some(timings) = timings {
+                    tracing::info!(
+                        "Wasm VP {} compiled in {}ns and executed in {}ns",
+                        addr,
+                        timings.compile_nanos,
+                        timings.execution_nanos
+                    );
+                }
+                accepted
+            }
+            Address::Internal(internal_addr) => {
+                let epoch = state.in_mem().block.epoch;
+                tracing::debug!(
+                    "Running native VP {} at epoch {}",
+                    addr,
+                    epoch
+                );
+                #[cfg(any(test, feature = "debug-results"))]
+                result.native_vp_epochs.insert(addr.clone(), epoch);
+                #[cfg(any(test, feature = "debug-results"))]
+                result.validated_keys.insert(
+                    addr.clone(),
+                    keys_changed
+                        .iter()
+                        .filter(|key| key.fst_address() == Some(addr))
+                        .cloned()
+                        .collect(),
+                );
+                // Charge a fixed cost for the setup of the native VP, so that
+                // the txs triggering many of them are metered proportionally
+                gas_meter
+                    .borrow_mut()
+                    .consume(NATIVE_VP_INIT_GAS)
+                    .map_err(|err| Error::GasError(err.to_string()))?;
+                #[cfg(any(test, feature = "debug-results"))]
+                let read_keys = RefCell::new(BTreeSet::new());
+                let mut ctx = native_vp::Ctx::new(
+                    addr,
+                    state,
+                    tx,
+                    tx_index,
+                    &gas_meter,
+                    &keys_changed,
+                    &verifiers,
+                    vp_wasm_cache.clone(),
+                );
+                ctx.rng_seed = rng_seed;
+                #[cfg(any(test, feature = "read-set"))]
+                {
+                    ctx.read_keys = Some(&read_keys);
+                }
+
+                let validate = || match internal_addr {
+                    InternalAddress::PoS => {
+                        let pos = PosVP { ctx };
+                        pos.validate_tx(tx, &keys_changed, &verifiers)
+                            .map_err(Error::PosNativeVpError)
+                    }
+                    InternalAddress::Ibc => {
+                        let ibc = Ibc { ctx };
+                        ibc.validate_tx(tx, &keys_changed, &verifiers)
+                            .map_err(Error::IbcNativeVpError)
+                    }
+                    InternalAddress::Parameters => {
+                        let parameters = ParametersVp { ctx };
+                        parameters
+                            .validate_tx(tx, &keys_changed, &verifiers)
+                            .map_err(Error::ParametersNativeVpError)
+                    }
+                    InternalAddress::PosSlashPool => Err(
+                        Error::AccessForbidden((*internal_addr).clone()),
+                    ),
+                    InternalAddress::Governance => {
+                        let governance = GovernanceVp { ctx };
+                        governance
+                            .validate_tx(tx, &keys_changed, &verifiers)
+                            .map_err(Error::GovernanceNativeVpError)
+                    }
+                    InternalAddress::Multitoken => {
+                        let multitoken = MultitokenVp { ctx };
+                        multitoken
+                            .validate_tx(tx, &keys_changed, &verifiers)
+                            .map_err(Error::MultitokenNativeVpError)
+                    }
+                    InternalAddress::EthBridge => {
+                        let bridge = EthBridge { ctx };
+                        bridge
+                            .validate_tx(tx, &keys_changed, &verifiers)
+                            .map_err(Error::EthBridgeNativeVpError)
+                    }
+                    InternalAddress::EthBridgePool => {
+                        let bridge_pool = BridgePoolVp { ctx };
+                        bridge_pool
+                            .validate_tx(tx, &keys_changed, &verifiers)
+                            .map_err(Error::BridgePoolNativeVpError)
+                    }
+                    InternalAddress::Pgf => {
+                        let pgf_vp = PgfVp { ctx };
+                        pgf_vp
+                            .validate_tx(tx, &keys_changed, &verifiers)
+                            .map_err(Error::PgfNativeVpError)
+                    }
+                    InternalAddress::Nut(_) => {
+                        let non_usable_tokens = NonUsableTokens { ctx };
+                        non_usable_tokens
+                            .validate_tx(tx, &keys_changed, &verifiers)
+                            .map_err(Error::NutNativeVpError)
+                    }
+                    internal_addr @ (InternalAddress::IbcToken(_)
+                    | InternalAddress::Erc20(_)) => {
+                        // The address should be a part of a multitoken
+                        // key
+                        verifiers
+                            .contains(&Address::Internal(
+                                InternalAddress::Multitoken,
+                            ))
+                            .ok_or_else(|| {
+                                Error::AccessForbidden(internal_addr.clone())
+                            })
+                    }
+                    InternalAddress::Masp => {
+                        let masp = MaspVp { ctx };
+                        masp.validate_tx(tx, &keys_changed, &verifiers)
+                            .map_err(Error::MaspNativeVpError)
+                    }
+                    InternalAddress::TempStorage => Err(
+                        // Temp storage changes must never be committed
+                        Error::AccessForbidden((*internal_addr).clone()),
+                    ),
+                };
+                // A panicking native VP rejects the tx instead of unwinding
+                // through the thread pool
+                let accepted = panic::catch_unwind(AssertUnwindSafe(validate))
+                    .unwrap_or_else(|_| {
+                        tracing::error!("The native VP of {} panicked", addr);
+                        Err(Error::VpPanic { addr: addr.clone() })
+                    });
+                #[cfg(any(test, feature = "debug-results"))]
+                {
+                    let mut read_keys = read_keys.take();
+                    let is_parameter_key =
+                        namada_parameters::storage::is_parameter_key;
+                    result.read_parameters.extend(
+                        read_keys
+                            .iter()
+                            .filter(|key| is_parameter_key(key))
+                            .cloned(),
+                    );
+                    result.read_keys.append(&mut read_keys);
+                }
+                accepted
+            }
+        };
+        // Tell a VP running over its own ceiling apart from any other
+        // failure
+        let tx_accepted = match (tx_accepted, max_vp_gas) {
+            (Err(_), Some(cap))
+                if gas_meter.borrow().get_vp_consumed_gas() > Gas::from(cap) =>
+            {
+                Err(Error::VpGasCeilingExceeded(addr.clone(), cap))
+            }
+            (tx_accepted, _) => tx_accepted,
+        };
+
+        if let Some(VpObserver(observe)) = vp_observer {
+            let error = tx_accepted.as_ref().err().map(ToString::to_string);
+            observe(
+                addr,
+                tx_accepted.is_ok(),
+                error.as_deref(),
+                gas_meter.borrow().get_vp_consumed_gas().into(),
+            );
+        }
+
+        tx_accepted.map_or_else(
+            |err| {
+                result.status_flags.insert(err.vp_status_flags());
+                result.rejected_vps.insert(addr.clone());
+                result.errors.push(err.vp_rejection(addr));
+            },
+            |()| {
+                result.accepted_vps.insert(addr.clone());
+            },
+        );
+
+        // Execution of VPs can (and must) be short-circuited
+        // only in case of a gas overflow to prevent the
+        // transaction from consuming resources that have not
+        // been acquired in the corresponding wrapper tx. For
+        // all the other errors we keep evaluating the vps. This
+        // allows to display a consistent VpsResult across all
+        // nodes and find any invalid signatures
+        let gas_meter = gas_meter.into_inner();
+        #[cfg(any(test, feature = "debug-results"))]
+        result.per_vp_gas.insert(addr.clone(), gas_meter.get_vp_consumed_gas());
+        result
+            .gas_used
+            .set(gas_meter)
+            .map_err(|err| Error::GasError(err.to_string()))?;
+
+        Ok(result)
+    };
+    let run_vps = |group: &BTreeSet<Address>| match reduce_chunk_size {
+        Some(chunk_size) => group
+            .iter()
+            .collect::<Vec<_>>()
+            .par_chunks(chunk_size.max(1))
+            .map(|chunk| {
+                chunk.iter().try_fold(VpsResult::default(), |result, addr| {
+                    run_vp(result, *addr)
+                })
+            })
+            .try_reduce(VpsResult::default, |a, b| {
+                merge_vp_results(a, b, tx_gas_meter)
+            }),
+        None => group
+            .par_iter()
+            .try_fold(VpsResult::default, &run_vp)
+            .try_reduce(VpsResult::default, |a, b| {
+                merge_vp_results(a, b, tx_gas_meter)
+            }),
+    };
+
+    let (native_result, wasm_result) = match verifiers.first() {
+        Some(addr) if verifiers.len() == 1 => {
+            // A single verifier, e.g. the multitoken VP of a transfer, is run
+            // on the current thread, its result being folded and reduced like
+            // on the thread pool
+            let result = run_vp(VpsResult::default(), addr).and_then(|result| {
+                merge_vp_results(VpsResult::default(), result, tx_gas_meter)
+            });
+            if matches!(addr, Address::Internal(_)) {
+                (result, Ok(VpsResult::default()))
+            } else {
+                (Ok(VpsResult::default()), result)
+            }
+        }
+        _ => {
+            // Native VPs don't use the wasm cache and have their own cost
+            // profiles, run them apart from the wasm VPs
+            let (native_vps, wasm_vps): (BTreeSet<_>, BTreeSet<_>) = verifiers
+                .iter()
+                .cloned()
+                .partition(|addr| matches!(addr, Address::Internal(_)));
+            rayon::join(|| run_vps(&native_vps), || run_vps(&wasm_vps))
+        }
+    };
+
+    native_result
+        .and_then(|native_result| {
+            merge_vp_results(native_result, wasm_result?, tx_gas_meter)
+        })
+        .map(|mut result| {
+            // The parallel reduction appends the errors in the order the VPs
+            // complete, which differs across nodes
+            result.errors.sort();
+            result
+        })
+        .map_err(|err| match err {
+            // Report the VPs that never ran because of the short-circuit, the
+            // nodes may abort at different points
+            Error::GasError(msg) => Error::VpsGasError {
+                msg,
+                skipped_vps: started_vps
+                    .into_iter()
+                    .filter_map(|(addr, started)| {
+                        (!started.into_inner()).then_some(addr)
+                    })
+                    .collect(),
+            },
+            err => err,
+        })
+}
+
+/// Execute the VPs like [`execute_vps`], streaming the outcome of each VP to
+/// `on_vp_result` as soon as it completes, with the error it failed with if
+/// it rejected the tx. The callback is invoked from the parallel tasks in the
+/// order the VPs complete, which is not deterministic, but the returned
+/// result is the same as without streaming.
+#[allow(clippy::too_many_arguments)]
+pub fn execute_vps_streaming<S, CA, F>(
+    verifiers: BTreeSet<Address>,
+    keys_changed: BTreeSet<storage::Key>,
+    tx: &Tx,
+    tx_index: &TxIndex,
+    state: &S,
+    tx_gas_meter: &TxGasMeter,
+    vp_gas_budget: Option<u64>,
+    max_vp_gas: Option<u64>,
+    reduce_chunk_size: Option<usize>,
+    on_vp_result: F,
+    vp_wasm_cache: &mut VpCache<CA>,
+) -> Result<VpsResult>
+where
+    S: State + Sync,
+    CA: 'static + WasmCacheAccess + Sync,
+    F: Fn(&Address, std::result::Result<(), &str>) + Sync,
+{
+    let observe = |addr: &Address,
+                   accepted: bool,
+                   error: Option<&str>,
+                   _gas: u64| {
+        on_vp_result(
+            addr,
+            if accepted {
+                Ok(())
+            } else {
+                Err(error.unwrap_or_default())
+            },
+        )
+    };
+    execute_vps(
+        verifiers,
+        keys_changed,
+        tx,
+        tx_index,
+        state,
+        tx_gas_meter,
+        vp_gas_budget,
+        max_vp_gas,
+        reduce_chunk_size,
+        Some(VpObserver(&observe)),
+        vp_wasm_cache,
+    )
+}
+
+/// Merge VP results from parallel runs
+fn merge_vp_results(
+    mut a: VpsResult,
+    mut b: VpsResult,
+    tx_gas_meter: &TxGasMeter,
+) -> Result<VpsResult> {
+    a.accepted_vps.extend(b.accepted_vps);
+    a.rejected_vps.extend(b.rejected_vps);
+    a.errors.append(&mut b.errors);
+    a.status_flags |= b.status_flags;
+    #[cfg(any(test, feature = "debug-results"))]
+    {
+        a.native_vp_epochs.append(&mut b.native_vp_epochs);
+        a.read_keys.append(&mut b.read_keys);
+        a.per_vp_gas.append(&mut b.per_vp_gas);
+        a.read_parameters.append(&mut b.read_parameters);
+        a.vp_code_hashes.append(&mut b.vp_code_hashes);
+        a.validated_keys.append(&mut b.validated_keys);
+    }
+
+    a.gas_used
+        .merge(b.gas_used, tx_gas_meter)
+        .map_err(|err| Error::GasError(err.to_string()))?;
+
+    Ok(a)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use borsh_ext::BorshSerializeExt;
+    use namada_core::address;
+    use namada_core::storage::{BlockHeight, Key, KeySeg};
+    use namada_core::validity_predicate::VpError;
+    use namada_ethereum_bridge::test_utils;
+    use namada_state::{StateRead, StorageWrite};
+    use namada_test_utils::TestWasms;
+    use namada_tx::data::{
+        NativeVpReason, TxType, VpErrorClass, VpRejection, VpRejectionReason,
+    };
+
+    use super::*;
+    use crate::ledger::protocol::tests::credit;
+    use crate::token::Amount;
+
+    #[test]
+    /// Tests that the epoch resolved by native VPs is recorded in the VPs
+    /// result when the tx is applied right after an epoch boundary
+    fn test_native_vp_effective_epoch() {
+        let (mut state, _validators) = test_utils::setup_default_storage();
+
+        // cross an epoch boundary
+        let height = state.in_mem().block.height;
+        let new_epoch = state.in_mem().block.epoch.next();
+        state.in_mem_mut().block.epoch = new_epoch;
+        state.in_mem_mut().block.pred_epochs.new_epoch(height);
+
+        let mut tx = Tx::from_type(TxType::Raw);
+        tx.set_code(namada_tx::Code::new(vec![], None));
+        tx.set_data(namada_tx::Data::new(vec![]));
+
+        let multitoken = Address::Internal(InternalAddress::Multitoken);
+        let verifiers = BTreeSet::from([multitoken.clone()]);
+
+        let (mut vp_cache, _) =
+            wasm::compilation_cache::common::testing::cache();
+        let gas_meter = TxGasMeter::new(u64::MAX);
+
+        let result = execute_vps(
+            verifiers,
+            BTreeSet::new(),
+            &tx,
+            &TxIndex::default(),
+            &state,
+            &gas_meter,
+            None,
+            None,
+            None,
+            None,
+            &mut vp_cache,
+        )
+        .unwrap();
+        assert_eq!(result.native_vp_epochs.get(&multitoken), Some(&new_epoch));
+    }
+
+    #[test]
+    /// Tests that the balance keys of a transfer show up in the keys read by
+    /// the multitoken VP
+    fn test_native_vp_read_keys() {
+        let (mut state, _validators) = test_utils::setup_default_storage();
+
+        let token_address = Address::Established([0xff; 20].into());
+        let src_address = Address::Established([0xab; 20].into());
+        let dst_address = Address::Established([0xba; 20].into());
+
+        credit(&mut state, &token_address, &src_address, 1000.into());
+        state.commit_tx();
+        state.commit_block().unwrap();
+
+        let mut tx = Tx::from_type(TxType::Raw);
+        tx.set_code(namada_tx::Code::new(vec![], None));
+        tx.set_data(namada_tx::Data::new(vec![]));
+
+        namada_token::transfer(
+            &mut state,
+            &token_address,
+            &src_address,
+            &dst_address,
+            500.into(),
+        )
+        .unwrap();
+        let src_key = namada_token::storage_key::balance_key(
+            &token_address,
+            &src_address,
+        );
+        let dst_key = namada_token::storage_key::balance_key(
+            &token_address,
+            &dst_address,
+        );
+        let changed_keys = BTreeSet::from([src_key.clone(), dst_key.clone()]);
+        let verifiers =
+            BTreeSet::from([Address::Internal(InternalAddress::Multitoken)]);
+
+        let (mut vp_cache, _) =
+            wasm::compilation_cache::common::testing::cache();
+        let gas_meter = TxGasMeter::new(u64::MAX);
+
+        let result = execute_vps(
+            verifiers,
+            changed_keys,
+            &tx,
+            &TxIndex::default(),
+            &state,
+            &gas_meter,
+            None,
+            None,
+            None,
+            None,
+            &mut vp_cache,
+        )
+        .unwrap();
+        assert!(result.read_keys.contains(&src_key));
+        assert!(result.read_keys.contains(&dst_key));
+    }
+
+    #[test]
+    /// Tests that the protocol parameters consulted by a native VP are
+    /// recorded, apart from the other keys it read
+    fn test_native_vp_read_parameters() {
+        let (mut state, _validators) = test_utils::setup_default_storage();
+        let token_address = Address::Established([0xff; 20].into());
+        let src_address = Address::Established([0xab; 20].into());
+        let dst_address = Address::Established([0xba; 20].into());
+        namada_token::transfer(
+            &mut state,
+            &token_address,
+            &src_address,
+            &dst_address,
+            0.into(),
+        )
+        .unwrap();
+
+        let mut tx = Tx::from_type(TxType::Raw);
+        tx.set_code(namada_tx::Code::new(vec![], None));
+        tx.set_data(namada_tx::Data::new(vec![]));
+        let src_key = namada_token::storage_key::balance_key(
+            &token_address,
+            &src_address,
+        );
+        let dst_key = namada_token::storage_key::balance_key(
+            &token_address,
+            &dst_address,
+        );
+        let changed_keys = BTreeSet::from([src_key.clone(), dst_key]);
+        let verifiers =
+            BTreeSet::from([Address::Internal(InternalAddress::Multitoken)]);
+        let (mut vp_cache, _) =
+            wasm::compilation_cache::common::testing::cache();
+
+        let result = execute_vps(
+            verifiers,
+            changed_keys,
+            &tx,
+            &TxIndex::default(),
+            &state,
+            &TxGasMeter::new(u64::MAX),
+            None,
+            None,
+            None,
+            None,
+            &mut vp_cache,
+        )
+        .unwrap();
+        // the multitoken VP checks if the native token is transferable
+        let transferable_key =
+            namada_parameters::storage::get_native_token_transferable_key();
+        assert!(result.read_parameters.contains(&transferable_key));
+        assert!(result.read_keys.contains(&transferable_key));
+        // other keys are not reported as parameters
+        assert!(result.read_keys.contains(&src_key));
+        assert!(!result.read_parameters.contains(&src_key));
+    }
+
+    #[test]
+    /// Tests that a tx changing keys without triggering any verifier is
+    /// flagged, or rejected when the `reject_unverified_changes` parameter is
+    /// set
+    fn test_check_vps_no_verifiers() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let (mut vp_cache, _) =
+            wasm::compilation_cache::common::testing::cache();
+        let mut tx = Tx::from_type(TxType::Raw);
+        tx.set_code(namada_tx::Code::new(vec![], None));
+        tx.set_data(namada_tx::Data::new(vec![]));
+
+        // a key without an address has no VP to verify its change
+        let key = Key::parse("unverified").unwrap();
+        state.write_log_mut().write(&key, vec![0]).unwrap();
+
+        let mut gas_meter = TxGasMeter::new(u64::MAX);
+        let vps_result = check_vps(CheckVps {
+            tx: &tx,
+            tx_index: &TxIndex::default(),
+            state: &*state,
+            tx_gas_meter: &mut gas_meter,
+            verifiers_from_tx: &BTreeSet::new(),
+            vp_wasm_cache: &mut vp_cache,
+            reduce_chunk_size: None,
+            vp_observer: None,
+            block_accumulators: None,
+            internal_access_limits: None,
+        })
+        .unwrap();
+        assert!(vps_result.status_flags.contains(VpStatusFlags::NO_VERIFIERS));
+        assert!(vps_result.rejected_vps.is_empty());
+
+        // strict mode
+        let strict_key =
+            namada_parameters::storage::get_reject_unverified_changes_key();
+        state.write(&strict_key, true).unwrap();
+        let result = check_vps(CheckVps {
+            tx: &tx,
+            tx_index: &TxIndex::default(),
+            state: &*state,
+            tx_gas_meter: &mut gas_meter,
+            verifiers_from_tx: &BTreeSet::new(),
+            vp_wasm_cache: &mut vp_cache,
+            reduce_chunk_size: None,
+            vp_observer: None,
+            block_accumulators: None,
+            internal_access_limits: None,
+        });
+        assert!(matches!(result.unwrap_err(), Error::NoVerifiers));
+    }
+
+    #[test]
+    /// Tests that a tx triggering more verifiers than allowed by the
+    /// `max_verifiers_per_tx` parameter is rejected
+    fn test_check_vps_too_many_verifiers() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let (mut vp_cache, _) =
+            wasm::compilation_cache::common::testing::cache();
+        let mut tx = Tx::from_type(TxType::Raw);
+        tx.set_code(namada_tx::Code::new(vec![], None));
+        tx.set_data(namada_tx::Data::new(vec![]));
+        let verifiers = BTreeSet::from([
+            Address::Internal(InternalAddress::Parameters),
+            Address::Internal(InternalAddress::Multitoken),
+            Address::Internal(InternalAddress::TempStorage),
+        ]);
+
+        // the default limit is not hit
+        let mut gas_meter = TxGasMeter::new(u64::MAX);
+        let vps_result = check_vps(CheckVps {
+            tx: &tx,
+            tx_index: &TxIndex::default(),
+            state: &*state,
+            tx_gas_meter: &mut gas_meter,
+            verifiers_from_tx: &verifiers,
+            vp_wasm_cache: &mut vp_cache,
+            reduce_chunk_size: None,
+            vp_observer: None,
+            block_accumulators: None,
+            internal_access_limits: None,
+        })
+        .unwrap();
+        assert_eq!(
+            vps_result.rejected_vps,
+            BTreeSet::from([Address::Internal(InternalAddress::TempStorage)])
+        );
+
+        let max_key =
+            namada_parameters::storage::get_max_verifiers_per_tx_key();
+        state.write(&max_key, 2_u64).unwrap();
+        let result = check_vps(CheckVps {
+            tx: &tx,
+            tx_index: &TxIndex::default(),
+            state: &*state,
+            tx_gas_meter: &mut gas_meter,
+            verifiers_from_tx: &verifiers,
+            vp_wasm_cache: &mut vp_cache,
+            reduce_chunk_size: None,
+            vp_observer: None,
+            block_accumulators: None,
+            internal_access_limits: None,
+        });
+        assert!(matches!(
+            result.unwrap_err(),
+            Error::TooManyVerifiers { count: 3, max: 2 }
+        ));
+    }
+
+    #[test]
+    /// Tests that a fixed VP gas budget bounds the VPs gas on top of the gas
+    /// left to the tx, which is still charged for the VPs
+    fn test_vp_gas_budget() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let token_address = Address::Established([0xff; 20].into());
+        let src_address = Address::Established([0xab; 20].into());
+        let dst_address = Address::Established([0xba; 20].into());
+        namada_token::transfer(
+            &mut state,
+            &token_address,
+            &src_address,
+            &dst_address,
+            0.into(),
+        )
+        .unwrap();
+
+        let mut tx = Tx::from_type(TxType::Raw);
+        tx.set_code(namada_tx::Code::new(vec![], None));
+        tx.set_data(namada_tx::Data::new(vec![]));
+        let changed_keys = BTreeSet::from([
+            namada_token::storage_key::balance_key(
+                &token_address,
+                &src_address,
+            ),
+            namada_token::storage_key::balance_key(
+                &token_address,
+                &dst_address,
+            ),
+        ]);
+        let verifiers =
+            BTreeSet::from([Address::Internal(InternalAddress::Multitoken)]);
+        let (mut vp_cache, _) =
+            wasm::compilation_cache::common::testing::cache();
+
+        // the tx has consumed all of its gas, the budget doesn't extend it
+        let mut exhausted_meter = TxGasMeter::new(1_000);
+        exhausted_meter.consume(1_000).unwrap();
+        for vp_gas_budget in [None, Some(u64::MAX)] {
+            let result = execute_vps(
+                verifiers.clone(),
+                changed_keys.clone(),
+                &tx,
+                &TxIndex::default(),
+                &*state,
+                &exhausted_meter,
+                vp_gas_budget,
+                None,
+                None,
+                None,
+                &mut vp_cache,
+            );
+            assert!(matches!(result.unwrap_err(), Error::VpsGasError { .. }));
+        }
+
+        // the VPs gas fitting the budget is charged to the tx
+        state
+            .write(
+                &namada_parameters::storage::get_vp_gas_budget_key(),
+                u64::MAX,
+            )
+            .unwrap();
+        let mut gas_meter = TxGasMeter::new(u64::MAX);
+        let vps_result = check_vps(CheckVps {
+            tx: &tx,
+            tx_index: &TxIndex::default(),
+            state: &*state,
+            tx_gas_meter: &mut gas_meter,
+            verifiers_from_tx: &verifiers,
+            vp_wasm_cache: &mut vp_cache,
+            reduce_chunk_size: None,
+            vp_observer: None,
+            block_accumulators: None,
+            internal_access_limits: None,
+        })
+        .unwrap();
+        let vps_gas = vps_result.gas_used.get_current_gas().unwrap();
+        assert!(vps_gas > Gas::default());
+        assert_eq!(gas_meter.get_tx_consumed_gas(), vps_gas);
+
+        // the tx has plenty of gas, but the VPs budget is exhausted
+        let result = execute_vps(
+            verifiers,
+            changed_keys,
+            &tx,
+            &TxIndex::default(),
+            &*state,
+            &TxGasMeter::new(u64::MAX),
+            Some(0),
+            None,
+            None,
+            None,
+            &mut vp_cache,
+        );
+        assert!(matches!(result.unwrap_err(), Error::VpsGasError { .. }));
+    }
+
+    #[test]
+    /// Tests that the gas consumed by each VP is recorded in the result
+    fn test_per_vp_gas() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let token_address = Address::Established([0xff; 20].into());
+        let src_address = Address::Established([0xab; 20].into());
+        let dst_address = Address::Established([0xba; 20].into());
+        namada_token::transfer(
+            &mut state,
+            &token_address,
+            &src_address,
+            &dst_address,
+            0.into(),
+        )
+        .unwrap();
+
+        let mut tx = Tx::from_type(TxType::Raw);
+        tx.set_code(namada_tx::Code::new(vec![], None));
+        tx.set_data(namada_tx::Data::new(vec![]));
+        let changed_keys = BTreeSet::from([
+            namada_token::storage_key::balance_key(
+                &token_address,
+                &src_address,
+            ),
+            namada_token::storage_key::balance_key(
+                &token_address,
+                &dst_address,
+            ),
+        ]);
+        let multitoken = Address::Internal(InternalAddress::Multitoken);
+        let verifiers = BTreeSet::from([
+            multitoken.clone(),
+            Address::Internal(InternalAddress::Parameters),
+        ]);
+        let (mut vp_cache, _) =
+            wasm::compilation_cache::common::testing::cache();
+
+        let result = execute_vps(
+            verifiers.clone(),
+            changed_keys,
+            &tx,
+            &TxIndex::default(),
+            &*state,
+            &TxGasMeter::new(u64::MAX),
+            None,
+            None,
+            None,
+            None,
+            &mut vp_cache,
+        )
+        .unwrap();
+        assert_eq!(
+            result.per_vp_gas.keys().cloned().collect::<BTreeSet<_>>(),
+            verifiers
+        );
+        // the multitoken VP read the balances
+        assert!(result.per_vp_gas[&multitoken] > Gas::default());
+    }
+
+    #[test]
+    /// Tests that each native VP reports the changed keys of its own storage
+    /// as the ones it validated
+    fn test_native_vp_validated_keys() {
+        let (state, _) = test_utils::setup_default_storage();
+        let token_address = Address::Established([0xff; 20].into());
+        let src_address = Address::Established([0xab; 20].into());
+
+        let mut tx = Tx::from_type(TxType::Raw);
+        tx.set_code(namada_tx::Code::new(vec![], None));
+        tx.set_data(namada_tx::Data::new(vec![]));
+        let balance_key = namada_token::storage_key::balance_key(
+            &token_address,
+            &src_address,
+        );
+        let parameter_key =
+            namada_parameters::storage::get_max_verifiers_per_tx_key();
+        let changed_keys =
+            BTreeSet::from([balance_key.clone(), parameter_key.clone()]);
+        let multitoken = Address::Internal(InternalAddress::Multitoken);
+        let parameters = Address::Internal(InternalAddress::Parameters);
+        let pgf = Address::Internal(InternalAddress::Pgf);
+        let verifiers = BTreeSet::from([
+            multitoken.clone(),
+            parameters.clone(),
+            pgf.clone(),
+        ]);
+        let (mut vp_cache, _) =
+            wasm::compilation_cache::common::testing::cache();
+
+        let result = execute_vps(
+            verifiers,
+            changed_keys,
+            &tx,
+            &TxIndex::default(),
+            &*state,
+            &TxGasMeter::new(u64::MAX),
+            None,
+            None,
+            None,
+            None,
+            &mut vp_cache,
+        )
+        .unwrap();
+        assert_eq!(
+            result.validated_keys,
+            BTreeMap::from([
+                (multitoken, BTreeSet::from([balance_key])),
+                (parameters, BTreeSet::from([parameter_key])),
+                (pgf, BTreeSet::new()),
+            ])
+        );
+    }
+
+    #[test]
+    /// Tests that each native VP is charged for the setup of its context, even
+    /// when it has nothing to validate
+    fn test_native_vp_init_gas() {
+        let (state, _) = test_utils::setup_default_storage();
+        let mut tx = Tx::from_type(TxType::Raw);
+        tx.set_code(namada_tx::Code::new(vec![], None));
+        tx.set_data(namada_tx::Data::new(vec![]));
+        let (mut vp_cache, _) =
+            wasm::compilation_cache::common::testing::cache();
+
+        let mut run = |verifiers: &[InternalAddress]| {
+            let result = execute_vps(
+                verifiers.iter().cloned().map(Address::Internal).collect(),
+                BTreeSet::default(),
+                &tx,
+                &TxIndex::default(),
+                &*state,
+                &TxGasMeter::new(u64::MAX),
+                None,
+                None,
+                None,
+                None,
+                &mut vp_cache,
+            )
+            .unwrap();
+            assert!(result.rejected_vps.is_empty());
+            for gas in result.per_vp_gas.values() {
+                assert!(*gas >= Gas::from(NATIVE_VP_INIT_GAS));
+            }
+            let mut gas_meter = TxGasMeter::new(u64::MAX);
+            gas_meter.add_vps_gas(&result.gas_used).unwrap();
+            gas_meter.get_tx_consumed_gas()
+        };
+
+        let single = run(&[InternalAddress::Parameters]);
+        let many =
+            run(&[InternalAddress::Parameters, InternalAddress::Multitoken]);
+        assert_eq!(single, Gas::from(NATIVE_VP_INIT_GAS));
+        assert!(many > single);
+    }
+
+    #[test]
+    /// Tests that the VP observer is notified once of the result of each VP
+    fn test_vp_observer() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let token_address = Address::Established([0xff; 20].into());
+        let src_address = Address::Established([0xab; 20].into());
+        let dst_address = Address::Established([0xba; 20].into());
+        namada_token::transfer(
+            &mut state,
+            &token_address,
+            &src_address,
+            &dst_address,
+            0.into(),
+        )
+        .unwrap();
+
+        let mut tx = Tx::from_type(TxType::Raw);
+        tx.set_code(namada_tx::Code::new(vec![], None));
+        tx.set_data(namada_tx::Data::new(vec![]));
+        let changed_keys = BTreeSet::from([
+            namada_token::storage_key::balance_key(
+                &token_address,
+                &src_address,
+            ),
+            namada_token::storage_key::balance_key(
+                &token_address,
+                &dst_address,
+            ),
+        ]);
+        let verifiers = BTreeSet::from([
+            Address::Internal(InternalAddress::Multitoken),
+            Address::Internal(InternalAddress::Pgf),
+        ]);
+        let (mut vp_cache, _) =
+            wasm::compilation_cache::common::testing::cache();
+
+        let observed = Mutex::new(vec![]);
+        let observe = |addr: &Address,
+                       accepted: bool,
+                       error: Option<&str>,
+                       gas: u64| {
+            observed.lock().unwrap().push((
+                addr.clone(),
+                accepted,
+                error.map(ToOwned::to_owned),
+                gas,
+            ))
+        };
+        let result = execute_vps(
+            verifiers.clone(),
+            changed_keys,
+            &tx,
+            &TxIndex::default(),
+            &*state,
+            &TxGasMeter::new(u64::MAX),
+            None,
+            None,
+            None,
+            Some(VpObserver(&observe)),
+            &mut vp_cache,
+        )
+        .unwrap();
+
+        let observed = observed.into_inner().unwrap();
+        assert_eq!(observed.len(), verifiers.len());
+        assert_eq!(
+            observed
+                .iter()
+                .map(|(addr, ..)| addr.clone())
+                .collect::<BTreeSet<_>>(),
+            verifiers
+        );
+        for (addr, accepted, error, gas) in observed {
+            assert_eq!(accepted, !result.rejected_vps.contains(&addr));
+            assert_eq!(error.is_none(), accepted);
+            assert_eq!(gas, u64::from(result.per_vp_gas[&addr]));
+        }
+    }
+
+    #[test]
+    /// Tests that the VPs result doesn't depend on the size of the chunks of
+    /// verifiers run serially within each parallel task
+    fn test_vps_reduce_chunk_size() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let token_address = Address::Established([0xff; 20].into());
+        let src_address = Address::Established([0xab; 20].into());
+        let dst_address = Address::Established([0xba; 20].into());
+        namada_token::transfer(
+            &mut state,
+            &token_address,
+            &src_address,
+            &dst_address,
+            0.into(),
+        )
+        .unwrap();
+
+        let mut tx = Tx::from_type(TxType::Raw);
+        tx.set_code(namada_tx::Code::new(vec![], None));
+        tx.set_data(namada_tx::Data::new(vec![]));
+        let changed_keys = BTreeSet::from([
+            namada_token::storage_key::balance_key(
+                &token_address,
+                &src_address,
+            ),
+            namada_token::storage_key::balance_key(
+                &token_address,
+                &dst_address,
+            ),
+        ]);
+        // No VP is stored for the source, its wasm VP fails
+        let verifiers = BTreeSet::from([
+            Address::Internal(InternalAddress::Multitoken),
+            Address::Internal(InternalAddress::Parameters),
+            Address::Internal(InternalAddress::Pgf),
+            src_address,
+        ]);
+        let (mut vp_cache, _) =
+            wasm::compilation_cache::common::testing::cache();
+
+        let mut run = |reduce_chunk_size| {
+            let result = execute_vps(
+                verifiers.clone(),
+                changed_keys.clone(),
+                &tx,
+                &TxIndex::default(),
+                &*state,
+                &TxGasMeter::new(u64::MAX),
+                None,
+                None,
+                reduce_chunk_size,
+                None,
+                &mut vp_cache,
+            )
+            .unwrap();
+            let mut gas_meter = TxGasMeter::new(u64::MAX);
+            gas_meter.add_vps_gas(&result.gas_used).unwrap();
+            let mut errors = result.errors;
+            errors.sort();
+            (
+                result.accepted_vps,
+                result.rejected_vps,
+                errors,
+                result.per_vp_gas,
+                result.native_vp_epochs,
+                gas_meter.get_tx_consumed_gas(),
+            )
+        };
+        let expected = run(None);
+        for reduce_chunk_size in [0, 1, 2, 3, 10] {
+            assert_eq!(run(Some(reduce_chunk_size)), expected);
+        }
+    }
+
+    #[test]
+    /// Tests that the outcome of every VP is streamed and that streaming
+    /// doesn't change the result
+    fn test_execute_vps_streaming() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let token_address = Address::Established([0xff; 20].into());
+        let src_address = Address::Established([0xab; 20].into());
+        let dst_address = Address::Established([0xba; 20].into());
+        namada_token::transfer(
+            &mut state,
+            &token_address,
+            &src_address,
+            &dst_address,
+            0.into(),
+        )
+        .unwrap();
+
+        let mut tx = Tx::from_type(TxType::Raw);
+        tx.set_code(namada_tx::Code::new(vec![], None));
+        tx.set_data(namada_tx::Data::new(vec![]));
+        let changed_keys = BTreeSet::from([
+            namada_token::storage_key::balance_key(
+                &token_address,
+                &src_address,
+            ),
+            namada_token::storage_key::balance_key(
+                &token_address,
+                &dst_address,
+            ),
+        ]);
+        let verifiers = BTreeSet::from([
+            Address::Internal(InternalAddress::Multitoken),
+            Address::Internal(InternalAddress::Parameters),
+            Address::Internal(InternalAddress::Pgf),
+        ]);
+        let (mut vp_cache, _) =
+            wasm::compilation_cache::common::testing::cache();
+
+        let streamed = Mutex::new(vec![]);
+        let result = execute_vps_streaming(
+            verifiers.clone(),
+            changed_keys.clone(),
+            &tx,
+            &TxIndex::default(),
+            &*state,
+            &TxGasMeter::new(u64::MAX),
+            None,
+            None,
+            None,
+            |addr, outcome| {
+                streamed
+                    .lock()
+                    .unwrap()
+                    .push((addr.clone(), outcome.map_err(ToOwned::to_owned)))
+            },
+            &mut vp_cache,
+        )
+        .unwrap();
+        let streamed = streamed.into_inner().unwrap();
+        assert_eq!(streamed.len(), verifiers.len());
+        assert_eq!(
+            streamed
+                .iter()
+                .map(|(addr, _)| addr.clone())
+                .collect::<BTreeSet<_>>(),
+            verifiers
+        );
+        for (addr, outcome) in &streamed {
+            assert_eq!(outcome.is_ok(), result.accepted_vps.contains(addr));
+        }
+
+        let expected = execute_vps(
+            verifiers,
+            changed_keys,
+            &tx,
+            &TxIndex::default(),
+            &*state,
+            &TxGasMeter::new(u64::MAX),
+            None,
+            None,
+            None,
+            None,
+            &mut vp_cache,
+        )
+        .unwrap();
+        assert_eq!(result.accepted_vps, expected.accepted_vps);
+        assert_eq!(result.rejected_vps, expected.rejected_vps);
+        assert_eq!(result.per_vp_gas, expected.per_vp_gas);
+    }
+
+    #[test]
+    /// Tests that a rejection by the MASP VP is told apart from the rejection
+    /// by any other VP
+    fn test_rejected_by_masp() {
+        let (state, _) = test_utils::setup_default_storage();
+        // the tx carries no shielded action
+        let mut tx = Tx::from_type(TxType::Raw);
+        tx.set_code(namada_tx::Code::new(vec![], None));
+        tx.set_data(namada_tx::Data::new(vec![]));
+        let (mut vp_cache, _) =
+            wasm::compilation_cache::common::testing::cache();
+
+        let masp = Address::Internal(InternalAddress::Masp);
+        let temp_storage = Address::Internal(InternalAddress::TempStorage);
+        for (verifier, rejected_by_masp) in
+            [(masp, true), (temp_storage, false)]
+        {
+            let result = execute_vps(
+                BTreeSet::from([verifier.clone()]),
+                BTreeSet::from([Key::from(verifier.to_db_key())]),
+                &tx,
+                &TxIndex::default(),
+                &*state,
+                &TxGasMeter::new(u64::MAX),
+                None,
+                None,
+                None,
+                None,
+                &mut vp_cache,
+            )
+            .unwrap();
+            assert_eq!(result.rejected_vps, BTreeSet::from([verifier]));
+            assert_eq!(result.rejected_by_masp(), rejected_by_masp);
+        }
+    }
+
+    #[test]
+    /// Tests that the rejections by the native VPs carry their reasons, apart
+    /// from the rejections by the wasm VPs
+    fn test_native_vp_reasons() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let token_address = Address::Established([0xff; 20].into());
+        let dst_address = Address::Established([0xba; 20].into());
+        // the tx credits a balance without any debit or mint
+        let balance_key = namada_token::storage_key::balance_key(
+            &token_address,
+            &dst_address,
+        );
+        state
+            .write_log_mut()
+            .write(&balance_key, Amount::from(100).serialize_to_vec())
+            .unwrap();
+
+        let mut tx = Tx::from_type(TxType::Raw);
+        tx.set_code(namada_tx::Code::new(vec![], None));
+        tx.set_data(namada_tx::Data::new(vec![]));
+        let multitoken = Address::Internal(InternalAddress::Multitoken);
+        let temp_storage = Address::Internal(InternalAddress::TempStorage);
+        // No VP is stored for the destination, its wasm VP fails
+        let verifiers = BTreeSet::from([
+            multitoken.clone(),
+            temp_storage.clone(),
+            dst_address.clone(),
+        ]);
+        let (mut vp_cache, _) =
+            wasm::compilation_cache::common::testing::cache();
+
+        let result = execute_vps(
+            verifiers.clone(),
+            BTreeSet::from([balance_key]),
+            &tx,
+            &TxIndex::default(),
+            &*state,
+            &TxGasMeter::new(u64::MAX),
+            None,
+            None,
+            None,
+            None,
+            &mut vp_cache,
+        )
+        .unwrap();
+        assert_eq!(result.rejected_vps, verifiers);
+        assert_eq!(result.errors.len(), 3);
+        let rejections: BTreeMap<_, _> = result
+            .errors
+            .into_iter()
+            .map(|rejection| (rejection.addr.clone(), rejection))
+            .collect();
+        let reasons: BTreeMap<_, _> = rejections
+            .iter()
+            .map(|(addr, rejection)| (addr, rejection.native_vp_reason()))
+            .collect();
+        assert_eq!(
+            reasons,
+            BTreeMap::from([
+                (&multitoken, Some(NativeVpReason::Rejected)),
+                (&temp_storage, Some(NativeVpReason::AccessForbidden)),
+                (&dst_address, None),
+            ])
+        );
+        assert!(matches!(
+            rejections[&multitoken].reason,
+            VpRejectionReason::Multitoken(_)
+        ));
+        assert_eq!(rejections[&multitoken].class, VpErrorClass::Logic);
+        assert_eq!(
+            rejections[&temp_storage],
+            VpRejection {
+                addr: temp_storage.clone(),
+                class: VpErrorClass::Access,
+                reason: VpRejectionReason::AccessForbidden(
+                    Error::AccessForbidden(InternalAddress::TempStorage)
+                        .to_string()
+                ),
+            }
+        );
+        assert!(matches!(
+            rejections[&dst_address].reason,
+            VpRejectionReason::Other(_)
+        ));
+        assert_eq!(NativeVpReason::AccessForbidden.to_u32(), 1);
+        assert_eq!(
+            NativeVpReason::from_u32(1),
+            Some(NativeVpReason::AccessForbidden)
+        );
+    }
+
+    #[test]
+    /// Tests that the errors of the VPs are reported in the same order across
+    /// runs, sorted by verifier
+    fn test_vp_errors_order() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let token_address = Address::Established([0xff; 20].into());
+        let dst_address = Address::Established([0xba; 20].into());
+        let other_address = Address::Established([0xab; 20].into());
+        // the tx credits a balance without any debit or mint
+        let balance_key = namada_token::storage_key::balance_key(
+            &token_address,
+            &dst_address,
+        );
+        state
+            .write_log_mut()
+            .write(&balance_key, Amount::from(100).serialize_to_vec())
+            .unwrap();
+
+        let mut tx = Tx::from_type(TxType::Raw);
+        tx.set_code(namada_tx::Code::new(vec![], None));
+        tx.set_data(namada_tx::Data::new(vec![]));
+        // No VP is stored for the established addresses, their wasm VPs fail
+        let verifiers = BTreeSet::from([
+            Address::Internal(InternalAddress::Multitoken),
+            Address::Internal(InternalAddress::TempStorage),
+            dst_address,
+            other_address,
+        ]);
+        let (mut vp_cache, _) =
+            wasm::compilation_cache::common::testing::cache();
+
+        let mut run = || {
+            execute_vps(
+                verifiers.clone(),
+                BTreeSet::from([balance_key.clone()]),
+                &tx,
+                &TxIndex::default(),
+                &*state,
+                &TxGasMeter::new(u64::MAX),
+                None,
+                None,
+                Some(1),
+                None,
+                &mut vp_cache,
+            )
+            .unwrap()
+        };
+        let first = run();
+        assert_eq!(first.errors.len(), verifiers.len());
+        assert!(first.errors.windows(2).all(|pair| pair[0] <= pair[1]));
+        for _ in 0..20 {
+            let result = run();
+            assert_eq!(result.errors, first.errors);
+        }
+    }
+
+    #[test]
+    /// Tests that the VP code hash run for each account verifier is recorded
+    /// in the result, matching the hash stored for the account
+    fn test_vp_code_hashes() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let addr = address::testing::established_address_1();
+        let vp_always_true = TestWasms::VpAlwaysTrue.read_bytes();
+        let vp_hash = Hash::sha256(&vp_always_true);
+        let code_len = (vp_always_true.len() as u64).serialize_to_vec();
+        state
+            .write_log_mut()
+            .write(&Key::wasm_code(&vp_hash), vp_always_true.serialize_to_vec())
+            .unwrap();
+        state
+            .write_log_mut()
+            .write(&Key::wasm_code_len(&vp_hash), code_len)
+            .unwrap();
+        state
+            .write_log_mut()
+            .write(&Key::validity_predicate(&addr), vp_hash.serialize_to_vec())
+            .unwrap();
+        state.commit_tx();
+        state.commit_block().unwrap();
+
+        let mut tx = Tx::from_type(TxType::Raw);
+        tx.set_code(namada_tx::Code::new(vec![], None));
+        tx.set_data(namada_tx::Data::new(vec![]));
+        let (mut vp_cache, _) =
+            wasm::compilation_cache::common::testing::cache();
+        let result = execute_vps(
+            BTreeSet::from([
+                addr.clone(),
+                Address::Internal(InternalAddress::Multitoken),
+            ]),
+            BTreeSet::default(),
+            &tx,
+            &TxIndex::default(),
+            &*state,
+            &TxGasMeter::new(u64::MAX),
+            None,
+            None,
+            None,
+            None,
+            &mut vp_cache,
+        )
+        .unwrap();
+        assert!(result.rejected_vps.is_empty());
+
+        let (stored_hash, _) = state.validity_predicate(&addr).unwrap();
+        assert_eq!(stored_hash, Some(vp_hash));
+        assert_eq!(result.vp_code_hashes, BTreeMap::from([(addr, vp_hash)]));
+    }
+
+    #[test]
+    /// Tests that a single verifier is run on the current thread, with the
+    /// same result as among other verifiers
+    fn test_single_verifier_fast_path() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let token_address = Address::Established([0xff; 20].into());
+        let src_address = Address::Established([0xab; 20].into());
+        let dst_address = Address::Established([0xba; 20].into());
+        namada_token::transfer(
+            &mut state,
+            &token_address,
+            &src_address,
+            &dst_address,
+            0.into(),
+        )
+        .unwrap();
+
+        let mut tx = Tx::from_type(TxType::Raw);
+        tx.set_code(namada_tx::Code::new(vec![], None));
+        tx.set_data(namada_tx::Data::new(vec![]));
+        let changed_keys = BTreeSet::from([
+            namada_token::storage_key::balance_key(
+                &token_address,
+                &src_address,
+            ),
+            namada_token::storage_key::balance_key(
+                &token_address,
+                &dst_address,
+            ),
+        ]);
+        let multitoken = Address::Internal(InternalAddress::Multitoken);
+        let (mut vp_cache, _) =
+            wasm::compilation_cache::common::testing::cache();
+
+        let threads = Mutex::new(vec![]);
+        let observe = |_: &Address, _: bool, _: Option<&str>, _gas: u64| {
+            threads.lock().unwrap().push(std::thread::current().id());
+        };
+        let mut run = |verifiers: BTreeSet<Address>| {
+            execute_vps(
+                verifiers,
+                changed_keys.clone(),
+                &tx,
+                &TxIndex::default(),
+                &*state,
+                &TxGasMeter::new(u64::MAX),
+                None,
+                None,
+                None,
+                Some(VpObserver(&observe)),
+                &mut vp_cache,
+            )
+            .unwrap()
+        };
+
+        let single = run(BTreeSet::from([multitoken.clone()]));
+        assert_eq!(
+            threads.lock().unwrap().as_slice(),
+            &[std::thread::current().id()]
+        );
+        let among_others = run(BTreeSet::from([
+            multitoken.clone(),
+            Address::Internal(InternalAddress::Parameters),
+        ]));
+        assert_eq!(single.accepted_vps, BTreeSet::from([multitoken.clone()]));
+        assert!(among_others.accepted_vps.contains(&multitoken));
+        assert_eq!(
+            single.per_vp_gas[&multitoken],
+            among_others.per_vp_gas[&multitoken]
+        );
+    }
+
+    #[test]
+    /// Tests that the seed of the randomness of the VPs only depends on the tx
+    /// hash and the block height
+    fn test_vp_rng_seed() {
+        let tx_hash = Hash::sha256(b"tx");
+        let height = BlockHeight(10);
+        let seed = native_vp::vp_rng_seed(&tx_hash, height);
+        assert_eq!(seed, native_vp::vp_rng_seed(&tx_hash, height));
+        assert_ne!(seed, native_vp::vp_rng_seed(&tx_hash, BlockHeight(11)));
+        assert_ne!(
+            seed,
+            native_vp::vp_rng_seed(&Hash::sha256(b"other tx"), height)
+        );
+    }
+
+    #[test]
+    /// Tests that a panic of a native VP is reported as a rejection flagged
+    /// as such
+    fn test_vp_panic_rejection() {
+        let addr = Address::Internal(InternalAddress::Governance);
+        let err = Error::VpPanic { addr: addr.clone() };
+        assert_eq!(err.vp_status_flags(), VpStatusFlags::VP_PANIC);
+        assert_eq!(
+            err.vp_rejection(&addr).native_vp_reason(),
+            Some(NativeVpReason::Panicked)
+        );
+        assert_eq!(
+            err.to_string(),
+            format!("The native VP of {addr} panicked")
+        );
+        assert_eq!(
+            Error::InvalidSectionSignature(String::new()).vp_status_flags(),
+            VpStatusFlags::INVALID_SIGNATURE
+        );
+        assert!(Error::AccessForbidden(InternalAddress::TempStorage)
+            .vp_status_flags()
+            .is_empty());
+    }
+
+    #[test]
+    /// Tests the classification of the VP errors
+    fn test_vp_error_classes() {
+        let addr = Address::Internal(InternalAddress::Multitoken);
+        assert_eq!(
+            Error::GasError("out of gas".to_string()).vp_error_class(),
+            VpErrorClass::Gas
+        );
+        assert_eq!(
+            Error::VpGasCeilingExceeded(addr.clone(), 1).vp_error_class(),
+            VpErrorClass::Gas
+        );
+        assert_eq!(
+            Error::InvalidSectionSignature(String::new()).vp_error_class(),
+            VpErrorClass::Signature
+        );
+        assert_eq!(
+            Error::AccessForbidden(InternalAddress::TempStorage)
+                .vp_error_class(),
+            VpErrorClass::Access
+        );
+        assert_eq!(
+            Error::VpRunnerError(wasm::run::Error::VpError(
+                VpError::Unspecified
+            ))
+            .vp_error_class(),
+            VpErrorClass::Logic
+        );
+        assert_eq!(
+            Error::VpPanic { addr }.vp_error_class(),
+            VpErrorClass::Runtime
+        );
+    }
+
+    #[test]
+    /// Tests that the prefetched VP code hashes match the ones read from
+    /// storage, and that only the accounts are prefetched
+    fn test_prefetch_vp_hashes() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let with_vp = Address::Established([0xab; 20].into());
+        let without_vp = Address::Established([0xba; 20].into());
+        let implicit = namada_core::address::testing::gen_implicit_address();
+        let vp_hash = Hash::sha256(b"vp");
+        state
+            .write_log_mut()
+            .write(
+                &Key::validity_predicate(&with_vp),
+                vp_hash.serialize_to_vec(),
+            )
+            .unwrap();
+        state.commit_tx();
+        state.commit_block().unwrap();
+
+        let masp = Address::Internal(InternalAddress::Masp);
+        let verifiers = BTreeSet::from([
+            with_vp.clone(),
+            without_vp.clone(),
+            implicit.clone(),
+            masp,
+        ]);
+        let vp_hashes = prefetch_vp_hashes(&*state, &verifiers).unwrap();
+        assert_eq!(vp_hashes.len(), 3);
+        assert_eq!(vp_hashes[&with_vp].0, Some(vp_hash));
+        assert_eq!(vp_hashes[&without_vp].0, None);
+        for addr in [&with_vp, &without_vp, &implicit] {
+            assert_eq!(
+                vp_hashes[addr],
+                state.validity_predicate(addr).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    /// Tests that the results of the native and wasm VPs, which run as
+    /// separate groups, are merged together
+    fn test_native_and_wasm_vps_merged() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let token_address = Address::Established([0xff; 20].into());
+        let src_address = Address::Established([0xab; 20].into());
+        let dst_address = Address::Established([0xba; 20].into());
+        namada_token::transfer(
+            &mut state,
+            &token_address,
+            &src_address,
+            &dst_address,
+            0.into(),
+        )
+        .unwrap();
+
+        let mut tx = Tx::from_type(TxType::Raw);
+        tx.set_code(namada_tx::Code::new(vec![], None));
+        tx.set_data(namada_tx::Data::new(vec![]));
+        let changed_keys = BTreeSet::from([
+            namada_token::storage_key::balance_key(
+                &token_address,
+                &src_address,
+            ),
+            namada_token::storage_key::balance_key(
+                &token_address,
+                &dst_address,
+            ),
+        ]);
+        let multitoken = Address::Internal(InternalAddress::Multitoken);
+        // No VP is stored for the source, its wasm VP fails
+        let verifiers =
+            BTreeSet::from([multitoken.clone(), src_address.clone()]);
+        let (mut vp_cache, _) =
+            wasm::compilation_cache::common::testing::cache();
+
+        let result = execute_vps(
+            verifiers.clone(),
+            changed_keys,
+            &tx,
+            &TxIndex::default(),
+            &*state,
+            &TxGasMeter::new(u64::MAX),
+            None,
+            None,
+            None,
+            None,
+            &mut vp_cache,
+        )
+        .unwrap();
+        assert!(result.rejected_vps.contains(&src_address));
+        assert!(
+            result.accepted_vps.contains(&multitoken)
+                || result.rejected_vps.contains(&multitoken)
+        );
+        assert_eq!(
+            result.per_vp_gas.keys().cloned().collect::<BTreeSet<_>>(),
+            verifiers
+        );
+        assert!(result.native_vp_epochs.contains_key(&multitoken));
+    }
+
+    #[test]
+    /// Tests that a VP exceeding the gas ceiling per VP is rejected without
+    /// affecting the other VPs
+    fn test_max_vp_gas() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let token_address = Address::Established([0xff; 20].into());
+        let src_address = Address::Established([0xab; 20].into());
+        let dst_address = Address::Established([0xba; 20].into());
+        namada_token::transfer(
+            &mut state,
+            &token_address,
+            &src_address,
+            &dst_address,
+            0.into(),
+        )
+        .unwrap();
+
+        let mut tx = Tx::from_type(TxType::Raw);
+        tx.set_code(namada_tx::Code::new(vec![], None));
+        tx.set_data(namada_tx::Data::new(vec![]));
+        let changed_keys = BTreeSet::from([
+            namada_token::storage_key::balance_key(
+                &token_address,
+                &src_address,
+            ),
+            namada_token::storage_key::balance_key(
+                &token_address,
+                &dst_address,
+            ),
+        ]);
+        let multitoken = Address::Internal(InternalAddress::Multitoken);
+        let verifiers = BTreeSet::from([multitoken.clone()]);
+        let (mut vp_cache, _) =
+            wasm::compilation_cache::common::testing::cache();
+
+        let result = execute_vps(
+            verifiers.clone(),
+            changed_keys.clone(),
+            &tx,
+            &TxIndex::default(),
+            &*state,
+            &TxGasMeter::new(u64::MAX),
+            None,
+            None,
+            None,
+            None,
+            &mut vp_cache,
+        )
+        .unwrap();
+        assert_eq!(result.accepted_vps, verifiers);
+        let vp_gas = u64::from(result.per_vp_gas[&multitoken]);
+
+        // a ceiling below the gas consumed by the VP rejects it
+        let result = execute_vps(
+            verifiers.clone(),
+            changed_keys.clone(),
+            &tx,
+            &TxIndex::default(),
+            &*state,
+            &TxGasMeter::new(u64::MAX),
+            None,
+            Some(vp_gas - 1),
+            None,
+            None,
+            &mut vp_cache,
+        )
+        .unwrap();
+        assert_eq!(result.rejected_vps, verifiers);
+        assert_eq!(
+            result.errors,
+            vec![
+                Error::VpGasCeilingExceeded(multitoken.clone(), vp_gas - 1)
+                    .vp_rejection(&multitoken)
+            ]
+        );
+
+        // a ceiling matching it doesn't
+        let result = execute_vps(
+            verifiers.clone(),
+            changed_keys,
+            &tx,
+            &TxIndex::default(),
+            &*state,
+            &TxGasMeter::new(u64::MAX),
+            None,
+            Some(vp_gas),
+            None,
+            None,
+            &mut vp_cache,
+        )
+        .unwrap();
+        assert_eq!(result.accepted_vps, verifiers);
+    }
+
+    #[test]
+    fn test_native_vp_out_of_gas() {
+        let (mut state, _validators) = test_utils::setup_default_storage();
+
+        // some random token address
+        let token_address = Address::Established([0xff; 20].into());
+
+        let src_address = Address::Established([0xab; 20].into());
+        let dst_address = Address::Established([0xba; 20].into());
+
+        // supply an address with 1000 of said token
+        credit(&mut state, &token_address, &src_address, 1000.into());
+
+        // commit storage changes. this will act as the
+        // initial state of the chain
+        state.commit_tx();
+        state.commit_block().unwrap();
+
+        // "execute" a dummy tx, by manually performing its state changes
+        let (dummy_tx, changed_keys, verifiers) = {
+            let mut tx = Tx::from_type(TxType::Raw);
+            tx.set_code(namada_tx::Code::new(vec![], None));
+            tx.set_data(namada_tx::Data::new(vec![]));
+
+            // transfer half of the supply of src to dst
+            namada_token::transfer(
+                &mut state,
+                &token_address,
+                &src_address,
+                &dst_address,
+                500.into(),
+            )
+            .unwrap();
+
+            let changed_keys = {
+                let mut set = BTreeSet::new();
+                set.insert(namada_token::storage_key::balance_key(
+                    &token_address,
+                    &src_address,
+                ));
+                set.insert(namada_token::storage_key::balance_key(
+                    &token_address,
+                    &dst_address,
+                ));
+                set
+            };
+
+            let verifiers = {
+                let mut set = BTreeSet::new();
+                set.insert(Address::Internal(InternalAddress::Multitoken));
+                set
+            };
+
+            (tx, changed_keys, verifiers)
+        };
+
+        // temp vp cache
+        let (mut vp_cache, _) =
+            wasm::compilation_cache::common::testing::cache();
+
+        // gas meter with no gas left
+        let gas_meter = TxGasMeter::new(0);
+
+        let result = execute_vps(
+            verifiers,
+            changed_keys,
+            &dummy_tx,
+            &TxIndex::default(),
+            &state,
+            &gas_meter,
+            None,
+            None,
+            None,
+            None,
+            &mut vp_cache,
+        );
+        assert!(matches!(result.unwrap_err(), Error::VpsGasError { .. }));
+    }
+
+    #[test]
+    /// Tests that the VPs not evaluated because of a gas overflow are
+    /// reported
+    fn test_vps_gas_error_skipped_vps() {
+        let (mut state, _) = test_utils::setup_default_storage();
+        let token_address = Address::Established([0xff; 20].into());
+        let src_address = Address::Established([0xab; 20].into());
+        let dst_address = Address::Established([0xba; 20].into());
+        namada_token::transfer(
+            &mut state,
+            &token_address,
+            &src_address,
+            &dst_address,
+            0.into(),
+        )
+        .unwrap();
+
+        let mut tx = Tx::from_type(TxType::Raw);
+        tx.set_code(namada_tx::Code::new(vec![], None));
+        tx.set_data(namada_tx::Data::new(vec![]));
+        let changed_keys = BTreeSet::from([
+            namada_token::storage_key::balance_key(
+                &token_address,
+                &src_address,
+            ),
+            namada_token::storage_key::balance_key(
+                &token_address,
+                &dst_address,
+            ),
+        ]);
+        let pgf = Address::Internal(InternalAddress::Pgf);
+        let verifiers = BTreeSet::from([
+            Address::Internal(InternalAddress::Multitoken),
+            pgf.clone(),
+        ]);
+        let (mut vp_cache, _) =
+            wasm::compilation_cache::common::testing::cache();
+
+        // On a single thread the VPs run in order, the multitoken VP runs out
+        // of gas and the execution is short-circuited before the PGF VP
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(1)
+            .build()
+            .unwrap();
+        let state = &*state;
+        let result = pool.install(|| {
+            execute_vps(
+                verifiers,
+                changed_keys,
+                &tx,
+                &TxIndex::default(),
+                state,
+                &TxGasMeter::new(0),
+                None,
+                None,
+                None,
+                None,
+                &mut vp_cache,
+            )
+        });
+        match result.unwrap_err() {
+            Error::VpsGasError { skipped_vps, .. } => {
+                assert_eq!(skipped_vps, BTreeSet::from([pgf]));
+            }
+            err => panic!("Unexpected error: {err}"),
+        }
+    }
+}
@@ -83,6 +83,11 @@ pub enum TxRuntimeError {
     NoValueInResultBuffer,
     #[error("VP code is not allowed in allowlist parameter.")]
     DisallowedVp,
+    #[error(
+        "The value of {len} bytes written to {key} exceeds the maximum of \
+         {max} bytes"
+    )]
+    WriteTooLarge { key: String, len: u64, max: u64 },
 }
 
 /// Result of a tx host env fn call
@@ -133,6 +138,9 @@ where
     pub result_buffer: MutHostRef<'a, &'a Option<Vec<u8>>>,
     /// Storage for byte buffer values yielded from the guest.
     pub yielded_value: MutHostRef<'a, &'a Option<Vec<u8>>>,
+    /// The optional cap on the size of a single written value, read once
+    /// before the tx execution
+    pub max_single_write_bytes: Option<u64>,
     /// VP WASM compilation cache (this is available in tx context, because
     /// we're pre-compiling VPs from [`tx_init_account`])
     #[cfg(feature = "wasm-runtime")]
@@ -173,6 +181,7 @@ where
         verifiers: &mut BTreeSet<Address>,
         result_buffer: &mut Option<Vec<u8>>,
         yielded_value: &mut Option<Vec<u8>>,
+        max_single_write_bytes: Option<u64>,
         #[cfg(feature = "wasm-runtime")] vp_wasm_cache: &mut VpCache<CA>,
         #[cfg(feature = "wasm-runtime")] tx_wasm_cache: &mut TxCache<CA>,
     ) -> Self {
@@ -203,6 +212,7 @@ where
             verifiers,
             result_buffer,
             yielded_value,
+            max_single_write_bytes,
             #[cfg(feature = "wasm-runtime")]
             vp_wasm_cache,
             #[cfg(feature = "wasm-runtime")]
@@ -286,6 +296,7 @@ where
             verifiers: self.verifiers.clone(),
             result_buffer: self.result_buffer.clone(),
             yielded_value: self.yielded_value.clone(),
+            max_single_write_bytes: self.max_single_write_bytes,
             #[cfg(feature = "wasm-runtime")]
             vp_wasm_cache: self.vp_wasm_cache.clone(),
             #[cfg(feature = "wasm-runtime")]
@@ -636,6 +647,12 @@ where
 
     let key = Key::parse(key).map_err(TxRuntimeError::StorageDataError)?;
 
+    #[cfg(any(test, feature = "read-set"))]
+    {
+        let write_log = unsafe { env.ctx.write_log.get() };
+        write_log.record_read(&key);
+    }
+
     // try to read from the write log first
     let state = env.state();
     let present = state.has_key(&key)?;
@@ -668,6 +685,12 @@ where
 
     let key = Key::parse(key).map_err(TxRuntimeError::StorageDataError)?;
 
+    #[cfg(any(test, feature = "read-set"))]
+    {
+        let write_log = unsafe { env.ctx.write_log.get() };
+        write_log.record_read(&key);
+    }
+
     let state = env.state();
     let value = state.read_bytes(&key)?;
     match value {
@@ -877,6 +900,8 @@ where
         .read_string(key_ptr, key_len as _)
         .map_err(|e| TxRuntimeError::MemoryError(Box::new(e)))?;
     tx_charge_gas::<MEM, D, H, CA>(env, gas)?;
+    check_write_len::<MEM, D, H, CA>(env, &key, val_len)?;
+
     let (value, gas) = env
         .memory
         .read_bytes(val_ptr, val_len as _)
@@ -919,6 +944,7 @@ where
         .read_string(key_ptr, key_len as _)
         .map_err(|e| TxRuntimeError::MemoryError(Box::new(e)))?;
     tx_charge_gas::<MEM, D, H, CA>(env, gas)?;
+    check_write_len::<MEM, D, H, CA>(env, &key, val_len)?;
     let (value, gas) = env
         .memory
         .read_bytes(val_ptr, val_len as _)
@@ -939,6 +965,29 @@ where
     tx_charge_gas::<MEM, D, H, CA>(env, gas)
 }
 
+/// Reject a value larger than the optional `max_single_write_bytes` cap of
+/// the tx context, before reading it from the wasm memory
+fn check_write_len<MEM, D, H, CA>(
+    env: &TxVmEnv<MEM, D, H, CA>,
+    key: &str,
+    len: u64,
+) -> TxResult<()>
+where
+    MEM: VmMemory,
+    D: 'static + DB + for<'iter> DBIter<'iter>,
+    H: 'static + StorageHasher,
+    CA: WasmCacheAccess,
+{
+    match env.ctx.max_single_write_bytes {
+        Some(max) if len > max => Err(TxRuntimeError::WriteTooLarge {
+            key: key.to_string(),
+            len,
+            max,
+        }),
+        _ => Ok(()),
+    }
+}
+
 fn check_address_existence<MEM, D, H, CA>(
     env: &TxVmEnv<MEM, D, H, CA>,
     key: &Key,
@@ -1481,6 +1530,7 @@ where
         .map_err(TxRuntimeError::EncodingError)?;
 
     let key = Key::validity_predicate(&addr);
+    check_write_len::<MEM, D, H, CA>(env, &key.to_string(), code_hash_len)?;
     let (code_hash, gas) = env
         .memory
         .read_bytes(code_hash_ptr, code_hash_len as _)
@@ -2424,9 +2474,12 @@ pub mod testing {
         CA,
     >
     where
-        S: State,
+        S: State + StorageRead,
         CA: WasmCacheAccess,
     {
+        let max_single_write_bytes =
+            crate::parameters::storage::get_max_single_write_bytes(&*state)
+                .unwrap();
         let (write_log, in_mem, db) = state.split_borrow();
         TxVmEnv::new(
             NativeMemory,
@@ -2441,6 +2494,7 @@ pub mod testing {
             verifiers,
             result_buffer,
             yielded_value,
+            max_single_write_bytes,
             #[cfg(feature = "wasm-runtime")]
             vp_wasm_cache,
             #[cfg(feature = "wasm-runtime")]
@@ -2470,7 +2524,7 @@ pub mod testing {
         CA,
     >
     where
-        S: State,
+        S: State + StorageRead,
         CA: WasmCacheAccess,
     {
         let store = crate::vm::wasm::compilation_cache::common::store();
@@ -2479,6 +2533,9 @@ pub mod testing {
         let mut wasm_memory = WasmMemory::default();
         wasm_memory.inner.initialize(initial_memory);
 
+        let max_single_write_bytes =
+            crate::parameters::storage::get_max_single_write_bytes(&*state)
+                .unwrap();
         let (write_log, in_mem, db) = state.split_borrow();
         TxVmEnv::new(
             wasm_memory,
@@ -2493,6 +2550,7 @@ pub mod testing {
             verifiers,
             result_buffer,
             yielded_value,
+            max_single_write_bytes,
             #[cfg(feature = "wasm-runtime")]
             vp_wasm_cache,
             #[cfg(feature = "wasm-runtime")]
@@ -191,6 +191,10 @@ where
     let mut result_buffer: Option<Vec<u8>> = None;
     let mut yielded_value: Option<Vec<u8>> = None;
 
+    // Read the cap on the written values once, the tx can't change it
+    let max_single_write_bytes =
+        crate::parameters::storage::get_max_single_write_bytes(&*state)
+            .map_err(|e| Error::StorageError(e.to_string()))?;
     let sentinel = RefCell::new(TxSentinel::default());
     let (write_log, in_mem, db) = state.split_borrow();
     let env = TxVmEnv::new(
@@ -206,6 +210,7 @@ where
         &mut verifiers,
         &mut result_buffer,
         &mut yielded_value,
+        max_single_write_bytes,
         vp_wasm_cache,
         tx_wasm_cache,
     );
@@ -278,6 +283,53 @@ where
 /// that triggered the execution.
 #[allow(clippy::too_many_arguments)]
 pub fn vp<S, CA>(
+    vp_code_hash: Hash,
+    tx: &Tx,
+    tx_index: &TxIndex,
+    address: &Address,
+    state: &S,
+    gas_meter: &RefCell<VpGasMeter>,
+    keys_changed: &BTreeSet<Key>,
+    verifiers: &BTreeSet<Address>,
+    vp_wasm_cache: VpCache<CA>,
+) -> Result<()>
+where
+    S: StateRead,
+    CA: 'static + WasmCacheAccess,
+{
+    vp_with_timings(
+        vp_code_hash,
+        tx,
+        tx_index,
+        address,
+        state,
+        gas_meter,
+        keys_changed,
+        verifiers,
+        vp_wasm_cache,
+        None,
+    )
+}
+
+/// The wall-clock time spent running a wasm VP, only measured with the
+/// `vp-timings` feature. The timings differ across nodes and runs, so they are
+/// only meant for diagnostics and are never part of the result of a tx.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct VpTimings {
+    /// Nanoseconds spent compiling the wasm code, or fetching it from the
+    /// compilation cache
+    pub compile_nanos: u64,
+    /// Nanoseconds spent executing the wasm code
+    pub execution_nanos: u64,
+}
+
+/// Execute a validity predicate code like [`vp`]. When `timings` are given
+/// and the `vp-timings` feature is enabled, they are set to the time spent
+/// compiling the wasm module, or fetching it from the cache, and to the time
+/// spent executing it.
+#[allow(clippy::too_many_arguments)]
+#[cfg_attr(not(feature = "vp-timings"), allow(unused_variables))]
+pub fn vp_with_timings<S, CA>(
     vp_code_hash: Hash,
     tx: &Tx,
     tx_index: &TxIndex,
@@ -287,18 +339,26 @@ pub fn vp<S, CA>(
     keys_changed: &BTreeSet<Key>,
     verifiers: &BTreeSet<Address>,
     mut vp_wasm_cache: VpCache<CA>,
+    timings: Option<&mut VpTimings>,
 ) -> Result<()>
 where
     S: StateRead,
     CA: 'static + WasmCacheAccess,
 {
-    // Compile the wasm module
+    // Compile the wasm module. The wall clock is only read for the
+    // diagnostics of the `vp-timings` feature, it never affects the outcome
+    // of the VP.
+    #[cfg(feature = "vp-timings")]
+    #[allow(clippy::disallowed_methods)]
+    let compile_start = timings.is_some().then(std::time::Instant::now);
     let (module, store) = fetch_or_compile(
         &mut vp_wasm_cache,
         &Commitment::Hash(vp_code_hash),
         state,
         gas_meter,
     )?;
+    #[cfg(feature = "vp-timings")]
+    let compile_time = compile_start.map(|start| start.elapsed());
 
     let mut iterators: PrefixIterators<'_, <S as StateRead>::D> =
         PrefixIterators::default();
@@ -333,7 +393,10 @@ where
     let yielded_value_borrow = env.ctx.yielded_value.clone();
     let imports = vp_imports(&store, initial_memory, env);
 
-    run_vp(
+    #[cfg(feature = "vp-timings")]
+    #[allow(clippy::disallowed_methods)]
+    let execution_start = std::time::Instant::now();
+    let result = run_vp(
         module,
         imports,
         &vp_code_hash,
@@ -342,7 +405,13 @@ where
         keys_changed,
         verifiers,
         yielded_value_borrow,
-    )
+    );
+    #[cfg(feature = "vp-timings")]
+    if let (Some(timings), Some(compile_time)) = (timings, compile_time) {
+        timings.compile_nanos = compile_time.as_nanos() as u64;
+        timings.execution_nanos = execution_start.elapsed().as_nanos() as u64;
+    }
+    result
 }
 
 #[allow(clippy::too_many_arguments)]
@@ -571,6 +640,53 @@ pub fn prepare_wasm_code<T: AsRef<[u8]>>(code: T) -> Result<Vec<u8>> {
     elements::serialize(module).map_err(Error::SerializationError)
 }
 
+/// Preload and compile the VP code of the given accounts into the VP cache,
+/// e.g. before applying the txs of a block, so that the VPs are not compiled
+/// on demand while the txs are being applied. The addresses without a wasm VP
+/// are skipped. The VPs already in the cache are only fetched, so that the
+/// warming is idempotent, and the compilations are coordinated through the
+/// shared state of the cache, so that it can be warmed concurrently.
+pub fn warm_vp_cache<'a, S, CA>(
+    state: &S,
+    addresses: impl IntoIterator<Item = &'a Address>,
+    vp_wasm_cache: &mut VpCache<CA>,
+) -> Result<()>
+where
+    S: StateRead,
+    CA: 'static + WasmCacheAccess,
+{
+    let mut code_hashes = BTreeSet::new();
+    for addr in addresses {
+        if let Address::Internal(_) = addr {
+            continue;
+        }
+        let (code_hash, _gas) = state
+            .validity_predicate(addr)
+            .map_err(|e| Error::StorageError(e.to_string()))?;
+        code_hashes.extend(code_hash);
+    }
+    for code_hash in code_hashes {
+        if vp_wasm_cache.fetch(&code_hash)?.is_some() {
+            continue;
+        }
+        let key = Key::wasm_code(&code_hash);
+        let code = state
+            .read::<Vec<u8>>(&key)
+            .map_err(|e| {
+                Error::LoadWasmCode(format!(
+                    "Read wasm code failed: key {key}, error {e}"
+                ))
+            })?
+            .ok_or_else(|| {
+                Error::LoadWasmCode(format!(
+                    "No wasm code in storage: key {key}"
+                ))
+            })?;
+        vp_wasm_cache.compile_or_fetch(code)?;
+    }
+    Ok(())
+}
+
 // Fetch or compile a WASM code from the cache or storage. Account for the
 // loading and code compilation gas costs.
 fn fetch_or_compile<S, CN, CA>(
@@ -878,6 +994,7 @@ mod tests {
     use borsh_ext::BorshSerializeExt;
     use itertools::Either;
     use namada_state::StorageWrite;
+    use namada_test_utils::tx_data::TxWriteData;
     use namada_test_utils::TestWasms;
     use namada_token::DenominatedAmount;
     use namada_tx::data::{Fee, TxType};
@@ -1359,6 +1476,83 @@ mod tests {
         }
     }
 
+    /// Test that warming the VP cache compiles the VPs of the accounts once,
+    /// skipping the accounts without a VP
+    #[test]
+    fn test_warm_vp_cache() {
+        let mut state = TestState::default();
+        let with_vp = state.in_mem_mut().address_gen.generate_address("vp");
+        let without_vp =
+            state.in_mem_mut().address_gen.generate_address("no vp");
+
+        let vp_code = TestWasms::VpAlwaysTrue.read_bytes();
+        let code_hash = Hash::sha256(&vp_code);
+        let code_len = vp_code.len() as u64;
+        state.write(&Key::wasm_code(&code_hash), vp_code).unwrap();
+        state.write(&Key::wasm_code_len(&code_hash), code_len).unwrap();
+        state.write(&Key::validity_predicate(&with_vp), code_hash).unwrap();
+        state.commit_tx();
+        state.commit_block().unwrap();
+
+        let (mut vp_cache, _) =
+            wasm::compilation_cache::common::testing::cache();
+        assert!(vp_cache.fetch(&code_hash).unwrap().is_none());
+        let addresses = [with_vp, without_vp, crate::address::PGF];
+        for _ in 0..2 {
+            warm_vp_cache(&state, &addresses, &mut vp_cache).unwrap();
+            assert_eq!(vp_cache.get_size(), 1);
+            assert!(vp_cache.fetch(&code_hash).unwrap().is_some());
+        }
+    }
+
+    /// Test that the time spent compiling a wasm VP is measured apart from its
+    /// execution and drops once the VP is in the compilation cache
+    #[cfg(feature = "vp-timings")]
+    #[test]
+    fn test_vp_timings() {
+        let mut state = TestState::default();
+        let addr = state.in_mem_mut().address_gen.generate_address("rng seed");
+        let keys_changed = BTreeSet::new();
+        let verifiers = BTreeSet::new();
+        let tx_index = TxIndex::default();
+
+        let vp_code = TestWasms::VpAlwaysTrue.read_bytes();
+        let code_hash = Hash::sha256(&vp_code);
+        let code_len = vp_code.len() as u64;
+        state.write(&Key::wasm_code(&code_hash), vp_code).unwrap();
+        state.write(&Key::wasm_code_len(&code_hash), code_len).unwrap();
+
+        let mut outer_tx = Tx::from_type(TxType::Raw);
+        outer_tx.set_code(Code::new(vec![], None));
+        outer_tx.set_data(Data::new(vec![]));
+        let (vp_cache, _) = wasm::compilation_cache::common::testing::cache();
+        let run = || {
+            let gas_meter = RefCell::new(VpGasMeter::new_from_tx_meter(
+                &TxGasMeter::new_from_sub_limit(TX_GAS_LIMIT.into()),
+            ));
+            let mut timings = VpTimings::default();
+            vp_with_timings(
+                code_hash,
+                &outer_tx,
+                &tx_index,
+                &addr,
+                &state,
+                &gas_meter,
+                &keys_changed,
+                &verifiers,
+                vp_cache.clone(),
+                Some(&mut timings),
+            )
+            .unwrap();
+            timings
+        };
+
+        let cold = run();
+        let warm = run();
+        assert!(warm.compile_nanos < cold.compile_nanos);
+        assert!(cold.execution_nanos > 0);
+    }
+
     /// Test that when a transaction wasm goes over the wasm memory limit in the
     /// value returned from host environment call during wasm execution, the
     /// execution is aborted.
@@ -1774,6 +1968,66 @@ mod tests {
         assert!(matches!(result.unwrap_err(), Error::GasError(_)));
     }
 
+    /// Test that the `max_single_write_bytes` cap in storage when the tx
+    /// starts bounds the size of the values it writes
+    #[test]
+    fn test_tx_write_too_large() {
+        let mut state = TestState::default();
+        let gas_meter =
+            RefCell::new(TxGasMeter::new_from_sub_limit(TX_GAS_LIMIT.into()));
+        let tx_index = TxIndex::default();
+
+        let tx_write = TestWasms::TxWriteStorageKey.read_bytes();
+        // store the wasm code
+        let code_hash = Hash::sha256(&tx_write);
+        let code_len = (tx_write.len() as u64).serialize_to_vec();
+        let key = Key::wasm_code(&code_hash);
+        let len_key = Key::wasm_code_len(&code_hash);
+        state.write_log_mut().write(&key, tx_write.clone()).unwrap();
+        state.write_log_mut().write(&len_key, code_len).unwrap();
+        state
+            .write(
+                &crate::parameters::storage::get_max_single_write_bytes_key(),
+                4_u64,
+            )
+            .unwrap();
+
+        let (mut vp_cache, _) =
+            wasm::compilation_cache::common::testing::cache();
+        let (mut tx_cache, _) =
+            wasm::compilation_cache::common::testing::cache();
+        let mut write = |key: &str, len: usize| {
+            let tx_data = TxWriteData {
+                key: Key::parse(key).unwrap(),
+                value: vec![1_u8; len],
+            };
+            let mut outer_tx = Tx::from_type(TxType::Raw);
+            outer_tx.set_code(Code::new(tx_write.clone(), None));
+            outer_tx.set_data(Data::new(tx_data.serialize_to_vec()));
+            tx(
+                &mut state,
+                &gas_meter,
+                &tx_index,
+                &outer_tx,
+                &mut vp_cache,
+                &mut tx_cache,
+            )
+        };
+
+        assert!(write("within_cap", 4).is_ok());
+        let error =
+            write("over_cap", 5).expect_err("Expected a too large write");
+        let Error::RuntimeError(rt_error) = &error else {
+            panic!("Unexpected error: {error}");
+        };
+        let source_err =
+            rt_error.source().expect("No runtime error source found");
+        assert!(matches!(
+            source_err.downcast_ref(),
+            Some(TxRuntimeError::WriteTooLarge { len: 5, max: 4, .. })
+        ));
+    }
+
     fn execute_tx_with_code(tx_code: Vec<u8>) -> Result<BTreeSet<Address>> {
         let tx_data = vec![];
         let tx_index = TxIndex::default();
@@ -1,7 +1,7 @@
 //! Protocol parameters
 pub mod storage;
 mod wasm_allowlist;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
 
 use namada_core::address::{Address, InternalAddress};
 use namada_core::chain::ProposalBytes;
@@ -288,6 +288,48 @@ where
     Ok(gas_cost_table.get(token).map(|amount| amount.to_owned()))
 }
 
+/// Read the optional fee tolerance of the given token, the fees paid in the
+/// token being accepted when short of the required fees by less than it.
+/// Returns `None` if no tolerance is set for the token.
+pub fn read_fee_tolerance<S>(
+    storage: &S,
+    token: &Address,
+) -> namada_storage::Result<Option<token::Amount>>
+where
+    S: StorageRead,
+{
+    let tolerance_table: Option<BTreeMap<Address, token::Amount>> =
+        storage.read(&storage::get_fee_tolerance_key())?;
+    Ok(tolerance_table.and_then(|table| table.get(token).copied()))
+}
+
+/// Read the optional gas deposit of the given fee token, charged on top of the
+/// fees and only refunded if the inner tx is accepted. Returns `None` if no
+/// deposit is set for the token.
+pub fn read_gas_deposit<S>(
+    storage: &S,
+    token: &Address,
+) -> namada_storage::Result<Option<token::Amount>>
+where
+    S: StorageRead,
+{
+    let deposit_table: Option<BTreeMap<Address, token::Amount>> =
+        storage.read(&storage::get_gas_deposit_key())?;
+    Ok(deposit_table.and_then(|table| table.get(token).copied()))
+}
+
+/// Read the optional allowlist of the tokens the given fee payer may pay the
+/// fees in. Returns `None` if the fee payer is not restricted.
+pub fn read_fee_token_allowlist<S>(
+    storage: &S,
+    payer: &Address,
+) -> namada_storage::Result<Option<BTreeSet<Address>>>
+where
+    S: StorageRead,
+{
+    storage.read(&storage::get_fee_token_allowlist_key(payer))
+}
+
 /// Read all the parameters from storage. Returns the parameters and gas
 /// cost.
 pub fn read<S>(storage: &S) -> namada_storage::Result<Parameters>
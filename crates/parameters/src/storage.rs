@@ -1,7 +1,11 @@
 //! Parameters storage
 
-use namada_core::address::Address;
-use namada_core::storage::{DbKeySeg, Key};
+use std::collections::BTreeMap;
+
+use namada_core::address::{Address, InternalAddress};
+use namada_core::parameters::{FeeSplit, ProposerOverflowPolicy};
+use namada_core::storage::{DbKeySeg, Key, KeySeg};
+use namada_core::token;
 use namada_macros::StorageKeys;
 use namada_storage::StorageRead;
 
@@ -41,6 +45,27 @@ struct Keys {
     fee_unshielding_descriptions_limit: &'static str,
     max_signatures_per_transaction: &'static str,
     native_token_transferable: &'static str,
+    // ========================================
+    // Optional parameters, disabled when missing from storage
+    // ========================================
+    max_accounts_per_block: &'static str,
+    max_fee_amount: &'static str,
+    fee_tolerance: &'static str,
+    reject_unverified_changes: &'static str,
+    vp_gas_budget: &'static str,
+    refund_unused_gas: &'static str,
+    max_fee_unshields_per_block: &'static str,
+    max_vp_gas: &'static str,
+    proposer_overflow_policy: &'static str,
+    fee_split: &'static str,
+    wrapper_gas_per_byte: &'static str,
+    max_single_write_bytes: &'static str,
+    internal_access_limits: &'static str,
+    max_fee_tokens_per_block: &'static str,
+    max_verifiers_per_tx: &'static str,
+    fee_token_allowlists: &'static str,
+    gas_deposit: &'static str,
+    protocol_tx_min_signers: &'static str,
 }
 
 /// Returns if the key is a parameter key.
@@ -56,7 +81,7 @@ pub fn is_protocol_parameter_key(key: &Key) -> bool {
         {
             segment.as_str()
         }
-        _ => return false,
+        _ => return is_fee_token_allowlist_key(key).is_some(),
     };
     Keys::ALL.binary_search(&segment).is_ok()
 }
@@ -194,3 +219,256 @@ pub fn is_native_token_transferable(
         ),
     )
 }
+
+/// Storage key used for the max number of accounts initialized per block
+pub fn get_max_accounts_per_block_key() -> Key {
+    get_max_accounts_per_block_key_at_addr(ADDRESS)
+}
+
+/// Helper function to retrieve the optional `max_accounts_per_block`
+/// protocol parameter from storage
+pub fn get_max_accounts_per_block(
+    storage: &impl StorageRead,
+) -> std::result::Result<Option<u64>, namada_storage::Error> {
+    storage.read(&get_max_accounts_per_block_key())
+}
+
+/// Storage key used for the table of max fee amounts per fee token
+pub fn get_max_fee_amount_key() -> Key {
+    get_max_fee_amount_key_at_addr(ADDRESS)
+}
+
+/// Helper function to retrieve the max fee amount of the given token from the
+/// optional `max_fee_amount` protocol parameter in storage
+pub fn get_max_fee_amount(
+    storage: &impl StorageRead,
+    token: &Address,
+) -> std::result::Result<Option<token::Amount>, namada_storage::Error> {
+    let max_fee_table: Option<BTreeMap<Address, token::Amount>> =
+        storage.read(&get_max_fee_amount_key())?;
+    Ok(max_fee_table.and_then(|table| table.get(token).copied()))
+}
+
+/// Storage key used for the table of fee tolerances per fee token
+pub fn get_fee_tolerance_key() -> Key {
+    get_fee_tolerance_key_at_addr(ADDRESS)
+}
+
+/// Storage key used for the flag to reject txs changing storage keys without
+/// triggering any verifier
+pub fn get_reject_unverified_changes_key() -> Key {
+    get_reject_unverified_changes_key_at_addr(ADDRESS)
+}
+
+/// Helper function to retrieve the optional `reject_unverified_changes`
+/// protocol parameter from storage
+pub fn get_reject_unverified_changes(
+    storage: &impl StorageRead,
+) -> std::result::Result<Option<bool>, namada_storage::Error> {
+    storage.read(&get_reject_unverified_changes_key())
+}
+
+/// Storage key used for the fixed gas budget of each VP
+pub fn get_vp_gas_budget_key() -> Key {
+    get_vp_gas_budget_key_at_addr(ADDRESS)
+}
+
+/// Helper function to retrieve the optional `vp_gas_budget` protocol
+/// parameter from storage
+pub fn get_vp_gas_budget(
+    storage: &impl StorageRead,
+) -> std::result::Result<Option<u64>, namada_storage::Error> {
+    storage.read(&get_vp_gas_budget_key())
+}
+
+/// Storage key used for the flag to refund the unused gas of wrapper txs
+pub fn get_refund_unused_gas_key() -> Key {
+    get_refund_unused_gas_key_at_addr(ADDRESS)
+}
+
+/// Helper function to retrieve the optional `refund_unused_gas` protocol
+/// parameter from storage
+pub fn get_refund_unused_gas(
+    storage: &impl StorageRead,
+) -> std::result::Result<Option<bool>, namada_storage::Error> {
+    storage.read(&get_refund_unused_gas_key())
+}
+
+/// Storage key used for the maximum number of fee unshieldings per block
+pub fn get_max_fee_unshields_per_block_key() -> Key {
+    get_max_fee_unshields_per_block_key_at_addr(ADDRESS)
+}
+
+/// Helper function to retrieve the optional `max_fee_unshields_per_block`
+/// protocol parameter from storage
+pub fn get_max_fee_unshields_per_block(
+    storage: &impl StorageRead,
+) -> std::result::Result<Option<u64>, namada_storage::Error> {
+    storage.read(&get_max_fee_unshields_per_block_key())
+}
+
+/// Storage key used for the gas ceiling of any single VP
+pub fn get_max_vp_gas_key() -> Key {
+    get_max_vp_gas_key_at_addr(ADDRESS)
+}
+
+/// Helper function to retrieve the optional `max_vp_gas` protocol parameter
+/// from storage
+pub fn get_max_vp_gas(
+    storage: &impl StorageRead,
+) -> std::result::Result<Option<u64>, namada_storage::Error> {
+    storage.read(&get_max_vp_gas_key())
+}
+
+/// Storage key used for the policy on the fees overflowing the balance of the
+/// block proposer
+pub fn get_proposer_overflow_policy_key() -> Key {
+    get_proposer_overflow_policy_key_at_addr(ADDRESS)
+}
+
+/// Helper function to retrieve the optional `proposer_overflow_policy`
+/// protocol parameter from storage
+pub fn get_proposer_overflow_policy(
+    storage: &impl StorageRead,
+) -> std::result::Result<Option<ProposerOverflowPolicy>, namada_storage::Error>
+{
+    storage.read(&get_proposer_overflow_policy_key())
+}
+
+/// Storage key used for the split of the wrapper fees
+pub fn get_fee_split_key() -> Key {
+    get_fee_split_key_at_addr(ADDRESS)
+}
+
+/// Helper function to retrieve the optional `fee_split` protocol parameter
+/// from storage
+pub fn get_fee_split(
+    storage: &impl StorageRead,
+) -> std::result::Result<Option<FeeSplit>, namada_storage::Error> {
+    storage.read(&get_fee_split_key())
+}
+
+/// Storage key used for the gas cost per byte of the wrapper txs
+pub fn get_wrapper_gas_per_byte_key() -> Key {
+    get_wrapper_gas_per_byte_key_at_addr(ADDRESS)
+}
+
+/// Helper function to retrieve the optional `wrapper_gas_per_byte` protocol
+/// parameter from storage
+pub fn get_wrapper_gas_per_byte(
+    storage: &impl StorageRead,
+) -> std::result::Result<Option<u64>, namada_storage::Error> {
+    storage.read(&get_wrapper_gas_per_byte_key())
+}
+
+/// Storage key used for the maximum byte size of a value written by a tx
+pub fn get_max_single_write_bytes_key() -> Key {
+    get_max_single_write_bytes_key_at_addr(ADDRESS)
+}
+
+/// Helper function to retrieve the optional `max_single_write_bytes` protocol
+/// parameter from storage
+pub fn get_max_single_write_bytes(
+    storage: &impl StorageRead,
+) -> std::result::Result<Option<u64>, namada_storage::Error> {
+    storage.read(&get_max_single_write_bytes_key())
+}
+
+/// Storage key used for the per block limits of the txs triggering the VPs of
+/// internal addresses
+pub fn get_internal_access_limits_key() -> Key {
+    get_internal_access_limits_key_at_addr(ADDRESS)
+}
+
+/// Helper function to retrieve the optional `internal_access_limits` protocol
+/// parameter from storage
+pub fn get_internal_access_limits(
+    storage: &impl StorageRead,
+) -> std::result::Result<
+    Option<BTreeMap<InternalAddress, u64>>,
+    namada_storage::Error,
+> {
+    storage.read(&get_internal_access_limits_key())
+}
+
+/// Storage key used for the maximum number of distinct tokens the fees may be
+/// paid in per block
+pub fn get_max_fee_tokens_per_block_key() -> Key {
+    get_max_fee_tokens_per_block_key_at_addr(ADDRESS)
+}
+
+/// Helper function to retrieve the optional `max_fee_tokens_per_block`
+/// protocol parameter from storage
+pub fn get_max_fee_tokens_per_block(
+    storage: &impl StorageRead,
+) -> std::result::Result<Option<u64>, namada_storage::Error> {
+    storage.read(&get_max_fee_tokens_per_block_key())
+}
+
+/// The maximum number of verifiers of a tx if the `max_verifiers_per_tx`
+/// parameter is not set, high enough for any legitimate tx
+pub const DEFAULT_MAX_VERIFIERS_PER_TX: u64 = 1_000;
+
+/// Storage key used for the maximum number of verifiers per tx
+pub fn get_max_verifiers_per_tx_key() -> Key {
+    get_max_verifiers_per_tx_key_at_addr(ADDRESS)
+}
+
+/// Helper function to retrieve the `max_verifiers_per_tx` protocol parameter
+/// from storage, defaulting to [`DEFAULT_MAX_VERIFIERS_PER_TX`]
+pub fn get_max_verifiers_per_tx(
+    storage: &impl StorageRead,
+) -> std::result::Result<u64, namada_storage::Error> {
+    storage
+        .read(&get_max_verifiers_per_tx_key())
+        .map(|max| max.unwrap_or(DEFAULT_MAX_VERIFIERS_PER_TX))
+}
+
+/// Storage key prefix of the allowlists of the tokens the restricted fee
+/// payers may pay the fees in
+pub fn get_fee_token_allowlists_key() -> Key {
+    get_fee_token_allowlists_key_at_addr(ADDRESS)
+}
+
+/// Storage key used for the allowlist of the tokens the given fee payer may
+/// pay the fees in
+pub fn get_fee_token_allowlist_key(payer: &Address) -> Key {
+    get_fee_token_allowlists_key()
+        .push(&payer.to_db_key())
+        .expect("Cannot obtain a storage key")
+}
+
+/// Returns the fee payer of the key, if it's the key of a fee token allowlist
+pub fn is_fee_token_allowlist_key(key: &Key) -> Option<&Address> {
+    match &key.segments[..] {
+        [
+            DbKeySeg::AddressSeg(addr),
+            DbKeySeg::StringSeg(segment),
+            DbKeySeg::AddressSeg(payer),
+        ] if addr == &ADDRESS
+            && segment == Keys::VALUES.fee_token_allowlists =>
+        {
+            Some(payer)
+        }
+        _ => None,
+    }
+}
+
+/// Storage key used for the table of refundable gas deposits per fee token
+pub fn get_gas_deposit_key() -> Key {
+    get_gas_deposit_key_at_addr(ADDRESS)
+}
+
+/// Storage key used for the minimum number of distinct validators that must
+/// sign the digest carried by a protocol tx
+pub fn get_protocol_tx_min_signers_key() -> Key {
+    get_protocol_tx_min_signers_key_at_addr(ADDRESS)
+}
+
+/// Helper function to retrieve the optional `protocol_tx_min_signers`
+/// protocol parameter from storage
+pub fn get_protocol_tx_min_signers(
+    storage: &impl StorageRead,
+) -> std::result::Result<Option<u64>, namada_storage::Error> {
+    storage.read(&get_protocol_tx_min_signers_key())
+}
@@ -1,7 +1,10 @@
 //! Replay protection storage keys
 
+use borsh::{BorshDeserialize, BorshSerialize};
+use namada_core::address::Address;
 use namada_core::hash::Hash;
-use namada_core::storage::Key;
+use namada_core::storage::{BlockHeight, Key};
+use namada_core::token::Amount;
 
 const ERROR_MSG: &str = "Cannot obtain a valid db key";
 
@@ -19,3 +22,44 @@ pub fn current_prefix() -> Key {
 pub fn current_key(hash: &Hash) -> Key {
     current_prefix().push(&hash.to_string()).expect(ERROR_MSG)
 }
+
+/// Get the prefix of the expiry heights of the transaction hashes
+pub fn expiry_prefix() -> Key {
+    Key::parse("expiry").expect(ERROR_MSG)
+}
+
+/// Get the key of the expiry height of the transaction hash
+pub fn expiry_key(hash: &Hash) -> Key {
+    expiry_prefix().push(&hash.to_string()).expect(ERROR_MSG)
+}
+
+/// Get the prefix of the transaction hashes expiring at the given height
+pub fn expiring_prefix(height: BlockHeight) -> Key {
+    Key::parse("expiring")
+        .expect(ERROR_MSG)
+        .push(&height)
+        .expect(ERROR_MSG)
+}
+
+/// Get the key of the transaction hash under the prefix of its expiry height
+pub fn expiring_key(height: BlockHeight, hash: &Hash) -> Key {
+    expiring_prefix(height)
+        .push(&hash.to_string())
+        .expect(ERROR_MSG)
+}
+
+/// Compact record of the execution of a transaction, stored as the value of
+/// its replay protection entry so that it can be audited without
+/// re-executing it
+#[derive(
+    Clone, Debug, Default, PartialEq, Eq, BorshSerialize, BorshDeserialize,
+)]
+pub struct TxAudit {
+    /// The gas used by the transaction
+    pub gas_used: u64,
+    /// The token and the amount of the fees charged to the wrapper's fee
+    /// payer, net of any refund, if any
+    pub fee_charged: Option<(Address, Amount)>,
+    /// Whether the transaction was accepted
+    pub accepted: bool,
+}
@@ -160,6 +160,32 @@ pub trait StateRead: StorageRead + Debug {
             }
         }
     }
+
+    /// Find where the replay protection entry of the given tx hash originates
+    /// from, if any. The write log of the current block is checked before the
+    /// committed blocks.
+    fn replay_protection_entry_origin(
+        &self,
+        hash: &Hash,
+    ) -> Result<Option<ReplayOrigin>> {
+        if self.write_log().has_replay_protection_entry(hash) {
+            return Ok(Some(ReplayOrigin::CurrentBlock));
+        }
+        Ok((self.db().has_replay_protection_entry(hash)?
+            && !self.is_replay_protection_entry_expired(hash)?)
+            .then_some(ReplayOrigin::CommittedBlock))
+    }
+
+    /// Check if the replay protection entry of the given tx hash committed to
+    /// storage expired before the current block height. Expired entries are
+    /// ignored until they are pruned.
+    fn is_replay_protection_entry_expired(&self, hash: &Hash) -> Result<bool> {
+        let height = self.in_mem().block.height;
+        Ok(self
+            .db()
+            .read_replay_protection_expiry(hash)?
+            .is_some_and(|expiry| expiry < height))
+    }
 }
 
 /// Common trait for write log, DB and in-memory state.
@@ -394,6 +420,25 @@ pub fn merklize_all_keys(_key: &storage::Key) -> bool {
     true
 }
 
+/// The origin of the replay protection entry of a tx hash
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplayOrigin {
+    /// The entry was written in the current block, which is still in the
+    /// write log
+    CurrentBlock,
+    /// The entry was committed in a previous block
+    CommittedBlock,
+}
+
+impl std::fmt::Display for ReplayOrigin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::CurrentBlock => write!(f, "the current block"),
+            Self::CommittedBlock => write!(f, "a committed block"),
+        }
+    }
+}
+
 #[allow(missing_docs)]
 #[derive(Error, Debug)]
 pub enum Error {
@@ -214,15 +214,31 @@ where
         // hashes from the previous block to the general bucket
         self.move_current_replay_protection_entries(batch)?;
 
+        // Then prunes the entries that expired with the previous block. The
+        // ones expiring with this block may still be written below and are
+        // pruned with the next one.
+        if let Some(height) = self.0.in_mem.block.height.checked_prev() {
+            self.prune_expired_replay_protection_entries(batch, height)?;
+        }
+
+        let audits =
+            std::mem::take(&mut self.0.write_log.replay_protection_audits);
+        let expiries =
+            std::mem::take(&mut self.0.write_log.replay_protection_expiries);
         for hash in
             std::mem::take(&mut self.0.write_log.replay_protection).iter()
         {
             self.write_replay_protection_entry(
                 batch,
                 &replay_protection::current_key(hash),
+                audits.get(hash),
             )?;
+            if let Some(expiry) = expiries.get(hash) {
+                self.write_replay_protection_expiry(batch, hash, *expiry)?;
+            }
         }
         debug_assert!(self.0.write_log.replay_protection.is_empty());
+        self.0.write_log.fee_unshieldings.clear();
 
         if let Some(address_gen) = self.0.write_log.address_gen.take() {
             self.0.in_mem.address_gen = address_gen
@@ -342,18 +358,31 @@ where
         Ok(())
     }
 
-    /// Check it the given transaction's hash is already present in storage
+    /// Check it the given transaction's hash is already present in storage,
+    /// ignoring the expired entries
     pub fn has_replay_protection_entry(&self, hash: &Hash) -> Result<bool> {
-        Ok(self.db.has_replay_protection_entry(hash)?)
+        Ok(self.db.has_replay_protection_entry(hash)?
+            && !self.is_replay_protection_entry_expired(hash)?)
     }
 
-    /// Write the provided tx hash to storage
+    /// Read the audit record committed together with the replay protection
+    /// entry of the given tx hash, if any
+    pub fn read_replay_protection_audit(
+        &self,
+        hash: &Hash,
+    ) -> Result<Option<replay_protection::TxAudit>> {
+        Ok(self.db.read_replay_protection_audit(hash)?)
+    }
+
+    /// Write the provided tx hash to storage, optionally with the audit
+    /// record of the transaction
     pub fn write_replay_protection_entry(
         &mut self,
         batch: &mut D::WriteBatch,
         key: &Key,
+        audit: Option<&replay_protection::TxAudit>,
     ) -> Result<()> {
-        self.db.write_replay_protection_entry(batch, key)?;
+        self.db.write_replay_protection_entry(batch, key, audit)?;
         Ok(())
     }
 
@@ -365,6 +394,29 @@ where
         Ok(self.db.move_current_replay_protection_entries(batch)?)
     }
 
+    /// Write the height after which the replay protection entry of the
+    /// provided tx hash expires
+    pub fn write_replay_protection_expiry(
+        &mut self,
+        batch: &mut D::WriteBatch,
+        hash: &Hash,
+        expiry: BlockHeight,
+    ) -> Result<()> {
+        self.db.write_replay_protection_expiry(batch, hash, expiry)?;
+        Ok(())
+    }
+
+    /// Delete the replay protection entries expiring at the given height
+    pub fn prune_expired_replay_protection_entries(
+        &mut self,
+        batch: &mut D::WriteBatch,
+        height: BlockHeight,
+    ) -> Result<()> {
+        Ok(self
+            .db
+            .prune_expired_replay_protection_entries(batch, height)?)
+    }
+
     /// Get oldest epoch which has the valid signed nonce of the bridge pool
     fn get_oldest_epoch_with_valid_nonce(&self) -> Result<Option<Epoch>> {
         let last_height = self.in_mem.get_last_block_height();
@@ -1024,25 +1076,27 @@ where
         &mut self.write_log
     }
 
-    /// Check if the given tx hash has already been processed
+    /// Check if the given tx hash has already been processed, ignoring the
+    /// expired entries
     pub fn has_replay_protection_entry(&self, hash: &Hash) -> Result<bool> {
         if self.write_log.has_replay_protection_entry(hash) {
             return Ok(true);
         }
 
-        self.db()
-            .has_replay_protection_entry(hash)
-            .map_err(Error::DbError)
+        self.has_committed_replay_protection_entry(hash)
     }
 
-    /// Check if the given tx hash has already been committed to storage
+    /// Check if the given tx hash has already been committed to storage,
+    /// ignoring the expired entries
     pub fn has_committed_replay_protection_entry(
         &self,
         hash: &Hash,
     ) -> Result<bool> {
-        self.db()
+        Ok(self
+            .db()
             .has_replay_protection_entry(hash)
-            .map_err(Error::DbError)
+            .map_err(Error::DbError)?
+            && !self.is_replay_protection_entry_expired(hash)?)
     }
 }
 
@@ -6,10 +6,12 @@ use std::collections::{BTreeMap, BTreeSet};
 use itertools::Itertools;
 use namada_core::address::{Address, EstablishedAddressGen};
 use namada_core::collections::{HashMap, HashSet};
+use namada_core::ethereum_structs::EthBridgeEvent;
 use namada_core::hash::Hash;
 use namada_core::ibc::IbcEvent;
 use namada_core::storage;
 use namada_gas::{MEMORY_ACCESS_GAS_PER_BYTE, STORAGE_WRITE_GAS_PER_BYTE};
+use namada_replay_protection::TxAudit;
 use thiserror::Error;
 
 #[allow(missing_docs)]
@@ -81,9 +83,28 @@ pub struct WriteLog {
         HashMap<storage::Key, StorageModification>,
     /// The IBC events for the current transaction
     pub(crate) ibc_events: BTreeSet<IbcEvent>,
+    /// The Ethereum bridge events for the current transaction
+    pub(crate) eth_bridge_events: BTreeSet<EthBridgeEvent>,
     /// Storage modifications for the replay protection storage, always
     /// committed regardless of the result of the transaction
     pub(crate) replay_protection: HashSet<Hash>,
+    /// The audit records of the transactions, written together with their
+    /// replay protection entries, if any, when the block is committed
+    pub(crate) replay_protection_audits: HashMap<Hash, TxAudit>,
+    /// The heights after which the replay protection entries of the
+    /// transactions expire, written together with the entries, if any, when
+    /// the block is committed
+    pub(crate) replay_protection_expiries: HashMap<Hash, storage::BlockHeight>,
+    /// The storage keys read by the current transaction, if recorded
+    pub(crate) tx_read_keys: BTreeSet<storage::Key>,
+    /// When started, the modifications found in the `block_write_log` before
+    /// any protocol write or delete, used to revert them
+    pub(crate) protocol_journal:
+        Option<HashMap<storage::Key, Option<StorageModification>>>,
+    /// The outcomes of the fee unshieldings evaluated in the current block,
+    /// keyed by the hash of their wrapper, kept regardless of the result of
+    /// the transactions
+    pub(crate) fee_unshieldings: HashMap<Hash, bool>,
 }
 
 /// Write log prefix iterator
@@ -111,7 +132,13 @@ impl Default for WriteLog {
             tx_temp_log: HashMap::with_capacity(1),
             tx_precommit_write_log: HashMap::with_capacity(100),
             ibc_events: BTreeSet::new(),
+            eth_bridge_events: BTreeSet::new(),
             replay_protection: HashSet::with_capacity(1_000),
+            replay_protection_audits: HashMap::with_capacity(1_000),
+            replay_protection_expiries: HashMap::new(),
+            tx_read_keys: BTreeSet::new(),
+            protocol_journal: None,
+            fee_unshieldings: HashMap::new(),
         }
     }
 }
@@ -248,6 +275,7 @@ impl WriteLog {
         if self.tx_temp_log.contains_key(key) {
             return Err(Error::UpdateTemporaryValue);
         }
+        self.journal_protocol_modification(key);
         if let Some(prev) = self
             .block_write_log
             .insert(key.clone(), StorageModification::Write { value })
@@ -348,6 +376,7 @@ impl WriteLog {
         if key.is_validity_predicate().is_some() {
             return Err(Error::DeleteVp);
         }
+        self.journal_protocol_modification(key);
         if let Some(prev) = self
             .block_write_log
             .insert(key.clone(), StorageModification::Delete)
@@ -363,6 +392,47 @@ impl WriteLog {
         Ok(())
     }
 
+    /// Start journaling the protocol writes and deletes, so that they can be
+    /// reverted with [`WriteLog::revert_protocol_journal`]
+    pub fn start_protocol_journal(&mut self) {
+        self.protocol_journal = Some(HashMap::new());
+    }
+
+    /// Stop journaling the protocol writes and deletes, keeping them in the
+    /// block write log
+    pub fn stop_protocol_journal(&mut self) {
+        self.protocol_journal = None;
+    }
+
+    /// Revert the protocol writes and deletes performed since the journal was
+    /// started and stop journaling
+    pub fn revert_protocol_journal(&mut self) {
+        let Some(journal) = self.protocol_journal.take() else {
+            return;
+        };
+        for (key, prev) in journal {
+            match prev {
+                Some(modification) => {
+                    self.block_write_log.insert(key, modification);
+                }
+                None => {
+                    self.block_write_log.swap_remove(&key);
+                }
+            }
+        }
+    }
+
+    /// Record the modification of the given key found in the block write
+    /// log, if journaling and not already recorded
+    fn journal_protocol_modification(&mut self, key: &storage::Key) {
+        if let Some(journal) = self.protocol_journal.as_mut() {
+            if !journal.contains_key(key) {
+                let prev = self.block_write_log.get(key).cloned();
+                journal.insert(key.clone(), prev);
+            }
+        }
+    }
+
     /// Initialize a new account and return the gas cost.
     pub fn init_account(
         &mut self,
@@ -394,6 +464,11 @@ impl WriteLog {
         len as u64 * MEMORY_ACCESS_GAS_PER_BYTE
     }
 
+    /// Set an Ethereum bridge event
+    pub fn emit_eth_bridge_event(&mut self, event: EthBridgeEvent) {
+        self.eth_bridge_events.insert(event);
+    }
+
     /// Get the non-temporary storage keys changed and accounts keys initialized
     /// in the current transaction. The account keys point to the validity
     /// predicates of the newly created accounts. The keys in the precommit are
@@ -405,6 +480,19 @@ impl WriteLog {
             .collect()
     }
 
+    /// Get the total byte size of the values written in the current
+    /// transaction
+    pub fn get_written_bytes(&self) -> u64 {
+        self.tx_write_log
+            .values()
+            .map(|modification| match modification {
+                StorageModification::Write { value } => value.len() as u64,
+                StorageModification::Delete
+                | StorageModification::InitAccount { .. } => 0,
+            })
+            .sum()
+    }
+
     /// Get the storage keys changed and accounts keys initialized in the
     /// current transaction and precommit. The account keys point to the
     /// validity predicates of the newly created accounts.
@@ -456,6 +544,21 @@ impl WriteLog {
         std::mem::take(&mut self.ibc_events)
     }
 
+    /// Take the Ethereum bridge events of the current transaction
+    pub fn take_eth_bridge_events(&mut self) -> BTreeSet<EthBridgeEvent> {
+        std::mem::take(&mut self.eth_bridge_events)
+    }
+
+    /// Record a storage key read by the current transaction
+    pub fn record_read(&mut self, key: &storage::Key) {
+        self.tx_read_keys.insert(key.clone());
+    }
+
+    /// Take the storage keys read by the current transaction
+    pub fn take_read_keys(&mut self) -> BTreeSet<storage::Key> {
+        std::mem::take(&mut self.tx_read_keys)
+    }
+
     /// Get the IBC event of the current transaction
     pub fn get_ibc_events(&self) -> &BTreeSet<IbcEvent> {
         &self.ibc_events
@@ -488,16 +591,20 @@ impl WriteLog {
         self.block_write_log.extend(tx_precommit_write_log);
         self.tx_temp_log.clear();
         self.take_ibc_events();
+        self.take_eth_bridge_events();
+        self.tx_read_keys.clear();
     }
 
-    /// Drop the current transaction's write log and IBC events and precommit
-    /// when it's declined by any of the triggered validity predicates.
-    /// Starts a new transaction write log a clears the temp write log.
+    /// Drop the current transaction's write log and events and precommit when
+    /// it's declined by any of the triggered validity predicates. Starts a new
+    /// transaction write log a clears the temp write log.
     pub fn drop_tx(&mut self) {
         self.tx_precommit_write_log.clear();
         self.tx_write_log.clear();
         self.tx_temp_log.clear();
         self.ibc_events.clear();
+        self.eth_bridge_events.clear();
+        self.tx_read_keys.clear();
     }
 
     /// Drop the current transaction's write log but keep the precommit one.
@@ -595,6 +702,44 @@ impl WriteLog {
         Ok(())
     }
 
+    /// Record the audit record of the transaction with the given hash,
+    /// replacing any previous one. The record is only persisted if the hash
+    /// ends up in the replay protection storage
+    pub fn write_tx_audit(&mut self, hash: Hash, audit: TxAudit) {
+        self.replay_protection_audits.insert(hash, audit);
+    }
+
+    /// The audit recorded in the current block for the tx with the given hash,
+    /// if any
+    pub fn tx_audit_mut(&mut self, hash: &Hash) -> Option<&mut TxAudit> {
+        self.replay_protection_audits.get_mut(hash)
+    }
+
+    /// Record the height after which the replay protection entry of the
+    /// transaction with the given hash expires and can be pruned. The expiry
+    /// is only persisted if the hash ends up in the replay protection storage.
+    /// Only the wrapper hashes are given an expiry: an inner tx can be wrapped
+    /// again by anyone, so the entry of its hash is never pruned.
+    pub fn write_tx_expiry(
+        &mut self,
+        hash: Hash,
+        expiry: storage::BlockHeight,
+    ) {
+        self.replay_protection_expiries.insert(hash, expiry);
+    }
+
+    /// The outcome of the fee unshielding of the wrapper with the given hash,
+    /// if already evaluated in the current block
+    pub fn fee_unshielding_outcome(&self, hash: &Hash) -> Option<bool> {
+        self.fee_unshieldings.get(hash).copied()
+    }
+
+    /// Record the outcome of the fee unshielding of the wrapper with the given
+    /// hash. Only the outcome of the first evaluation in the block is kept
+    pub fn write_fee_unshielding_outcome(&mut self, hash: Hash, valid: bool) {
+        self.fee_unshieldings.entry(hash).or_insert(valid);
+    }
+
     /// Remove the transaction hash because redundant
     pub(crate) fn redundant_tx_hash(&mut self, hash: &Hash) -> Result<()> {
         if !self.replay_protection.swap_remove(hash) {
@@ -615,7 +760,7 @@ mod tests {
     use proptest::prelude::*;
 
     use super::*;
-    use crate::StateRead;
+    use crate::{StateRead, DB};
 
     #[test]
     fn test_crud_value() {
@@ -830,6 +975,62 @@ mod tests {
         assert_eq!(value, None);
     }
 
+    #[test]
+    fn test_revert_protocol_journal() {
+        let mut write_log = WriteLog::default();
+        let updated = storage::Key::parse("updated").unwrap();
+        let deleted = storage::Key::parse("deleted").unwrap();
+        let inserted = storage::Key::parse("inserted").unwrap();
+        let kept = storage::Key::parse("kept").unwrap();
+
+        write_log.protocol_write(&updated, vec![1]).unwrap();
+        write_log.protocol_write(&deleted, vec![2]).unwrap();
+
+        write_log.start_protocol_journal();
+        write_log.protocol_write(&updated, vec![3]).unwrap();
+        write_log.protocol_write(&updated, vec![4]).unwrap();
+        write_log.protocol_delete(&deleted).unwrap();
+        write_log.protocol_write(&inserted, vec![5]).unwrap();
+        write_log.revert_protocol_journal();
+
+        // the block write log is back to its state before the journal
+        assert_eq!(
+            write_log.block_write_log.get(&updated),
+            Some(&StorageModification::Write { value: vec![1] })
+        );
+        assert_eq!(
+            write_log.block_write_log.get(&deleted),
+            Some(&StorageModification::Write { value: vec![2] })
+        );
+        assert!(write_log.block_write_log.get(&inserted).is_none());
+
+        // writes after stopping the journal are kept
+        write_log.start_protocol_journal();
+        write_log.protocol_write(&kept, vec![6]).unwrap();
+        write_log.stop_protocol_journal();
+        write_log.revert_protocol_journal();
+        assert_eq!(
+            write_log.block_write_log.get(&kept),
+            Some(&StorageModification::Write { value: vec![6] })
+        );
+    }
+
+    #[test]
+    fn test_fee_unshielding_outcome() {
+        let mut write_log = WriteLog::default();
+        let hash = Hash::sha256(b"wrapper");
+        assert_eq!(write_log.fee_unshielding_outcome(&hash), None);
+
+        write_log.write_fee_unshielding_outcome(hash, false);
+        // the outcome of the first evaluation is kept
+        write_log.write_fee_unshielding_outcome(hash, true);
+        assert_eq!(write_log.fee_unshielding_outcome(&hash), Some(false));
+
+        // regardless of the result of the tx
+        write_log.drop_tx();
+        assert_eq!(write_log.fee_unshielding_outcome(&hash), Some(false));
+    }
+
     #[test]
     fn test_replay_protection_commit() {
         let mut state = crate::testing::TestState::default();
@@ -917,6 +1118,101 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_replay_protection_expiry() {
+        let mut state = crate::testing::TestState::default();
+        let expiring = Hash::sha256("tx1".as_bytes());
+        let lasting = Hash::sha256("tx2".as_bytes());
+
+        state
+            .in_mem_mut()
+            .begin_block(storage::BlockHeight(1))
+            .unwrap();
+        {
+            let write_log = state.write_log_mut();
+            write_log.write_tx_hash(expiring).unwrap();
+            write_log.write_tx_expiry(expiring, storage::BlockHeight(2));
+            write_log.write_tx_hash(lasting).unwrap();
+        }
+        state.commit_block().expect("commit failed");
+
+        // The entry still protects its tx at the expiry height
+        state
+            .in_mem_mut()
+            .begin_block(storage::BlockHeight(2))
+            .unwrap();
+        assert!(state.has_replay_protection_entry(&expiring).unwrap());
+
+        // Past the expiry height, the entry is ignored until pruned
+        state
+            .in_mem_mut()
+            .begin_block(storage::BlockHeight(3))
+            .unwrap();
+        assert!(!state.has_replay_protection_entry(&expiring).unwrap());
+        assert!(
+            state
+                .replay_protection_entry_origin(&expiring)
+                .unwrap()
+                .is_none()
+        );
+        assert!(state.db().has_replay_protection_entry(&expiring).unwrap());
+        assert!(state.has_replay_protection_entry(&lasting).unwrap());
+
+        state.commit_block().expect("commit failed");
+        assert!(!state.db().has_replay_protection_entry(&expiring).unwrap());
+        assert!(
+            state
+                .db()
+                .read_replay_protection_expiry(&expiring)
+                .unwrap()
+                .is_none()
+        );
+        assert!(state.has_replay_protection_entry(&lasting).unwrap());
+    }
+
+    #[test]
+    fn test_replay_protection_expiry_bounded() {
+        let mut state = crate::testing::TestState::default();
+        let mut wrappers = vec![];
+
+        for height in 1..=10 {
+            let wrapper = Hash::sha256(format!("wrapper{height}").as_bytes());
+            state
+                .in_mem_mut()
+                .begin_block(storage::BlockHeight(height))
+                .unwrap();
+            {
+                let write_log = state.write_log_mut();
+                write_log.write_tx_hash(wrapper).unwrap();
+                write_log
+                    .write_tx_expiry(wrapper, storage::BlockHeight(height));
+            }
+            state.commit_block().expect("commit failed");
+            wrappers.push(wrapper);
+
+            // The entries expiring with the previous blocks are pruned, so
+            // the stored entries don't grow with the number of blocks
+            let stored_entries = wrappers
+                .iter()
+                .filter(|hash| {
+                    state.db().has_replay_protection_entry(hash).unwrap()
+                })
+                .count();
+            let stored_expiries = wrappers
+                .iter()
+                .filter(|hash| {
+                    state
+                        .db()
+                        .read_replay_protection_expiry(hash)
+                        .unwrap()
+                        .is_some()
+                })
+                .count();
+            assert_eq!(stored_entries, 1);
+            assert_eq!(stored_expiries, 1);
+        }
+    }
+
     // Test that writing a value on top of a temporary write is not allowed
     #[test]
     fn test_write_after_temp_disallowed() {
@@ -12,6 +12,7 @@ use namada_merkle_tree::{
     Error as MerkleTreeError, MerkleTreeStoresRead, MerkleTreeStoresWrite,
     StoreType,
 };
+use namada_replay_protection::TxAudit;
 use regex::Regex;
 use thiserror::Error;
 
@@ -154,6 +155,20 @@ pub trait DB: Debug {
     /// Check if the given replay protection entry exists
     fn has_replay_protection_entry(&self, hash: &Hash) -> Result<bool>;
 
+    /// Read the audit record of the given replay protection entry, if the
+    /// entry exists and carries one
+    fn read_replay_protection_audit(
+        &self,
+        hash: &Hash,
+    ) -> Result<Option<TxAudit>>;
+
+    /// Read the height after which the given replay protection entry expires,
+    /// if any
+    fn read_replay_protection_expiry(
+        &self,
+        hash: &Hash,
+    ) -> Result<Option<BlockHeight>>;
+
     /// Read the latest value for account subspace key from the DB
     fn read_subspace_val(&self, key: &Key) -> Result<Option<Vec<u8>>>;
 
@@ -242,11 +257,13 @@ pub trait DB: Debug {
         last_height: BlockHeight,
     ) -> Result<Option<ethereum_events::Uint>>;
 
-    /// Write a replay protection entry
+    /// Write a replay protection entry, optionally with the audit record of
+    /// the transaction
     fn write_replay_protection_entry(
         &mut self,
         batch: &mut Self::WriteBatch,
         key: &Key,
+        audit: Option<&TxAudit>,
     ) -> Result<()>;
 
     /// Move the current replay protection bucket to the general one
@@ -255,6 +272,23 @@ pub trait DB: Debug {
         batch: &mut Self::WriteBatch,
     ) -> Result<()>;
 
+    /// Write the height after which the replay protection entry of the given
+    /// tx hash expires, indexing the hash by its expiry height
+    fn write_replay_protection_expiry(
+        &mut self,
+        batch: &mut Self::WriteBatch,
+        hash: &Hash,
+        expiry: BlockHeight,
+    ) -> Result<()>;
+
+    /// Delete the replay protection entries expiring at the given height,
+    /// together with their expiry
+    fn prune_expired_replay_protection_entries(
+        &mut self,
+        batch: &mut Self::WriteBatch,
+        height: BlockHeight,
+    ) -> Result<()>;
+
     /// Prune non-persisted diffs that are only kept for one block for rollback
     fn prune_non_persisted_diffs(
         &mut self,
@@ -15,7 +15,7 @@ use namada_merkle_tree::{
     tree_key_prefix_with_epoch, tree_key_prefix_with_height,
     MerkleTreeStoresRead, StoreType,
 };
-use namada_replay_protection as replay_protection;
+use namada_replay_protection::{self as replay_protection, TxAudit};
 use regex::Regex;
 
 use crate::db::{
@@ -340,6 +340,43 @@ impl DB for MockDB {
         Ok(false)
     }
 
+    fn read_replay_protection_audit(
+        &self,
+        hash: &Hash,
+    ) -> Result<Option<TxAudit>> {
+        let prefix_key =
+            Key::parse("replay_protection").map_err(Error::KeyError)?;
+        for key in [
+            replay_protection::current_key(hash),
+            replay_protection::key(hash),
+        ] {
+            let key = prefix_key.join(&key);
+            match self.0.borrow().get(&key.to_string()) {
+                // Entries written without an audit record have no value
+                Some(bytes) if bytes.is_empty() => return Ok(None),
+                Some(bytes) => {
+                    return decode(bytes).map(Some).map_err(Error::CodingError);
+                }
+                None => continue,
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn read_replay_protection_expiry(
+        &self,
+        hash: &Hash,
+    ) -> Result<Option<BlockHeight>> {
+        let key = Key::parse("replay_protection")
+            .map_err(Error::KeyError)?
+            .join(&replay_protection::expiry_key(hash));
+        match self.0.borrow().get(&key.to_string()) {
+            Some(bytes) => decode(bytes).map(Some).map_err(Error::CodingError),
+            None => Ok(None),
+        }
+    }
+
     fn read_diffs_val(
         &self,
         key: &Key,
@@ -557,12 +594,14 @@ impl DB for MockDB {
         &mut self,
         _batch: &mut Self::WriteBatch,
         key: &Key,
+        audit: Option<&TxAudit>,
     ) -> Result<()> {
         let key = Key::parse("replay_protection")
             .map_err(Error::KeyError)?
             .join(key);
+        let value = audit.map(encode).unwrap_or_default();
 
-        match self.0.borrow_mut().insert(key.to_string(), vec![]) {
+        match self.0.borrow_mut().insert(key.to_string(), value) {
             Some(_) => Err(Error::DBError(format!(
                 "Replay protection key {key} already in storage"
             ))),
@@ -599,8 +638,70 @@ impl DB for MockDB {
                 .push(&hash)
                 .map_err(Error::KeyError)?;
 
-            self.0.borrow_mut().remove(&current_key.to_string());
-            self.0.borrow_mut().insert(key.to_string(), vec![]);
+            // Keep the audit record of the entry, if any
+            let value = self
+                .0
+                .borrow_mut()
+                .remove(&current_key.to_string())
+                .unwrap_or_default();
+            self.0.borrow_mut().insert(key.to_string(), value);
+        }
+
+        Ok(())
+    }
+
+    fn write_replay_protection_expiry(
+        &mut self,
+        _batch: &mut Self::WriteBatch,
+        hash: &Hash,
+        expiry: BlockHeight,
+    ) -> Result<()> {
+        let prefix_key =
+            Key::parse("replay_protection").map_err(Error::KeyError)?;
+        let key = prefix_key.join(&replay_protection::expiry_key(hash));
+        self.0.borrow_mut().insert(key.to_string(), encode(&expiry));
+        let key =
+            prefix_key.join(&replay_protection::expiring_key(expiry, hash));
+        self.0.borrow_mut().insert(key.to_string(), encode(&()));
+        Ok(())
+    }
+
+    fn prune_expired_replay_protection_entries(
+        &mut self,
+        _batch: &mut Self::WriteBatch,
+        height: BlockHeight,
+    ) -> Result<()> {
+        let prefix_key =
+            Key::parse("replay_protection").map_err(Error::KeyError)?;
+        // Only the hashes expiring at this height are visited
+        let expiring_prefix = format!(
+            "{}/",
+            prefix_key.join(&replay_protection::expiring_prefix(height))
+        );
+        let expired_hashes = self
+            .0
+            .borrow()
+            .range(expiring_prefix.clone()..)
+            .map(|(key, _)| key)
+            .take_while(|key| key.starts_with(&expiring_prefix))
+            .map(|key| {
+                key[expiring_prefix.len()..].parse::<Hash>().map_err(|_| {
+                    Error::DBError(format!("Invalid tx hash {key}"))
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        for hash in expired_hashes {
+            for key in [
+                replay_protection::key(&hash),
+                replay_protection::current_key(&hash),
+                replay_protection::expiry_key(&hash),
+                replay_protection::expiring_key(height, &hash),
+            ] {
+                self.0
+                    .borrow_mut()
+                    .remove(&prefix_key.join(&key).to_string());
+            }
         }
 
         Ok(())
@@ -39,6 +39,7 @@ mod tests {
     use namada::ledger::native_vp::ibc::{
         get_dummy_header as tm_dummy_header, Error as IbcError,
     };
+    use namada::ledger::parameters;
     use namada::ledger::tx_env::TxEnv;
     use namada::token::{self, Amount};
     use namada::tx::Tx;
@@ -303,6 +304,53 @@ mod tests {
         tx::ctx().write(&vp_key, vp_hash).unwrap();
     }
 
+    /// Test that a tx writing a value larger than the `max_single_write_bytes`
+    /// parameter fails
+    #[test]
+    #[should_panic = "WriteTooLarge"]
+    fn test_tx_write_too_large_rejected() {
+        // The environment must be initialized first
+        tx_host_env::init();
+
+        tx_host_env::with(|tx_env| {
+            tx_env
+                .state
+                .write(
+                    &parameters::storage::get_max_single_write_bytes_key(),
+                    100_u64,
+                )
+                .unwrap();
+        });
+
+        // A value within the limit can be written
+        let key = storage::Key::parse("key").unwrap();
+        tx::ctx().write_bytes(&key, vec![1_u8; 100]).unwrap();
+
+        tx::ctx().write_bytes(&key, vec![1_u8; 101]).unwrap();
+    }
+
+    /// Test that a tx writing a temporary value larger than the
+    /// `max_single_write_bytes` parameter fails
+    #[test]
+    #[should_panic = "WriteTooLarge"]
+    fn test_tx_write_temp_too_large_rejected() {
+        // The environment must be initialized first
+        tx_host_env::init();
+
+        tx_host_env::with(|tx_env| {
+            tx_env
+                .state
+                .write(
+                    &parameters::storage::get_max_single_write_bytes_key(),
+                    100_u64,
+                )
+                .unwrap();
+        });
+
+        let key = storage::Key::parse("key").unwrap();
+        tx::ctx().write_bytes_temp(&key, vec![1_u8; 101]).unwrap();
+    }
+
     /// Test that a tx initializing a new account with validity predicate that
     /// is not in the allowlist fails
     #[test]
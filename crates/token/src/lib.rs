@@ -41,5 +41,6 @@ where
     if is_new_epoch {
         conversion::update_allowed_conversions(storage)?;
     }
+    prune_block_fees(storage)?;
     Ok(())
 }
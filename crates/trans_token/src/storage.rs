@@ -216,6 +216,21 @@ where
     storage.write(&total_supply_key, new_total_supply)
 }
 
+/// Delete the fees collected in the previous block, so that the block fees
+/// keys only hold the fees of the last block.
+pub fn prune_block_fees<S>(storage: &mut S) -> storage::Result<()>
+where
+    S: StorageRead + StorageWrite,
+{
+    let height = storage.get_block_height()?;
+    match height.checked_prev() {
+        Some(prev_height) => {
+            storage.delete_prefix(&block_fees_prefix(prev_height))
+        }
+        None => Ok(()),
+    }
+}
+
 /// Add denomination info if it exists in storage.
 pub fn denominated(
     amount: token::Amount,
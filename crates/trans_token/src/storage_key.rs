@@ -13,6 +13,10 @@ pub const MINTER_STORAGE_KEY: &str = "minter";
 pub const MINTED_STORAGE_KEY: &str = "minted";
 /// Key segment for token parameters
 pub const PARAMETERS_STORAGE_KEY: &str = "parameters";
+/// Key segment for fee payment anomalies
+pub const FEE_ANOMALY_STORAGE_KEY: &str = "fee_anomaly";
+/// Key segment for the fees collected in a block
+pub const BLOCK_FEES_STORAGE_KEY: &str = "block_fees";
 
 /// Gets the key for the given token address, error with the given
 /// message to expect if the key is not in the address
@@ -73,6 +77,46 @@ pub fn minted_balance_key(token_addr: &Address) -> storage::Key {
         .expect("Cannot obtain a storage key")
 }
 
+/// Obtain a storage key for the shortfall of the fees the given payer failed
+/// to pay in the given token, for later remediation.
+pub fn fee_anomaly_key(token_addr: &Address, payer: &Address) -> storage::Key {
+    storage::Key::from(
+        Address::Internal(InternalAddress::Multitoken).to_db_key(),
+    )
+    .push(&token_addr.to_db_key())
+    .expect("Cannot obtain a storage key")
+    .push(&FEE_ANOMALY_STORAGE_KEY.to_owned())
+    .expect("Cannot obtain a storage key")
+    .push(&payer.to_db_key())
+    .expect("Cannot obtain a storage key")
+}
+
+/// Obtain a storage key prefix for the fees collected in the block at the
+/// given height.
+pub fn block_fees_prefix(height: storage::BlockHeight) -> storage::Key {
+    storage::Key::from(
+        Address::Internal(InternalAddress::Multitoken).to_db_key(),
+    )
+    .push(&BLOCK_FEES_STORAGE_KEY.to_owned())
+    .expect("Cannot obtain a storage key")
+    .push(&height)
+    .expect("Cannot obtain a storage key")
+}
+
+/// Obtain a storage key for the fees in the given token collected by the given
+/// block proposer in the block at the given height.
+pub fn block_fees_key(
+    token_addr: &Address,
+    proposer: &Address,
+    height: storage::BlockHeight,
+) -> storage::Key {
+    block_fees_prefix(height)
+        .push(&token_addr.to_db_key())
+        .expect("Cannot obtain a storage key")
+        .push(&proposer.to_db_key())
+        .expect("Cannot obtain a storage key")
+}
+
 /// Check if the given storage key is a balance key for the given token. If it
 /// is, return the owner. For minted balances, use
 /// [`is_any_minted_balance_key()`].
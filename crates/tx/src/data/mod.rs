@@ -13,6 +13,8 @@ pub mod protocol;
 /// wrapper txs with encrypted payloads
 pub mod wrapper;
 
+#[cfg(feature = "debug-results")]
+use std::collections::BTreeMap;
 use std::collections::BTreeSet;
 use std::fmt::{self, Display};
 use std::str::FromStr;
@@ -27,6 +29,7 @@ use namada_core::ethereum_structs::EthBridgeEvent;
 use namada_core::hash::Hash;
 use namada_core::ibc::IbcEvent;
 use namada_core::storage;
+use namada_core::token::{Amount, Denomination};
 use namada_gas::{Gas, VpsGas};
 use namada_macros::BorshDeserializer;
 #[cfg(feature = "migrations")]
@@ -186,6 +189,48 @@ pub struct TxResult {
     pub ibc_events: BTreeSet<IbcEvent>,
     /// Ethereum bridge events emitted by the transaction
     pub eth_bridge_events: BTreeSet<EthBridgeEvent>,
+    /// Validators whose votes were newly tallied by a protocol transaction
+    pub newly_counted: Vec<Address>,
+    /// Storage keys read by the transaction and the native VPs, only
+    /// collected with the `read-set` feature
+    #[cfg(feature = "debug-results")]
+    pub read_keys: BTreeSet<storage::Key>,
+    /// Debug info on whether the wasm compilation caches were accessed in
+    /// read/write mode (`Some(false)` for read-only), `None` if no wasm was
+    /// run
+    #[cfg(feature = "debug-results")]
+    pub wasm_cache_read_write: Option<bool>,
+    /// The denomination of the fee token used to resolve the fee amount of a
+    /// wrapper transaction, `None` for other transaction types
+    pub fee_denom: Option<Denomination>,
+    /// Total byte size of the values written by the transaction
+    pub written_bytes: u64,
+    /// The token the fees of a wrapper transaction were paid with, `None` for
+    /// other transaction types
+    pub fee_token: Option<Address>,
+    /// The tokens with a balance changed by the transaction
+    pub tokens_touched: BTreeSet<Address>,
+    /// The results of the inner transactions of a batch, keyed by their
+    /// header hash, up to the first one that failed. Empty for non-batch
+    /// transactions
+    pub batch_results: Vec<(Hash, std::result::Result<TxResult, String>)>,
+    /// The balance of the block proposer in the fee token after the fees of a
+    /// wrapper transaction were transferred to it, `None` for other
+    /// transaction types or if the fees were only checked
+    pub proposer_balance: Option<Amount>,
+    /// The byte size of a wrapper transaction that its wrapper gas was charged
+    /// for, `None` for other transaction types
+    pub wrapper_tx_bytes: Option<u64>,
+    /// Whether the transaction was accepted without changing any key nor
+    /// emitting any event, only flagged if requested at dispatch
+    pub is_noop: bool,
+    /// The gas used by each inner transaction of a batch, in order, up to the
+    /// first one that was rejected. Empty for non-batch transactions
+    pub per_inner_gas: Vec<u64>,
+    /// The aggregate gas used by the inner transactions of a batch, i.e. the
+    /// sum of `per_inner_gas`. The rest of `gas_used` is the gas of the
+    /// wrapper
+    pub inner_gas_used: u64,
 }
 
 impl TxResult {
@@ -193,6 +238,25 @@ impl TxResult {
     pub fn is_accepted(&self) -> bool {
         self.vps_result.rejected_vps.is_empty()
     }
+
+    /// Merge the data derived from the wrapper of the transaction into its
+    /// result: the keys changed by the wrapper, the fee token with its
+    /// denomination, the balance of the block proposer after the fees were
+    /// paid and the byte size of the wrapper
+    pub fn merge_wrapper(
+        &mut self,
+        mut wrapper_changed_keys: BTreeSet<storage::Key>,
+        fee_token: Address,
+        fee_denom: Option<Denomination>,
+        proposer_balance: Option<Amount>,
+        wrapper_tx_bytes: u64,
+    ) {
+        self.wrapper_changed_keys.append(&mut wrapper_changed_keys);
+        self.fee_token = Some(fee_token);
+        self.fee_denom = fee_denom;
+        self.proposer_balance = proposer_balance;
+        self.wrapper_tx_bytes = Some(wrapper_tx_bytes);
+    }
 }
 
 bitflags! {
@@ -204,6 +268,11 @@ bitflags! {
     pub struct VpStatusFlags: u64 {
         /// The transaction had an invalid signature.
         const INVALID_SIGNATURE = 0b0000_0001;
+        /// The transaction changed storage keys without triggering any
+        /// verifier.
+        const NO_VERIFIERS = 0b0000_0010;
+        /// A native VP panicked while validating the transaction.
+        const VP_PANIC = 0b0000_0100;
     }
 }
 
@@ -233,6 +302,199 @@ impl BorshDeserialize for VpStatusFlags {
 #[cfg(feature = "migrations")]
 namada_macros::derive_borshdeserializer!(VpStatusFlags);
 
+/// The reasons for which a native VP may reject a transaction, for clients
+/// to map to localized messages.
+/// The codes must not change with versions, only new ones may be added.
+#[derive(
+    Debug,
+    Copy,
+    Clone,
+    FromPrimitive,
+    ToPrimitive,
+    PartialEq,
+    Eq,
+    Hash,
+    BorshSerialize,
+    BorshDeserialize,
+    BorshDeserializer,
+    Serialize,
+    Deserialize,
+)]
+#[repr(u8)]
+#[borsh(use_discriminant = true)]
+pub enum NativeVpReason {
+    // WARN: These codes shouldn't be changed between version!
+    // =========================================================================
+    /// The native VP rejected the changes of the tx
+    Rejected = 0,
+    /// The storage of the internal address can't be accessed by txs
+    AccessForbidden = 1,
+    /// The native VP exceeded the gas ceiling of a single VP
+    GasCeilingExceeded = 2,
+    /// The native VP panicked
+    Panicked = 3,
+    // =========================================================================
+    // WARN: These codes shouldn't be changed between version!
+}
+
+impl NativeVpReason {
+    /// Convert to `u32`.
+    pub fn to_u32(&self) -> u32 {
+        ToPrimitive::to_u32(self).unwrap()
+    }
+
+    /// Convert from `u32`.
+    pub fn from_u32(raw: u32) -> Option<Self> {
+        FromPrimitive::from_u32(raw)
+    }
+}
+
+/// The class of the error of a VP rejecting a transaction, for clients to
+/// filter the errors by
+#[derive(
+    Debug,
+    Copy,
+    Clone,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    BorshSerialize,
+    BorshDeserialize,
+    BorshDeserializer,
+    Serialize,
+    Deserialize,
+)]
+pub enum VpErrorClass {
+    /// An invalid or missing signature
+    Signature,
+    /// The VP ran out of gas
+    Gas,
+    /// The VP rejected the changes of the transaction
+    Logic,
+    /// The transaction accessed a storage it isn't allowed to
+    Access,
+    /// The VP failed to run, e.g. it panicked or couldn't be loaded
+    Runtime,
+}
+
+/// The reason of the rejection of a transaction by a VP, tagged with the
+/// category of the originating error and carrying its message
+#[derive(
+    Debug,
+    Clone,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    BorshSerialize,
+    BorshDeserialize,
+    BorshDeserializer,
+    Serialize,
+    Deserialize,
+)]
+pub enum VpRejectionReason {
+    /// Rejected by the PoS VP
+    Pos(String),
+    /// Rejected by the IBC VP
+    Ibc(String),
+    /// Rejected by the parameters VP
+    Parameters(String),
+    /// Rejected by the governance VP
+    Governance(String),
+    /// Rejected by the multitoken VP
+    Multitoken(String),
+    /// Rejected by the Ethereum bridge VP
+    EthBridge(String),
+    /// Rejected by the Ethereum bridge pool VP
+    BridgePool(String),
+    /// Rejected by the PGF VP
+    Pgf(String),
+    /// Rejected by the non-usable tokens VP
+    Nut(String),
+    /// Rejected by the MASP VP
+    Masp(String),
+    /// The storage of the internal address can't be accessed by txs
+    AccessForbidden(String),
+    /// The VP exceeded the gas ceiling of a single VP
+    GasCeilingExceeded(String),
+    /// The VP panicked
+    Panicked(String),
+    /// Any other failure of the VP
+    Other(String),
+}
+
+impl VpRejectionReason {
+    /// The message of the originating error
+    pub fn message(&self) -> &str {
+        match self {
+            Self::Pos(msg)
+            | Self::Ibc(msg)
+            | Self::Parameters(msg)
+            | Self::Governance(msg)
+            | Self::Multitoken(msg)
+            | Self::EthBridge(msg)
+            | Self::BridgePool(msg)
+            | Self::Pgf(msg)
+            | Self::Nut(msg)
+            | Self::Masp(msg)
+            | Self::AccessForbidden(msg)
+            | Self::GasCeilingExceeded(msg)
+            | Self::Panicked(msg)
+            | Self::Other(msg) => msg,
+        }
+    }
+
+    /// The stable code of this reason, if reported by a native VP
+    pub fn native_vp_reason(&self) -> NativeVpReason {
+        match self {
+            Self::AccessForbidden(_) => NativeVpReason::AccessForbidden,
+            Self::GasCeilingExceeded(_) => NativeVpReason::GasCeilingExceeded,
+            Self::Panicked(_) => NativeVpReason::Panicked,
+            _ => NativeVpReason::Rejected,
+        }
+    }
+}
+
+/// The rejection of a transaction by a VP
+#[derive(
+    Debug,
+    Clone,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    BorshSerialize,
+    BorshDeserialize,
+    BorshDeserializer,
+    Serialize,
+    Deserialize,
+)]
+pub struct VpRejection {
+    /// The address of the VP
+    pub addr: Address,
+    /// The class of the error, for clients to filter by
+    pub class: VpErrorClass,
+    /// The reason of the rejection
+    pub reason: VpRejectionReason,
+}
+
+impl VpRejection {
+    /// The reason of the rejection as a stable code, only for the native
+    /// VPs
+    pub fn native_vp_reason(&self) -> Option<NativeVpReason> {
+        matches!(self.addr, Address::Internal(_))
+            .then(|| self.reason.native_vp_reason())
+    }
+}
+
+impl fmt::Display for VpRejection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} in {}", self.reason.message(), self.addr)
+    }
+}
+
 /// Result of checking a transaction with validity predicates
 // TODO derive BorshSchema after <https://github.com/near/borsh-rs/issues/82>
 #[derive(
@@ -252,12 +514,51 @@ pub struct VpsResult {
     pub rejected_vps: BTreeSet<Address>,
     /// The total gas used by all the VPs
     pub gas_used: VpsGas,
-    /// Errors occurred in any of the VPs, if any
-    pub errors: Vec<(Address, String)>,
+    /// The rejections by any of the VPs, if any. Use
+    /// [`VpsResult::errors_sorted`] for their canonical order.
+    pub errors: Vec<VpRejection>,
     /// Validity predicate status flags, containing info
     /// about conditions that caused their evaluation to
     /// fail.
     pub status_flags: VpStatusFlags,
+    /// The block epoch that each native VP resolved from state
+    #[cfg(feature = "debug-results")]
+    pub native_vp_epochs: BTreeMap<Address, storage::Epoch>,
+    /// Storage keys read by the native VPs, only collected with the
+    /// `read-set` feature
+    #[cfg(feature = "debug-results")]
+    pub read_keys: BTreeSet<storage::Key>,
+    /// The gas consumed by each VP
+    #[cfg(feature = "debug-results")]
+    pub per_vp_gas: BTreeMap<Address, Gas>,
+    /// Protocol parameter keys read by the native VPs, only collected with
+    /// the `read-set` feature
+    #[cfg(feature = "debug-results")]
+    pub read_parameters: BTreeSet<storage::Key>,
+    /// The VP code hash resolved for each account verifier, for auditing
+    #[cfg(feature = "debug-results")]
+    pub vp_code_hashes: BTreeMap<Address, Hash>,
+    /// The changed keys that each native VP validated, i.e. the ones in its
+    /// own storage, for the detection of conflicting txs
+    #[cfg(feature = "debug-results")]
+    pub validated_keys: BTreeMap<Address, BTreeSet<storage::Key>>,
+}
+
+impl VpsResult {
+    /// Check if the MASP VP is among the VPs that rejected the transaction
+    pub fn rejected_by_masp(&self) -> bool {
+        self.rejected_vps.contains(&namada_core::address::MASP)
+    }
+
+    /// The rejections by the VPs in their canonical order: sorted by
+    /// address, then class, then reason. The order is guaranteed to be the
+    /// same across nodes, regardless of the order in which the VPs
+    /// completed.
+    pub fn errors_sorted(&self) -> Vec<&VpRejection> {
+        let mut errors: Vec<_> = self.errors.iter().collect();
+        errors.sort();
+        errors
+    }
 }
 
 impl fmt::Display for TxResult {
@@ -296,12 +597,7 @@ impl fmt::Display for VpsResult {
             "{}{}{}",
             iterable_to_string("Accepted", self.accepted_vps.iter()),
             iterable_to_string("Rejected", self.rejected_vps.iter()),
-            iterable_to_string(
-                "Errors",
-                self.errors
-                    .iter()
-                    .map(|(addr, err)| format!("{} in {}", err, addr))
-            ),
+            iterable_to_string("Errors", self.errors.iter()),
         )
     }
 }
@@ -518,3 +814,78 @@ mod test_process_tx {
         assert_matches!(result, TxError::SigError(_));
     }
 }
+
+#[cfg(test)]
+mod test_tx_result {
+    use namada_core::address::testing::{established_address_1, nam};
+
+    use super::*;
+
+    /// Test that merging the data of the wrapper keeps the inner result and
+    /// reports the wrapper data like the dispatch of the wrapper used to
+    #[test]
+    fn test_merge_wrapper() {
+        let inner_key = storage::Key::parse("inner").unwrap();
+        let wrapper_key = storage::Key::parse("wrapper").unwrap();
+        let mut result = TxResult {
+            changed_keys: BTreeSet::from([inner_key.clone()]),
+            initialized_accounts: vec![established_address_1()],
+            ..Default::default()
+        };
+        result.merge_wrapper(
+            BTreeSet::from([wrapper_key.clone()]),
+            nam(),
+            Some(6.into()),
+            Some(Amount::from(100)),
+            42,
+        );
+
+        assert_eq!(result.changed_keys, BTreeSet::from([inner_key]));
+        assert_eq!(result.initialized_accounts, vec![established_address_1()]);
+        assert_eq!(result.wrapper_changed_keys, BTreeSet::from([wrapper_key]));
+        assert_eq!(result.fee_token, Some(nam()));
+        assert_eq!(result.fee_denom, Some(6.into()));
+        assert_eq!(result.proposer_balance, Some(Amount::from(100)));
+        assert_eq!(result.wrapper_tx_bytes, Some(42));
+    }
+}
+
+#[cfg(test)]
+mod test_vps_result {
+    use namada_core::address::testing::{
+        established_address_1, established_address_2, established_address_3,
+    };
+
+    use super::*;
+
+    /// Test that the errors of the VPs are returned in their canonical order
+    /// regardless of the order they were appended in
+    #[test]
+    fn test_errors_sorted() {
+        let mut addrs = [
+            established_address_1(),
+            established_address_2(),
+            established_address_3(),
+        ];
+        addrs.sort();
+        let rejection = |addr: &Address, class, msg: &str| VpRejection {
+            addr: addr.clone(),
+            class,
+            reason: VpRejectionReason::Other(msg.to_string()),
+        };
+        let errors = [
+            rejection(&addrs[2], VpErrorClass::Logic, "rejected"),
+            rejection(&addrs[0], VpErrorClass::Signature, "invalid signature"),
+            rejection(&addrs[1], VpErrorClass::Gas, "out of gas"),
+        ];
+        let expected = vec![&errors[1], &errors[2], &errors[0]];
+
+        for order in [[0, 1, 2], [2, 1, 0], [1, 0, 2]] {
+            let mut result = VpsResult::default();
+            for i in order {
+                result.errors.push(errors[i].clone());
+            }
+            assert_eq!(result.errors_sorted(), expected);
+        }
+    }
+}
@@ -57,6 +57,10 @@ impl ProtocolTx {
 #[derive(
     Clone,
     Debug,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
     BorshSerialize,
     BorshDeserialize,
     BorshDeserializer,
@@ -81,6 +85,8 @@ pub enum ProtocolTxType {
     BridgePoolVext,
     /// Validator set update signed by some validator
     ValSetUpdateVext,
+    /// Rotation of the Ethereum bridge hot key of some validator
+    EthHotKeyRotation,
 }
 
 impl ProtocolTxType {
@@ -96,6 +102,7 @@ impl ProtocolTxType {
                 | Self::EthEventsVext
                 | Self::BridgePoolVext
                 | Self::ValSetUpdateVext
+                | Self::EthHotKeyRotation
         )
     }
 }
@@ -14,7 +14,7 @@ pub mod wrapper_tx {
     };
     use namada_core::hash::Hash;
     use namada_core::key::*;
-    use namada_core::storage::Epoch;
+    use namada_core::storage::{BlockHeight, Epoch};
     use namada_core::token::{Amount, DenominatedAmount, Transfer};
     use namada_core::uint::Uint;
     use namada_gas::Gas;
@@ -243,6 +243,42 @@ pub mod wrapper_tx {
         }
     }
 
+    /// An optional extension of a wrapper tx, carried in its own
+    /// [`Section::WrapperExtension`] rather than in the [`WrapperTx`] header,
+    /// so that the encoding and the signature of the wrappers without
+    /// extensions are unchanged. The section is covered by the signature of
+    /// the wrapper like any other section of the tx.
+    #[derive(
+        Debug,
+        Clone,
+        PartialEq,
+        BorshSerialize,
+        BorshDeserialize,
+        BorshDeserializer,
+        BorshSchema,
+        Serialize,
+        Deserialize,
+        Eq,
+    )]
+    pub enum WrapperExtension {
+        /// The last height at which the wrapper can be applied. Past this
+        /// height, the replay protection entry of the wrapper is pruned, that
+        /// of its inner tx is kept.
+        ExpiryHeight(BlockHeight),
+        /// Ordered list of fees to pay instead, each in its own token and at
+        /// its own amount per gas unit, if the balance of the fee payer in
+        /// the fee token of the wrapper is insufficient
+        FeeTokenFallbacks(Vec<Fee>),
+    }
+
+    impl WrapperExtension {
+        /// Produce a SHA-256 hash of this section
+        pub fn hash<'a>(&self, hasher: &'a mut Sha256) -> &'a mut Sha256 {
+            hasher.update(self.serialize_to_vec());
+            hasher
+        }
+    }
+
     #[cfg(test)]
     mod test_gas_limits {
         use super::*;
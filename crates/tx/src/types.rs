@@ -20,7 +20,7 @@ use namada_core::collections::{HashMap, HashSet};
 use namada_core::key::*;
 use namada_core::masp::AssetData;
 use namada_core::sign::SignatureIndex;
-use namada_core::storage::Epoch;
+use namada_core::storage::{BlockHeight, Epoch};
 use namada_core::time::DateTimeUtc;
 use namada_macros::BorshDeserializer;
 #[cfg(feature = "migrations")]
@@ -31,7 +31,7 @@ use sha2::{Digest, Sha256};
 use thiserror::Error;
 
 use crate::data::protocol::ProtocolTx;
-use crate::data::{hash_tx, Fee, GasLimit, TxType, WrapperTx};
+use crate::data::{hash_tx, Fee, GasLimit, TxType, WrapperExtension, WrapperTx};
 use crate::proto;
 
 /// Represents an error in signature verification
@@ -767,6 +767,8 @@ pub enum Section {
     MaspBuilder(MaspBuilder),
     /// Wrap a header with a section for the purposes of computing hashes
     Header(Header),
+    /// An optional extension of the wrapper of the transaction
+    WrapperExtension(WrapperExtension),
 }
 
 impl Section {
@@ -789,6 +791,7 @@ impl Section {
                 hasher
             }
             Self::Header(header) => header.hash(hasher),
+            Self::WrapperExtension(extension) => extension.hash(hasher),
         }
     }
 
@@ -879,6 +882,15 @@ impl Section {
             None
         }
     }
+
+    /// Extract the wrapper extension from this section if possible
+    pub fn wrapper_extension(&self) -> Option<WrapperExtension> {
+        if let Self::WrapperExtension(data) = self {
+            Some(data.clone())
+        } else {
+            None
+        }
+    }
 }
 
 /// A Namada transaction header indicating where transaction subcomponents can
@@ -1518,6 +1530,41 @@ impl Tx {
         self
     }
 
+    /// Add an extension of the wrapper to the tx builder. Must be added before
+    /// the wrapper is signed
+    pub fn add_wrapper_extension(
+        &mut self,
+        extension: WrapperExtension,
+    ) -> &mut Self {
+        let _sec = self.add_section(Section::WrapperExtension(extension));
+        self
+    }
+
+    /// Get the expiry height of the wrapper, set by the first of its
+    /// [`WrapperExtension::ExpiryHeight`] extensions, if any
+    pub fn wrapper_expiry_height(&self) -> Option<BlockHeight> {
+        self.sections.iter().find_map(|section| match section {
+            Section::WrapperExtension(WrapperExtension::ExpiryHeight(
+                height,
+            )) => Some(*height),
+            _ => None,
+        })
+    }
+
+    /// Get the fallback fees of the wrapper, set by the first of its
+    /// [`WrapperExtension::FeeTokenFallbacks`] extensions, if any
+    pub fn wrapper_fee_token_fallbacks(&self) -> Vec<Fee> {
+        self.sections
+            .iter()
+            .find_map(|section| match section {
+                Section::WrapperExtension(
+                    WrapperExtension::FeeTokenFallbacks(fees),
+                ) => Some(fees.clone()),
+                _ => None,
+            })
+            .unwrap_or_default()
+    }
+
     /// Add fee payer keypair to the tx builder
     pub fn sign_wrapper(&mut self, keypair: common::SecretKey) -> &mut Self {
         self.protocol_filter();
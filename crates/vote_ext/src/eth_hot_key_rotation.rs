@@ -0,0 +1,49 @@
+//! Types for rotating the Ethereum bridge hot key of a validator. The
+//! rotation is signed with the hot key it replaces.
+
+use namada_core::address::Address;
+use namada_core::borsh::{BorshDeserialize, BorshSchema, BorshSerialize};
+use namada_core::key::common;
+use namada_core::storage::Epoch;
+use namada_macros::BorshDeserializer;
+#[cfg(feature = "migrations")]
+use namada_migrations::*;
+use namada_tx::Signed;
+
+/// The rotation of the Ethereum bridge hot key of some validator.
+#[derive(
+    Debug,
+    Clone,
+    PartialEq,
+    Eq,
+    BorshSerialize,
+    BorshDeserialize,
+    BorshDeserializer,
+    BorshSchema,
+)]
+pub struct EthHotKeyRotation {
+    /// The validator rotating its hot key
+    pub validator_addr: Address,
+    /// The epoch at which the rotation was signed.
+    ///
+    /// The rotation is only valid in this epoch, which
+    /// protects it against replays.
+    pub epoch: Epoch,
+    /// The new Ethereum bridge hot key of the validator
+    pub new_hot_key: common::PublicKey,
+}
+
+/// Alias for [`EthHotKeyRotation`].
+pub type Rotation = EthHotKeyRotation;
+
+/// A [`Rotation`] signed with the current hot key of
+/// the validator.
+pub type SignedRotation = Signed<Rotation>;
+
+impl Rotation {
+    /// Creates a new signed [`Rotation`].
+    #[inline]
+    pub fn sign(&self, sk: &common::SecretKey) -> SignedRotation {
+        Signed::new(sk, self.clone())
+    }
+}
@@ -1,6 +1,7 @@
 //! This module contains types necessary for processing vote extensions.
 
 pub mod bridge_pool_roots;
+pub mod eth_hot_key_rotation;
 pub mod ethereum_events;
 pub mod validator_set_update;
 
@@ -115,6 +116,8 @@ ethereum_tx_data_declare! {
         BridgePoolVext(bridge_pool_roots::SignedVext),
         /// Validator set update signed by some validator
         ValSetUpdateVext(validator_set_update::SignedVext),
+        /// Rotation of the Ethereum bridge hot key of some validator
+        EthHotKeyRotation(eth_hot_key_rotation::SignedRotation),
     }
 }
 
@@ -178,6 +181,7 @@ impl EthereumTxData {
             EthEventsVext,
             BridgePoolVext,
             ValSetUpdateVext,
+            EthHotKeyRotation,
         }
     }
 
@@ -211,6 +215,10 @@ impl EthereumTxData {
                 BorshDeserialize::try_from_slice(data)
                     .map(EthereumTxData::ValSetUpdateVext)
             },
+            ProtocolTxType::EthHotKeyRotation => |data| {
+                BorshDeserialize::try_from_slice(data)
+                    .map(EthereumTxData::EthHotKeyRotation)
+            },
         };
         deserialize(data)
             .map_err(|err| TxError::Deserialization(err.to_string()))